@@ -0,0 +1,63 @@
+//! Counts the heap allocations made per keystroke when typing into a rope, with leaf storage
+//! recycled through a `ChunkPool` and without.
+//! Run with `cargo bench --bench keystroke_allocations`.
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Instant;
+use kaudocore::core::buffer::memory::ChunkPool;
+use kaudocore::core::buffer::rope::Rope;
+
+struct CountingAllocator;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        unsafe { System.alloc(layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { System.dealloc(ptr, layout) }
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        unsafe { System.realloc(ptr, layout, new_size) }
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+const KEYSTROKES: usize = 20_000;
+
+/// Types `KEYSTROKES` chars, one at a time, in the middle of a 1 MiB document.
+fn type_text(name: &str, pool: ChunkPool) {
+    let text = "The quick brown fox jumps over the lazy dog.\n".repeat((1 << 20) / 45);
+    let mut rope = Rope::from_text(&text).with_pool(Arc::new(pool));
+    let cursor = text.len() / 2;
+
+    let start = Instant::now();
+    let before = ALLOCATIONS.load(Ordering::Relaxed);
+    for i in 0..KEYSTROKES {
+        let ch = if i % 60 == 59 { '\n' } else { 'a' };
+        rope.insert_char(cursor + i, ch).unwrap();
+    }
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed) - before;
+    let elapsed = start.elapsed();
+
+    println!(
+        "{:<10} {:>6.2} allocations/keystroke {:>8.0} ns/keystroke  pool: {:?}",
+        name,
+        allocations as f64 / KEYSTROKES as f64,
+        elapsed.as_nanos() as f64 / KEYSTROKES as f64,
+        rope.pool().stats(),
+    );
+}
+
+fn main() {
+    type_text("unpooled", ChunkPool::disabled());
+    type_text("pooled", ChunkPool::default());
+}
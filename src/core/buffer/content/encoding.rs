@@ -5,9 +5,13 @@
 use std::borrow::Cow;
 use std::error::Error;
 use std::fmt;
+use crate::core::buffer::content::lossless::{raw_byte, LosslessText};
+use crate::utils::encoding::conversion::{CodePage, DecodeStep};
+use crate::utils::encoding::detection::EncodingDetector;
 
-#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Default)]
 pub enum Encoding {
+    #[default]
     UTF8,
     UTF16,
     UTF16LE,
@@ -15,6 +19,11 @@ pub enum Encoding {
     LATIN1,
     WINDOWS1252,
     ASCII,
+    KOI8R,
+    WINDOWS1251,
+    SHIFTJIS,
+    GBK,
+    EUCKR,
 }
 
 impl Encoding {
@@ -27,6 +36,11 @@ impl Encoding {
             Encoding::LATIN1 => "ISO-8859-1",
             Encoding::WINDOWS1252 => "WINDOWS-1252",
             Encoding::ASCII => "ASCII",
+            Encoding::KOI8R => "KOI8-R",
+            Encoding::WINDOWS1251 => "WINDOWS-1251",
+            Encoding::SHIFTJIS => "Shift_JIS",
+            Encoding::GBK => "GBK",
+            Encoding::EUCKR => "EUC-KR",
         }
     }
 
@@ -39,6 +53,11 @@ impl Encoding {
             Encoding::LATIN1,
             Encoding::WINDOWS1252,
             Encoding::ASCII,
+            Encoding::KOI8R,
+            Encoding::WINDOWS1251,
+            Encoding::SHIFTJIS,
+            Encoding::GBK,
+            Encoding::EUCKR,
         ]
     }
 
@@ -51,14 +70,58 @@ impl Encoding {
             "ISO-8859-1" => Some(Encoding::LATIN1),
             "WINDOWS-1252" => Some(Encoding::WINDOWS1252),
             "ASCII" => Some(Encoding::ASCII),
+            "KOI8-R" => Some(Encoding::KOI8R),
+            "WINDOWS-1251" => Some(Encoding::WINDOWS1251),
+            "Shift_JIS" => Some(Encoding::SHIFTJIS),
+            "GBK" => Some(Encoding::GBK),
+            "EUC-KR" => Some(Encoding::EUCKR),
             _ => None,
         }
     }
-}
 
-impl Default for Encoding {
-    fn default() -> Self {
-        Encoding::UTF8
+    /// The byte order mark of the encoding, empty for those that have none.
+    pub fn bom(&self) -> &'static [u8] {
+        match self {
+            Encoding::UTF8 => &[0xEF, 0xBB, 0xBF],
+            Encoding::UTF16 | Encoding::UTF16LE => &[0xFF, 0xFE],
+            Encoding::UTF16BE => &[0xFE, 0xFF],
+            _ => &[],
+        }
+    }
+
+    /// Whether `ch` has a representation in the encoding.
+    pub fn can_encode(&self, ch: char) -> bool {
+        match self {
+            Encoding::UTF8 | Encoding::UTF16 | Encoding::UTF16LE | Encoding::UTF16BE => true,
+            Encoding::LATIN1 => (ch as u32) <= 0xFF,
+            Encoding::WINDOWS1252 => windows1252_byte(ch).is_some(),
+            Encoding::ASCII => ch.is_ascii(),
+            Encoding::KOI8R | Encoding::WINDOWS1251 | Encoding::SHIFTJIS | Encoding::GBK | Encoding::EUCKR => {
+                CodePage::for_encoding(self).is_some_and(|page| page.encode_char(ch, &mut Vec::with_capacity(2)))
+            }
+        }
+    }
+
+    /// Decodes the character at the start of `bytes`.
+    pub fn decode_step(&self, bytes: &[u8]) -> DecodeStep {
+        let Some(&byte) = bytes.first() else {
+            return DecodeStep::Incomplete;
+        };
+        match self {
+            Encoding::UTF8 => utf8_step(bytes),
+            Encoding::UTF16 | Encoding::UTF16LE => utf16_step(bytes, u16::from_le_bytes),
+            Encoding::UTF16BE => utf16_step(bytes, u16::from_be_bytes),
+            Encoding::LATIN1 => DecodeStep::Char(byte as char, 1),
+            Encoding::WINDOWS1252 => DecodeStep::Char(windows1252_char(byte), 1),
+            Encoding::ASCII if byte.is_ascii() => DecodeStep::Char(byte as char, 1),
+            Encoding::ASCII => DecodeStep::Invalid(1),
+            Encoding::KOI8R | Encoding::WINDOWS1251 | Encoding::SHIFTJIS | Encoding::GBK | Encoding::EUCKR => {
+                match CodePage::for_encoding(self) {
+                    Some(page) => page.decode_step(bytes),
+                    None => DecodeStep::Invalid(1),
+                }
+            }
+        }
     }
 }
 
@@ -131,7 +194,7 @@ impl EncodingHandler {
     pub fn set_encoding(&mut self, encoding: Encoding) {
         self.encoding = encoding;
         if self.with_bom {
-            self.bom_bytes = match encoding {
+            self.bom_bytes = match self.encoding {
                 Encoding::UTF8 => vec![0xEF, 0xBB, 0xBF],
                 Encoding::UTF16LE => vec![0xFF, 0xFE],
                 Encoding::UTF16BE => vec![0xFE, 0xFF],
@@ -154,26 +217,11 @@ impl EncodingHandler {
         }
     }
 
+    /// Picks the most likely encoding of `bytes`; see `EncodingDetector` for the ranked
+    /// candidates.
     pub fn detect_encoding(bytes: &[u8]) -> Result<(Encoding, bool), EncodingError> {
-        if bytes.starts_with(&[0xEF, 0xBB, 0xBF]) {
-            return Ok((Encoding::UTF8, true));
-        }
-        if bytes.starts_with(&[0xFF, 0xFE]) {
-            return Ok((Encoding::UTF16LE, true));
-        }
-        if bytes.starts_with(&[0xFE, 0xFF]) {
-            return Ok((Encoding::UTF16BE, true));
-        }
-
-        if std::str::from_utf8(bytes).is_ok() {
-            return Ok((Encoding::UTF8, false));
-        }
-
-        if bytes.iter().all(|&b| b < 128) {
-            return Ok((Encoding::ASCII, false));
-        }
-
-        Ok((Encoding::LATIN1, false))
+        let best = EncodingDetector::default().best(bytes);
+        Ok((best.encoding, best.has_bom))
     }
 
     pub fn decode(&self, bytes: &[u8]) -> Result<String, EncodingError> {
@@ -185,16 +233,16 @@ impl EncodingHandler {
                     .map_err(|e| EncodingError::InvalidSequence(e.to_string()))
             }
             Encoding::UTF16 | Encoding::UTF16LE => {
-                self.decode_utf16le(data)
+                self.decode_utf16le(&data)
             }
             Encoding::UTF16BE => {
-                self.decode_utf16be(data)
+                self.decode_utf16be(&data)
             }
             Encoding::LATIN1 => {
                 Ok(data.iter().map(|&b| b as char).collect())
             }
             Encoding::WINDOWS1252 => {
-                self.decode_windows1252(data)
+                self.decode_windows1252(&data)
             }
             Encoding::ASCII => {
                 if data.iter().all(|&b| b < 128) {
@@ -203,9 +251,44 @@ impl EncodingHandler {
                     Err(EncodingError::InvalidSequence("Caractères non-ASCII détectés".to_string()))
                 }
             }
+            Encoding::KOI8R | Encoding::WINDOWS1251 | Encoding::SHIFTJIS | Encoding::GBK | Encoding::EUCKR => {
+                self.code_page()?.decode(&data)
+            }
         }
     }
 
+    /// Decodes `bytes` without failing: each byte that isn't part of a valid sequence becomes
+    /// a raw-byte marker, & the runs of such bytes are reported with the text.
+    pub fn decode_lossless(&self, bytes: &[u8]) -> LosslessText {
+        let (data, _) = self.skip_bom(bytes);
+        let offset = bytes.len() - data.len();
+        let mut decoded = LosslessText::default();
+        let mut pos = 0;
+        while pos < data.len() {
+            let len = match self.encoding.decode_step(&data[pos..]) {
+                // A genuine marker char is kept as raw bytes too, or saving would write it as one byte.
+                DecodeStep::Char(ch, len) if raw_byte(ch).is_some() => {
+                    decoded.push_raw(&data[pos..pos + len], offset + pos);
+                    len
+                }
+                DecodeStep::Char(ch, len) => {
+                    decoded.text.push(ch);
+                    len
+                }
+                DecodeStep::Invalid(len) => {
+                    decoded.push_raw(&data[pos..pos + len], offset + pos);
+                    len
+                }
+                DecodeStep::Incomplete => {
+                    decoded.push_raw(&data[pos..], offset + pos);
+                    data.len() - pos
+                }
+            };
+            pos += len;
+        }
+        decoded
+    }
+
     pub fn encode(&self, text: &str) -> Result<Vec<u8>, EncodingError> {
         let mut result = Vec::new();
 
@@ -213,6 +296,32 @@ impl EncodingHandler {
             result.extend_from_slice(&self.bom_bytes);
         }
 
+        self.encode_into(text, &mut result)?;
+        Ok(result)
+    }
+
+    /// Encodes `text` like `encode`, except that raw-byte markers are written as the bytes they
+    /// stand for, so that `encode_lossless(decode_lossless(bytes))` gives back `bytes`.
+    pub fn encode_lossless(&self, text: &str) -> Result<Vec<u8>, EncodingError> {
+        let mut result = Vec::new();
+
+        if self.with_bom {
+            result.extend_from_slice(&self.bom_bytes);
+        }
+
+        let mut start = 0;
+        for (idx, ch) in text.char_indices() {
+            if let Some(byte) = raw_byte(ch) {
+                self.encode_into(&text[start..idx], &mut result)?;
+                result.push(byte);
+                start = idx + ch.len_utf8();
+            }
+        }
+        self.encode_into(&text[start..], &mut result)?;
+        Ok(result)
+    }
+
+    fn encode_into(&self, text: &str, result: &mut Vec<u8>) -> Result<(), EncodingError> {
         match &self.encoding {
             Encoding::UTF8 => {
                 result.extend_from_slice(text.as_bytes());
@@ -253,17 +362,23 @@ impl EncodingHandler {
                     }
                 }
             }
+            Encoding::KOI8R | Encoding::WINDOWS1251 | Encoding::SHIFTJIS | Encoding::GBK | Encoding::EUCKR => {
+                result.extend(self.code_page()?.encode(text)?);
+            }
         }
 
-        Ok(result)
+        Ok(())
     }
 
-    fn skip_bom(&self, bytes: &[u8]) -> (Cow<[u8]>, bool) {
+    fn code_page(&self) -> Result<CodePage, EncodingError> {
+        CodePage::for_encoding(&self.encoding)
+            .ok_or_else(|| EncodingError::UnsupportedEncoding(self.encoding.name().to_string()))
+    }
+
+    fn skip_bom<'a>(&self, bytes: &'a [u8]) -> (Cow<'a, [u8]>, bool) {
         if bytes.starts_with(&[0xEF, 0xBB, 0xBF]) {
             (Cow::Borrowed(&bytes[3..]), true)
-        } else if bytes.starts_with(&[0xFF, 0xFE]) {
-            (Cow::Borrowed(&bytes[2..]), true)
-        } else if bytes.starts_with(&[0xFE, 0xFF]) {
+        } else if bytes.starts_with(&[0xFF, 0xFE]) || bytes.starts_with(&[0xFE, 0xFF]) {
             (Cow::Borrowed(&bytes[2..]), true)
         } else {
             (Cow::Borrowed(bytes), false)
@@ -271,7 +386,7 @@ impl EncodingHandler {
     }
 
     fn decode_utf16le(&self, bytes: &[u8]) -> Result<String, EncodingError> {
-        if bytes.len() % 2 != 0 {
+        if !bytes.len().is_multiple_of(2) {
             return Err(EncodingError::InvalidSequence("Longueur impaire pour UTF-16".to_string()));
         }
 
@@ -286,7 +401,7 @@ impl EncodingHandler {
     }
 
     fn decode_utf16be(&self, bytes: &[u8]) -> Result<String, EncodingError> {
-        if bytes.len() % 2 != 0 {
+        if !bytes.len().is_multiple_of(2) {
             return Err(EncodingError::InvalidSequence("Longueur impaire pour UTF-16".to_string()));
         }
 
@@ -301,86 +416,92 @@ impl EncodingHandler {
     }
 
     fn decode_windows1252(&self, bytes: &[u8]) -> Result<String, EncodingError> {
-        let mut result = String::new();
-        
-        for &byte in bytes {
-            let ch = match byte {
-                0x80 => '€',
-                0x82 => '‚',
-                0x83 => 'ƒ',
-                0x84 => '„',
-                0x85 => '…',
-                0x86 => '†',
-                0x87 => '‡',
-                0x88 => 'ˆ',
-                0x89 => '‰',
-                0x8A => 'Š',
-                0x8B => '‹',
-                0x8C => 'Œ',
-                0x8E => 'Ž',
-                0x91 => '‘',
-                0x92 => '’',
-                0x93 => '"',
-                0x94 => '"',
-                0x95 => '•',
-                0x96 => '–',
-                0x97 => '—',
-                0x98 => '˜',
-                0x99 => '™',
-                0x9A => 'š',
-                0x9B => '›',
-                0x9C => 'œ',
-                0x9E => 'ž',
-                0x9F => 'Ÿ',
-                _ => byte as char,
-            };
-            result.push(ch);
-        }
-        
-        Ok(result)
+        Ok(bytes.iter().map(|&byte| windows1252_char(byte)).collect())
     }
 
     fn encode_windows1252(&self, text: &str) -> Result<Vec<u8>, EncodingError> {
-        let mut result = Vec::new();
-        
-        for ch in text.chars() {
-            let byte = match ch {
-                '€' => 0x80,
-                '‚' => 0x82,
-                'ƒ' => 0x83,
-                '„' => 0x84,
-                '…' => 0x85,
-                '†' => 0x86,
-                '‡' => 0x87,
-                'ˆ' => 0x88,
-                '‰' => 0x89,
-                'Š' => 0x8A,
-                '‹' => 0x8B,
-                'Œ' => 0x8C,
-                'Ž' => 0x8E,
-                '‘' => 0x91,
-                '’' => 0x92,
-                '"' => 0x93,
-                '"' => 0x94,
-                '•' => 0x95,
-                '–' => 0x96,
-                '—' => 0x97,
-                '˜' => 0x98,
-                '™' => 0x99,
-                'š' => 0x9A,
-                '›' => 0x9B,
-                'œ' => 0x9C,
-                'ž' => 0x9E,
-                'Ÿ' => 0x9F,
-                c if (c as u32) <= 255 => c as u8,
-                c => return Err(EncodingError::ConversionFailed(
-                    format!("Caractère '{}' non représentable en Windows-1252", c)
-                )),
-            };
-            result.push(byte);
-        }
-        
-        Ok(result)
+        text.chars()
+            .map(|ch| windows1252_byte(ch).ok_or_else(|| {
+                EncodingError::ConversionFailed(format!("Caractère '{}' non représentable en Windows-1252", ch))
+            }))
+            .collect()
+    }
+}
+
+fn utf8_step(bytes: &[u8]) -> DecodeStep {
+    let len = match bytes[0] {
+        0x00..=0x7F => 1,
+        0xC2..=0xDF => 2,
+        0xE0..=0xEF => 3,
+        0xF0..=0xF4 => 4,
+        _ => return DecodeStep::Invalid(1),
+    };
+    match std::str::from_utf8(&bytes[..len.min(bytes.len())]) {
+        Ok(text) => text.chars().next().map_or(DecodeStep::Incomplete, |ch| DecodeStep::Char(ch, len)),
+        Err(error) => match error.error_len() {
+            Some(invalid) => DecodeStep::Invalid(invalid),
+            None => DecodeStep::Incomplete,
+        },
+    }
+}
+
+fn utf16_step(bytes: &[u8], unit: fn([u8; 2]) -> u16) -> DecodeStep {
+    if bytes.len() < 2 {
+        return DecodeStep::Incomplete;
+    }
+    let first = unit([bytes[0], bytes[1]]);
+    if !(0xD800..0xDC00).contains(&first) {
+        return char::from_u32(first as u32).map_or(DecodeStep::Invalid(2), |ch| DecodeStep::Char(ch, 2));
+    }
+    if bytes.len() < 4 {
+        return DecodeStep::Incomplete;
+    }
+    match char::decode_utf16([first, unit([bytes[2], bytes[3]])]).next() {
+        Some(Ok(ch)) => DecodeStep::Char(ch, 4),
+        _ => DecodeStep::Invalid(2),
+    }
+}
+
+/// Decodes a Windows-1252 byte. The five undefined bytes map to the C1 control chars.
+fn windows1252_char(byte: u8) -> char {
+    match byte {
+        0x80 => '€',
+        0x82 => '‚',
+        0x83 => 'ƒ',
+        0x84 => '„',
+        0x85 => '…',
+        0x86 => '†',
+        0x87 => '‡',
+        0x88 => 'ˆ',
+        0x89 => '‰',
+        0x8A => 'Š',
+        0x8B => '‹',
+        0x8C => 'Œ',
+        0x8E => 'Ž',
+        0x91 => '‘',
+        0x92 => '’',
+        0x93 => '“',
+        0x94 => '”',
+        0x95 => '•',
+        0x96 => '–',
+        0x97 => '—',
+        0x98 => '˜',
+        0x99 => '™',
+        0x9A => 'š',
+        0x9B => '›',
+        0x9C => 'œ',
+        0x9E => 'ž',
+        0x9F => 'Ÿ',
+        _ => byte as char,
+    }
+}
+
+/// Encodes a char as Windows-1252. The C1 control chars only have a byte where Windows-1252
+/// leaves it undefined, since the others decode as the chars of 0x80..=0x9F.
+fn windows1252_byte(ch: char) -> Option<u8> {
+    match ch as u32 {
+        0x00..=0x7F | 0xA0..=0xFF => Some(ch as u8),
+        _ => (0x80..=0x9F).find(|&byte| windows1252_char(byte) == ch),
     }
 }
 
@@ -434,6 +555,37 @@ mod tests {
         assert!(result.is_err());
     }
 
+    #[test]
+    fn test_detect_legacy_encoding() {
+        let handler = EncodingHandler::new(Encoding::SHIFTJIS);
+        let text = "これは日本語のファイルです。";
+
+        let encoded = handler.encode(text).unwrap();
+        let (encoding, has_bom) = EncodingHandler::detect_encoding(&encoded).unwrap();
+
+        assert_eq!(encoding, Encoding::SHIFTJIS);
+        assert!(!has_bom);
+        assert_eq!(EncodingHandler::new(encoding).decode(&encoded).unwrap(), text);
+        assert_eq!(Encoding::from_name(Encoding::EUCKR.name()), Some(Encoding::EUCKR));
+    }
+
+    #[test]
+    fn test_windows1252_round_trip() {
+        let handler = EncodingHandler::new(Encoding::WINDOWS1252);
+        let bytes: Vec<u8> = (0x80..=0x9F).collect();
+        let text = handler.decode(&bytes).unwrap();
+        assert_eq!(handler.encode(&text).unwrap(), bytes);
+        assert!(text.chars().all(|ch| Encoding::WINDOWS1252.can_encode(ch)));
+
+        // C1 chars whose byte decodes as another char can't be written.
+        for ch in ['\u{80}', '\u{93}', '\u{9F}'] {
+            assert!(!Encoding::WINDOWS1252.can_encode(ch));
+            assert!(handler.encode(&ch.to_string()).is_err());
+        }
+        assert!(Encoding::WINDOWS1252.can_encode('\u{81}'));
+        assert!(Encoding::WINDOWS1252.can_encode('é'));
+    }
+
     #[test]
     fn test_latin1_encoding() {
         let handler = EncodingHandler::new(Encoding::LATIN1);
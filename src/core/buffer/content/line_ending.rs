@@ -1,7 +1,9 @@
 /// line_ending.rs
 /// File for handling line endings in text buffers.
 /// This module provides functionality to detect, convert, & manage line endings.
+use std::collections::HashMap;
 use std::fmt;
+use std::ops::Range;
 use std::str::FromStr;
 use thiserror::Error;
 use unicode_segmentation::UnicodeSegmentation;
@@ -80,6 +82,18 @@ impl LineEnding {
         }
     }
 
+    /// Returns the line ending made of the single char `ch`, if any. `CRLF` is never returned.
+    pub fn from_char(ch: char) -> Option<LineEnding> {
+        match ch {
+            '\n' => Some(LineEnding::LF),
+            '\r' => Some(LineEnding::CR),
+            '\u{0085}' => Some(LineEnding::NEL),
+            '\u{2028}' => Some(LineEnding::LS),
+            '\u{2029}' => Some(LineEnding::PS),
+            _ => None,
+        }
+    }
+
     pub fn as_bytes(&self, encoding: &Encoding) -> Vec<u8> {
         let handler = EncodingHandler::new(encoding.clone());
         handler.encode(self.as_str()).unwrap_or_default()
@@ -165,10 +179,12 @@ impl LineEnding {
         }
     }
 
+    /// Rewrites the line endings of `text`, written with `self`, as LF. A CR left alone in CRLF
+    /// text, as mixed line endings have, ends a line too.
     pub fn normalize_to_lf(&self, text: &str) -> String {
         match self {
             LineEnding::LF => text.to_string(),
-            LineEnding::CRLF => text.replace("\r\n", "\n"),
+            LineEnding::CRLF => text.replace("\r\n", "\n").replace('\r', "\n"),
             LineEnding::CR => text.replace('\r', "\n"),
             LineEnding::NEL => text.replace('\u{0085}', "\n"),
             LineEnding::LS => text.replace('\u{2028}', "\n"),
@@ -191,10 +207,7 @@ impl LineEnding {
             LineEnding::PS => text.matches('\u{2029}').count() + 1,
             LineEnding::Unknown => {
                 let normalized = text.replace("\r\n", "\n")
-                    .replace('\r', "\n")
-                    .replace('\u{0085}', "\n")
-                    .replace('\u{2028}', "\n")
-                    .replace('\u{2029}', "\n");
+                    .replace(['\r', '\u{0085}', '\u{2028}', '\u{2029}'], "\n");
                 normalized.matches('\n').count() + 1
             }
         }
@@ -216,6 +229,24 @@ impl LineEnding {
         }
     }
 
+    /// Iterates over the line endings of `text` with their byte offsets.
+    pub fn find_all(text: &str) -> LineEndings<'_> {
+        LineEndings { text, pos: 0 }
+    }
+
+    /// Counts the line endings of `text` per variant & lists the lines that end otherwise
+    /// than most, unlike `detect` which only picks the most frequent one.
+    pub fn analyze(text: &str) -> LineEndingAnalysis {
+        LineEndingAnalysis::from_endings(Self::find_all(text).map(|(_, ending)| ending))
+    }
+
+    /// Rewrites every line ending of `text`, whatever its variant, to `target`, returning the
+    /// changes so that they can be undone.
+    pub fn normalize(text: &str, target: LineEnding) -> (String, Normalization) {
+        let normalization = Normalization::new(target, Self::find_all(text));
+        (apply_edits(text, &normalization.edits()), normalization)
+    }
+
     pub fn all() -> Vec<LineEnding> {
         vec![
             LineEnding::LF,
@@ -246,6 +277,145 @@ impl LineEnding {
     }
 }
 
+/// Yields the byte offset & variant of every line ending of a text, a `CRLF` pair once.
+#[derive(Debug, Clone)]
+pub struct LineEndings<'a> {
+    text: &'a str,
+    pos: usize,
+}
+
+impl Iterator for LineEndings<'_> {
+    type Item = (usize, LineEnding);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let rest = &self.text[self.pos..];
+        let idx = rest.find(|ch| LineEnding::from_char(ch).is_some())?;
+        let offset = self.pos + idx;
+        let ending = if rest[idx..].starts_with("\r\n") {
+            LineEnding::CRLF
+        } else {
+            rest[idx..].chars().next().and_then(LineEnding::from_char).unwrap_or(LineEnding::LF)
+        };
+        self.pos = offset + ending.as_str().len();
+        Some((offset, ending))
+    }
+}
+
+/// How the lines of a text end, see `LineEnding::analyze`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LineEndingAnalysis {
+    pub counts: HashMap<LineEnding, usize>,
+    /// The most frequent line ending, the first of `LineEnding::all` on ties.
+    pub dominant: Option<LineEnding>,
+    /// Line, counted from 0, & line ending of each line that doesn't end with `dominant`.
+    pub outliers: Vec<(usize, LineEnding)>,
+}
+
+impl LineEndingAnalysis {
+    /// Analyzes the line endings, in order, of a text.
+    pub fn from_endings(endings: impl IntoIterator<Item = LineEnding>) -> Self {
+        let endings: Vec<LineEnding> = endings.into_iter().collect();
+        let mut counts = HashMap::new();
+        for &ending in &endings {
+            *counts.entry(ending).or_insert(0) += 1;
+        }
+        let max = counts.values().copied().max().unwrap_or(0);
+        let dominant = LineEnding::all().into_iter().find(|ending| max > 0 && counts.get(ending) == Some(&max));
+        let outliers = endings
+            .iter()
+            .enumerate()
+            .filter(|&(_, ending)| Some(*ending) != dominant)
+            .map(|(line, &ending)| (line, ending))
+            .collect();
+        Self { counts, dominant, outliers }
+    }
+
+    pub fn count(&self, line_ending: LineEnding) -> usize {
+        self.counts.get(&line_ending).copied().unwrap_or(0)
+    }
+
+    /// Number of line endings.
+    pub fn total(&self) -> usize {
+        self.counts.values().sum()
+    }
+
+    /// Whether the lines don't all end alike.
+    pub fn is_mixed(&self) -> bool {
+        !self.outliers.is_empty()
+    }
+}
+
+/// A line ending rewritten by a normalization.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LineEndingChange {
+    /// Where the new line ending is in the normalized text.
+    pub offset: usize,
+    pub original: LineEnding,
+}
+
+/// What normalizing the line endings of a text changed, enough to undo it: apply `edits` to
+/// the original text to normalize it & `undo_edits` to the normalized text to get it back.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Normalization {
+    pub target: LineEnding,
+    pub changes: Vec<LineEndingChange>,
+}
+
+impl Normalization {
+    /// Plans the normalization to `target` of a text with the given line endings, in order.
+    pub fn new(target: LineEnding, endings: impl IntoIterator<Item = (usize, LineEnding)>) -> Self {
+        let mut changes = Vec::new();
+        if target != LineEnding::Unknown {
+            let mut shift = 0isize;
+            for (offset, ending) in endings.into_iter().filter(|&(_, ending)| ending != target) {
+                changes.push(LineEndingChange { offset: offset.saturating_add_signed(shift), original: ending });
+                shift += target.as_str().len() as isize - ending.as_str().len() as isize;
+            }
+        }
+        Self { target, changes }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty()
+    }
+
+    /// Edits normalizing the original text, sorted as `Rope::apply_edits` wants them.
+    pub fn edits(&self) -> Vec<(Range<usize>, &'static str)> {
+        let mut shift = 0isize;
+        self.changes
+            .iter()
+            .map(|change| {
+                let start = change.offset.saturating_add_signed(-shift);
+                shift += self.target.as_str().len() as isize - change.original.as_str().len() as isize;
+                (start..start + change.original.as_str().len(), self.target.as_str())
+            })
+            .collect()
+    }
+
+    /// Edits turning the normalized text back into the original one.
+    pub fn undo_edits(&self) -> Vec<(Range<usize>, &'static str)> {
+        let len = self.target.as_str().len();
+        self.changes.iter().map(|change| (change.offset..change.offset + len, change.original.as_str())).collect()
+    }
+
+    /// Gives back the original of `normalized`.
+    pub fn undo(&self, normalized: &str) -> String {
+        apply_edits(normalized, &self.undo_edits())
+    }
+}
+
+fn apply_edits(text: &str, edits: &[(Range<usize>, &str)]) -> String {
+    let mut result = String::with_capacity(text.len());
+    let mut pos = 0;
+    for (range, replacement) in edits {
+        result.push_str(&text[pos..range.start]);
+        result.push_str(replacement);
+        pos = range.end;
+    }
+    result.push_str(&text[pos..]);
+    result
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -281,6 +451,14 @@ mod tests {
         assert!(!normalized.contains('\r'));
     }
 
+    #[test]
+    fn test_normalize_mixed_crlf_to_lf() {
+        let text = "a\r\nb\rc\nd\r";
+        assert_eq!(LineEnding::CRLF.normalize_to_lf(text), "a\nb\nc\nd\n");
+        assert_eq!(LineEnding::CRLF.convert_text(text, LineEnding::LF), "a\nb\nc\nd\n");
+        assert_eq!(LineEnding::LF.normalize_to_lf(text), text);
+    }
+
     #[test]
     fn test_unicode_line_endings() {
         assert!(LineEnding::NEL.is_unicode());
@@ -297,6 +475,34 @@ mod tests {
         assert!("invalid".parse::<LineEnding>().is_err());
     }
 
+    #[test]
+    fn test_analyze_mixed_line_endings() {
+        let text = "a\r\nb\nc\r\nd\re\r\nf";
+        let analysis = LineEnding::analyze(text);
+        assert_eq!(analysis.count(LineEnding::CRLF), 3);
+        assert_eq!(analysis.count(LineEnding::LF), 1);
+        assert_eq!(analysis.count(LineEnding::CR), 1);
+        assert_eq!(analysis.total(), 5);
+        assert_eq!(analysis.dominant, Some(LineEnding::CRLF));
+        assert_eq!(analysis.outliers, vec![(1, LineEnding::LF), (3, LineEnding::CR)]);
+        assert!(analysis.is_mixed());
+        assert!(!LineEnding::analyze("one\ntwo\n").is_mixed());
+        assert_eq!(LineEnding::analyze("").dominant, None);
+    }
+
+    #[test]
+    fn test_normalize_and_undo() {
+        let text = "a\r\nb\nc\u{2028}d\r";
+        let (normalized, normalization) = LineEnding::normalize(text, LineEnding::CRLF);
+        assert_eq!(normalized, "a\r\nb\r\nc\r\nd\r\n");
+        assert_eq!(normalization.changes.len(), 3);
+        assert_eq!(normalization.undo(&normalized), text);
+
+        let (same, nothing) = LineEnding::normalize("x\ny", LineEnding::LF);
+        assert_eq!(same, "x\ny");
+        assert!(nothing.is_empty());
+    }
+
     #[test]
     fn test_empty_text() {
         assert_eq!(LineEnding::LF.count_lines(""), 1);
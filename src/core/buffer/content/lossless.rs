@@ -0,0 +1,129 @@
+/// lossless.rs
+/// Raw-byte markers, which stand in the text for bytes a file's encoding can't decode, so that
+/// opening & saving a file that isn't valid in its encoding gives back its exact bytes.
+use std::ops::Range;
+
+/// Marker `RAW_BYTE_BASE + b` stands for the raw byte `b`. The 256 markers, U+10FF00 to
+/// U+10FFFF, close the last private use plane, where real text hardly ever goes.
+pub const RAW_BYTE_BASE: u32 = 0x10FF00;
+
+/// The marker standing for `byte`.
+pub fn raw_byte_marker(byte: u8) -> char {
+    char::from_u32(RAW_BYTE_BASE + byte as u32).unwrap_or(char::REPLACEMENT_CHARACTER)
+}
+
+/// The byte `ch` stands for, if it is a marker.
+pub fn raw_byte(ch: char) -> Option<u8> {
+    (ch as u32).checked_sub(RAW_BYTE_BASE).map(|byte| byte as u8)
+}
+
+/// Byte ranges of the runs of markers in `text`, e.g. to highlight them after edits.
+pub fn raw_byte_runs(text: &str) -> Vec<Range<usize>> {
+    let mut runs: Vec<Range<usize>> = Vec::new();
+    for (idx, ch) in text.char_indices().filter(|&(_, ch)| raw_byte(ch).is_some()) {
+        match runs.last_mut() {
+            Some(run) if run.end == idx => run.end = idx + ch.len_utf8(),
+            _ => runs.push(idx..idx + ch.len_utf8()),
+        }
+    }
+    runs
+}
+
+/// A run of bytes that didn't decode.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InvalidRun {
+    /// Where the markers are in the decoded text.
+    pub text: Range<usize>,
+    /// Where the bytes were in the input.
+    pub bytes: Range<usize>,
+}
+
+/// Text decoded by `EncodingHandler::decode_lossless`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LosslessText {
+    pub text: String,
+    /// The runs of undecodable bytes, in order.
+    pub invalid: Vec<InvalidRun>,
+}
+
+impl LosslessText {
+    /// Whether every byte decoded.
+    pub fn is_clean(&self) -> bool {
+        self.invalid.is_empty()
+    }
+
+    pub fn invalid_bytes(&self) -> usize {
+        self.invalid.iter().map(|run| run.bytes.len()).sum()
+    }
+
+    /// Appends markers for `bytes`, which were at `offset` in the input.
+    pub(crate) fn push_raw(&mut self, bytes: &[u8], offset: usize) {
+        let start = self.text.len();
+        self.text.extend(bytes.iter().map(|&byte| raw_byte_marker(byte)));
+        match self.invalid.last_mut() {
+            Some(run) if run.text.end == start && run.bytes.end == offset => {
+                run.text.end = self.text.len();
+                run.bytes.end += bytes.len();
+            }
+            _ => self.invalid.push(InvalidRun { text: start..self.text.len(), bytes: offset..offset + bytes.len() }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::buffer::content::encoding::{Encoding, EncodingHandler};
+
+    #[test]
+    fn test_markers() {
+        for byte in [0x00, 0x80, 0xFF] {
+            assert_eq!(raw_byte(raw_byte_marker(byte)), Some(byte));
+        }
+        assert_eq!(raw_byte('a'), None);
+        assert_eq!(raw_byte('\u{10FEFF}'), None);
+
+        let text = format!("a{}{}b{}", raw_byte_marker(1), raw_byte_marker(2), raw_byte_marker(3));
+        assert_eq!(raw_byte_runs(&text), vec![1..9, 10..14]);
+    }
+
+    #[test]
+    fn test_utf8_round_trip() {
+        let bytes = b"caf\xc3\xa9 \xff\xfe ok \xe2\x82 end \xf4\x8f\xbc\x80";
+        let handler = EncodingHandler::new(Encoding::UTF8);
+        assert!(handler.decode(bytes).is_err());
+
+        let decoded = handler.decode_lossless(bytes);
+        assert!(decoded.text.starts_with("café "));
+        assert_eq!(decoded.invalid.len(), 3);
+        assert_eq!(decoded.invalid[0].bytes, 6..8);
+        assert_eq!(decoded.invalid[1].bytes, 12..14);
+        // A genuine U+10FF00 stays raw so that it can't be confused with a marker.
+        assert_eq!(decoded.invalid[2].bytes, 19..23);
+        assert_eq!(decoded.invalid_bytes(), 8);
+        assert_eq!(&decoded.text[decoded.invalid[0].text.clone()], format!("{}{}", raw_byte_marker(0xFF), raw_byte_marker(0xFE)));
+        assert_eq!(handler.encode_lossless(&decoded.text).unwrap(), bytes);
+    }
+
+    #[test]
+    fn test_other_encodings_round_trip() {
+        let samples: [(Encoding, &[u8]); 4] = [
+            (Encoding::UTF16LE, b"h\0i\0\x00\xd8!\0x"),
+            (Encoding::ASCII, b"plain \x80\x81 text"),
+            (Encoding::SHIFTJIS, b"\x82\xa0 \xa0 \x81"),
+            (Encoding::WINDOWS1252, b"caf\xe9 \x81"),
+        ];
+        for (encoding, bytes) in samples {
+            let handler = EncodingHandler::new(encoding.clone());
+            let decoded = handler.decode_lossless(bytes);
+            assert_eq!(handler.encode_lossless(&decoded.text).unwrap(), bytes, "{}", encoding);
+        }
+
+        let mut handler = EncodingHandler::new(Encoding::UTF8);
+        handler.set_bom(true);
+        let bytes = b"\xEF\xBB\xBFbom \xC0";
+        let decoded = handler.decode_lossless(bytes);
+        assert_eq!(decoded.invalid[0].bytes, 7..8);
+        assert_eq!(handler.encode_lossless(&decoded.text).unwrap(), bytes);
+    }
+}
@@ -1,7 +1,9 @@
 //! Buffer content module
-//! Reexports encoding, line ending, streaming, and validation modules
+//! Reexports encoding, line ending, lossless, reencode, streaming, and validation modules
 
 pub mod encoding;
 pub mod line_ending;
+pub mod lossless;
+pub mod reencode;
 pub mod streaming;
-pub mod validation;
\ No newline at end of file
+pub mod validation;
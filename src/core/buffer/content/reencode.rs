@@ -0,0 +1,393 @@
+/// reencode.rs
+/// Converting a document to another encoding, "save with encoding": a preview lists the
+/// characters the target encoding can't represent, & a strategy decides what becomes of them.
+use std::ops::Range;
+use crate::core::buffer::content::encoding::{Encoding, EncodingError, EncodingHandler};
+use crate::core::buffer::content::line_ending::LineEnding;
+use crate::core::buffer::content::lossless::raw_byte;
+use crate::core::buffer::rope::rope::Rope;
+
+/// What to do with a character the target encoding can't represent.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum UnrepresentableStrategy {
+    /// Refuse to convert.
+    #[default]
+    Fail,
+    /// Write `?` instead.
+    Replace,
+    /// Write a numeric character reference, `&#8364;` for `€`.
+    NumericEscape,
+    /// Write the closest ASCII, `e` for `ě` or `"` for `“`, or `?` when there is none.
+    Transliterate,
+}
+
+impl UnrepresentableStrategy {
+    /// Text to write in `encoding` instead of `ch`, `None` for `Fail`. A raw-byte marker
+    /// stands for no char, so every other strategy writes `?` for it.
+    pub fn substitute(&self, ch: char, encoding: &Encoding) -> Option<String> {
+        match self {
+            UnrepresentableStrategy::Fail => None,
+            _ if raw_byte(ch).is_some() => Some("?".to_string()),
+            UnrepresentableStrategy::Replace => Some("?".to_string()),
+            UnrepresentableStrategy::NumericEscape => Some(format!("&#{};", ch as u32)),
+            UnrepresentableStrategy::Transliterate => Some(
+                transliterate(ch)
+                    .filter(|text| text.chars().all(|ch| encoding.can_encode(ch)))
+                    .unwrap_or("?")
+                    .to_string(),
+            ),
+        }
+    }
+}
+
+/// A character the target encoding can't represent.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Unrepresentable {
+    pub ch: char,
+    /// Byte offset in the text.
+    pub offset: usize,
+    /// Line & column, in chars, both counted from 0.
+    pub line: usize,
+    pub column: usize,
+}
+
+/// The characters of a text that `encoding` can't represent, in order.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConversionPreview {
+    pub encoding: Encoding,
+    pub unrepresentable: Vec<Unrepresentable>,
+}
+
+impl ConversionPreview {
+    /// Whether the whole text converts as is.
+    pub fn is_clean(&self) -> bool {
+        self.unrepresentable.is_empty()
+    }
+
+    /// The distinct unrepresentable characters, in order of first appearance.
+    pub fn chars(&self) -> Vec<char> {
+        let mut chars: Vec<char> = Vec::new();
+        for issue in &self.unrepresentable {
+            if !chars.contains(&issue.ch) {
+                chars.push(issue.ch);
+            }
+        }
+        chars
+    }
+
+    /// Edits substituting every unrepresentable character, sorted as `Rope::apply_edits`
+    /// wants them; `Fail` fails on the first one.
+    pub fn edits(&self, strategy: UnrepresentableStrategy) -> Result<Vec<(Range<usize>, String)>, EncodingError> {
+        self.unrepresentable
+            .iter()
+            .map(|issue| match strategy.substitute(issue.ch, &self.encoding) {
+                Some(text) => Ok((issue.offset..issue.offset + issue.ch.len_utf8(), text)),
+                None => Err(self.error(issue)),
+            })
+            .collect()
+    }
+
+    fn error(&self, issue: &Unrepresentable) -> EncodingError {
+        EncodingError::ConversionFailed(format!(
+            "{} character(s) cannot be represented in {}, the first '{}' at line {}, column {}",
+            self.unrepresentable.len(),
+            self.encoding.name(),
+            issue.ch,
+            issue.line + 1,
+            issue.column + 1
+        ))
+    }
+}
+
+/// Converts documents to `encoding`.
+///
+/// `preview` scans a text without changing anything; `apply` substitutes the characters that
+/// can't be represented in a rope & switches its `EncodingHandler` to the new encoding & BOM
+/// state, all or nothing. Raw-byte markers stand for bytes of the encoding the text was read
+/// in, so they are only written back as is when converting to that same encoding; otherwise
+/// they are unrepresentable like any char.
+#[derive(Debug, Clone)]
+pub struct EncodingConversion {
+    encoding: Encoding,
+    strategy: UnrepresentableStrategy,
+    /// Encoding the text was read in, which `apply` takes from the handler if unset.
+    source: Option<Encoding>,
+    /// BOM to write, or `None` to keep the handler's choice.
+    bom: Option<bool>,
+}
+
+impl EncodingConversion {
+    pub fn new(encoding: Encoding) -> Self {
+        Self { encoding, strategy: UnrepresentableStrategy::default(), source: None, bom: None }
+    }
+
+    pub fn with_strategy(mut self, strategy: UnrepresentableStrategy) -> Self {
+        self.strategy = strategy;
+        self
+    }
+
+    /// The encoding the text was read in, whose raw-byte markers are kept when converting to it.
+    pub fn with_source(mut self, source: Encoding) -> Self {
+        self.source = Some(source);
+        self
+    }
+
+    /// Writes a BOM, if the encoding has one, or not, whatever the handler did so far.
+    pub fn with_bom(mut self, with_bom: bool) -> Self {
+        self.bom = Some(with_bom);
+        self
+    }
+
+    pub fn encoding(&self) -> &Encoding {
+        &self.encoding
+    }
+
+    pub fn strategy(&self) -> UnrepresentableStrategy {
+        self.strategy
+    }
+
+    pub fn preview(&self, text: &str) -> ConversionPreview {
+        let mut scanner = Scanner::new(&self.encoding, self.keeps_raw_bytes(self.source.as_ref()));
+        scanner.scan(text);
+        scanner.finish()
+    }
+
+    pub fn preview_rope(&self, rope: &Rope) -> ConversionPreview {
+        self.scan_rope(rope, self.source.as_ref())
+    }
+
+    /// `text` with its unrepresentable characters substituted.
+    pub fn convert(&self, text: &str) -> Result<String, EncodingError> {
+        let edits = self.preview(text).edits(self.strategy)?;
+        let mut result = String::with_capacity(text.len());
+        let mut pos = 0;
+        for (range, replacement) in &edits {
+            result.push_str(&text[pos..range.start]);
+            result.push_str(replacement);
+            pos = range.end;
+        }
+        result.push_str(&text[pos..]);
+        Ok(result)
+    }
+
+    /// Converts the text of `rope` in one edit & points `handler` at the new encoding.
+    /// Returns what was substituted; on error neither is changed.
+    pub fn apply(&self, rope: &mut Rope, handler: &mut EncodingHandler) -> Result<ConversionPreview, EncodingError> {
+        let preview = self.scan_rope(rope, Some(self.source.as_ref().unwrap_or(handler.encoding())));
+        let edits = preview.edits(self.strategy)?;
+        rope.apply_edits(&edits).map_err(|error| EncodingError::ConversionFailed(error.to_string()))?;
+
+        let with_bom = self.bom.unwrap_or(handler.has_bom()) && !self.encoding.bom().is_empty();
+        handler.set_encoding(self.encoding.clone());
+        handler.set_bom(with_bom);
+        Ok(preview)
+    }
+
+    fn scan_rope(&self, rope: &Rope, source: Option<&Encoding>) -> ConversionPreview {
+        let mut scanner = Scanner::new(&self.encoding, self.keeps_raw_bytes(source));
+        for chunk in rope.chunks() {
+            scanner.scan(chunk.as_ref());
+        }
+        scanner.finish()
+    }
+
+    /// Whether raw-byte markers read in `source` are written back as the bytes they stand for.
+    fn keeps_raw_bytes(&self, source: Option<&Encoding>) -> bool {
+        source == Some(&self.encoding)
+    }
+}
+
+/// Finds unrepresentable characters in a text fed one piece at a time.
+struct Scanner<'a> {
+    encoding: &'a Encoding,
+    /// Whether raw-byte markers are representable, being bytes of the target encoding.
+    keep_raw: bool,
+    unrepresentable: Vec<Unrepresentable>,
+    offset: usize,
+    line: usize,
+    column: usize,
+    /// Whether the last char was a CR, which a LF completes into one line ending.
+    after_cr: bool,
+}
+
+impl<'a> Scanner<'a> {
+    fn new(encoding: &'a Encoding, keep_raw: bool) -> Self {
+        Self { encoding, keep_raw, unrepresentable: Vec::new(), offset: 0, line: 0, column: 0, after_cr: false }
+    }
+
+    fn scan(&mut self, text: &str) {
+        for (idx, ch) in text.char_indices() {
+            // Line endings such as NEL, LS & PS may not be representable either; they are
+            // reported at the end of the line they end.
+            let representable = match raw_byte(ch) {
+                Some(_) => self.keep_raw,
+                None => self.encoding.can_encode(ch),
+            };
+            if !representable {
+                self.unrepresentable.push(Unrepresentable { ch, offset: self.offset + idx, line: self.line, column: self.column });
+            }
+            if let Some(ending) = LineEnding::from_char(ch) {
+                if !(ending == LineEnding::LF && self.after_cr) {
+                    self.line += 1;
+                }
+                self.column = 0;
+                self.after_cr = ending == LineEnding::CR;
+                continue;
+            }
+            self.after_cr = false;
+            self.column += 1;
+        }
+        self.offset += text.len();
+    }
+
+    fn finish(self) -> ConversionPreview {
+        ConversionPreview { encoding: self.encoding.clone(), unrepresentable: self.unrepresentable }
+    }
+}
+
+/// Closest ASCII for common accented letters, ligatures & typographic punctuation.
+fn transliterate(ch: char) -> Option<&'static str> {
+    let text = match ch {
+        'À'..='Å' | 'Ā' | 'Ă' | 'Ą' => "A",
+        'à'..='å' | 'ā' | 'ă' | 'ą' => "a",
+        'Æ' => "AE",
+        'æ' => "ae",
+        'Ç' | 'Ć' | 'Č' => "C",
+        'ç' | 'ć' | 'č' => "c",
+        'Ď' | 'Đ' => "D",
+        'ď' | 'đ' => "d",
+        'È'..='Ë' | 'Ē' | 'Ė' | 'Ę' | 'Ě' => "E",
+        'è'..='ë' | 'ē' | 'ė' | 'ę' | 'ě' => "e",
+        'Ğ' => "G",
+        'ğ' => "g",
+        'Ì'..='Ï' | 'Ī' | 'Į' | 'İ' => "I",
+        'ì'..='ï' | 'ī' | 'į' | 'ı' => "i",
+        'Ł' => "L",
+        'ł' => "l",
+        'Ñ' | 'Ń' | 'Ň' => "N",
+        'ñ' | 'ń' | 'ň' => "n",
+        'Ò'..='Ö' | 'Ø' | 'Ō' | 'Ő' => "O",
+        'ò'..='ö' | 'ø' | 'ō' | 'ő' => "o",
+        'Œ' => "OE",
+        'œ' => "oe",
+        'Ř' => "R",
+        'ř' => "r",
+        'Ś' | 'Ş' | 'Š' => "S",
+        'ś' | 'ş' | 'š' => "s",
+        'ß' => "ss",
+        'Ť' => "T",
+        'ť' => "t",
+        'Ù'..='Ü' | 'Ū' | 'Ů' | 'Ű' => "U",
+        'ù'..='ü' | 'ū' | 'ů' | 'ű' => "u",
+        'Ý' | 'Ÿ' => "Y",
+        'ý' | 'ÿ' => "y",
+        'Ź' | 'Ż' | 'Ž' => "Z",
+        'ź' | 'ż' | 'ž' => "z",
+        '‘' | '’' | '‚' | '′' => "'",
+        '“' | '”' | '„' | '″' => "\"",
+        '‹' => "<",
+        '›' => ">",
+        '«' => "<<",
+        '»' => ">>",
+        '‐' | '‑' | '‒' | '–' | '—' | '−' => "-",
+        '…' => "...",
+        '•' | '·' => "*",
+        '×' => "x",
+        '÷' => "/",
+        '€' => "EUR",
+        '©' => "(C)",
+        '®' => "(R)",
+        '™' => "(TM)",
+        '\u{A0}' | '\u{2002}'..='\u{200A}' | '\u{202F}' => " ",
+        _ => return None,
+    };
+    Some(text)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_preview_positions() {
+        let text = "naïve café\r\n“quoted” — ok\nπ";
+        let preview = EncodingConversion::new(Encoding::LATIN1).preview(text);
+        let found: Vec<(char, usize, usize)> = preview.unrepresentable.iter().map(|issue| (issue.ch, issue.line, issue.column)).collect();
+        assert_eq!(found, vec![('“', 1, 0), ('”', 1, 7), ('—', 1, 9), ('π', 2, 0)]);
+        assert_eq!(&text[preview.unrepresentable[3].offset..], "π");
+        assert_eq!(preview.chars().len(), 4);
+        assert!(EncodingConversion::new(Encoding::UTF16LE).preview(text).is_clean());
+        // Windows-1252 has the typographic quotes & dashes.
+        assert_eq!(EncodingConversion::new(Encoding::WINDOWS1252).preview(text).chars(), vec!['π']);
+    }
+
+    #[test]
+    fn test_unrepresentable_line_endings() {
+        let text = "one\u{2028}two\u{85}three\r\n";
+        let preview = EncodingConversion::new(Encoding::ASCII).preview(text);
+        let found: Vec<(char, usize, usize)> = preview.unrepresentable.iter().map(|issue| (issue.ch, issue.line, issue.column)).collect();
+        assert_eq!(found, vec![('\u{2028}', 0, 3), ('\u{85}', 1, 3)]);
+        let conversion = EncodingConversion::new(Encoding::ASCII).with_strategy(UnrepresentableStrategy::Replace);
+        assert_eq!(conversion.convert(text).unwrap(), "one?two?three\r\n");
+        assert!(EncodingConversion::new(Encoding::ASCII).convert(text).is_err());
+        assert!(EncodingConversion::new(Encoding::UTF8).preview(text).is_clean());
+    }
+
+    #[test]
+    fn test_raw_bytes_to_other_encoding() {
+        let utf8 = EncodingHandler::new(Encoding::UTF8);
+        let decoded = utf8.decode_lossless(b"caf\xC3\xA9 \xFF end");
+        let mut rope = Rope::from_text(&decoded.text);
+
+        // Back to the encoding they came from, the stray bytes are written as is.
+        let same = EncodingConversion::new(Encoding::UTF8).with_source(Encoding::UTF8);
+        assert!(same.preview(&decoded.text).is_clean());
+        let found = EncodingConversion::new(Encoding::UTF16LE).with_source(Encoding::UTF8).preview(&decoded.text);
+        assert_eq!(found.unrepresentable.iter().map(|issue| issue.column).collect::<Vec<_>>(), vec![5]);
+
+        let mut handler = utf8.clone();
+        let conversion = EncodingConversion::new(Encoding::UTF16LE);
+        assert!(conversion.apply(&mut rope, &mut handler).is_err());
+        conversion.with_strategy(UnrepresentableStrategy::Replace).apply(&mut rope, &mut handler).unwrap();
+        let bytes = handler.encode_lossless(&rope.to_string()).unwrap();
+        assert_eq!(handler.decode(&bytes).unwrap(), "café ? end");
+    }
+
+    #[test]
+    fn test_strategies() {
+        let text = "“Zürich” ≈ 5€";
+        let conversion = EncodingConversion::new(Encoding::ASCII);
+        let error = conversion.convert(text).unwrap_err();
+        assert!(error.to_string().contains("line 1, column 1"));
+
+        let convert = |strategy| conversion.clone().with_strategy(strategy).convert(text).unwrap();
+        assert_eq!(convert(UnrepresentableStrategy::Replace), "?Z?rich? ? 5?");
+        assert_eq!(convert(UnrepresentableStrategy::NumericEscape), "&#8220;Z&#252;rich&#8221; &#8776; 5&#8364;");
+        assert_eq!(convert(UnrepresentableStrategy::Transliterate), "\"Zurich\" ? 5EUR");
+    }
+
+    #[test]
+    fn test_apply_to_rope_and_handler() {
+        let text = "line ü\n".repeat(400) + "ǅ end";
+        let mut rope = Rope::from_text(&text);
+        let mut handler = EncodingHandler::new(Encoding::UTF8).with_bom();
+
+        let conversion = EncodingConversion::new(Encoding::LATIN1);
+        assert!(conversion.apply(&mut rope, &mut handler).is_err());
+        assert_eq!(rope.to_string(), text);
+        assert_eq!(handler.encoding(), &Encoding::UTF8);
+
+        let preview = conversion.with_strategy(UnrepresentableStrategy::Replace).apply(&mut rope, &mut handler).unwrap();
+        assert_eq!(preview.unrepresentable.len(), 1);
+        assert_eq!(preview.unrepresentable[0].line, 400);
+        assert_eq!(handler.encoding(), &Encoding::LATIN1);
+        assert!(!handler.has_bom());
+        let bytes = handler.encode(&rope.to_string()).unwrap();
+        assert!(bytes.ends_with(b"\xFC\n? end"));
+
+        EncodingConversion::new(Encoding::UTF16BE).apply(&mut rope, &mut handler).unwrap();
+        assert!(!handler.has_bom());
+        EncodingConversion::new(Encoding::UTF8).with_bom(true).apply(&mut rope, &mut handler).unwrap();
+        assert_eq!(handler.bom_bytes(), Encoding::UTF8.bom());
+    }
+}
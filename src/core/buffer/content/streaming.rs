@@ -2,8 +2,9 @@
 /// File containing streaming functions for buffer content.
 use std::io::{self, Read, Write, BufReader, BufWriter};
 use crate::core::buffer::content::encoding::{Encoding, EncodingHandler, EncodingError};
-use crate::core::buffer::content::validation::{validate_utf8, ValidationError};
+use crate::core::buffer::content::validation::ValidationError;
 use crate::core::buffer::content::line_ending::LineEnding;
+use crate::utils::encoding::streaming::IncrementalDecoder;
 use std::result::Result;
 use std::str;
 use std::error::Error;
@@ -60,109 +61,174 @@ impl From<ValidationError> for StreamingError {
     }
 }
 
+/// Reads & decodes a stream chunk by chunk or line by line. A sequence cut by the end of a
+/// chunk is completed by the next one, whatever the encoding.
 pub struct StreamReader<R: Read> {
     reader: BufReader<R>,
-    encoding_handler: EncodingHandler,
+    decoder: IncrementalDecoder,
+    /// Bytes of the chunk being read.
     buffer: Vec<u8>,
     chunk_size: usize,
     validate_content: bool,
+    /// Text decoded but not returned yet, from `consumed` on.
+    decoded: String,
+    consumed: usize,
+    eof: bool,
 }
 
 impl<R: Read> StreamReader<R> {
     pub fn new(reader: R, encoding: Encoding) -> Self {
         Self {
             reader: BufReader::new(reader),
-            encoding_handler: EncodingHandler::new(encoding),
+            decoder: IncrementalDecoder::new(encoding),
             buffer: Vec::new(),
             chunk_size: 8192,
             validate_content: true,
+            decoded: String::new(),
+            consumed: 0,
+            eof: false,
         }
     }
 
     pub fn with_chunk_size(mut self, size: usize) -> Self {
-        self.chunk_size = size;
+        self.chunk_size = size.max(1);
         self
     }
 
+    /// Reports invalid UTF-8 as `ValidationError::InvalidUtf8` rather than as an encoding error.
     pub fn with_validation(mut self, validate: bool) -> Self {
         self.validate_content = validate;
         self
     }
 
-    pub fn read_chunk(&mut self) -> Result<Option<String>, StreamingError> {
-        let mut chunk = vec![0u8; self.chunk_size];
-        let bytes_read = self.reader.read(&mut chunk)?;
-        
-        if bytes_read == 0 {
-            return Ok(None);
-        }
+    /// Keeps undecodable bytes as raw-byte markers instead of failing, which a lossless
+    /// `StreamWriter` writes back as the same bytes.
+    pub fn with_lossless(mut self, lossless: bool) -> Self {
+        self.decoder = self.decoder.with_lossless(lossless);
+        self
+    }
 
-        chunk.truncate(bytes_read);
-        
-        if self.validate_content {
-            validate_utf8(&chunk)?;
+    /// Reads the text of the next chunk of at most `chunk_size` bytes.
+    pub fn read_chunk(&mut self) -> Result<Option<String>, StreamingError> {
+        loop {
+            if self.consumed < self.decoded.len() {
+                let text = self.decoded[self.consumed..].to_string();
+                self.consumed = self.decoded.len();
+                return Ok(Some(text));
+            }
+            if !self.fill()? {
+                return Ok(None);
+            }
         }
-
-        let text = self.encoding_handler.decode(&chunk)?;
-        Ok(Some(text))
     }
 
+    /// Reads the next line, line ending included.
     pub fn read_line(&mut self) -> Result<Option<String>, StreamingError> {
-        let mut line_bytes = Vec::new();
-        let mut byte = [0u8; 1];
+        Ok(self.read_line_with_ending()?.map(|(mut line, ending)| {
+            if let Some(ending) = ending {
+                line.push_str(ending.as_str());
+            }
+            line
+        }))
+    }
 
+    /// Reads the next line & returns its line ending apart, `None` for a last line without one.
+    /// Any `LineEnding` ends a line: LF, CRLF, CR, NEL, LS or PS.
+    pub fn read_line_with_ending(&mut self) -> Result<Option<(String, Option<LineEnding>)>, StreamingError> {
+        // Bytes after `consumed` known to hold no line ending.
+        let mut scanned = 0;
         loop {
-            match self.reader.read(&mut byte)? {
-                0 => {
-                    if line_bytes.is_empty() {
-                        return Ok(None);
-                    }
-                    break;
+            let rest = &self.decoded[self.consumed..];
+            if let Some(idx) = rest[scanned..].find(|ch| LineEnding::from_char(ch).is_some()).map(|idx| idx + scanned) {
+                let ch = rest[idx..].chars().next().unwrap_or('\n');
+                if ch == '\r' && idx + 1 == rest.len() && !self.eof {
+                    // The CR may be the first half of a CRLF.
+                    scanned = idx;
+                    self.fill()?;
+                    continue;
                 }
-                1 => {
-                    line_bytes.push(byte[0]);
-                    if byte[0] == b'\n' {
-                        break;
-                    }
+                let (ending, len) = if rest[idx..].starts_with("\r\n") {
+                    (LineEnding::CRLF, 2)
+                } else {
+                    (LineEnding::from_char(ch).unwrap_or(LineEnding::LF), ch.len_utf8())
+                };
+                let line = rest[..idx].to_string();
+                self.consumed += idx + len;
+                return Ok(Some((line, Some(ending))));
+            }
+            scanned = rest.len();
+            if !self.fill()? {
+                if self.consumed == self.decoded.len() {
+                    return Ok(None);
                 }
-                _ => unreachable!(),
+                let line = self.decoded[self.consumed..].to_string();
+                self.consumed = self.decoded.len();
+                return Ok(Some((line, None)));
             }
         }
-
-        if self.validate_content {
-            validate_utf8(&line_bytes)?;
-        }
-
-        let text = self.encoding_handler.decode(&line_bytes)?;
-        Ok(Some(text))
     }
 
     pub fn read_all(&mut self) -> Result<String, StreamingError> {
-        let mut buffer = Vec::new();
-        self.reader.read_to_end(&mut buffer)?;
-
-        if self.validate_content {
-            validate_utf8(&buffer)?;
-        }
-
-        let text = self.encoding_handler.decode(&buffer)?;
+        while self.fill()? {}
+        let text = self.decoded[self.consumed..].to_string();
+        self.consumed = self.decoded.len();
         Ok(text)
     }
 
     pub fn encoding(&self) -> &Encoding {
-        self.encoding_handler.encoding()
+        self.decoder.encoding()
     }
 
+    /// Decodes what comes next with `encoding`, as the start of a new stream.
     pub fn set_encoding(&mut self, encoding: Encoding) {
-        self.encoding_handler.set_encoding(encoding);
+        self.decoder = IncrementalDecoder::new(encoding).with_lossless(self.decoder.is_lossless());
     }
+
+    /// Decodes the next chunk into `decoded`, returning false once the stream is over.
+    fn fill(&mut self) -> Result<bool, StreamingError> {
+        if self.eof {
+            return Ok(false);
+        }
+        self.decoded.drain(..self.consumed);
+        self.consumed = 0;
+
+        self.buffer.resize(self.chunk_size, 0);
+        let bytes_read = self.reader.read(&mut self.buffer)?;
+        let before = self.decoded.len();
+        let result = if bytes_read == 0 {
+            self.eof = true;
+            self.decoder.finish(&mut self.decoded)
+        } else {
+            self.decoder.decode(&self.buffer[..bytes_read], &mut self.decoded)
+        };
+        result.map_err(|error| match self.decoder.encoding() {
+            Encoding::UTF8 if self.validate_content => StreamingError::ValidationError(ValidationError::InvalidUtf8),
+            _ => StreamingError::EncodingError(error),
+        })?;
+        Ok(bytes_read > 0 || self.decoded.len() > before)
+    }
+}
+
+/// How `StreamWriter::write_text` treats the line endings of the text.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum LineEndingMode {
+    /// Every line ending, whatever its variant, is written as the writer's line ending.
+    #[default]
+    Normalize,
+    /// Each line is written with its own line ending, so mixed line endings survive a save.
+    /// Saving opts into it.
+    Preserve,
 }
 
 pub struct StreamWriter<W: Write> {
     writer: BufWriter<W>,
     encoding_handler: EncodingHandler,
     line_ending: LineEnding,
+    line_ending_mode: LineEndingMode,
+    /// A `\r` ending the last text normalized, held back in case the next text starts with `\n`.
+    pending_cr: bool,
     auto_flush: bool,
+    lossless: bool,
 }
 
 impl<W: Write> StreamWriter<W> {
@@ -171,7 +237,10 @@ impl<W: Write> StreamWriter<W> {
             writer: BufWriter::new(writer),
             encoding_handler: EncodingHandler::new(encoding),
             line_ending: LineEnding::default(),
+            line_ending_mode: LineEndingMode::default(),
+            pending_cr: false,
             auto_flush: false,
+            lossless: false,
         }
     }
 
@@ -180,25 +249,54 @@ impl<W: Write> StreamWriter<W> {
         self
     }
 
+    pub fn with_line_ending_mode(mut self, mode: LineEndingMode) -> Self {
+        self.line_ending_mode = mode;
+        self
+    }
+
     pub fn with_auto_flush(mut self, auto_flush: bool) -> Self {
         self.auto_flush = auto_flush;
         self
     }
 
     pub fn with_bom(mut self) -> Self {
-        self.encoding_handler = self.encoding_handler.with_bom();
+        self.encoding_handler.set_bom(true);
+        self
+    }
+
+    /// Writes raw-byte markers as the bytes they stand for.
+    pub fn with_lossless(mut self, lossless: bool) -> Self {
+        self.lossless = lossless;
         self
     }
 
+    /// Writes `text`. When normalizing, a `\r` ending the text is only written with the next
+    /// text or on `flush`, so that a `CRLF` split between two calls is written as one line ending.
     pub fn write_text(&mut self, text: &str) -> Result<(), StreamingError> {
-        let converted_text = if self.line_ending != LineEnding::default() {
-            let detected = LineEnding::detect(text).unwrap_or_default();
-            detected.convert_text(text, self.line_ending)
-        } else {
-            text.to_string()
+        let converted_text = match self.line_ending_mode {
+            LineEndingMode::Normalize => {
+                let mut pending = String::with_capacity(text.len() + 1);
+                if std::mem::take(&mut self.pending_cr) {
+                    pending.push('\r');
+                }
+                pending.push_str(text);
+                if pending.ends_with('\r') {
+                    pending.pop();
+                    self.pending_cr = true;
+                }
+                LineEnding::normalize(&pending, self.line_ending).0
+            }
+            LineEndingMode::Preserve => text.to_string(),
         };
+        self.write_converted(&converted_text)
+    }
 
-        let bytes = self.encoding_handler.encode(&converted_text)?;
+    fn write_converted(&mut self, converted_text: &str) -> Result<(), StreamingError> {
+        let bytes = if self.lossless {
+            self.encoding_handler.encode_lossless(converted_text)?
+        } else {
+            self.encoding_handler.encode(converted_text)?
+        };
         self.writer.write_all(&bytes)?;
         
         if self.auto_flush {
@@ -223,7 +321,12 @@ impl<W: Write> StreamWriter<W> {
         Ok(())
     }
 
+    /// Writes a held back `\r` as a line ending of its own, then flushes the underlying writer.
     pub fn flush(&mut self) -> Result<(), StreamingError> {
+        if std::mem::take(&mut self.pending_cr) {
+            let line_ending = LineEnding::normalize("\r", self.line_ending).0;
+            self.write_converted(&line_ending)?;
+        }
         self.writer.flush()?;
         Ok(())
     }
@@ -243,11 +346,19 @@ impl<W: Write> StreamWriter<W> {
     pub fn set_line_ending(&mut self, line_ending: LineEnding) {
         self.line_ending = line_ending;
     }
+
+    pub fn line_ending_mode(&self) -> LineEndingMode {
+        self.line_ending_mode
+    }
+
+    pub fn set_line_ending_mode(&mut self, mode: LineEndingMode) {
+        self.line_ending_mode = mode;
+    }
 }
 
 impl<W: Write> Drop for StreamWriter<W> {
     fn drop(&mut self) {
-        let _ = self.writer.flush();
+        let _ = self.flush();
     }
 }
 
@@ -260,21 +371,38 @@ pub fn stream_convert<R: Read, W: Write>(
     to_line_ending: LineEnding,
 ) -> Result<usize, StreamingError> {
     let mut stream_reader = StreamReader::new(reader, from_encoding);
+    // The chunks are converted here, which keeps them as they are when both line endings match.
     let mut stream_writer = StreamWriter::new(writer, to_encoding)
         .with_line_ending(to_line_ending)
+        .with_line_ending_mode(LineEndingMode::Preserve)
         .with_auto_flush(true);
 
     let mut total_bytes = 0;
+    let mut carried_cr = false;
+
+    loop {
+        let chunk = stream_reader.read_chunk()?;
+        let mut text = String::new();
+        if std::mem::take(&mut carried_cr) {
+            text.push('\r');
+        }
+        text.push_str(chunk.as_deref().unwrap_or_default());
+        // A CRLF split between two chunks is converted once its LF is read.
+        if chunk.is_some() && text.ends_with('\r') {
+            text.pop();
+            carried_cr = true;
+        }
 
-    while let Some(chunk) = stream_reader.read_chunk()? {
         let converted_chunk = if from_line_ending != to_line_ending {
-            from_line_ending.convert_text(&chunk, to_line_ending)
+            from_line_ending.convert_text(&text, to_line_ending)
         } else {
-            chunk
+            text
         };
-
         stream_writer.write_text(&converted_chunk)?;
         total_bytes += converted_chunk.len();
+        if chunk.is_none() {
+            break;
+        }
     }
 
     stream_writer.flush()?;
@@ -310,6 +438,26 @@ mod tests {
         assert_eq!(result, "Hello\nWorld\n");
     }
 
+    #[test]
+    fn test_stream_writer_mixed_line_endings() {
+        let text = "one\r\ntwo\nthree\r\n";
+        let mut normalized = Vec::new();
+        StreamWriter::new(&mut normalized, Encoding::UTF8)
+            .with_line_ending(LineEnding::CRLF)
+            .with_line_ending_mode(LineEndingMode::Normalize)
+            .write_text(text)
+            .unwrap();
+        assert_eq!(normalized, b"one\r\ntwo\r\nthree\r\n");
+
+        let mut preserved = Vec::new();
+        StreamWriter::new(&mut preserved, Encoding::UTF8)
+            .with_line_ending(LineEnding::LF)
+            .with_line_ending_mode(LineEndingMode::Preserve)
+            .write_text(text)
+            .unwrap();
+        assert_eq!(preserved, text.as_bytes());
+    }
+
     #[test]
     fn test_stream_convert() {
         let input = "Hello\r\nWorld\r\n".as_bytes();
@@ -329,6 +477,52 @@ mod tests {
         assert!(bytes_written > 0);
     }
 
+    /// Reader handing out at most `max` bytes per read.
+    struct ShortReads<'a>(&'a [u8], usize);
+
+    impl Read for ShortReads<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            let len = self.1.min(buf.len()).min(self.0.len());
+            buf[..len].copy_from_slice(&self.0[..len]);
+            self.0 = &self.0[len..];
+            Ok(len)
+        }
+    }
+
+    #[test]
+    fn test_stream_convert_split_crlf() {
+        let input = b"ab\r\ncd\r\n";
+        for (to, expected) in [(LineEnding::CRLF, "ab\r\ncd\r\n"), (LineEnding::LF, "ab\ncd\n")] {
+            let mut output = Vec::new();
+            stream_convert(ShortReads(input, 3), &mut output, Encoding::UTF8, Encoding::UTF8, LineEnding::CRLF, to).unwrap();
+            assert_eq!(String::from_utf8(output).unwrap(), expected);
+        }
+
+        let mut output = Vec::new();
+        {
+            let mut writer = StreamWriter::new(&mut output, Encoding::UTF8)
+                .with_line_ending(LineEnding::CRLF)
+                .with_line_ending_mode(LineEndingMode::Normalize);
+            for part in ["ab\r", "\ncd\r", "\n\r"] {
+                writer.write_text(part).unwrap();
+            }
+        }
+        assert_eq!(String::from_utf8(output).unwrap(), "ab\r\ncd\r\n\r\n");
+    }
+
+    #[test]
+    fn test_stream_writer_normalizes_by_default() {
+        let text = "one\r\ntwo\nthree\r";
+        let mut output = Vec::new();
+        StreamWriter::new(&mut output, Encoding::UTF8).with_line_ending(LineEnding::CRLF).write_text(text).unwrap();
+        assert_eq!(output, b"one\r\ntwo\r\nthree\r\n");
+
+        let mut output = Vec::new();
+        let writer = StreamWriter::new(&mut output, Encoding::UTF8).with_line_ending(LineEnding::CRLF);
+        writer.with_line_ending_mode(LineEndingMode::Preserve).write_text(text).unwrap();
+        assert_eq!(output, text.as_bytes());
+    }
+
     #[test]
     fn test_stream_reader_chunks() {
         let data = "Hello World Test".as_bytes();
@@ -345,6 +539,51 @@ mod tests {
         let combined: String = chunks.into_iter().collect();
         assert_eq!(combined, "Hello World Test");
     }
+
+    #[test]
+    fn test_stream_reader_split_sequences() {
+        let text = "日本語 & émoji 🎉";
+        let mut reader = StreamReader::new(Cursor::new(text.as_bytes()), Encoding::UTF8).with_chunk_size(1);
+        let mut combined = String::new();
+        while let Some(chunk) = reader.read_chunk().unwrap() {
+            combined.push_str(&chunk);
+        }
+        assert_eq!(combined, text);
+
+        let utf16: Vec<u8> = text.encode_utf16().flat_map(u16::to_be_bytes).collect();
+        let mut reader = StreamReader::new(Cursor::new(utf16), Encoding::UTF16BE).with_chunk_size(3);
+        assert_eq!(reader.read_all().unwrap(), text);
+
+        let mut reader = StreamReader::new(Cursor::new(&b"ok\xFF"[..]), Encoding::UTF8);
+        assert!(matches!(reader.read_all(), Err(StreamingError::ValidationError(ValidationError::InvalidUtf8))));
+    }
+
+    #[test]
+    fn test_stream_reader_lines() {
+        let text = "lf\ncrlf\r\ncr\rnel\u{85}ls\u{2028}ps\u{2029}\r\nlast";
+        for chunk_size in [1, 2, 7, 8192] {
+            let mut reader = StreamReader::new(Cursor::new(text.as_bytes()), Encoding::UTF8).with_chunk_size(chunk_size);
+            let mut lines = Vec::new();
+            while let Some(line) = reader.read_line_with_ending().unwrap() {
+                lines.push(line);
+            }
+            assert_eq!(lines, vec![
+                ("lf".to_string(), Some(LineEnding::LF)),
+                ("crlf".to_string(), Some(LineEnding::CRLF)),
+                ("cr".to_string(), Some(LineEnding::CR)),
+                ("nel".to_string(), Some(LineEnding::NEL)),
+                ("ls".to_string(), Some(LineEnding::LS)),
+                ("ps".to_string(), Some(LineEnding::PS)),
+                ("".to_string(), Some(LineEnding::CRLF)),
+                ("last".to_string(), None),
+            ]);
+        }
+
+        let mut reader = StreamReader::new(Cursor::new("a\rb\r".as_bytes()), Encoding::UTF8).with_chunk_size(2);
+        assert_eq!(reader.read_line().unwrap().as_deref(), Some("a\r"));
+        assert_eq!(reader.read_line().unwrap().as_deref(), Some("b\r"));
+        assert_eq!(reader.read_line().unwrap(), None);
+    }
 }
 
 // --Made by still-eau (Id discord: stilau_)
\ No newline at end of file
@@ -10,6 +10,8 @@ use regex::Regex;
 use lazy_static::lazy_static;
 use crate::core::buffer::content::encoding::{Encoding, EncodingHandler, EncodingError};
 use crate::core::buffer::content::line_ending::LineEnding;
+use crate::core::buffer::content::lossless::{raw_byte, raw_byte_marker};
+use crate::utils::encoding::conversion::DecodeStep;
 
 #[derive(Debug, Clone)]
 pub enum ValidationError {
@@ -98,15 +100,280 @@ pub fn validate_encoding_compatibility(content: &[u8], encoding: &Encoding) -> R
 }
 
 pub fn validate_content(content: &[u8], encoding: &Encoding, line_ending: LineEnding) -> Result<(), ValidationError> {
-    validate_encoding_compatibility(content, encoding)?;
-    
-    let handler = EncodingHandler::new(encoding.clone());
-    let content_str = handler.decode(content)?;
-    
-    validate_control_characters(&content_str)?;
-    validate_line_endings(&content_str, line_ending)?;
-    
-    Ok(())
+    let mut options = ValidationOptions::new(encoding.clone()).with_control_characters(ValidationPolicy::Reject);
+    options = match line_ending {
+        LineEnding::Unknown => options.with_mixed_line_endings(ValidationPolicy::Ignore),
+        expected => options.with_mixed_line_endings(ValidationPolicy::Reject).with_line_ending(expected),
+    };
+    ContentValidator::new(options).validate(content).into_result().map(|_| ())
+}
+
+/// What to do with an offending sequence, control char or line ending.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum ValidationPolicy {
+    /// Doesn't check.
+    Ignore,
+    /// Reports it & fails the validation.
+    Reject,
+    /// Reports it & keeps it.
+    #[default]
+    Warn,
+    /// Reports it & replaces it: with U+FFFD for invalid sequences & control chars, with the
+    /// expected line ending for other line endings.
+    Replace,
+}
+
+/// What a `ContentValidator` checks, per encoding.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ValidationOptions {
+    pub encoding: Encoding,
+    /// Bytes that don't decode in `encoding`.
+    pub invalid_sequences: ValidationPolicy,
+    /// Control chars other than tabs & line endings.
+    pub control_characters: ValidationPolicy,
+    /// Line endings other than `line_ending`.
+    pub mixed_line_endings: ValidationPolicy,
+    /// The line ending every line should end with; the most frequent one when `None`.
+    pub line_ending: Option<LineEnding>,
+}
+
+impl ValidationOptions {
+    /// Rejects invalid sequences & warns about control chars & mixed line endings.
+    pub fn new(encoding: Encoding) -> Self {
+        Self {
+            encoding,
+            invalid_sequences: ValidationPolicy::Reject,
+            control_characters: ValidationPolicy::Warn,
+            mixed_line_endings: ValidationPolicy::Warn,
+            line_ending: None,
+        }
+    }
+
+    pub fn with_invalid_sequences(mut self, policy: ValidationPolicy) -> Self {
+        self.invalid_sequences = policy;
+        self
+    }
+
+    pub fn with_control_characters(mut self, policy: ValidationPolicy) -> Self {
+        self.control_characters = policy;
+        self
+    }
+
+    pub fn with_mixed_line_endings(mut self, policy: ValidationPolicy) -> Self {
+        self.mixed_line_endings = policy;
+        self
+    }
+
+    pub fn with_line_ending(mut self, line_ending: LineEnding) -> Self {
+        self.line_ending = Some(line_ending);
+        self
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ValidationIssueKind {
+    InvalidSequence(Vec<u8>),
+    ControlCharacter(char),
+    MixedLineEnding { found: LineEnding, expected: LineEnding },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ValidationIssue {
+    pub kind: ValidationIssueKind,
+    /// Where it starts in the validated bytes.
+    pub offset: usize,
+    /// Where it, or what replaced it, starts in the report's text.
+    pub text_offset: usize,
+    /// The policy applied to it.
+    pub policy: ValidationPolicy,
+}
+
+impl ValidationIssue {
+    pub fn to_error(&self) -> ValidationError {
+        match &self.kind {
+            ValidationIssueKind::InvalidSequence(_) => ValidationError::EncodingError(EncodingError::InvalidSequence(
+                format!("invalid sequence at byte {}", self.offset),
+            )),
+            ValidationIssueKind::ControlCharacter(ch) => ValidationError::ControlCharacterFound(*ch),
+            ValidationIssueKind::MixedLineEnding { found, .. } => ValidationError::InvalidLineEnding(found.as_str().to_string()),
+        }
+    }
+}
+
+/// Every issue a `ContentValidator` found, in order, with the decoded text.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ValidationReport {
+    /// The decoded content after replacements. Invalid sequences that were not replaced are
+    /// kept as raw-byte markers.
+    pub text: String,
+    /// The line ending other line endings were checked against.
+    pub line_ending: Option<LineEnding>,
+    pub issues: Vec<ValidationIssue>,
+}
+
+impl ValidationReport {
+    pub fn is_clean(&self) -> bool {
+        self.issues.is_empty()
+    }
+
+    /// Whether an issue is under the `Reject` policy.
+    pub fn is_rejected(&self) -> bool {
+        self.rejections().next().is_some()
+    }
+
+    pub fn rejections(&self) -> impl Iterator<Item = &ValidationIssue> {
+        self.issues.iter().filter(|issue| issue.policy == ValidationPolicy::Reject)
+    }
+
+    pub fn warnings(&self) -> impl Iterator<Item = &ValidationIssue> {
+        self.issues.iter().filter(|issue| issue.policy == ValidationPolicy::Warn)
+    }
+
+    /// The text, or the first rejected issue as an error.
+    pub fn into_result(self) -> Result<String, ValidationError> {
+        if let Some(issue) = self.rejections().next() {
+            return Err(issue.to_error());
+        }
+        Ok(self.text)
+    }
+}
+
+/// Decodes & checks content in one pass, or two when the expected line ending has to be found
+/// first, applying the policy of each kind of issue & reporting them all.
+#[derive(Debug, Clone)]
+pub struct ContentValidator {
+    options: ValidationOptions,
+}
+
+impl ContentValidator {
+    pub fn new(options: ValidationOptions) -> Self {
+        Self { options }
+    }
+
+    pub fn options(&self) -> &ValidationOptions {
+        &self.options
+    }
+
+    /// Validates `bytes` in the options' encoding. Its BOM is skipped.
+    pub fn validate(&self, bytes: &[u8]) -> ValidationReport {
+        let start = if bytes.starts_with(self.options.encoding.bom()) { self.options.encoding.bom().len() } else { 0 };
+        self.run(DecodedChars { bytes, encoding: &self.options.encoding, pos: start }, bytes.len())
+    }
+
+    /// Validates text already decoded, whatever the options' encoding.
+    pub fn validate_text(&self, text: &str) -> ValidationReport {
+        self.run(DecodedChars { bytes: text.as_bytes(), encoding: &Encoding::UTF8, pos: 0 }, text.len())
+    }
+
+    fn run(&self, chars: DecodedChars<'_>, len: usize) -> ValidationReport {
+        let options = &self.options;
+        let line_ending = match options.mixed_line_endings {
+            ValidationPolicy::Ignore => None,
+            _ => options.line_ending.or_else(|| most_frequent_line_ending(chars.clone())),
+        };
+        let mut report = ValidationReport { text: String::with_capacity(len), line_ending, issues: Vec::new() };
+        let mut chars = chars.peekable();
+        while let Some((offset, decoded)) = chars.next() {
+            let text_offset = report.text.len();
+            let (kind, policy) = match decoded {
+                Decoded::Invalid(bytes) => {
+                    if options.invalid_sequences == ValidationPolicy::Replace {
+                        report.text.push(char::REPLACEMENT_CHARACTER);
+                    } else {
+                        report.text.extend(bytes.iter().map(|&byte| raw_byte_marker(byte)));
+                    }
+                    (ValidationIssueKind::InvalidSequence(bytes.to_vec()), options.invalid_sequences)
+                }
+                Decoded::Char(ch) if LineEnding::from_char(ch).is_some() => {
+                    let found = match ch {
+                        '\r' if matches!(chars.peek(), Some((_, Decoded::Char('\n')))) => {
+                            chars.next();
+                            LineEnding::CRLF
+                        }
+                        _ => LineEnding::from_char(ch).unwrap_or(LineEnding::LF),
+                    };
+                    match line_ending {
+                        Some(expected) if found != expected => {
+                            let replace = options.mixed_line_endings == ValidationPolicy::Replace;
+                            report.text.push_str(if replace { expected.as_str() } else { found.as_str() });
+                            (ValidationIssueKind::MixedLineEnding { found, expected }, options.mixed_line_endings)
+                        }
+                        _ => {
+                            report.text.push_str(found.as_str());
+                            continue;
+                        }
+                    }
+                }
+                Decoded::Char(ch) if ch.is_control() && ch != '\t' => {
+                    let replace = options.control_characters == ValidationPolicy::Replace;
+                    report.text.push(if replace { char::REPLACEMENT_CHARACTER } else { ch });
+                    (ValidationIssueKind::ControlCharacter(ch), options.control_characters)
+                }
+                Decoded::Char(ch) => {
+                    report.text.push(ch);
+                    continue;
+                }
+            };
+            if policy != ValidationPolicy::Ignore {
+                report.issues.push(ValidationIssue { kind, offset, text_offset, policy });
+            }
+        }
+        report
+    }
+}
+
+/// The most frequent line ending, the first of `LineEnding::all` on ties.
+fn most_frequent_line_ending(chars: DecodedChars<'_>) -> Option<LineEnding> {
+    let all = LineEnding::all();
+    let mut counts = vec![0usize; all.len()];
+    let mut chars = chars.peekable();
+    while let Some((_, decoded)) = chars.next() {
+        let Decoded::Char(ch) = decoded else {
+            continue;
+        };
+        let Some(mut found) = LineEnding::from_char(ch) else {
+            continue;
+        };
+        if ch == '\r' && matches!(chars.peek(), Some((_, Decoded::Char('\n')))) {
+            chars.next();
+            found = LineEnding::CRLF;
+        }
+        if let Some(idx) = all.iter().position(|&ending| ending == found) {
+            counts[idx] += 1;
+        }
+    }
+    let max = counts.iter().copied().max().filter(|&max| max > 0)?;
+    counts.iter().position(|&count| count == max).map(|idx| all[idx])
+}
+
+enum Decoded<'a> {
+    Char(char),
+    Invalid(&'a [u8]),
+}
+
+/// Chars of `bytes` with their offsets; a genuine raw-byte marker char counts as invalid so
+/// that the text can't mistake it for one standing for a byte.
+#[derive(Clone)]
+struct DecodedChars<'a> {
+    bytes: &'a [u8],
+    encoding: &'a Encoding,
+    pos: usize,
+}
+
+impl<'a> Iterator for DecodedChars<'a> {
+    type Item = (usize, Decoded<'a>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let offset = self.pos;
+        let rest = self.bytes.get(offset..).filter(|rest| !rest.is_empty())?;
+        let (decoded, len) = match self.encoding.decode_step(rest) {
+            DecodeStep::Char(ch, len) if raw_byte(ch).is_none() => (Decoded::Char(ch), len),
+            DecodeStep::Char(_, len) | DecodeStep::Invalid(len) => (Decoded::Invalid(&rest[..len]), len),
+            DecodeStep::Incomplete => (Decoded::Invalid(rest), rest.len()),
+        };
+        self.pos += len;
+        Some((offset, decoded))
+    }
 }
 
 #[cfg(test)]
@@ -155,6 +422,51 @@ mod tests {
         assert!(validate_content(content, &Encoding::UTF8, LineEnding::LF).is_ok());
     }
 
+    #[test]
+    fn test_validation_respects_encoding() {
+        let utf16: Vec<u8> = "line1\r\nline2".encode_utf16().flat_map(u16::to_le_bytes).collect();
+        assert!(validate_content(&utf16, &Encoding::UTF16LE, LineEnding::CRLF).is_ok());
+        assert!(validate_content(b"caf\xe9", &Encoding::LATIN1, LineEnding::LF).is_ok());
+        assert!(validate_content(b"caf\xe9", &Encoding::UTF8, LineEnding::LF).is_err());
+    }
+
+    #[test]
+    fn test_report_lists_every_issue() {
+        let options = ValidationOptions::new(Encoding::UTF8)
+            .with_control_characters(ValidationPolicy::Warn)
+            .with_mixed_line_endings(ValidationPolicy::Reject);
+        let report = ContentValidator::new(options).validate(b"a\nb\r\nc\x00\xFF\nd\r\x01");
+        assert_eq!(report.line_ending, Some(LineEnding::LF));
+        let kinds: Vec<_> = report.issues.iter().map(|issue| (issue.kind.clone(), issue.offset, issue.policy)).collect();
+        assert_eq!(kinds, vec![
+            (ValidationIssueKind::MixedLineEnding { found: LineEnding::CRLF, expected: LineEnding::LF }, 3, ValidationPolicy::Reject),
+            (ValidationIssueKind::ControlCharacter('\0'), 6, ValidationPolicy::Warn),
+            (ValidationIssueKind::InvalidSequence(vec![0xFF]), 7, ValidationPolicy::Reject),
+            (ValidationIssueKind::MixedLineEnding { found: LineEnding::CR, expected: LineEnding::LF }, 10, ValidationPolicy::Reject),
+            (ValidationIssueKind::ControlCharacter('\u{1}'), 11, ValidationPolicy::Warn),
+        ]);
+        assert_eq!(report.warnings().count(), 2);
+        assert!(report.is_rejected());
+        assert!(matches!(report.into_result(), Err(ValidationError::InvalidLineEnding(ending)) if ending == "\r\n"));
+    }
+
+    #[test]
+    fn test_replace_policy() {
+        let options = ValidationOptions::new(Encoding::WINDOWS1251)
+            .with_control_characters(ValidationPolicy::Replace)
+            .with_mixed_line_endings(ValidationPolicy::Replace)
+            .with_line_ending(LineEnding::CRLF);
+        let report = ContentValidator::new(options).validate(b"\xcf\xf0\xe8\n\x07\r\n");
+        assert_eq!(report.text, "При\r\n\u{FFFD}\r\n");
+        assert_eq!(report.issues.len(), 2);
+        assert_eq!(report.issues[1].text_offset, 8);
+        assert!(!report.is_rejected());
+
+        let replaced = ContentValidator::new(ValidationOptions::new(Encoding::UTF8).with_invalid_sequences(ValidationPolicy::Replace))
+            .validate_text("tab\tis fine");
+        assert!(replaced.is_clean());
+    }
+
     #[test]
     fn test_validate_unicode_line_endings() {
         let content = "line1\u{0085}line2";
@@ -0,0 +1,10 @@
+//! Memory module
+//! Reexports pool, slab, and stats modules
+
+pub mod pool;
+pub mod slab;
+pub mod stats;
+
+pub use pool::{ChunkPool, Pool, Reusable};
+pub use slab::Slab;
+pub use stats::AllocationStats;
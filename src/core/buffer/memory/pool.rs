@@ -0,0 +1,230 @@
+/// pool.rs
+/// Defines `Pool`, which recycles growable buffers, and `ChunkPool`, which recycles the storage
+/// of rope chunks so that an edit reuses the buffers freed by the previous one.
+use std::sync::Mutex;
+use crate::core::buffer::memory::stats::AllocationStats;
+
+/// A buffer that can be emptied & reused, such as a `String` or a `Vec`.
+pub trait Reusable {
+    fn with_capacity(capacity: usize) -> Self;
+
+    /// Capacity in elements, as understood by `with_capacity`.
+    fn capacity(&self) -> usize;
+
+    /// Bytes of heap memory held by the buffer.
+    fn heap_bytes(&self) -> usize;
+
+    fn clear(&mut self);
+}
+
+impl Reusable for String {
+    fn with_capacity(capacity: usize) -> Self {
+        String::with_capacity(capacity)
+    }
+
+    fn capacity(&self) -> usize {
+        self.capacity()
+    }
+
+    fn heap_bytes(&self) -> usize {
+        self.capacity()
+    }
+
+    fn clear(&mut self) {
+        self.clear()
+    }
+}
+
+impl<T> Reusable for Vec<T> {
+    fn with_capacity(capacity: usize) -> Self {
+        Vec::with_capacity(capacity)
+    }
+
+    fn capacity(&self) -> usize {
+        self.capacity()
+    }
+
+    fn heap_bytes(&self) -> usize {
+        self.capacity() * std::mem::size_of::<T>()
+    }
+
+    fn clear(&mut self) {
+        self.clear()
+    }
+}
+
+/// Number of size classes, the last one holding every capacity from `2^(CLASSES - 1)` up.
+const CLASSES: usize = 32;
+
+#[derive(Debug)]
+struct PoolState<T> {
+    /// Class `k` holds buffers whose capacity lies in `[2^k, 2^(k + 1))`.
+    classes: Vec<Vec<T>>,
+    stats: AllocationStats,
+}
+
+/// A thread-safe pool of emptied buffers sorted by capacity class.
+///
+/// `acquire` hands out a buffer at least as large as requested, allocating one rounded up to a
+/// power of two when no pooled buffer fits. Released buffers are kept until `max_retained_bytes`
+/// is reached, after which they are dropped.
+#[derive(Debug)]
+pub struct Pool<T> {
+    state: Mutex<PoolState<T>>,
+    max_retained_bytes: usize,
+}
+
+impl<T: Reusable> Pool<T> {
+    pub fn new(max_retained_bytes: usize) -> Self {
+        Self {
+            state: Mutex::new(PoolState { classes: (0..CLASSES).map(|_| Vec::new()).collect(), stats: AllocationStats::default() }),
+            max_retained_bytes,
+        }
+    }
+
+    /// Returns an empty buffer with room for at least `capacity` elements.
+    pub fn acquire(&self, capacity: usize) -> T {
+        let mut state = self.state.lock().expect("pool lock poisoned");
+        let first = class_of(capacity.next_power_of_two());
+        for class in first..(first + 2).min(CLASSES) {
+            if let Some(buffer) = state.classes[class].pop() {
+                state.stats.reuses += 1;
+                state.stats.retained_bytes -= buffer.heap_bytes();
+                return buffer;
+            }
+        }
+        state.stats.allocations += 1;
+        T::with_capacity(capacity.next_power_of_two())
+    }
+
+    /// Hands `buffer` back for reuse.
+    pub fn release(&self, mut buffer: T) {
+        let bytes = buffer.heap_bytes();
+        if bytes == 0 {
+            return;
+        }
+        let mut state = self.state.lock().expect("pool lock poisoned");
+        state.stats.releases += 1;
+        if state.stats.retained_bytes + bytes > self.max_retained_bytes {
+            return;
+        }
+        buffer.clear();
+        state.stats.retained_bytes += bytes;
+        state.classes[class_of(buffer.capacity())].push(buffer);
+    }
+
+    pub fn stats(&self) -> AllocationStats {
+        self.state.lock().expect("pool lock poisoned").stats
+    }
+
+    /// Drops every pooled buffer.
+    pub fn clear(&self) {
+        let mut state = self.state.lock().expect("pool lock poisoned");
+        state.classes.iter_mut().for_each(Vec::clear);
+        state.stats.retained_bytes = 0;
+    }
+}
+
+impl<T: Reusable> Default for Pool<T> {
+    fn default() -> Self {
+        Self::new(1 << 20)
+    }
+}
+
+fn class_of(capacity: usize) -> usize {
+    (capacity.max(1).ilog2() as usize).min(CLASSES - 1)
+}
+
+/// Recycles the text & line ending storage of rope chunks.
+#[derive(Debug, Default)]
+pub struct ChunkPool {
+    strings: Pool<String>,
+    offsets: Pool<Vec<usize>>,
+}
+
+impl ChunkPool {
+    pub fn new(max_retained_bytes: usize) -> Self {
+        Self {
+            strings: Pool::new(max_retained_bytes / 2),
+            offsets: Pool::new(max_retained_bytes / 2),
+        }
+    }
+
+    /// A pool that never keeps anything, so every request allocates.
+    pub fn disabled() -> Self {
+        Self::new(0)
+    }
+
+    pub fn acquire_string(&self, capacity: usize) -> String {
+        self.strings.acquire(capacity)
+    }
+
+    pub fn release_string(&self, text: String) {
+        self.strings.release(text)
+    }
+
+    pub fn acquire_offsets(&self, capacity: usize) -> Vec<usize> {
+        self.offsets.acquire(capacity)
+    }
+
+    pub fn release_offsets(&self, offsets: Vec<usize>) {
+        self.offsets.release(offsets)
+    }
+
+    pub fn stats(&self) -> AllocationStats {
+        self.strings.stats() + self.offsets.stats()
+    }
+
+    pub fn clear(&self) {
+        self.strings.clear();
+        self.offsets.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_acquire_reuses_released_buffers() {
+        let pool: Pool<String> = Pool::default();
+        let mut text = pool.acquire(100);
+        assert!(text.capacity() >= 128);
+        text.push_str("hello");
+        let ptr = text.as_ptr();
+        pool.release(text);
+
+        let reused = pool.acquire(120);
+        assert!(reused.is_empty());
+        assert_eq!(reused.as_ptr(), ptr);
+        let stats = pool.stats();
+        assert_eq!((stats.allocations, stats.reuses, stats.releases), (1, 1, 1));
+        assert_eq!(stats.retained_bytes, 0);
+    }
+
+    #[test]
+    fn test_acquire_never_returns_smaller_buffers() {
+        let pool: Pool<Vec<usize>> = Pool::default();
+        pool.release(Vec::with_capacity(16));
+        let buffer = pool.acquire(40);
+        assert!(buffer.capacity() >= 40);
+        assert_eq!(pool.stats().reuses, 0);
+        assert_eq!(pool.stats().retained_bytes, 16 * std::mem::size_of::<usize>());
+    }
+
+    #[test]
+    fn test_retention_limit() {
+        let pool: Pool<String> = Pool::new(100);
+        pool.release(String::with_capacity(64));
+        pool.release(String::with_capacity(64));
+        assert_eq!(pool.stats().retained_bytes, 64);
+        assert_eq!(pool.stats().releases, 2);
+        pool.clear();
+        assert_eq!(pool.stats().retained_bytes, 0);
+
+        let disabled = ChunkPool::disabled();
+        disabled.release_string(String::with_capacity(8));
+        disabled.acquire_string(8);
+        assert_eq!(disabled.stats().reuses, 0);
+    }
+}
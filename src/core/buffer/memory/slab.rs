@@ -0,0 +1,196 @@
+/// slab.rs
+/// Defines `Slab`, a vector of slots addressed by stable keys, whose freed slots are reused.
+use std::ops::{Index, IndexMut};
+use crate::core::buffer::memory::stats::AllocationStats;
+
+#[derive(Clone, Debug)]
+enum Slot<T> {
+    Occupied(T),
+    /// A free slot, linking to the next free one.
+    Vacant(usize),
+}
+
+/// Stores values in slots whose keys stay valid until the value is removed. Inserting reuses
+/// the most recently freed slot, so a structure that keeps allocating & freeing nodes, such as
+/// the piece tree, does not allocate once it has reached its working size.
+#[derive(Clone, Debug)]
+pub struct Slab<T> {
+    slots: Vec<Slot<T>>,
+    /// First free slot, or `slots.len()` when there is none.
+    next_free: usize,
+    len: usize,
+    stats: AllocationStats,
+}
+
+impl<T> Slab<T> {
+    pub fn new() -> Self {
+        Self::with_capacity(0)
+    }
+
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self { slots: Vec::with_capacity(capacity), next_free: 0, len: 0, stats: AllocationStats::default() }
+    }
+
+    /// Number of occupied slots.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Number of slots, free ones included.
+    #[inline]
+    pub fn slots(&self) -> usize {
+        self.slots.len()
+    }
+
+    pub fn insert(&mut self, value: T) -> usize {
+        self.len += 1;
+        let key = self.next_free;
+        if key == self.slots.len() {
+            self.stats.allocations += 1;
+            self.slots.push(Slot::Occupied(value));
+            self.next_free = self.slots.len();
+            return key;
+        }
+
+        self.stats.reuses += 1;
+        self.stats.retained_bytes -= std::mem::size_of::<Slot<T>>();
+        match std::mem::replace(&mut self.slots[key], Slot::Occupied(value)) {
+            Slot::Vacant(next) => self.next_free = next,
+            Slot::Occupied(_) => unreachable!("free list points to an occupied slot"),
+        }
+        key
+    }
+
+    pub fn remove(&mut self, key: usize) -> Option<T> {
+        if !self.contains(key) {
+            return None;
+        }
+        self.len -= 1;
+        self.stats.releases += 1;
+        self.stats.retained_bytes += std::mem::size_of::<Slot<T>>();
+        let slot = std::mem::replace(&mut self.slots[key], Slot::Vacant(self.next_free));
+        self.next_free = key;
+        match slot {
+            Slot::Occupied(value) => Some(value),
+            Slot::Vacant(_) => unreachable!("slot checked above"),
+        }
+    }
+
+    pub fn contains(&self, key: usize) -> bool {
+        matches!(self.slots.get(key), Some(Slot::Occupied(_)))
+    }
+
+    pub fn get(&self, key: usize) -> Option<&T> {
+        match self.slots.get(key) {
+            Some(Slot::Occupied(value)) => Some(value),
+            _ => None,
+        }
+    }
+
+    pub fn get_mut(&mut self, key: usize) -> Option<&mut T> {
+        match self.slots.get_mut(key) {
+            Some(Slot::Occupied(value)) => Some(value),
+            _ => None,
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.slots.clear();
+        self.next_free = 0;
+        self.len = 0;
+        self.stats.retained_bytes = 0;
+    }
+
+    /// Reserves room for `additional` more values, counting the free slots.
+    pub fn reserve(&mut self, additional: usize) {
+        let free = self.slots.len() - self.len;
+        self.slots.reserve(additional.saturating_sub(free));
+    }
+
+    /// Releases the free slots at the end and any spare capacity.
+    pub fn shrink_to_fit(&mut self) {
+        while let Some(Slot::Vacant(_)) = self.slots.last() {
+            self.slots.pop();
+            self.stats.retained_bytes -= std::mem::size_of::<Slot<T>>();
+        }
+        self.next_free = self.slots.len();
+        for key in (0..self.slots.len()).rev() {
+            if let Slot::Vacant(next) = &mut self.slots[key] {
+                *next = self.next_free;
+                self.next_free = key;
+            }
+        }
+        self.slots.shrink_to_fit();
+    }
+
+    pub fn stats(&self) -> AllocationStats {
+        self.stats
+    }
+}
+
+impl<T> Default for Slab<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> Index<usize> for Slab<T> {
+    type Output = T;
+
+    fn index(&self, key: usize) -> &T {
+        self.get(key).expect("no value at slab key")
+    }
+}
+
+impl<T> IndexMut<usize> for Slab<T> {
+    fn index_mut(&mut self, key: usize) -> &mut T {
+        self.get_mut(key).expect("no value at slab key")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_insert_remove_reuse() {
+        let mut slab = Slab::new();
+        let a = slab.insert("a");
+        let b = slab.insert("b");
+        assert_eq!((a, b), (0, 1));
+        assert_eq!(slab.remove(a), Some("a"));
+        assert_eq!(slab.remove(a), None);
+        assert_eq!(slab.get(a), None);
+
+        let c = slab.insert("c");
+        assert_eq!(c, a);
+        assert_eq!(slab[c], "c");
+        assert_eq!(slab.len(), 2);
+        let stats = slab.stats();
+        assert_eq!((stats.allocations, stats.reuses, stats.releases), (2, 1, 1));
+    }
+
+    #[test]
+    fn test_shrink_to_fit() {
+        let mut slab = Slab::new();
+        let keys: Vec<usize> = (0..6).map(|value| slab.insert(value)).collect();
+        for &key in &keys[1..] {
+            if key != 2 {
+                slab.remove(key);
+            }
+        }
+        slab.shrink_to_fit();
+        assert_eq!(slab.slots(), 3);
+        assert_eq!(slab.insert(10), 1);
+        assert_eq!(slab.insert(11), 3);
+        assert_eq!(slab[2], 2);
+        slab[2] = 20;
+        assert_eq!(slab.get_mut(2), Some(&mut 20));
+    }
+}
@@ -0,0 +1,44 @@
+/// stats.rs
+/// Defines `AllocationStats`, the counters reported by the slab & pool allocators.
+use std::ops::{Add, AddAssign};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct AllocationStats {
+    /// Requests that had to allocate fresh storage.
+    pub allocations: usize,
+    /// Requests served with recycled storage.
+    pub reuses: usize,
+    /// Storage handed back for recycling.
+    pub releases: usize,
+    /// Bytes held for later reuse.
+    pub retained_bytes: usize,
+}
+
+impl AllocationStats {
+    /// Share of the requests served without allocating.
+    pub fn reuse_ratio(&self) -> f64 {
+        let requests = self.allocations + self.reuses;
+        if requests == 0 {
+            return 0.0;
+        }
+        self.reuses as f64 / requests as f64
+    }
+}
+
+impl Add for AllocationStats {
+    type Output = AllocationStats;
+
+    fn add(mut self, other: AllocationStats) -> AllocationStats {
+        self += other;
+        self
+    }
+}
+
+impl AddAssign for AllocationStats {
+    fn add_assign(&mut self, other: AllocationStats) {
+        self.allocations += other.allocations;
+        self.reuses += other.reuses;
+        self.releases += other.releases;
+        self.retained_bytes += other.retained_bytes;
+    }
+}
@@ -0,0 +1,11 @@
+//! Buffer module
+//! Reexports content, memory, piece table, rope, storage, and traits modules
+
+pub mod content;
+pub mod memory;
+pub mod piece_table;
+pub mod rope;
+pub mod storage;
+pub mod traits;
+
+pub use traits::{BufferError, TextBuffer};
@@ -0,0 +1,258 @@
+/// add_buffer.rs
+/// Defines `AddBuffer`, the append-only buffer holding the text inserted into a `PieceTable`.
+/// The text is kept in blocks that clones of the buffer share, so a snapshot never copies it.
+/// A paged buffer moves each block into a `PageStore` once it fills a page.
+use std::borrow::Cow;
+use std::fmt;
+use std::ops::Range;
+use std::sync::Arc;
+use crate::core::buffer::piece_table::original::{line_feed_positions, line_feeds_in, nth_line_feed_from};
+use crate::core::buffer::storage::backend::StorageError;
+use crate::core::buffer::storage::paging::{lock_store, Page, SharedPageStore};
+
+/// A block of appended text, either in memory along with the positions of its line feeds or
+/// sealed into a page.
+#[derive(Debug)]
+enum AddBlock {
+    Text { text: String, line_feeds: Vec<usize> },
+    Paged(Page),
+}
+
+impl Default for AddBlock {
+    fn default() -> Self {
+        AddBlock::Text { text: String::new(), line_feeds: Vec::new() }
+    }
+}
+
+/// The add buffer of a `PieceTable`, split into blocks.
+///
+/// Text is appended to the last block as long as no clone shares it; once a clone does, a new
+/// block is started instead. Cloning is O(1) and text that was handed out is never moved, so
+/// pieces address it by block & range within the block.
+///
+/// A paged buffer caps blocks at the page size of its store, and seals a block into the store
+/// once it is full or a new block is started, so only the last block stays in memory. Reading a
+/// sealed block fails if the store fails to load it.
+#[derive(Debug, Clone, Default)]
+pub struct AddBuffer {
+    blocks: Arc<Vec<Arc<AddBlock>>>,
+    len: usize,
+    /// Store & page size of a paged buffer.
+    paging: Option<(SharedPageStore, usize)>,
+}
+
+impl AddBuffer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// An add buffer sealing its blocks into `store`.
+    pub fn paged(store: &SharedPageStore) -> Self {
+        let page_size = lock_store(store).config().page_size.max(4);
+        Self { paging: Some((Arc::clone(store), page_size)), ..Self::default() }
+    }
+
+    /// An empty add buffer keeping its blocks where this one does.
+    pub fn cleared(&self) -> Self {
+        Self { paging: self.paging.clone(), ..Self::default() }
+    }
+
+    /// Total number of bytes appended, over all blocks.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    #[inline]
+    pub fn is_paged(&self) -> bool {
+        self.paging.is_some()
+    }
+
+    pub fn block_count(&self) -> usize {
+        self.blocks.len()
+    }
+
+    /// Number of blocks sealed into the page store.
+    pub fn paged_blocks(&self) -> usize {
+        self.blocks.iter().filter(|block| matches!(***block, AddBlock::Paged(_))).count()
+    }
+
+    /// Appends as much of `text` as fits in the current block, returning the block it went to
+    /// and its range within that block. Only a paged buffer can take less than all of `text`,
+    /// in which case the caller pushes the rest.
+    pub fn push(&mut self, text: &str) -> Result<(usize, Range<usize>), StorageError> {
+        let writable = match Arc::make_mut(&mut self.blocks).last_mut().and_then(Arc::get_mut) {
+            Some(AddBlock::Text { text: block, .. }) => Some(block.len()),
+            _ => None,
+        };
+        let mut taken = writable.map_or(0, |used| self.fitting(text, used));
+        if taken == 0 {
+            self.seal_last()?;
+            Arc::make_mut(&mut self.blocks).push(Arc::default());
+            // A block always takes at least one char, even one larger than a page.
+            taken = self.fitting(text, 0).max(text.ceil_char_boundary(1));
+        }
+
+        let blocks = Arc::make_mut(&mut self.blocks);
+        let index = blocks.len() - 1;
+        let Some(AddBlock::Text { text: block, line_feeds }) = Arc::get_mut(&mut blocks[index]) else {
+            unreachable!("last block is in memory & not shared");
+        };
+        let start = block.len();
+        block.push_str(&text[..taken]);
+        line_feeds.extend(line_feed_positions(&text[..taken], start));
+        let end = block.len();
+        self.len += taken;
+        if self.paging.as_ref().is_some_and(|(_, page_size)| end >= *page_size) {
+            self.seal_last()?;
+        }
+        Ok((index, start..end))
+    }
+
+    /// Text of `range` within `block`.
+    pub fn text(&self, block: usize, range: Range<usize>) -> Result<Cow<'_, str>, StorageError> {
+        match &*self.blocks[block] {
+            AddBlock::Text { text, .. } => Ok(Cow::Borrowed(&text[range])),
+            AddBlock::Paged(page) => Ok(Cow::Owned(page.read()?[range].to_string())),
+        }
+    }
+
+    pub fn is_char_boundary(&self, block: usize, offset: usize) -> Result<bool, StorageError> {
+        match &*self.blocks[block] {
+            AddBlock::Text { text, .. } => Ok(text.is_char_boundary(offset)),
+            AddBlock::Paged(page) => Ok(page.read()?.is_char_boundary(offset)),
+        }
+    }
+
+    /// Counts the line feeds within `range` of `block`.
+    pub fn line_feeds_in(&self, block: usize, range: Range<usize>) -> Result<usize, StorageError> {
+        match &*self.blocks[block] {
+            AddBlock::Text { line_feeds, .. } => Ok(line_feeds_in(line_feeds, range)),
+            AddBlock::Paged(page) if range == (0..page.len()) => Ok(page.line_feeds()),
+            AddBlock::Paged(page) => Ok(page.read()?.as_bytes()[range].iter().filter(|&&byte| byte == b'\n').count()),
+        }
+    }
+
+    /// Offset within `block` of its `n`th line feed at or after `start`.
+    pub fn nth_line_feed_from(&self, block: usize, start: usize, n: usize) -> Result<Option<usize>, StorageError> {
+        match &*self.blocks[block] {
+            AddBlock::Text { line_feeds, .. } => Ok(nth_line_feed_from(line_feeds, start, n)),
+            AddBlock::Paged(page) => {
+                let text = page.read()?;
+                Ok(line_feed_positions(&text[start..], start).nth(n))
+            }
+        }
+    }
+
+    /// Iterates over the text of the blocks, in the order it was appended.
+    pub fn chunks(&self) -> impl Iterator<Item = Result<Cow<'_, str>, StorageError>> + '_ {
+        self.blocks.iter().map(|block| match &**block {
+            AddBlock::Text { text, .. } => Ok(Cow::Borrowed(text.as_str())),
+            AddBlock::Paged(page) => Ok(Cow::Owned(page.read()?.to_string())),
+        })
+    }
+
+    /// Length of the longest prefix of `text` that fits in a block already holding `used` bytes.
+    fn fitting(&self, text: &str, used: usize) -> usize {
+        match &self.paging {
+            Some((_, page_size)) if used + text.len() > *page_size => text.floor_char_boundary(page_size.saturating_sub(used)),
+            _ => text.len(),
+        }
+    }
+
+    /// Moves the last block into the page store, if the buffer is paged.
+    fn seal_last(&mut self) -> Result<(), StorageError> {
+        let Some((store, _)) = &self.paging else {
+            return Ok(());
+        };
+        let Some(AddBlock::Text { text, .. }) = self.blocks.last().map(|block| &**block) else {
+            return Ok(());
+        };
+        if text.is_empty() {
+            return Ok(());
+        }
+        let page = Page::allocate(store, text)?;
+        let blocks = Arc::make_mut(&mut self.blocks);
+        *blocks.last_mut().expect("last block checked above") = Arc::new(AddBlock::Paged(page));
+        Ok(())
+    }
+}
+
+impl fmt::Display for AddBuffer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.chunks().try_for_each(|chunk| f.write_str(&chunk.map_err(|_| fmt::Error)?))
+    }
+}
+
+impl PartialEq<str> for AddBuffer {
+    fn eq(&self, other: &str) -> bool {
+        let mut rest = other;
+        self.len == other.len() && self.chunks().all(|chunk| match chunk.as_deref().ok().and_then(|chunk| rest.strip_prefix(chunk)) {
+            Some(tail) => {
+                rest = tail;
+                true
+            }
+            None => false,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::buffer::storage::backend::MemoryBackend;
+    use crate::core::buffer::storage::paging::{PageStore, StorageConfig};
+
+    #[test]
+    fn test_clones_share_blocks() {
+        let mut buffer = AddBuffer::new();
+        assert_eq!(buffer.push("ab\n").unwrap(), (0, 0..3));
+        assert_eq!(buffer.push("c").unwrap(), (0, 3..4));
+
+        let snapshot = buffer.clone();
+        assert_eq!(buffer.push("d\n").unwrap(), (1, 0..2));
+        assert_eq!(buffer.push("e").unwrap(), (1, 2..3));
+        assert_eq!(buffer.block_count(), 2);
+        assert_eq!(snapshot.block_count(), 1);
+        assert_eq!(&snapshot, "ab\nc");
+        assert_eq!(&buffer, "ab\ncd\ne");
+        assert_eq!(buffer.len(), 7);
+
+        drop(snapshot);
+        assert_eq!(buffer.push("f").unwrap(), (1, 3..4));
+        assert_eq!(buffer.line_feeds_in(1, 0..4), Ok(1));
+        assert_eq!(buffer.nth_line_feed_from(0, 0, 0), Ok(Some(2)));
+        assert_eq!(buffer.text(1, 1..4).unwrap(), "\nef");
+    }
+
+    #[test]
+    fn test_paged_blocks() {
+        let config = StorageConfig { page_size: 8, ..StorageConfig::default() };
+        let store = PageStore::with_backend(config, Box::new(MemoryBackend::new())).into_shared();
+        let mut buffer = AddBuffer::paged(&store);
+        assert_eq!(buffer.push("abc\n").unwrap(), (0, 0..4));
+        // Only what fits in the page goes to the current block, without splitting a char.
+        assert_eq!(buffer.push("dé\nfgh").unwrap(), (0, 4..8));
+        assert_eq!(buffer.paged_blocks(), 1);
+        assert_eq!(buffer.push("fgh").unwrap(), (1, 0..3));
+
+        let snapshot = buffer.clone();
+        assert_eq!(buffer.push("ij").unwrap(), (2, 0..2));
+        assert_eq!(buffer.paged_blocks(), 2);
+        assert_eq!(snapshot.paged_blocks(), 1);
+        assert_eq!(&buffer, "abc\ndé\nfghij");
+        assert_eq!(buffer.text(0, 4..7).unwrap(), "dé");
+        assert_eq!(buffer.line_feeds_in(0, 0..8), Ok(2));
+        assert_eq!(buffer.line_feeds_in(0, 1..8), Ok(2));
+        assert_eq!(buffer.nth_line_feed_from(0, 4, 0), Ok(Some(7)));
+        assert_eq!(buffer.is_char_boundary(0, 6), Ok(false));
+
+        drop((buffer, snapshot));
+        assert_eq!(lock_store(&store).len(), 0);
+    }
+}
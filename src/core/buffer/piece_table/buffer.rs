@@ -1,127 +1,189 @@
 /// buffer.rs
 /// Defines the Buffer struct which uses a Piece Table for efficient text editing.
-use std::ops::Range;
 use std::fmt;
 use std::cmp::Ordering;
 use std::hash::{Hash, Hasher};
+use std::ops::Range;
 use std::sync::Arc;
+use crate::core::buffer::memory::stats::AllocationStats;
+use crate::core::buffer::piece_table::descriptor::PieceDescriptor;
 use crate::core::buffer::piece_table::piece::Piece;
+use crate::core::buffer::piece_table::tree::{LineFeedCounter, PiecePosition, PieceTree, PieceTreeIter};
 
+/// The pieces of a piece table, in text order, along with the line feed count of each.
+///
+/// Pieces are kept in a `PieceTree`, so every index, offset & line feed lookup is O(log n).
+/// A piece added without its line feed count (`None`) is counted by the first line lookup that
+/// needs it.
 #[derive(Clone, Debug)]
 pub struct Buffer {
-    pieces: Vec<Arc<Piece>>,
-    total_length: usize,
+    tree: PieceTree,
 }
 
 impl Buffer {
     pub fn new() -> Self {
         Self {
-            pieces: Vec::new(),
-            total_length: 0,
+            tree: PieceTree::new(),
         }
     }
 
     pub fn with_capacity(capacity: usize) -> Self {
         Self {
-            pieces: Vec::with_capacity(capacity),
-            total_length: 0,
+            tree: PieceTree::with_capacity(capacity),
         }
     }
     
-    pub fn from_pieces(pieces: Vec<Piece>) -> Self {
-        let total_length = pieces.iter().map(|p| p.len()).sum();
-        Self {
-            pieces: pieces.into_iter().map(Arc::new).collect(),
-            total_length,
-        }
+    pub fn from_pieces<I>(pieces: I) -> Self
+    where
+        I: IntoIterator<Item = (Piece, Option<usize>)>
+    {
+        let mut buffer = Self::new();
+        buffer.extend_pieces(pieces);
+        buffer
     }
 
     #[inline]
     pub fn len(&self) -> usize {
-        self.total_length
+        self.tree.len()
     }
 
     #[inline]
     pub fn is_empty(&self) -> bool {
-        self.total_length == 0
+        self.tree.is_empty()
     }
 
-    pub fn pieces(&self) -> &[Arc<Piece>] {
-        &self.pieces
+    pub fn line_feeds(&self, count: LineFeedCounter) -> usize {
+        self.tree.total_line_feeds(count)
     }
 
     pub fn pieces_count(&self) -> usize {
-        self.pieces.len()
+        self.tree.count()
     }
 
-    pub fn add_piece(&mut self, piece: Piece) {
-        self.total_length += piece.len();
-        self.pieces.push(Arc::new(piece));
+    pub fn add_piece(&mut self, piece: Piece, line_feeds: Option<usize>) {
+        self.tree.insert(self.tree.count(), Arc::new(piece), line_feeds);
     }
 
-    pub fn insert_piece(&mut self, index: usize, piece: Piece) -> Result<(), &'static str> {
-        if index > self.pieces.len() {
+    pub fn insert_piece(&mut self, index: usize, piece: Piece, line_feeds: Option<usize>) -> Result<(), &'static str> {
+        if index > self.tree.count() {
             return Err("Index out of bounds");
         }
-        self.total_length += piece.len();
-        self.pieces.insert(index, Arc::new(piece));
+        self.tree.insert(index, Arc::new(piece), line_feeds);
         Ok(())
     }
 
     pub fn extend_pieces<I>(&mut self, pieces: I) 
     where 
-        I: IntoIterator<Item = Piece>
+        I: IntoIterator<Item = (Piece, Option<usize>)>
+    {
+        for (piece, line_feeds) in pieces {
+            self.add_piece(piece, line_feeds);
+        }
+    }
+
+    /// Replaces the pieces in `range` by `pieces`, returning the removed ones.
+    pub fn replace_pieces<I>(&mut self, range: Range<usize>, pieces: I) -> Vec<Arc<Piece>>
+    where
+        I: IntoIterator<Item = (Piece, Option<usize>)>
     {
-        for piece in pieces {
-            self.total_length += piece.len();
-            self.pieces.push(Arc::new(piece));
+        let removed = range.clone().filter_map(|_| self.tree.remove(range.start)).collect();
+        for (offset, (piece, line_feeds)) in pieces.into_iter().enumerate() {
+            self.tree.insert(range.start + offset, Arc::new(piece), line_feeds);
         }
+        removed
     }
 
     pub fn clear(&mut self) {
-        self.pieces.clear();
-        self.total_length = 0;
+        self.tree.clear();
     }
 
     pub fn get_piece(&self, index: usize) -> Option<&Arc<Piece>> {
-        self.pieces.get(index)
+        self.tree.get(index).map(|(piece, _)| piece)
+    }
+
+    /// Returns the piece at `index` along with its line feed count, if counted.
+    pub fn get_entry(&self, index: usize) -> Option<(&Arc<Piece>, Option<usize>)> {
+        self.tree.get(index)
+    }
+
+    /// Returns the descriptor of the piece at `index`, its global start being derived from the tree.
+    pub fn descriptor(&self, index: usize) -> Option<PieceDescriptor> {
+        let position = self.tree.position(index)?;
+        let (piece, _) = self.tree.get(index)?;
+        Some(PieceDescriptor::new(piece.clone(), position.start))
+    }
+
+    /// Locates the piece containing byte `offset`; the end of the text maps to one past the last piece.
+    pub fn find_offset(&self, offset: usize) -> PiecePosition {
+        self.tree.find_offset(offset)
+    }
+
+    /// Locates the piece holding the `line_feed`-th line feed (zero based).
+    pub fn find_line_feed(&self, line_feed: usize, count: LineFeedCounter) -> PiecePosition {
+        self.tree.find_line_feed(line_feed, count)
+    }
+
+    /// Number of line feeds in the pieces before `index`.
+    pub fn line_feeds_before(&self, index: usize, count: LineFeedCounter) -> usize {
+        self.tree.line_feeds_before(index, count)
     }
 
     pub fn remove_piece(&mut self, index: usize) -> Option<Arc<Piece>> {
-        if index >= self.pieces.len() {
-            return None;
-        }
-        let piece = self.pieces.remove(index);
-        self.total_length -= piece.len();
-        Some(piece)
+        self.tree.remove(index)
+    }
+
+    pub fn set_piece(&mut self, index: usize, piece: Piece, line_feeds: Option<usize>) -> Option<Arc<Piece>> {
+        self.tree.set(index, Arc::new(piece), line_feeds)
     }
 
     pub fn swap_pieces(&mut self, a: usize, b: usize) -> Result<(), &'static str> {
-        if a >= self.pieces.len() || b >= self.pieces.len() {
-            return Err("Index out of bounds");
-        }
-        self.pieces.swap(a, b);
+        let (piece_a, line_feeds_a) = self.tree.get(a).map(|(p, lf)| (p.clone(), lf)).ok_or("Index out of bounds")?;
+        let (piece_b, line_feeds_b) = self.tree.get(b).map(|(p, lf)| (p.clone(), lf)).ok_or("Index out of bounds")?;
+        self.tree.set(a, piece_b, line_feeds_b);
+        self.tree.set(b, piece_a, line_feeds_a);
         Ok(())
     }
 
     pub fn truncate_pieces(&mut self, len: usize) {
-        if len < self.pieces.len() {
-            let removed_length: usize = self.pieces[len..].iter().map(|p| p.len()).sum();
-            self.pieces.truncate(len);
-            self.total_length -= removed_length;
+        while self.tree.count() > len {
+            self.tree.remove(self.tree.count() - 1);
         }
     }
 
-    pub fn iter_pieces(&self) -> std::slice::Iter<Arc<Piece>> {
-        self.pieces.iter()
+    pub fn iter_pieces(&self) -> PieceTreeIter<'_> {
+        self.tree.iter()
+    }
+
+    /// Iterates over the pieces starting with the one at `index`.
+    pub fn iter_pieces_from(&self, index: usize) -> PieceTreeIter<'_> {
+        self.tree.iter_from(index)
+    }
+
+    /// Iterates over the descriptors of the pieces starting at `index`.
+    pub fn descriptors_from(&self, index: usize) -> impl Iterator<Item = PieceDescriptor> + '_ {
+        let start = self.tree.position(index).map_or(self.len(), |position| position.start);
+        self.tree.iter_from(index).scan(start, |global_start, piece| {
+            let descriptor = PieceDescriptor::new(piece.clone(), *global_start);
+            *global_start += piece.len();
+            Some(descriptor)
+        })
     }
 
     pub fn shrink_to_fit(&mut self) {
-        self.pieces.shrink_to_fit();
+        self.tree.shrink_to_fit();
+    }
+
+    pub fn allocation_stats(&self) -> AllocationStats {
+        self.tree.allocation_stats()
     }
 
     pub fn reserve(&mut self, additional: usize) {
-        self.pieces.reserve(additional);
+        self.tree.reserve(additional);
+    }
+
+    #[cfg(test)]
+    pub(crate) fn assert_invariants(&self) {
+        self.tree.assert_invariants();
     }
 }
 
@@ -133,13 +195,15 @@ impl Default for Buffer {
 
 impl fmt::Display for Buffer {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "Buffer[pieces: {}, length: {}]", self.pieces.len(), self.total_length)
+        write!(f, "Buffer[pieces: {}, length: {}]", self.pieces_count(), self.len())
     }
 }
 
 impl PartialEq for Buffer {
     fn eq(&self, other: &Self) -> bool {
-        self.total_length == other.total_length && self.pieces == other.pieces
+        self.len() == other.len()
+            && self.pieces_count() == other.pieces_count()
+            && self.iter_pieces().eq(other.iter_pieces())
     }
 }
 
@@ -0,0 +1,264 @@
+/// compaction.rs
+/// Reclaims the bytes of the add buffer of a `PieceTable` that no piece references any more.
+/// Compaction copies the text the add pieces still reference into a fresh add buffer, merging
+/// add pieces that end up contiguous. Pieces of the original buffer are kept as they are, so a
+/// mapped file stays mapped.
+use crate::core::buffer::piece_table::buffer::Buffer;
+use crate::core::buffer::piece_table::operations::{PieceTable, PieceTableError, ADD_BUFFER, ORIGINAL_BUFFER};
+use crate::core::buffer::piece_table::piece::Piece;
+
+/// Decides when `PieceTable::compact_if_needed` runs a compaction.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CompactionPolicy {
+    /// Compacts once unreachable bytes make up this share of the add buffer.
+    pub max_waste_ratio: f64,
+    /// Compacts once the average add piece gets shorter than this many bytes, provided some
+    /// add pieces follow one another and would be merged.
+    pub min_average_piece_len: usize,
+    /// Add buffers smaller than this are never compacted.
+    pub min_buffer_bytes: usize,
+}
+
+impl Default for CompactionPolicy {
+    fn default() -> Self {
+        Self {
+            max_waste_ratio: 0.5,
+            min_average_piece_len: 16,
+            min_buffer_bytes: 4096,
+        }
+    }
+}
+
+/// How much of the add buffer of a `PieceTable` is still referenced, and by how many pieces.
+///
+/// Only the add buffer is measured, as it is all compaction rewrites: the original buffer is
+/// either a map of the file, which takes no memory of its own, or shared with snapshots.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Fragmentation {
+    pub live_bytes: usize,
+    pub buffer_bytes: usize,
+    /// Pieces pointing into the add buffer.
+    pub pieces: usize,
+    /// Runs of add pieces following one another, each of which compaction turns into a piece.
+    pub runs: usize,
+}
+
+impl Fragmentation {
+    /// Bytes of the add buffer that no piece references.
+    #[inline]
+    pub fn wasted_bytes(&self) -> usize {
+        self.buffer_bytes - self.live_bytes
+    }
+
+    pub fn waste_ratio(&self) -> f64 {
+        if self.buffer_bytes == 0 {
+            return 0.0;
+        }
+        self.wasted_bytes() as f64 / self.buffer_bytes as f64
+    }
+
+    pub fn average_piece_len(&self) -> usize {
+        self.live_bytes.checked_div(self.pieces).unwrap_or(0)
+    }
+
+    pub fn exceeds(&self, policy: &CompactionPolicy) -> bool {
+        if self.buffer_bytes < policy.min_buffer_bytes {
+            return false;
+        }
+        let mergeable = self.pieces > self.runs;
+        self.waste_ratio() >= policy.max_waste_ratio || (mergeable && self.average_piece_len() < policy.min_average_piece_len)
+    }
+}
+
+/// Outcome of a compaction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CompactionReport {
+    /// Bytes of the add buffer.
+    pub bytes_before: usize,
+    pub bytes_after: usize,
+    /// Pieces of the whole table.
+    pub pieces_before: usize,
+    pub pieces_after: usize,
+}
+
+impl CompactionReport {
+    #[inline]
+    pub fn bytes_saved(&self) -> usize {
+        self.bytes_before - self.bytes_after
+    }
+}
+
+impl PieceTable {
+    pub fn fragmentation(&self) -> Fragmentation {
+        let mut fragmentation = Fragmentation { live_bytes: 0, buffer_bytes: self.added().len(), pieces: 0, runs: 0 };
+        let mut in_run = false;
+        for piece in self.pieces().iter_pieces() {
+            let is_added = piece.buffer_id != ORIGINAL_BUFFER;
+            if is_added {
+                fragmentation.live_bytes += piece.len();
+                fragmentation.pieces += 1;
+                fragmentation.runs += usize::from(!in_run);
+            }
+            in_run = is_added;
+        }
+        fragmentation
+    }
+
+    /// Copies the text the add pieces reference into a fresh add buffer, dropping unreachable
+    /// bytes and merging add pieces that follow one another. Original pieces are kept.
+    ///
+    /// The old add buffer is released rather than rewritten, so clones taken as snapshots
+    /// before the compaction keep their text. Fails, leaving the table unchanged, if a paged add
+    /// buffer fails to load or store the text.
+    pub fn compact(&mut self) -> Result<CompactionReport, PieceTableError> {
+        let bytes_before = self.added().len();
+        let pieces_before = self.piece_count();
+
+        let mut added = self.added().cleared();
+        let mut entries: Vec<(Piece, Option<usize>)> = Vec::with_capacity(pieces_before);
+        for index in 0..pieces_before {
+            let (piece, line_feeds) = self.pieces().get_entry(index).expect("index below piece count");
+            if piece.buffer_id == ORIGINAL_BUFFER {
+                entries.push(((**piece).clone(), line_feeds));
+                continue;
+            }
+            let text = self.piece_text(piece)?;
+            let mut rest = &*text;
+            while !rest.is_empty() {
+                let (block, range) = added.push(rest).map_err(PieceTableError::Storage)?;
+                rest = &rest[range.len()..];
+                // A paged buffer may split the piece, whose parts then count their own line feeds.
+                let line_feeds = match range.len() == piece.len() {
+                    true => line_feeds,
+                    false => Some(added.line_feeds_in(block, range.clone()).map_err(PieceTableError::Storage)?),
+                };
+                let piece = Piece::new(ADD_BUFFER + block, range);
+                match entries.last_mut() {
+                    Some((last, last_line_feeds)) if last.can_merge(&piece) && last.end() == piece.start() => {
+                        *last = last.merge(&piece).expect("mergeable pieces");
+                        *last_line_feeds = last_line_feeds.zip(line_feeds).map(|(last, own)| last + own);
+                    }
+                    _ => entries.push((piece, line_feeds)),
+                }
+            }
+        }
+        self.set_pieces(added, Buffer::from_pieces(entries));
+
+        Ok(CompactionReport {
+            bytes_before,
+            bytes_after: self.added().len(),
+            pieces_before,
+            pieces_after: self.piece_count(),
+        })
+    }
+
+    /// Compacts when the fragmentation exceeds `policy`.
+    pub fn compact_if_needed(&mut self, policy: &CompactionPolicy) -> Result<Option<CompactionReport>, PieceTableError> {
+        if self.fragmentation().exceeds(policy) {
+            return self.compact().map(Some);
+        }
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    fn fragmented() -> PieceTable {
+        let mut table = PieceTable::from_text(&"0123456789\n".repeat(100));
+        for idx in (0..100).rev() {
+            table.insert(idx * 11, "ab").unwrap();
+        }
+        table.delete(0..600).unwrap();
+        table
+    }
+
+    #[test]
+    fn test_fragmentation() {
+        let table = fragmented();
+        let fragmentation = table.fragmentation();
+        assert_eq!(fragmentation.buffer_bytes, 200);
+        assert_eq!(fragmentation.live_bytes, 106);
+        assert_eq!(fragmentation.wasted_bytes(), 94);
+        assert_eq!(fragmentation.pieces, 53);
+        assert_eq!(fragmentation.runs, 53);
+        assert_eq!(PieceTable::new().fragmentation().waste_ratio(), 0.0);
+    }
+
+    #[test]
+    fn test_compact() {
+        let mut table = fragmented();
+        let text = table.text();
+        let pieces = table.piece_count();
+
+        let report = table.compact().unwrap();
+        assert_eq!(report.bytes_saved(), 94);
+        assert_eq!(report.pieces_before, pieces);
+        assert_eq!(report.pieces_after, pieces);
+        assert_eq!(table.text(), text);
+        assert_eq!(table.added(), "ab".repeat(53).as_str());
+        assert_eq!(table.original().unwrap(), "0123456789\n".repeat(100));
+        assert_eq!(table.line_count(), text.matches('\n').count() + 1);
+
+        table.insert(3, "x").unwrap();
+        assert_eq!(table.slice(0..4).unwrap(), format!("{}x", &text[..3]));
+    }
+
+    #[test]
+    fn test_compact_merges_add_pieces() {
+        let mut table = PieceTable::from_text("xyz");
+        table.insert(0, "a\na").unwrap();
+        table.insert(0, "bb").unwrap();
+        assert_eq!(table.fragmentation().runs, 1);
+        assert_eq!(table.fragmentation().pieces, 2);
+
+        let report = table.compact().unwrap();
+        assert_eq!((report.pieces_before, report.pieces_after), (3, 2));
+        assert_eq!(table.text(), "bba\naxyz");
+        assert_eq!(table.added(), "bba\na");
+        assert_eq!(table.line_to_offset(1), Ok(4));
+    }
+
+    #[test]
+    fn test_compact_keeps_mapped_original() {
+        let path = std::env::temp_dir().join(format!("kaudocore-{}-compact.txt", std::process::id()));
+        std::fs::File::create(&path).unwrap().write_all("mapped line\n".repeat(1000).as_bytes()).unwrap();
+        let mut table = PieceTable::open(&path).unwrap();
+        for idx in 0..50 {
+            table.insert(idx * 13, "+").unwrap();
+        }
+        table.delete(0..300).unwrap();
+        let text = table.text();
+
+        assert_eq!(table.fragmentation().buffer_bytes, 50);
+        table.compact().unwrap();
+        assert!(table.original_buffer().is_mapped());
+        assert_eq!(table.fragmentation().buffer_bytes, table.fragmentation().live_bytes);
+        assert_eq!(table.text(), text);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_compact_keeps_snapshots() {
+        let mut table = fragmented();
+        let snapshot = table.clone();
+        table.compact().unwrap();
+        table.delete(0..10).unwrap();
+        assert_eq!(snapshot.text(), fragmented().text());
+        assert_eq!(snapshot.piece_count(), fragmented().piece_count());
+    }
+
+    #[test]
+    fn test_compact_if_needed() {
+        let policy = CompactionPolicy { min_buffer_bytes: 0, max_waste_ratio: 0.25, ..CompactionPolicy::default() };
+        let mut table = PieceTable::from_text(&"untouched text".repeat(4));
+        assert_eq!(table.compact_if_needed(&policy), Ok(None));
+
+        let mut table = fragmented();
+        assert!(table.compact_if_needed(&CompactionPolicy::default()).unwrap().is_none());
+        assert!(table.compact_if_needed(&policy).unwrap().is_some());
+        assert_eq!(table.compact_if_needed(&policy), Ok(None));
+    }
+}
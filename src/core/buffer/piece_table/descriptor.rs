@@ -3,10 +3,14 @@
 use std::ops::Range;
 use std::fmt::{self, Formatter};
 use std::cmp::Ordering;
-use std::hash::{Hash, Hasher};
+use std::hash::Hash;
 use std::sync::Arc;
 use crate::core::buffer::piece_table::piece::Piece;
 
+/// A piece along with the offset at which it starts in the text.
+///
+/// Descriptors are views handed out by `Buffer::descriptor` & `Buffer::descriptors_from`, which
+/// derive `global_start` from the piece tree; they are not stored, so edits never shift them.
 #[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub struct PieceDescriptor {
     pub piece: Arc<Piece>,
@@ -0,0 +1,16 @@
+//! Piece table module
+//! Reexports add_buffer, buffer, compaction, descriptor, operations, original, piece, and tree modules
+
+pub mod add_buffer;
+pub mod buffer;
+pub mod compaction;
+pub mod descriptor;
+pub mod operations;
+pub mod original;
+pub mod piece;
+pub mod tree;
+
+pub use add_buffer::AddBuffer;
+pub use compaction::{CompactionPolicy, CompactionReport, Fragmentation};
+pub use operations::{PieceTable, PieceTableError};
+pub use original::{MappedText, OriginalBuffer};
@@ -0,0 +1,592 @@
+/// operations.rs
+/// Defines the `PieceTable` text buffer and its editing operations.
+/// The text is described by a sequence of pieces pointing either into the immutable original
+/// buffer or into the append-only add buffer; edits only ever rewrite the piece sequence.
+use std::borrow::Cow;
+use std::error::Error;
+use std::fmt;
+use std::ops::Range;
+use std::sync::Arc;
+use crate::core::buffer::piece_table::add_buffer::AddBuffer;
+use crate::core::buffer::piece_table::buffer::Buffer;
+use crate::core::buffer::piece_table::descriptor::PieceDescriptor;
+use crate::core::buffer::piece_table::original::OriginalBuffer;
+use crate::core::buffer::piece_table::piece::Piece;
+use crate::core::buffer::storage::backend::StorageError;
+
+/// `Piece::buffer_id` of pieces pointing into the original text.
+pub const ORIGINAL_BUFFER: usize = 0;
+
+/// `Piece::buffer_id` of pieces pointing into the first block of the add buffer. Pieces in
+/// block `n` have `ADD_BUFFER + n`, so pieces from different blocks never merge.
+pub const ADD_BUFFER: usize = 1;
+
+/// Panic message of the infallible reads, for a page the page store fails to load.
+pub(crate) const PAGE_LOAD_FAILED: &str = "page store failed to load a page";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PieceTableError {
+    OutOfBounds { index: usize, len: usize },
+    InvalidRange(Range<usize>),
+    NotCharBoundary(usize),
+    Storage(StorageError),
+}
+
+impl fmt::Display for PieceTableError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PieceTableError::OutOfBounds { index, len } => write!(f, "Index {} out of bounds for piece table of length {}", index, len),
+            PieceTableError::InvalidRange(range) => write!(f, "Invalid range: {:?}", range),
+            PieceTableError::NotCharBoundary(index) => write!(f, "Index {} is not a char boundary", index),
+            PieceTableError::Storage(error) => write!(f, "Storage error: {}", error),
+        }
+    }
+}
+
+impl Error for PieceTableError {}
+
+/// A text buffer stored as a piece table.
+///
+/// Inserted text is appended to the add buffer and referenced by a new piece, so typing at the
+/// same place keeps growing a single piece. Deletions only shrink or drop pieces. Pieces live in
+/// a red-black tree and both buffers index their line feeds, so edits and offset & line lookups
+/// are O(log n) in the number of pieces.
+///
+/// The original buffer may be a memory map of the opened file (see `PieceTable::open`), in
+/// which case only the regions that are viewed or edited are ever read: pieces of a mapped
+/// original get their line feeds counted by the first line lookup that reaches them. Such a
+/// table is saved with `write_to`, which writes its pieces back as the bytes of the file.
+///
+/// Both buffers may also keep their text in a shared `PageStore` (see `PieceTable::paged`), so
+/// that a buffer larger than the memory budget of the store can be edited. Operations that read
+/// or store a page the store fails to load or save return `PieceTableError::Storage`; only
+/// `text`, which cannot fail, panics instead.
+///
+/// Both buffers and the piece tree are shared between clones, so a clone taken as a snapshot
+/// is O(1); the next edit copies the piece tree, never the text.
+#[derive(Debug, Clone)]
+pub struct PieceTable {
+    original: OriginalBuffer,
+    added: AddBuffer,
+    pieces: Arc<Buffer>,
+}
+
+impl PieceTable {
+    pub fn new() -> Self {
+        Self::from_text("")
+    }
+
+    pub fn from_text(text: &str) -> Self {
+        Self::from_original(OriginalBuffer::from_text(text))
+    }
+
+    pub fn from_original(original: OriginalBuffer) -> Self {
+        let mut pieces = Buffer::new();
+        let mut added = AddBuffer::new();
+        match &original {
+            // One piece per page, so that each piece reads a single page.
+            OriginalBuffer::Paged(paged) => {
+                for (range, line_feeds) in paged.pages() {
+                    pieces.add_piece(Piece::new(ORIGINAL_BUFFER, range), Some(line_feeds));
+                }
+                added = AddBuffer::paged(paged.store());
+            }
+            _ if original.is_empty() => {}
+            _ => {
+                let piece = Piece::new(ORIGINAL_BUFFER, 0..original.len());
+                let line_feeds = (!original.is_mapped()).then(|| original.line_feeds());
+                pieces.add_piece(piece, line_feeds);
+            }
+        }
+        Self {
+            original,
+            added,
+            pieces: Arc::new(pieces),
+        }
+    }
+
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.pieces.len()
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.pieces.is_empty()
+    }
+
+    pub fn original(&self) -> Result<Cow<'_, str>, PieceTableError> {
+        self.original.text(0..self.original.len()).map_err(PieceTableError::Storage)
+    }
+
+    pub fn original_buffer(&self) -> &OriginalBuffer {
+        &self.original
+    }
+
+    #[inline]
+    pub fn added(&self) -> &AddBuffer {
+        &self.added
+    }
+
+    pub fn pieces(&self) -> &Buffer {
+        &self.pieces
+    }
+
+    /// Swaps in a rewritten add buffer along with the pieces pointing into it.
+    pub(crate) fn set_pieces(&mut self, added: AddBuffer, pieces: Buffer) {
+        self.added = added;
+        self.pieces = Arc::new(pieces);
+    }
+
+    pub fn piece_count(&self) -> usize {
+        self.pieces.pieces_count()
+    }
+
+    /// Iterates over the pieces along with their position in the text.
+    pub fn descriptors(&self) -> impl Iterator<Item = PieceDescriptor> + '_ {
+        self.pieces.descriptors_from(0)
+    }
+
+    /// Returns the text referenced by `piece`.
+    pub fn piece_text(&self, piece: &Piece) -> Result<Cow<'_, str>, PieceTableError> {
+        self.buffer_text(piece.buffer_id, piece.range.clone())
+    }
+
+    /// Whether `offset` is a char boundary. An offset whose page the store fails to load is not.
+    pub fn is_char_boundary(&self, offset: usize) -> bool {
+        self.char_boundary(offset).unwrap_or(false)
+    }
+
+    fn char_boundary(&self, offset: usize) -> Result<bool, PieceTableError> {
+        if offset > self.len() {
+            return Ok(false);
+        }
+        let position = self.pieces.find_offset(offset);
+        let Some(piece) = self.pieces.get_piece(position.index) else {
+            return Ok(true);
+        };
+        let local = piece.start() + offset - position.start;
+        let is_boundary = match piece.buffer_id {
+            ORIGINAL_BUFFER => self.original.is_char_boundary(local),
+            id => self.added.is_char_boundary(id - ADD_BUFFER, local),
+        };
+        is_boundary.map_err(PieceTableError::Storage)
+    }
+
+    pub fn insert(&mut self, offset: usize, text: &str) -> Result<(), PieceTableError> {
+        let len = self.len();
+        if offset > len {
+            return Err(PieceTableError::OutOfBounds { index: offset, len });
+        }
+        if !self.char_boundary(offset)? {
+            return Err(PieceTableError::NotCharBoundary(offset));
+        }
+
+        // A paged add buffer may take the text over several blocks, each getting its own piece.
+        let (mut offset, mut rest) = (offset, text);
+        while !rest.is_empty() {
+            let (block, range) = self.added.push(rest).map_err(PieceTableError::Storage)?;
+            rest = &rest[range.len()..];
+            let len = range.len();
+            self.insert_piece(offset, Piece::new(ADD_BUFFER + block, range))?;
+            offset += len;
+        }
+        Ok(())
+    }
+
+    /// Inserts `piece` at `offset`, splitting the piece found there.
+    fn insert_piece(&mut self, offset: usize, piece: Piece) -> Result<(), PieceTableError> {
+        let position = self.pieces.find_offset(offset);
+        let index = position.index;
+        if offset > position.start {
+            let current = self.pieces.get_piece(index).expect("offset inside a piece").clone();
+            let (left, right) = current.split_at(offset - position.start);
+            let parts = [self.entry(left)?, self.entry(piece)?, self.entry(right)?];
+            Arc::make_mut(&mut self.pieces).replace_pieces(index..index + 1, parts);
+            return Ok(());
+        }
+
+        let entry = self.entry(piece)?;
+        Arc::make_mut(&mut self.pieces).replace_pieces(index..index, [entry]);
+        self.merge_adjacent(index);
+        Ok(())
+    }
+
+    pub fn delete(&mut self, range: Range<usize>) -> Result<(), PieceTableError> {
+        self.check_range(&range)?;
+        if range.is_empty() {
+            return Ok(());
+        }
+
+        let first = self.pieces.find_offset(range.start);
+        let mut kept = Vec::with_capacity(2);
+        let mut last = first.index;
+        for (index, descriptor) in self.pieces.descriptors_from(first.index).enumerate() {
+            if descriptor.global_start >= range.end {
+                break;
+            }
+            let (left, _, right) = descriptor.split_range(range.clone())
+                .expect("descriptor intersects the deleted range");
+            for part in left.into_iter().chain(right) {
+                kept.push(self.entry((*part.piece).clone())?);
+            }
+            last = first.index + index;
+        }
+
+        // The pieces around the deleted range may now continue one another.
+        Arc::make_mut(&mut self.pieces).replace_pieces(first.index..last + 1, kept);
+        self.merge_adjacent(first.index + usize::from(range.start > first.start));
+        Ok(())
+    }
+
+    pub fn slice(&self, range: Range<usize>) -> Result<String, PieceTableError> {
+        self.check_range(&range)?;
+        let mut result = String::with_capacity(range.len());
+        if range.is_empty() {
+            return Ok(result);
+        }
+        let first = self.pieces.find_offset(range.start);
+        for descriptor in self.pieces.descriptors_from(first.index) {
+            if descriptor.global_start >= range.end {
+                break;
+            }
+            let piece = &descriptor.piece;
+            let start = range.start.saturating_sub(descriptor.global_start);
+            let end = (range.end - descriptor.global_start).min(piece.len());
+            result.push_str(&self.buffer_text(piece.buffer_id, piece.start() + start..piece.start() + end)?);
+        }
+        Ok(result)
+    }
+
+    /// Returns the whole text. Panics if the page store fails to load a page; `slice` returns
+    /// the error instead.
+    pub fn text(&self) -> String {
+        let mut result = String::with_capacity(self.len());
+        for piece in self.pieces.iter_pieces() {
+            result.push_str(&self.piece_text(piece).expect(PAGE_LOAD_FAILED));
+        }
+        result
+    }
+
+    /// Number of lines, lines being separated by `\n`. Counts the line feeds of a mapped
+    /// original that were not counted yet, reading the whole file the first time.
+    pub fn line_count(&self) -> usize {
+        self.pieces.line_feeds(&|piece| self.count_line_feeds(piece)) + 1
+    }
+
+    /// Byte offset at which `line` starts.
+    pub fn line_to_offset(&self, line: usize) -> Result<usize, PieceTableError> {
+        if line == 0 {
+            return Ok(0);
+        }
+
+        let count = |piece: &Piece| self.count_line_feeds(piece);
+        let position = self.pieces.find_line_feed(line - 1, &count);
+        let Some(piece) = self.pieces.get_piece(position.index) else {
+            return Err(PieceTableError::OutOfBounds { index: line, len: self.line_count() });
+        };
+        let n = line - 1 - self.pieces.line_feeds_before(position.index, &count);
+        let idx = match piece.buffer_id {
+            ORIGINAL_BUFFER => self.original.nth_line_feed_from(piece.start(), n),
+            id => self.added.nth_line_feed_from(id - ADD_BUFFER, piece.start(), n),
+        };
+        let idx = idx.map_err(PieceTableError::Storage)?.expect("cached line feeds match the piece text");
+        Ok(position.start + idx - piece.start() + 1)
+    }
+
+    /// Index of the line containing `offset`.
+    pub fn offset_to_line(&self, offset: usize) -> Result<usize, PieceTableError> {
+        let len = self.len();
+        if offset > len {
+            return Err(PieceTableError::OutOfBounds { index: offset, len });
+        }
+        let position = self.pieces.find_offset(offset);
+        let local = match self.pieces.get_piece(position.index) {
+            Some(piece) => self.line_feeds_in(piece.buffer_id, piece.start()..piece.start() + offset - position.start)?,
+            None => 0,
+        };
+        Ok(self.pieces.line_feeds_before(position.index, &|piece| self.count_line_feeds(piece)) + local)
+    }
+
+    /// Returns the text of `line` without its line ending.
+    pub fn line(&self, line: usize) -> Result<String, PieceTableError> {
+        let start = self.line_to_offset(line)?;
+        let end = self.line_to_offset(line + 1).unwrap_or(self.len());
+        let text = self.slice(start..end)?;
+        let content = text.strip_suffix('\n').map(|rest| rest.strip_suffix('\r').unwrap_or(rest));
+        Ok(content.unwrap_or(&text).to_string())
+    }
+
+    /// Returns the text of `range` within a buffer.
+    fn buffer_text(&self, buffer_id: usize, range: Range<usize>) -> Result<Cow<'_, str>, PieceTableError> {
+        let text = match buffer_id {
+            ORIGINAL_BUFFER => self.original.text(range),
+            id => self.added.text(id - ADD_BUFFER, range),
+        };
+        text.map_err(PieceTableError::Storage)
+    }
+
+    /// Counts the line feeds within `range` of a buffer, in O(log n) unless the original is
+    /// mapped & its blocks in `range` were never counted.
+    fn line_feeds_in(&self, buffer_id: usize, range: Range<usize>) -> Result<usize, PieceTableError> {
+        let line_feeds = match buffer_id {
+            ORIGINAL_BUFFER => self.original.line_feeds_in(range),
+            id => self.added.line_feeds_in(id - ADD_BUFFER, range),
+        };
+        line_feeds.map_err(PieceTableError::Storage)
+    }
+
+    /// Counts the line feeds of a piece the tree has no count for. Only pieces of a mapped
+    /// original are left uncounted, & reading those never fails.
+    fn count_line_feeds(&self, piece: &Piece) -> usize {
+        self.line_feeds_in(piece.buffer_id, piece.range.clone()).expect("mapped pieces are read without a page store")
+    }
+
+    /// Pairs `piece` with its line feed count, as stored in the piece tree. Pieces of a mapped
+    /// original are left to be counted by the first line lookup that needs them.
+    fn entry(&self, piece: Piece) -> Result<(Piece, Option<usize>), PieceTableError> {
+        if piece.buffer_id == ORIGINAL_BUFFER && self.original.is_mapped() {
+            return Ok((piece, None));
+        }
+        let line_feeds = self.line_feeds_in(piece.buffer_id, piece.range.clone())?;
+        Ok((piece, Some(line_feeds)))
+    }
+
+    /// Merges the pieces at `index - 1` & `index` when the second continues the first. Original
+    /// pieces are not merged across pages, so each keeps reading a single page.
+    fn merge_adjacent(&mut self, index: usize) {
+        if index == 0 || index >= self.pieces.pieces_count() {
+            return;
+        }
+        let (prev, prev_line_feeds) = self.pieces.get_entry(index - 1).expect("index checked above");
+        let (next, next_line_feeds) = self.pieces.get_entry(index).expect("index checked above");
+        if prev.can_merge(next) && prev.end() == next.start() && !(prev.buffer_id == ORIGINAL_BUFFER && self.original.is_page_start(next.start())) {
+            let merged = prev.merge(next).expect("mergeable pieces");
+            let line_feeds = prev_line_feeds.zip(next_line_feeds).map(|(prev, next)| prev + next);
+            Arc::make_mut(&mut self.pieces).replace_pieces(index - 1..index + 1, [(merged, line_feeds)]);
+        }
+    }
+
+    fn check_range(&self, range: &Range<usize>) -> Result<(), PieceTableError> {
+        let len = self.len();
+        if range.start > range.end {
+            return Err(PieceTableError::InvalidRange(range.clone()));
+        }
+        if range.end > len {
+            return Err(PieceTableError::OutOfBounds { index: range.end, len });
+        }
+        if !self.char_boundary(range.start)? {
+            return Err(PieceTableError::NotCharBoundary(range.start));
+        }
+        if !self.char_boundary(range.end)? {
+            return Err(PieceTableError::NotCharBoundary(range.end));
+        }
+        Ok(())
+    }
+
+    #[cfg(test)]
+    fn assert_invariants(&self) {
+        self.pieces.assert_invariants();
+        for (piece, line_feeds) in (0..self.piece_count()).filter_map(|index| self.pieces.get_entry(index)) {
+            assert!(!piece.is_empty());
+            if let Some(line_feeds) = line_feeds {
+                assert_eq!(line_feeds, self.piece_text(piece).unwrap().matches('\n').count());
+            }
+        }
+    }
+}
+
+impl Default for PieceTable {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Display for PieceTable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for piece in self.pieces.iter_pieces() {
+            f.write_str(&self.piece_text(piece).map_err(|_| fmt::Error)?)?;
+        }
+        Ok(())
+    }
+}
+
+impl From<&str> for PieceTable {
+    fn from(s: &str) -> Self {
+        PieceTable::from_text(s)
+    }
+}
+
+impl From<String> for PieceTable {
+    fn from(s: String) -> Self {
+        PieceTable::from_text(&s)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    #[test]
+    fn test_empty_table() {
+        let table = PieceTable::new();
+        assert!(table.is_empty());
+        assert_eq!(table.piece_count(), 0);
+        assert_eq!(table.text(), "");
+        assert_eq!(table.line_count(), 1);
+    }
+
+    #[test]
+    fn test_insert() {
+        let mut table = PieceTable::from("Hello world");
+        table.insert(5, ",").unwrap();
+        table.insert(0, ">> ").unwrap();
+        table.insert(table.len(), "!").unwrap();
+        assert_eq!(table.text(), ">> Hello, world!");
+        assert_eq!(table.original().unwrap(), "Hello world");
+        assert_eq!(table.added(), ",>> !");
+    }
+
+    #[test]
+    fn test_snapshot_shares_buffers() {
+        let mut table = PieceTable::from("Hello world");
+        table.insert(5, ",").unwrap();
+        let snapshot = table.clone();
+        table.insert(6, " dear").unwrap();
+        table.delete(0..1).unwrap();
+        assert_eq!(snapshot.text(), "Hello, world");
+        assert_eq!(table.text(), "ello, dear world");
+        assert_eq!(snapshot.added().block_count(), 1);
+        assert_eq!(table.added().block_count(), 2);
+        assert_eq!(table.added(), ", dear");
+        table.assert_invariants();
+    }
+
+    #[test]
+    fn test_typing_merges_pieces() {
+        let mut table = PieceTable::from("ab");
+        for (i, ch) in "hello".chars().enumerate() {
+            table.insert(1 + i, &ch.to_string()).unwrap();
+        }
+        assert_eq!(table.text(), "ahellob");
+        assert_eq!(table.piece_count(), 3);
+    }
+
+    #[test]
+    fn test_delete() {
+        let mut table = PieceTable::from("Hello, beautiful world");
+        table.delete(5..16).unwrap();
+        assert_eq!(table.text(), "Hello world");
+        table.delete(0..table.len()).unwrap();
+        assert!(table.is_empty());
+        assert_eq!(table.piece_count(), 0);
+    }
+
+    #[test]
+    fn test_delete_rejoins_split_piece() {
+        let mut table = PieceTable::from("abcdef");
+        table.insert(3, "XYZ").unwrap();
+        assert_eq!(table.piece_count(), 3);
+        table.delete(3..6).unwrap();
+        assert_eq!(table.text(), "abcdef");
+        assert_eq!(table.piece_count(), 1);
+    }
+
+    #[test]
+    fn test_errors() {
+        let mut table = PieceTable::from("é");
+        assert_eq!(table.insert(1, "x"), Err(PieceTableError::NotCharBoundary(1)));
+        assert_eq!(table.insert(3, "x"), Err(PieceTableError::OutOfBounds { index: 3, len: 2 }));
+        assert_eq!(table.delete(0..1), Err(PieceTableError::NotCharBoundary(1)));
+        assert!(table.slice(0..3).is_err());
+    }
+
+    #[test]
+    fn test_slice_across_pieces() {
+        let mut table = PieceTable::from("0123456789");
+        table.insert(5, "abc").unwrap();
+        assert_eq!(table.slice(3..10).unwrap(), "34abc56");
+        assert_eq!(table.slice(5..8).unwrap(), "abc");
+        assert_eq!(table.slice(13..13).unwrap(), "");
+    }
+
+    #[test]
+    fn test_line_lookup() {
+        let mut table = PieceTable::from("one\ntwo\r\nthree");
+        table.insert(4, "inserted\n").unwrap();
+        assert_eq!(table.line_count(), 4);
+        assert_eq!(table.line_to_offset(1), Ok(4));
+        assert_eq!(table.line_to_offset(2), Ok(13));
+        assert!(table.line_to_offset(4).is_err());
+        assert_eq!(table.offset_to_line(12), Ok(1));
+        assert_eq!(table.offset_to_line(13), Ok(2));
+        assert_eq!(table.line(1).unwrap(), "inserted");
+        assert_eq!(table.line(2).unwrap(), "two");
+        assert_eq!(table.line(3).unwrap(), "three");
+    }
+
+    #[test]
+    fn test_many_pieces_stay_balanced() {
+        let mut table = PieceTable::from("x\n".repeat(1000).as_str());
+        for i in 0..2000 {
+            table.insert((i * 7) % table.len(), if i % 2 == 0 { "ab" } else { "\n" }).unwrap();
+        }
+        table.assert_invariants();
+        assert!(table.piece_count() > 1000);
+        let text = table.text();
+        assert_eq!(table.line_count(), text.matches('\n').count() + 1);
+        let (idx, _) = text.match_indices('\n').nth(1500).unwrap();
+        assert_eq!(table.line_to_offset(1501), Ok(idx + 1));
+        assert_eq!(table.offset_to_line(idx + 1), Ok(1501));
+    }
+
+    #[derive(Debug, Clone)]
+    enum Edit {
+        Insert(usize, String),
+        Delete(usize, usize),
+    }
+
+    fn floor_boundary(text: &str, idx: usize) -> usize {
+        (0..=idx.min(text.len())).rev().find(|&i| text.is_char_boundary(i)).unwrap_or(0)
+    }
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(64))]
+
+        #[test]
+        fn prop_edits_match_string_model(
+            initial in "[a-zé\\n]{0,200}",
+            edits in prop::collection::vec(prop_oneof![
+                (any::<usize>(), "[a-z🌍\\n]{0,20}").prop_map(|(at, text)| Edit::Insert(at, text)),
+                (any::<usize>(), 0usize..50).prop_map(|(at, len)| Edit::Delete(at, len)),
+            ], 1..60),
+        ) {
+            let mut table = PieceTable::from(initial.as_str());
+            let mut model = initial;
+            for edit in edits {
+                match edit {
+                    Edit::Insert(at, text) => {
+                        let at = floor_boundary(&model, at % (model.len() + 1));
+                        table.insert(at, &text).unwrap();
+                        model.insert_str(at, &text);
+                    }
+                    Edit::Delete(at, len) => {
+                        let start = floor_boundary(&model, at % (model.len() + 1));
+                        let end = floor_boundary(&model, start + len);
+                        table.delete(start..end).unwrap();
+                        model.replace_range(start..end, "");
+                    }
+                }
+                prop_assert_eq!(table.len(), model.len());
+                table.assert_invariants();
+            }
+            prop_assert_eq!(table.text(), model.clone());
+            prop_assert_eq!(table.line_count(), model.matches('\n').count() + 1);
+            for (line, (idx, _)) in model.match_indices('\n').enumerate() {
+                prop_assert_eq!(table.line_to_offset(line + 1), Ok(idx + 1));
+                prop_assert_eq!(table.offset_to_line(idx + 1), Ok(line + 1));
+            }
+        }
+    }
+}
@@ -0,0 +1,669 @@
+/// original.rs
+/// Defines `OriginalBuffer`, the immutable text a `PieceTable` starts from.
+/// The text is either held in memory, read from a read-only memory map of a file, which is
+/// validated & indexed lazily, one block at a time, or kept in the pages of a `PageStore`.
+use std::borrow::Cow;
+use std::fmt;
+use std::fs::File;
+use std::io::{Read, Write};
+use std::ops::Range;
+use std::path::Path;
+use std::sync::{Arc, OnceLock};
+use memmap2::Mmap;
+use crate::core::buffer::content::encoding::{Encoding, EncodingHandler};
+use crate::core::buffer::content::streaming::StreamingError;
+use crate::core::buffer::piece_table::operations::{PieceTable, ORIGINAL_BUFFER};
+use crate::core::buffer::storage::backend::StorageError;
+use crate::core::buffer::storage::paging::{PagedText, SharedPageStore};
+
+/// Size of the blocks a `MappedText` validates & indexes together.
+pub const BLOCK_SIZE: usize = 1 << 16;
+
+/// What a mapped block that isn't valid UTF-8 reads as in place of each byte that doesn't decode.
+/// It takes one byte like the byte it stands for, so offsets into the map stay valid, and
+/// `PieceTable::write_to` writes the byte it stands for back.
+pub const SUBSTITUTE: char = '\u{1A}';
+
+/// Outcome of checking a block: its line feeds and, when it isn't UTF-8, its repaired text.
+#[derive(Debug)]
+struct BlockCheck {
+    line_feeds: usize,
+    repaired: Option<Box<str>>,
+    substituted: usize,
+}
+
+/// UTF-8 text read from a read-only memory map.
+///
+/// Mapping only checks the first block. Every other block is validated & has its line feeds
+/// counted the first time it is accessed, and the positions of its line feeds are collected the
+/// first time a lookup needs them. Only the total line count reads the whole file.
+///
+/// A block is validated from its first char boundary to the first one after its end, so chars
+/// straddling blocks belong to the block they start in. A block found not to be UTF-8 past the
+/// first one reads with `SUBSTITUTE` in place of each byte that doesn't decode; `bytes` still
+/// gives the bytes of the file, which is what saving writes.
+pub struct MappedText {
+    map: Mmap,
+    checks: Box<[OnceLock<BlockCheck>]>,
+    blocks: Box<[OnceLock<Box<[u16]>>]>,
+}
+
+impl MappedText {
+    /// Maps `file`, returning `None` when its content starts with a BOM or its first block is not
+    /// UTF-8.
+    pub fn map(file: &File) -> std::io::Result<Option<Self>> {
+        // SAFETY: the map is read-only. As with any file map, the file must not be truncated
+        // or rewritten by another process while the buffer is open.
+        let map = unsafe { Mmap::map(file)? };
+        if map.starts_with(&[0xEF, 0xBB, 0xBF]) {
+            return Ok(None);
+        }
+
+        let count = map.len().div_ceil(BLOCK_SIZE);
+        let mapped = Self {
+            map,
+            checks: (0..count).map(|_| OnceLock::new()).collect(),
+            blocks: (0..count).map(|_| OnceLock::new()).collect(),
+        };
+        if count > 0 && mapped.check(0).repaired.is_some() {
+            return Ok(None);
+        }
+        Ok(Some(mapped))
+    }
+
+    /// Text of `range`, which must start & end on char boundaries. Borrowed from the map unless
+    /// `range` overlaps a block that isn't UTF-8.
+    pub fn text(&self, range: Range<usize>) -> Cow<'_, str> {
+        if range.is_empty() {
+            return Cow::Borrowed("");
+        }
+        let blocks = self.checked_block(range.start)..self.checked_block(range.end - 1) + 1;
+        if blocks.clone().all(|block| self.check(block).repaired.is_none()) {
+            assert!(self.is_char_boundary(range.start) && self.is_char_boundary(range.end), "{:?} splits a char", range);
+            // SAFETY: every block the range overlaps was validated as UTF-8, and the range
+            // starts & ends on char boundaries.
+            return Cow::Borrowed(unsafe { std::str::from_utf8_unchecked(&self.map[range]) });
+        }
+
+        let mut text = String::with_capacity(range.len());
+        for block in blocks {
+            let checked = self.checked_range(block);
+            let part = range.start.max(checked.start)..range.end.min(checked.end);
+            match &self.check(block).repaired {
+                Some(repaired) => text.push_str(&repaired[part.start - checked.start..part.end - checked.start]),
+                // SAFETY: the block was validated as UTF-8 and `part` starts & ends either on
+                // the boundaries of `range` or of the block.
+                None => text.push_str(unsafe { std::str::from_utf8_unchecked(&self.map[part]) }),
+            }
+        }
+        Cow::Owned(text)
+    }
+
+    /// Bytes of the file in `range`, as they are on disk.
+    pub fn bytes(&self, range: Range<usize>) -> &[u8] {
+        &self.map[range]
+    }
+
+    /// Whether `offset` falls between two chars, checking only the block it is in.
+    pub fn is_char_boundary(&self, offset: usize) -> bool {
+        if offset >= self.len() {
+            return offset == self.len();
+        }
+        let block = self.checked_block(offset);
+        match &self.check(block).repaired {
+            Some(repaired) => repaired.is_char_boundary(offset - self.checked_range(block).start),
+            None => !is_continuation(self.map[offset]),
+        }
+    }
+
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    /// Number of blocks validated so far.
+    pub fn checked_blocks(&self) -> usize {
+        self.checks.iter().filter(|check| check.get().is_some()).count()
+    }
+
+    /// Number of blocks whose line feed positions have been collected so far.
+    pub fn indexed_blocks(&self) -> usize {
+        self.blocks.iter().filter(|block| block.get().is_some()).count()
+    }
+
+    /// Number of bytes read as `SUBSTITUTE` in the blocks validated so far.
+    pub fn substituted_bytes(&self) -> usize {
+        self.checks.iter().filter_map(OnceLock::get).map(|check| check.substituted).sum()
+    }
+
+    /// Total number of line feeds. Reads every block not counted yet.
+    pub fn line_feeds(&self) -> usize {
+        (0..self.checks.len()).map(|block| self.check(block).line_feeds).sum()
+    }
+
+    /// Counts the line feeds within `range`, reading only the blocks it overlaps.
+    pub fn line_feeds_in(&self, range: Range<usize>) -> usize {
+        if range.is_empty() {
+            return 0;
+        }
+        let (first, last) = (range.start / BLOCK_SIZE, (range.end - 1) / BLOCK_SIZE);
+        (first..=last)
+            .map(|block| {
+                let start = block * BLOCK_SIZE;
+                self.line_feeds_in_block(block, range.start.max(start) - start..range.end.min(start + BLOCK_SIZE) - start)
+            })
+            .sum()
+    }
+
+    /// Offset of the `n`th line feed at or after `start`, reading blocks up to the one holding it.
+    pub fn nth_line_feed_from(&self, start: usize, mut n: usize) -> Option<usize> {
+        let mut block = start / BLOCK_SIZE;
+        if block >= self.blocks.len() {
+            return None;
+        }
+        let positions = self.block(block);
+        let skipped = positions.partition_point(|&idx| (idx as usize) < start - block * BLOCK_SIZE);
+        if let Some(&idx) = positions.get(skipped + n) {
+            return Some(block * BLOCK_SIZE + idx as usize);
+        }
+        n -= positions.len() - skipped;
+        loop {
+            block += 1;
+            if block >= self.checks.len() {
+                return None;
+            }
+            let line_feeds = self.check(block).line_feeds;
+            if n < line_feeds {
+                return Some(block * BLOCK_SIZE + self.block(block)[n] as usize);
+            }
+            n -= line_feeds;
+        }
+    }
+
+    /// Validates `block` and counts its line feeds, the first time it is accessed.
+    fn check(&self, block: usize) -> &BlockCheck {
+        self.checks[block].get_or_init(|| {
+            let bytes = &self.map[self.checked_range(block)];
+            // Bytes moved between neighbouring blocks are continuation bytes, so the count is
+            // that of the block's own bytes.
+            let line_feeds = bytes.iter().filter(|&&byte| byte == b'\n').count();
+            if std::str::from_utf8(bytes).is_ok() {
+                return BlockCheck { line_feeds, repaired: None, substituted: 0 };
+            }
+            let mut repaired = String::with_capacity(bytes.len());
+            let mut substituted = 0;
+            for chunk in bytes.utf8_chunks() {
+                repaired.push_str(chunk.valid());
+                repaired.extend(std::iter::repeat_n(SUBSTITUTE, chunk.invalid().len()));
+                substituted += chunk.invalid().len();
+            }
+            BlockCheck { line_feeds, repaired: Some(repaired.into()), substituted }
+        })
+    }
+
+    /// Bytes validated as `block`: from the first char boundary of the block to the first one
+    /// after it.
+    fn checked_range(&self, block: usize) -> Range<usize> {
+        let start = block * BLOCK_SIZE;
+        self.next_boundary(start)..self.next_boundary((start + BLOCK_SIZE).min(self.len()))
+    }
+
+    /// The block whose checked range holds `offset`.
+    fn checked_block(&self, offset: usize) -> usize {
+        let block = offset / BLOCK_SIZE;
+        if block > 0 && offset < self.next_boundary(block * BLOCK_SIZE) {
+            return block - 1;
+        }
+        block
+    }
+
+    /// Skips the continuation bytes, at most three, starting at `offset`.
+    fn next_boundary(&self, offset: usize) -> usize {
+        if offset == 0 {
+            return 0;
+        }
+        let end = (offset + 3).min(self.len());
+        offset + self.map[offset..end].iter().take_while(|&&byte| is_continuation(byte)).count()
+    }
+
+    /// Counts the line feeds within `range` of `block`, relative to its start.
+    fn line_feeds_in_block(&self, block: usize, range: Range<usize>) -> usize {
+        if range.start == 0 && block * BLOCK_SIZE + range.end >= (block * BLOCK_SIZE + BLOCK_SIZE).min(self.len()) {
+            return self.check(block).line_feeds;
+        }
+        let positions = self.block(block);
+        positions.partition_point(|&idx| (idx as usize) < range.end) - positions.partition_point(|&idx| (idx as usize) < range.start)
+    }
+
+    /// Offsets of the line feeds of `block`, relative to its start.
+    fn block(&self, block: usize) -> &[u16] {
+        self.blocks[block].get_or_init(|| {
+            let start = block * BLOCK_SIZE;
+            let end = (start + BLOCK_SIZE).min(self.len());
+            let bytes = &self.map[start..end];
+            bytes.iter().enumerate().filter(|&(_, &byte)| byte == b'\n').map(|(idx, _)| idx as u16).collect()
+        })
+    }
+}
+
+impl fmt::Debug for MappedText {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MappedText")
+            .field("len", &self.len())
+            .field("checked_blocks", &self.checked_blocks())
+            .field("indexed_blocks", &self.indexed_blocks())
+            .finish()
+    }
+}
+
+#[inline]
+fn is_continuation(byte: u8) -> bool {
+    byte & 0xC0 == 0x80
+}
+
+/// The immutable buffer referenced by `ORIGINAL_BUFFER` pieces. Cloning it is O(1).
+#[derive(Debug, Clone)]
+pub enum OriginalBuffer {
+    Text { text: Arc<str>, line_feeds: Arc<[usize]> },
+    Mapped(Arc<MappedText>),
+    Paged(Arc<PagedText>),
+}
+
+impl OriginalBuffer {
+    pub fn from_text(text: &str) -> Self {
+        OriginalBuffer::Text {
+            text: Arc::from(text),
+            line_feeds: line_feed_positions(text, 0).collect(),
+        }
+    }
+
+    /// Maps the file at `path`, or reads & decodes it when it is not UTF-8 or cannot be mapped.
+    /// Bytes that don't decode are kept as raw-byte markers, so a stray byte doesn't keep the
+    /// file from opening & saving losslessly writes it back.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, StreamingError> {
+        let mut file = File::open(path)?;
+        if let Ok(Some(mapped)) = MappedText::map(&file) {
+            return Ok(OriginalBuffer::Mapped(Arc::new(mapped)));
+        }
+
+        let mut bytes = Vec::new();
+        file.read_to_end(&mut bytes)?;
+        let (encoding, with_bom) = EncodingHandler::detect_encoding(&bytes)?;
+        let mut handler = EncodingHandler::new(encoding);
+        handler.set_bom(with_bom);
+        Ok(OriginalBuffer::from_text(&handler.decode_lossless(&bytes).text))
+    }
+
+    /// Text of `range`, which must start & end on char boundaries. Only a paged original can
+    /// fail to read it, when its store fails to load a page.
+    pub fn text(&self, range: Range<usize>) -> Result<Cow<'_, str>, StorageError> {
+        match self {
+            OriginalBuffer::Text { text, .. } => Ok(Cow::Borrowed(&text[range])),
+            OriginalBuffer::Mapped(mapped) => Ok(mapped.text(range)),
+            OriginalBuffer::Paged(paged) => Ok(Cow::Owned(paged.slice(range)?)),
+        }
+    }
+
+    pub fn is_char_boundary(&self, offset: usize) -> Result<bool, StorageError> {
+        match self {
+            OriginalBuffer::Text { text, .. } => Ok(text.is_char_boundary(offset)),
+            OriginalBuffer::Mapped(mapped) => Ok(mapped.is_char_boundary(offset)),
+            OriginalBuffer::Paged(paged) => paged.is_char_boundary(offset),
+        }
+    }
+
+    #[inline]
+    pub fn len(&self) -> usize {
+        match self {
+            OriginalBuffer::Text { text, .. } => text.len(),
+            OriginalBuffer::Mapped(mapped) => mapped.len(),
+            OriginalBuffer::Paged(paged) => paged.len(),
+        }
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    #[inline]
+    pub fn is_mapped(&self) -> bool {
+        matches!(self, OriginalBuffer::Mapped(_))
+    }
+
+    /// Whether a page of a paged original starts at `offset`.
+    pub fn is_page_start(&self, offset: usize) -> bool {
+        match self {
+            OriginalBuffer::Paged(paged) => paged.is_page_start(offset),
+            _ => false,
+        }
+    }
+
+    /// Total number of line feeds.
+    pub fn line_feeds(&self) -> usize {
+        match self {
+            OriginalBuffer::Text { line_feeds, .. } => line_feeds.len(),
+            OriginalBuffer::Mapped(mapped) => mapped.line_feeds(),
+            OriginalBuffer::Paged(paged) => paged.line_feeds(),
+        }
+    }
+
+    /// Counts the line feeds within `range`.
+    pub fn line_feeds_in(&self, range: Range<usize>) -> Result<usize, StorageError> {
+        match self {
+            OriginalBuffer::Text { line_feeds, .. } => Ok(line_feeds_in(line_feeds, range)),
+            OriginalBuffer::Mapped(mapped) => Ok(mapped.line_feeds_in(range)),
+            OriginalBuffer::Paged(paged) => paged.line_feeds_in(range),
+        }
+    }
+
+    /// Offset of the `n`th line feed at or after `start`.
+    pub fn nth_line_feed_from(&self, start: usize, n: usize) -> Result<Option<usize>, StorageError> {
+        match self {
+            OriginalBuffer::Text { line_feeds, .. } => Ok(nth_line_feed_from(line_feeds, start, n)),
+            OriginalBuffer::Mapped(mapped) => Ok(mapped.nth_line_feed_from(start, n)),
+            OriginalBuffer::Paged(paged) => paged.nth_line_feed_from(start, n),
+        }
+    }
+}
+
+impl PieceTable {
+    /// Opens the file at `path` without reading it into memory when it is UTF-8.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, StreamingError> {
+        Ok(PieceTable::from_original(OriginalBuffer::open(path)?))
+    }
+
+    /// Writes the text as UTF-8, raw-byte markers as the bytes they stand for & pieces of a
+    /// mapped original as the bytes of the file. A file opened as UTF-8 is thus saved back byte
+    /// for byte where it wasn't edited, bytes read as `SUBSTITUTE` included.
+    pub fn write_to(&self, mut writer: impl Write) -> std::io::Result<()> {
+        let utf8 = EncodingHandler::new(Encoding::UTF8);
+        for piece in self.pieces().iter_pieces() {
+            if let (ORIGINAL_BUFFER, OriginalBuffer::Mapped(mapped)) = (piece.buffer_id, self.original_buffer()) {
+                writer.write_all(mapped.bytes(piece.range.clone()))?;
+                continue;
+            }
+            let text = self.piece_text(piece).map_err(std::io::Error::other)?;
+            let bytes = utf8.encode_lossless(&text).map_err(std::io::Error::other)?;
+            writer.write_all(&bytes)?;
+        }
+        writer.flush()
+    }
+
+    /// Reads UTF-8 text into pages of `store`, which also takes the inserted text, so that only
+    /// the pages being viewed or edited are kept decoded in memory.
+    pub fn paged(reader: impl Read, store: &SharedPageStore) -> Result<Self, StorageError> {
+        let paged = PagedText::from_reader(reader, store)?;
+        Ok(PieceTable::from_original(OriginalBuffer::Paged(Arc::new(paged))))
+    }
+}
+
+/// Yields the positions of the line feeds of `text`, shifted by `offset`.
+pub fn line_feed_positions(text: &str, offset: usize) -> impl Iterator<Item = usize> + '_ {
+    text.bytes().enumerate().filter(|&(_, byte)| byte == b'\n').map(move |(idx, _)| offset + idx)
+}
+
+/// Counts the sorted line feed `positions` within `range` in O(log n).
+pub fn line_feeds_in(positions: &[usize], range: Range<usize>) -> usize {
+    positions.partition_point(|&idx| idx < range.end) - positions.partition_point(|&idx| idx < range.start)
+}
+
+/// Returns the `n`th of the sorted line feed `positions` at or after `start`.
+pub fn nth_line_feed_from(positions: &[usize], start: usize, n: usize) -> Option<usize> {
+    positions.get(positions.partition_point(|&idx| idx < start) + n).copied()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use std::path::PathBuf;
+    use crate::core::buffer::content::encoding::Encoding;
+    use crate::core::buffer::content::lossless::raw_byte_runs;
+    use crate::core::buffer::content::streaming::StreamWriter;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use crate::core::buffer::piece_table::operations::PieceTableError;
+    use crate::core::buffer::storage::backend::{MemoryBackend, PageId, StorageBackend};
+    use crate::core::buffer::traits::{BufferError, TextBuffer};
+    use crate::core::buffer::storage::paging::{lock_store, PageStore, StorageConfig};
+
+    fn temp_file(name: &str, bytes: &[u8]) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("kaudocore-{}-{}", std::process::id(), name));
+        File::create(&path).unwrap().write_all(bytes).unwrap();
+        path
+    }
+
+    #[test]
+    fn test_open_maps_utf8() {
+        let text = "line é\n".repeat(30_000);
+        let path = temp_file("mapped.txt", text.as_bytes());
+        let mut table = PieceTable::open(&path).unwrap();
+        let OriginalBuffer::Mapped(mapped) = table.original_buffer().clone() else {
+            panic!("UTF-8 file is not mapped");
+        };
+        assert_eq!(mapped.indexed_blocks(), 0);
+        assert_eq!(table.line_count(), 30_001);
+        assert_eq!(table.len(), text.len());
+
+        assert_eq!(table.line_to_offset(20_000).unwrap(), 20_000 * 8);
+        assert_eq!(table.offset_to_line(20_000 * 8 + 3).unwrap(), 20_000);
+        assert!(mapped.indexed_blocks() <= 2);
+
+        table.insert(8, "new\n").unwrap();
+        assert_eq!(table.line(1).unwrap(), "new");
+        assert_eq!(table.line(2).unwrap(), "line é");
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_open_checks_blocks_lazily() {
+        let text = "line é\n".repeat(30_000);
+        let path = temp_file("lazy.txt", text.as_bytes());
+        let mut table = PieceTable::open(&path).unwrap();
+        let OriginalBuffer::Mapped(mapped) = table.original_buffer().clone() else {
+            panic!("UTF-8 file is not mapped");
+        };
+        assert_eq!(mapped.checked_blocks(), 1);
+
+        table.insert(8, "new\n").unwrap();
+        table.delete(16..24).unwrap();
+        assert_eq!(table.line(1).unwrap(), "new");
+        assert_eq!(table.offset_to_line(30).unwrap(), 4);
+        assert_eq!(mapped.checked_blocks(), 1);
+
+        assert_eq!(table.line_count(), 30_001);
+        assert_eq!(mapped.checked_blocks(), text.len().div_ceil(BLOCK_SIZE));
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_mapped_blocks_split_chars_and_save_back() {
+        let mut bytes = format!("a{}\n", "é".repeat(BLOCK_SIZE)).into_bytes();
+        bytes.insert(BLOCK_SIZE + 1, 0xFF);
+        let path = temp_file("late-invalid.txt", &bytes);
+        let mut table = PieceTable::open(&path).unwrap();
+        let OriginalBuffer::Mapped(mapped) = table.original_buffer().clone() else {
+            panic!("file with a valid first block is not mapped");
+        };
+        assert_eq!(table.len(), bytes.len());
+        assert_eq!(mapped.substituted_bytes(), 0);
+
+        let text = table.text();
+        assert_eq!(mapped.substituted_bytes(), 1);
+        assert!(table.is_char_boundary(BLOCK_SIZE + 2));
+        assert!(!table.is_char_boundary(BLOCK_SIZE));
+        assert_eq!(table.slice(BLOCK_SIZE - 1..BLOCK_SIZE + 4).unwrap(), &text[BLOCK_SIZE - 1..BLOCK_SIZE + 4]);
+        assert_eq!(table.line_count(), 2);
+
+        // Saving writes the byte that didn't decode back, also after edits around it.
+        let mut saved = Vec::new();
+        table.write_to(&mut saved).unwrap();
+        assert_eq!(saved, bytes);
+        table.insert(0, "é").unwrap();
+        table.delete(BLOCK_SIZE + 1..BLOCK_SIZE + 3).unwrap();
+        let mut saved = Vec::new();
+        table.write_to(&mut saved).unwrap();
+        bytes.splice(BLOCK_SIZE - 1..BLOCK_SIZE + 1, []);
+        bytes.splice(0..0, "é".bytes());
+        assert_eq!(saved, bytes);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_open_falls_back_to_read() {
+        let path = temp_file("latin1.txt", b"caf\xe9\nna\xefve");
+        let table = PieceTable::open(&path).unwrap();
+        assert!(!table.original_buffer().is_mapped());
+        assert_eq!(table.text(), "café\nnaïve");
+
+        let path_bom = temp_file("bom.txt", b"\xEF\xBB\xBFhello");
+        assert_eq!(PieceTable::open(&path_bom).unwrap().text(), "hello");
+        std::fs::remove_file(path).unwrap();
+        std::fs::remove_file(path_bom).unwrap();
+        assert!(PieceTable::open("/nonexistent/kaudocore").is_err());
+    }
+
+    #[test]
+    fn test_open_keeps_invalid_bytes() {
+        let mut bytes = "première ligne\n".repeat(40).into_bytes();
+        bytes.splice(30..30, [0xFF, 0xC3]);
+        let path = temp_file("stray.txt", &bytes);
+        let table = PieceTable::open(&path).unwrap();
+        let text = table.text();
+        assert_eq!(raw_byte_runs(&text).len(), 1);
+
+        let mut saved = Vec::new();
+        StreamWriter::new(&mut saved, Encoding::UTF8).with_lossless(true).write_text(&text).unwrap();
+        assert_eq!(saved, bytes);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_mapped_line_feeds_match_text() {
+        let text: String = (0..BLOCK_SIZE).map(|idx| if idx % 7 == 0 { "\n" } else { "ab" }).collect();
+        let path = temp_file("blocks.txt", text.as_bytes());
+        let mapped = OriginalBuffer::open(&path).unwrap();
+        let owned = OriginalBuffer::from_text(&text);
+        assert!(mapped.is_mapped());
+        assert_eq!(mapped.line_feeds(), owned.line_feeds());
+        for start in [0, 1, BLOCK_SIZE - 1, BLOCK_SIZE, text.len()] {
+            assert_eq!(mapped.line_feeds_in(start..text.len()), owned.line_feeds_in(start..text.len()));
+            for n in [0, 1, 100, 5000] {
+                assert_eq!(mapped.nth_line_feed_from(start, n), owned.nth_line_feed_from(start, n));
+            }
+        }
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_paged_table_edits_beyond_memory_budget() {
+        let config = StorageConfig { page_size: 1024, memory_budget: 8 * 1024, cache_budget: 2 * 1024, ..StorageConfig::default() };
+        let store = PageStore::with_backend(config, Box::new(MemoryBackend::new())).into_shared();
+        let mut model: String = (0..4000u64).map(|idx| format!("{},é\n", idx.wrapping_mul(2_654_435_761) % 1_000_000_007)).collect();
+        let mut table = PieceTable::paged(model.as_bytes(), &store).unwrap();
+        assert!(table.len() > 4 * 8 * 1024);
+        assert_eq!(table.line_count(), 4001);
+        let snapshot = table.clone();
+        let original = model.clone();
+
+        for idx in 0..300 {
+            let at = model.floor_char_boundary(idx * 7919 % model.len());
+            match idx % 3 {
+                0 => {
+                    table.insert(at, "edit\n").unwrap();
+                    model.insert_str(at, "edit\n");
+                }
+                1 => {
+                    let end = model.floor_char_boundary((at + 40).min(model.len()));
+                    table.delete(at..end).unwrap();
+                    model.replace_range(at..end, "");
+                }
+                _ => {
+                    // Larger than a page, so it spans several add buffer blocks.
+                    let text = format!("{idx}ü\n").repeat(400);
+                    table.insert(at, &text).unwrap();
+                    model.insert_str(at, &text);
+                }
+            }
+        }
+
+        let stats = lock_store(&store).stats();
+        assert!(stats.resident_bytes() <= 8 * 1024);
+        assert!(stats.swapped_pages > 0);
+        assert!(table.added().paged_blocks() > 1);
+        assert_eq!(table.text(), model);
+        assert_eq!(table.line_count(), model.matches('\n').count() + 1);
+        for (line, (idx, _)) in model.match_indices('\n').enumerate().step_by(997) {
+            assert_eq!(table.line_to_offset(line + 1).unwrap(), idx + 1);
+            assert_eq!(table.offset_to_line(idx + 1).unwrap(), line + 1);
+        }
+        assert_eq!(snapshot.text(), original);
+
+        drop((table, snapshot));
+        assert_eq!(lock_store(&store).len(), 0);
+    }
+
+    /// Keeps pages in memory, failing to read them back once `failing` is set.
+    #[derive(Debug)]
+    struct FailingBackend {
+        pages: MemoryBackend,
+        failing: Arc<AtomicBool>,
+    }
+
+    impl StorageBackend for FailingBackend {
+        fn write(&mut self, id: PageId, bytes: &[u8]) -> Result<(), StorageError> {
+            self.pages.write(id, bytes)
+        }
+
+        fn read(&mut self, id: PageId) -> Result<Vec<u8>, StorageError> {
+            if self.failing.load(Ordering::SeqCst) {
+                return Err(StorageError::Io("swap file unreadable".to_string()));
+            }
+            self.pages.read(id)
+        }
+
+        fn remove(&mut self, id: PageId) -> Result<(), StorageError> {
+            self.pages.remove(id)
+        }
+
+        fn contains(&self, id: PageId) -> bool {
+            self.pages.contains(id)
+        }
+
+        fn len(&self) -> usize {
+            self.pages.len()
+        }
+
+        fn bytes_used(&self) -> usize {
+            self.pages.bytes_used()
+        }
+    }
+
+    #[test]
+    fn test_paged_table_returns_load_errors() {
+        let failing = Arc::new(AtomicBool::new(false));
+        let backend = FailingBackend { pages: MemoryBackend::new(), failing: failing.clone() };
+        let config = StorageConfig { page_size: 1024, memory_budget: 4 * 1024, cache_budget: 1024, ..StorageConfig::default() };
+        let store = PageStore::with_backend(config, Box::new(backend)).into_shared();
+        let text: String = (0..4000u64).map(|idx| format!("{}\n", idx.wrapping_mul(2_654_435_761) % 1_000_000_007)).collect();
+        let mut table = PieceTable::paged(text.as_bytes(), &store).unwrap();
+        assert!(lock_store(&store).stats().swapped_pages > 0);
+
+        failing.store(true, Ordering::SeqCst);
+        let error = StorageError::Io("swap file unreadable".to_string());
+        assert_eq!(table.slice(0..10), Err(PieceTableError::Storage(error.clone())));
+        assert_eq!(table.line(1), Err(PieceTableError::Storage(error.clone())));
+        assert_eq!(table.insert(5, "x"), Err(PieceTableError::Storage(error.clone())));
+        assert_eq!(TextBuffer::byte_to_char(&table, 100), Err(BufferError::Storage(error)));
+        assert!(!table.is_char_boundary(5));
+
+        failing.store(false, Ordering::SeqCst);
+        assert_eq!(table.line(1).unwrap(), text.lines().nth(1).unwrap());
+        assert_eq!(table.text(), text);
+    }
+}
@@ -3,8 +3,7 @@
 use std::ops::Range;
 use std::fmt;
 use std::cmp::Ordering;
-use std::hash::{Hash, Hasher};
-use std::sync::Arc;
+use std::hash::Hash;
 
 #[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub struct Piece {
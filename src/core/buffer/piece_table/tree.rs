@@ -0,0 +1,739 @@
+/// tree.rs
+/// Defines `PieceTree`, the red-black tree holding the pieces of a piece table in text order.
+/// Nodes live in an arena and cache the byte length, line feed count & number of pieces of their
+/// subtree, so lookups by piece index, byte offset or line feed all take O(log n).
+/// A piece may be inserted without its line feed count, which is then taken the first time a
+/// line lookup reaches it; lookups only ever count the pieces before the one they look for.
+use std::sync::{Arc, OnceLock};
+use crate::core::buffer::memory::slab::Slab;
+use crate::core::buffer::memory::stats::AllocationStats;
+use crate::core::buffer::piece_table::piece::Piece;
+
+/// Arena index of the sentinel node standing for every missing child & the root's parent.
+const NIL: usize = 0;
+
+/// Counts the line feeds of a piece that was inserted without its count.
+pub type LineFeedCounter<'a> = &'a dyn Fn(&Piece) -> usize;
+
+#[derive(Clone, Debug)]
+struct TreeNode {
+    piece: Arc<Piece>,
+    /// Empty until the piece's line feeds are counted.
+    line_feeds: OnceLock<usize>,
+    left: usize,
+    right: usize,
+    parent: usize,
+    red: bool,
+    subtree_len: usize,
+    /// Empty until every piece of the subtree is counted.
+    subtree_line_feeds: OnceLock<usize>,
+    subtree_count: usize,
+}
+
+impl TreeNode {
+    fn sentinel() -> Self {
+        TreeNode {
+            piece: Arc::new(Piece::default()),
+            line_feeds: OnceLock::from(0),
+            left: NIL,
+            right: NIL,
+            parent: NIL,
+            red: false,
+            subtree_len: 0,
+            subtree_line_feeds: OnceLock::from(0),
+            subtree_count: 0,
+        }
+    }
+}
+
+/// Where a byte offset or line feed falls in the tree.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PiecePosition {
+    /// Index of the piece in text order.
+    pub index: usize,
+    /// Byte offset at which the piece starts.
+    pub start: usize,
+}
+
+#[derive(Clone, Debug)]
+pub struct PieceTree {
+    nodes: Slab<TreeNode>,
+    root: usize,
+}
+
+impl PieceTree {
+    pub fn new() -> Self {
+        Self::with_capacity(0)
+    }
+
+    pub fn with_capacity(capacity: usize) -> Self {
+        let mut nodes = Slab::with_capacity(capacity + 1);
+        nodes.insert(TreeNode::sentinel());
+        Self { nodes, root: NIL }
+    }
+
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.nodes[self.root].subtree_len
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.root == NIL
+    }
+
+    #[inline]
+    pub fn count(&self) -> usize {
+        self.nodes[self.root].subtree_count
+    }
+
+    /// Total number of line feeds, counting every piece not counted yet.
+    pub fn total_line_feeds(&self, count: LineFeedCounter) -> usize {
+        self.subtree_line_feeds(self.root, count)
+    }
+
+    pub fn clear(&mut self) {
+        self.nodes.clear();
+        self.nodes.insert(TreeNode::sentinel());
+        self.root = NIL;
+    }
+
+    pub fn reserve(&mut self, additional: usize) {
+        self.nodes.reserve(additional);
+    }
+
+    pub fn shrink_to_fit(&mut self) {
+        self.nodes.shrink_to_fit();
+    }
+
+    /// Node allocations & reuses of the tree's slab.
+    pub fn allocation_stats(&self) -> AllocationStats {
+        self.nodes.stats()
+    }
+
+    /// Returns the piece at `index` and its line feed count, if counted.
+    pub fn get(&self, index: usize) -> Option<(&Arc<Piece>, Option<usize>)> {
+        let node = self.node_at(index)?;
+        Some((&self.nodes[node].piece, self.nodes[node].line_feeds.get().copied()))
+    }
+
+    /// Returns the position of the piece at `index`.
+    pub fn position(&self, index: usize) -> Option<PiecePosition> {
+        if index >= self.count() {
+            return None;
+        }
+        let mut position = PiecePosition { index, start: 0 };
+        let mut node = self.root;
+        let mut remaining = index;
+        loop {
+            let left = &self.nodes[self.nodes[node].left];
+            if remaining < left.subtree_count {
+                node = self.nodes[node].left;
+            } else {
+                position.start += left.subtree_len;
+                if remaining == left.subtree_count {
+                    return Some(position);
+                }
+                remaining -= left.subtree_count + 1;
+                position.start += self.nodes[node].piece.len();
+                node = self.nodes[node].right;
+            }
+        }
+    }
+
+    /// Returns the position of the piece containing byte `offset`; the end of the text maps to
+    /// one past the last piece.
+    pub fn find_offset(&self, offset: usize) -> PiecePosition {
+        if offset >= self.len() {
+            return PiecePosition { index: self.count(), start: self.len() };
+        }
+        let mut position = PiecePosition { index: 0, start: 0 };
+        let mut node = self.root;
+        let mut remaining = offset;
+        loop {
+            let left = &self.nodes[self.nodes[node].left];
+            if remaining < left.subtree_len {
+                node = self.nodes[node].left;
+                continue;
+            }
+            remaining -= left.subtree_len;
+            position.index += left.subtree_count;
+            position.start += left.subtree_len;
+            let current = &self.nodes[node];
+            if remaining < current.piece.len() {
+                return position;
+            }
+            remaining -= current.piece.len();
+            position.index += 1;
+            position.start += current.piece.len();
+            node = current.right;
+        }
+    }
+
+    /// Returns the position of the piece holding the `line_feed`-th line feed (zero based); past
+    /// the last line feed maps to one past the last piece.
+    pub fn find_line_feed(&self, line_feed: usize, count: LineFeedCounter) -> PiecePosition {
+        self.seek_line_feed(self.root, line_feed, count)
+            .unwrap_or(PiecePosition { index: self.count(), start: self.len() })
+    }
+
+    /// Number of line feeds in the pieces before `index`.
+    pub fn line_feeds_before(&self, index: usize, count: LineFeedCounter) -> usize {
+        let mut line_feeds = 0;
+        let mut node = self.root;
+        let mut remaining = index;
+        while node != NIL {
+            let left = self.nodes[node].left;
+            let left_count = self.nodes[left].subtree_count;
+            if remaining < left_count {
+                node = left;
+                continue;
+            }
+            line_feeds += self.subtree_line_feeds(left, count);
+            if remaining == left_count {
+                break;
+            }
+            line_feeds += self.own_line_feeds(node, count);
+            remaining -= left_count + 1;
+            node = self.nodes[node].right;
+        }
+        line_feeds
+    }
+
+    pub fn insert(&mut self, index: usize, piece: Arc<Piece>, line_feeds: Option<usize>) {
+        assert!(index <= self.count(), "piece index {} out of bounds", index);
+        let node = self.allocate(piece, line_feeds);
+
+        if self.root == NIL {
+            self.root = node;
+        } else if index == self.count() {
+            let last = self.maximum(self.root);
+            self.link(last, node, false);
+        } else {
+            let next = self.node_at(index).expect("index checked above");
+            match self.nodes[next].left {
+                NIL => self.link(next, node, true),
+                left => {
+                    let prev = self.maximum(left);
+                    self.link(prev, node, false);
+                }
+            }
+        }
+        self.insert_fixup(node);
+    }
+
+    pub fn remove(&mut self, index: usize) -> Option<Arc<Piece>> {
+        let node = self.node_at(index)?;
+        let mut removed_red = self.nodes[node].red;
+        let child;
+
+        if self.nodes[node].left == NIL {
+            child = self.nodes[node].right;
+            self.transplant(node, child);
+        } else if self.nodes[node].right == NIL {
+            child = self.nodes[node].left;
+            self.transplant(node, child);
+        } else {
+            let successor = self.minimum(self.nodes[node].right);
+            removed_red = self.nodes[successor].red;
+            child = self.nodes[successor].right;
+            if self.nodes[successor].parent == node {
+                self.nodes[child].parent = successor;
+            } else {
+                self.transplant(successor, child);
+                self.nodes[successor].right = self.nodes[node].right;
+                let right = self.nodes[successor].right;
+                self.nodes[right].parent = successor;
+            }
+            self.transplant(node, successor);
+            self.nodes[successor].left = self.nodes[node].left;
+            let left = self.nodes[successor].left;
+            self.nodes[left].parent = successor;
+            self.nodes[successor].red = self.nodes[node].red;
+        }
+
+        self.pull_up(self.nodes[child].parent);
+        if !removed_red {
+            self.delete_fixup(child);
+        }
+        self.nodes[NIL] = TreeNode::sentinel();
+
+        let removed = self.nodes.remove(node).expect("removed node is allocated");
+        Some(removed.piece)
+    }
+
+    /// Replaces the piece at `index`, returning the previous one.
+    pub fn set(&mut self, index: usize, piece: Arc<Piece>, line_feeds: Option<usize>) -> Option<Arc<Piece>> {
+        let node = self.node_at(index)?;
+        let previous = std::mem::replace(&mut self.nodes[node].piece, piece);
+        self.nodes[node].line_feeds = line_feeds.map_or_else(OnceLock::new, OnceLock::from);
+        self.pull_up(node);
+        Some(previous)
+    }
+
+    /// Iterates over the pieces, in text order, starting with the one at `index`.
+    pub fn iter_from(&self, index: usize) -> PieceTreeIter<'_> {
+        PieceTreeIter {
+            tree: self,
+            node: self.node_at(index).unwrap_or(NIL),
+        }
+    }
+
+    pub fn iter(&self) -> PieceTreeIter<'_> {
+        self.iter_from(0)
+    }
+
+    /// Looks for the `target`-th line feed in the subtree of `node`, returning the position of
+    /// the piece holding it within the subtree, or the line feeds of the subtree when it holds
+    /// fewer. Pieces after the one holding the line feed are never counted.
+    fn seek_line_feed(&self, node: usize, target: usize, count: LineFeedCounter) -> Result<PiecePosition, usize> {
+        let current = &self.nodes[node];
+        if let Some(&line_feeds) = current.subtree_line_feeds.get() && target >= line_feeds {
+            return Err(line_feeds);
+        }
+        let before = match self.seek_line_feed(current.left, target, count) {
+            Ok(position) => return Ok(position),
+            Err(line_feeds) => line_feeds,
+        };
+        let left = &self.nodes[current.left];
+        let own = self.own_line_feeds(node, count);
+        if target - before < own {
+            return Ok(PiecePosition { index: left.subtree_count, start: left.subtree_len });
+        }
+        match self.seek_line_feed(current.right, target - before - own, count) {
+            Ok(position) => Ok(PiecePosition {
+                index: left.subtree_count + 1 + position.index,
+                start: left.subtree_len + current.piece.len() + position.start,
+            }),
+            Err(after) => Err(*current.subtree_line_feeds.get_or_init(|| before + own + after)),
+        }
+    }
+
+    /// Line feeds of the piece of `node`, counting them if needed.
+    fn own_line_feeds(&self, node: usize, count: LineFeedCounter) -> usize {
+        *self.nodes[node].line_feeds.get_or_init(|| count(&self.nodes[node].piece))
+    }
+
+    /// Line feeds of the subtree of `node`, counting the pieces not counted yet.
+    fn subtree_line_feeds(&self, node: usize, count: LineFeedCounter) -> usize {
+        let current = &self.nodes[node];
+        *current.subtree_line_feeds.get_or_init(|| {
+            self.subtree_line_feeds(current.left, count) + self.own_line_feeds(node, count) + self.subtree_line_feeds(current.right, count)
+        })
+    }
+
+    fn node_at(&self, index: usize) -> Option<usize> {
+        if index >= self.count() {
+            return None;
+        }
+        let mut node = self.root;
+        let mut remaining = index;
+        loop {
+            let left_count = self.nodes[self.nodes[node].left].subtree_count;
+            if remaining < left_count {
+                node = self.nodes[node].left;
+            } else if remaining == left_count {
+                return Some(node);
+            } else {
+                remaining -= left_count + 1;
+                node = self.nodes[node].right;
+            }
+        }
+    }
+
+    fn allocate(&mut self, piece: Arc<Piece>, line_feeds: Option<usize>) -> usize {
+        let line_feeds = line_feeds.map_or_else(OnceLock::new, OnceLock::from);
+        let node = TreeNode {
+            subtree_len: piece.len(),
+            subtree_line_feeds: line_feeds.clone(),
+            subtree_count: 1,
+            piece,
+            line_feeds,
+            left: NIL,
+            right: NIL,
+            parent: NIL,
+            red: true,
+        };
+        self.nodes.insert(node)
+    }
+
+    fn link(&mut self, parent: usize, node: usize, as_left: bool) {
+        if as_left {
+            self.nodes[parent].left = node;
+        } else {
+            self.nodes[parent].right = node;
+        }
+        self.nodes[node].parent = parent;
+        self.pull_up(parent);
+    }
+
+    fn minimum(&self, mut node: usize) -> usize {
+        while self.nodes[node].left != NIL {
+            node = self.nodes[node].left;
+        }
+        node
+    }
+
+    fn maximum(&self, mut node: usize) -> usize {
+        while self.nodes[node].right != NIL {
+            node = self.nodes[node].right;
+        }
+        node
+    }
+
+    fn successor(&self, mut node: usize) -> usize {
+        if self.nodes[node].right != NIL {
+            return self.minimum(self.nodes[node].right);
+        }
+        let mut parent = self.nodes[node].parent;
+        while parent != NIL && node == self.nodes[parent].right {
+            node = parent;
+            parent = self.nodes[parent].parent;
+        }
+        parent
+    }
+
+    /// Recomputes the cached aggregates of `node` from its children.
+    fn pull(&mut self, node: usize) {
+        if node == NIL {
+            return;
+        }
+        let (left, right) = (&self.nodes[self.nodes[node].left], &self.nodes[self.nodes[node].right]);
+        let subtree_len = left.subtree_len + right.subtree_len;
+        let subtree_line_feeds = left.subtree_line_feeds.get().zip(right.subtree_line_feeds.get()).map(|(left, right)| left + right);
+        let subtree_count = left.subtree_count + right.subtree_count + 1;
+        let current = &mut self.nodes[node];
+        current.subtree_len = subtree_len + current.piece.len();
+        current.subtree_line_feeds = match (subtree_line_feeds, current.line_feeds.get()) {
+            (Some(children), Some(own)) => OnceLock::from(children + own),
+            _ => OnceLock::new(),
+        };
+        current.subtree_count = subtree_count;
+    }
+
+    fn pull_up(&mut self, mut node: usize) {
+        while node != NIL {
+            self.pull(node);
+            node = self.nodes[node].parent;
+        }
+    }
+
+    fn replace_child(&mut self, parent: usize, old: usize, new: usize) {
+        if parent == NIL {
+            self.root = new;
+        } else if self.nodes[parent].left == old {
+            self.nodes[parent].left = new;
+        } else {
+            self.nodes[parent].right = new;
+        }
+    }
+
+    fn transplant(&mut self, old: usize, new: usize) {
+        let parent = self.nodes[old].parent;
+        self.replace_child(parent, old, new);
+        self.nodes[new].parent = parent;
+    }
+
+    fn rotate_left(&mut self, node: usize) {
+        let pivot = self.nodes[node].right;
+        let inner = self.nodes[pivot].left;
+        self.nodes[node].right = inner;
+        if inner != NIL {
+            self.nodes[inner].parent = node;
+        }
+        self.transplant(node, pivot);
+        self.nodes[pivot].left = node;
+        self.nodes[node].parent = pivot;
+        self.pull(node);
+        self.pull(pivot);
+    }
+
+    fn rotate_right(&mut self, node: usize) {
+        let pivot = self.nodes[node].left;
+        let inner = self.nodes[pivot].right;
+        self.nodes[node].left = inner;
+        if inner != NIL {
+            self.nodes[inner].parent = node;
+        }
+        self.transplant(node, pivot);
+        self.nodes[pivot].right = node;
+        self.nodes[node].parent = pivot;
+        self.pull(node);
+        self.pull(pivot);
+    }
+
+    fn insert_fixup(&mut self, mut node: usize) {
+        while self.nodes[self.nodes[node].parent].red {
+            let parent = self.nodes[node].parent;
+            let grandparent = self.nodes[parent].parent;
+            let parent_is_left = parent == self.nodes[grandparent].left;
+            let uncle = if parent_is_left { self.nodes[grandparent].right } else { self.nodes[grandparent].left };
+
+            if self.nodes[uncle].red {
+                self.nodes[parent].red = false;
+                self.nodes[uncle].red = false;
+                self.nodes[grandparent].red = true;
+                node = grandparent;
+                continue;
+            }
+
+            let mut parent = parent;
+            if parent_is_left {
+                if node == self.nodes[parent].right {
+                    node = parent;
+                    self.rotate_left(node);
+                    parent = self.nodes[node].parent;
+                }
+                self.nodes[parent].red = false;
+                self.nodes[grandparent].red = true;
+                self.rotate_right(grandparent);
+            } else {
+                if node == self.nodes[parent].left {
+                    node = parent;
+                    self.rotate_right(node);
+                    parent = self.nodes[node].parent;
+                }
+                self.nodes[parent].red = false;
+                self.nodes[grandparent].red = true;
+                self.rotate_left(grandparent);
+            }
+        }
+        let root = self.root;
+        self.nodes[root].red = false;
+    }
+
+    fn delete_fixup(&mut self, mut node: usize) {
+        while node != self.root && !self.nodes[node].red {
+            let parent = self.nodes[node].parent;
+            if node == self.nodes[parent].left {
+                let mut sibling = self.nodes[parent].right;
+                if self.nodes[sibling].red {
+                    self.nodes[sibling].red = false;
+                    self.nodes[parent].red = true;
+                    self.rotate_left(parent);
+                    sibling = self.nodes[parent].right;
+                }
+                if !self.nodes[self.nodes[sibling].left].red && !self.nodes[self.nodes[sibling].right].red {
+                    self.nodes[sibling].red = true;
+                    node = parent;
+                } else {
+                    if !self.nodes[self.nodes[sibling].right].red {
+                        let inner = self.nodes[sibling].left;
+                        self.nodes[inner].red = false;
+                        self.nodes[sibling].red = true;
+                        self.rotate_right(sibling);
+                        sibling = self.nodes[parent].right;
+                    }
+                    self.nodes[sibling].red = self.nodes[parent].red;
+                    self.nodes[parent].red = false;
+                    let outer = self.nodes[sibling].right;
+                    self.nodes[outer].red = false;
+                    self.rotate_left(parent);
+                    node = self.root;
+                }
+            } else {
+                let mut sibling = self.nodes[parent].left;
+                if self.nodes[sibling].red {
+                    self.nodes[sibling].red = false;
+                    self.nodes[parent].red = true;
+                    self.rotate_right(parent);
+                    sibling = self.nodes[parent].left;
+                }
+                if !self.nodes[self.nodes[sibling].left].red && !self.nodes[self.nodes[sibling].right].red {
+                    self.nodes[sibling].red = true;
+                    node = parent;
+                } else {
+                    if !self.nodes[self.nodes[sibling].left].red {
+                        let inner = self.nodes[sibling].right;
+                        self.nodes[inner].red = false;
+                        self.nodes[sibling].red = true;
+                        self.rotate_left(sibling);
+                        sibling = self.nodes[parent].left;
+                    }
+                    self.nodes[sibling].red = self.nodes[parent].red;
+                    self.nodes[parent].red = false;
+                    let outer = self.nodes[sibling].left;
+                    self.nodes[outer].red = false;
+                    self.rotate_right(parent);
+                    node = self.root;
+                }
+            }
+        }
+        self.nodes[node].red = false;
+    }
+
+    /// Checks the red-black & aggregate invariants, panicking on the first violation.
+    #[cfg(test)]
+    pub(crate) fn assert_invariants(&self) {
+        fn check(tree: &PieceTree, node: usize) -> usize {
+            if node == NIL {
+                return 1;
+            }
+            let current = &tree.nodes[node];
+            let (left, right) = (&tree.nodes[current.left], &tree.nodes[current.right]);
+            if current.left != NIL {
+                assert_eq!(left.parent, node);
+            }
+            if current.right != NIL {
+                assert_eq!(right.parent, node);
+            }
+            assert!(!(current.red && (left.red || right.red)), "red node with a red child");
+            assert_eq!(current.subtree_len, left.subtree_len + right.subtree_len + current.piece.len());
+            let line_feeds = [&left.subtree_line_feeds, &right.subtree_line_feeds, &current.line_feeds].map(OnceLock::get);
+            match current.subtree_line_feeds.get() {
+                Some(&subtree) => assert_eq!(Some(subtree), line_feeds.into_iter().sum()),
+                None => assert!(line_feeds.contains(&None), "uncounted subtree of counted pieces"),
+            }
+            assert_eq!(current.subtree_count, left.subtree_count + right.subtree_count + 1);
+            let black_height = check(tree, current.left);
+            assert_eq!(black_height, check(tree, current.right), "unequal black heights");
+            black_height + usize::from(!current.red)
+        }
+        assert!(!self.nodes[self.root].red, "red root");
+        assert!(!self.nodes[NIL].red);
+        check(self, self.root);
+    }
+}
+
+impl Default for PieceTree {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// In-order iterator over the pieces of a `PieceTree`.
+pub struct PieceTreeIter<'a> {
+    tree: &'a PieceTree,
+    node: usize,
+}
+
+impl<'a> Iterator for PieceTreeIter<'a> {
+    type Item = &'a Arc<Piece>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.node == NIL {
+            return None;
+        }
+        let piece = &self.tree.nodes[self.node].piece;
+        self.node = self.tree.successor(self.node);
+        Some(piece)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn piece(start: usize, len: usize) -> Arc<Piece> {
+        Arc::new(Piece::with_length(1, start, len))
+    }
+
+    fn starts(tree: &PieceTree) -> Vec<usize> {
+        tree.iter().map(|piece| piece.start()).collect()
+    }
+
+    #[test]
+    fn test_insert_in_order() {
+        let mut tree = PieceTree::new();
+        let mut model = Vec::new();
+        for i in 0..500 {
+            let index = (i * 7919) % (model.len() + 1);
+            tree.insert(index, piece(i, i % 5 + 1), Some(i % 3));
+            model.insert(index, i);
+            tree.assert_invariants();
+        }
+        assert_eq!(starts(&tree), model);
+        assert_eq!(tree.count(), 500);
+        assert_eq!(tree.len(), (0..500).map(|i| i % 5 + 1).sum());
+        assert_eq!(tree.total_line_feeds(&|_| unreachable!()), (0..500).map(|i| i % 3).sum());
+    }
+
+    #[test]
+    fn test_remove_keeps_balance() {
+        let mut tree = PieceTree::new();
+        let mut model: Vec<usize> = (0..400).collect();
+        for i in 0..400 {
+            tree.insert(i, piece(i, 2), Some(1));
+        }
+        while !model.is_empty() {
+            let index = (model.len() * 31 + 7) % model.len();
+            let removed = tree.remove(index).unwrap();
+            assert_eq!(removed.start(), model.remove(index));
+            tree.assert_invariants();
+        }
+        assert!(tree.is_empty());
+        assert_eq!(tree.len(), 0);
+        assert!(tree.remove(0).is_none());
+    }
+
+    #[test]
+    fn test_removed_nodes_are_recycled() {
+        let mut tree = PieceTree::new();
+        for i in 0..100 {
+            tree.insert(i, piece(i, 1), Some(0));
+        }
+        for _ in 0..1000 {
+            tree.remove(50).unwrap();
+            tree.insert(10, piece(0, 1), Some(0));
+        }
+        tree.assert_invariants();
+        let stats = tree.allocation_stats();
+        assert_eq!(stats.allocations, 101);
+        assert_eq!(stats.reuses, 1000);
+    }
+
+    #[test]
+    fn test_find_offset_and_line_feed() {
+        let mut tree = PieceTree::new();
+        for i in 0..100 {
+            tree.insert(i, piece(i * 10, 10), Some(2));
+        }
+        let count: LineFeedCounter = &|_| unreachable!();
+        let position = tree.find_offset(255);
+        assert_eq!(position, PiecePosition { index: 25, start: 250 });
+        assert_eq!(tree.line_feeds_before(25, count), 50);
+        assert_eq!(tree.find_offset(1000).index, 100);
+        let position = tree.find_line_feed(51, count);
+        assert_eq!(position, PiecePosition { index: 25, start: 250 });
+        assert_eq!(tree.position(25), Some(position));
+        assert_eq!(tree.find_line_feed(200, count).index, 100);
+        assert_eq!(tree.line_feeds_before(100, count), 200);
+    }
+
+    #[test]
+    fn test_set_updates_aggregates() {
+        let mut tree = PieceTree::new();
+        for i in 0..10 {
+            tree.insert(i, piece(i, 1), Some(0));
+        }
+        tree.set(3, piece(100, 50), Some(4));
+        tree.assert_invariants();
+        assert_eq!(tree.len(), 59);
+        assert_eq!(tree.total_line_feeds(&|_| unreachable!()), 4);
+        assert_eq!(tree.iter_from(3).next().unwrap().start(), 100);
+    }
+
+    #[test]
+    fn test_uncounted_pieces_are_counted_up_to_the_match() {
+        let mut tree = PieceTree::new();
+        for i in 0..100 {
+            tree.insert(i, piece(i * 10, 10), (i % 2 == 0).then_some(1));
+        }
+        tree.assert_invariants();
+        let counted = std::sync::atomic::AtomicUsize::new(0);
+        let count = |piece: &Piece| {
+            assert!(piece.start() <= 310, "counted a piece after the match");
+            counted.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+            1
+        };
+        assert_eq!(tree.find_line_feed(31, &count), PiecePosition { index: 31, start: 310 });
+        assert_eq!(tree.line_feeds_before(31, &count), 31);
+        assert_eq!(counted.load(std::sync::atomic::Ordering::Relaxed), 16);
+        tree.assert_invariants();
+
+        assert_eq!(tree.total_line_feeds(&|_| 1), 100);
+        tree.remove(0);
+        tree.assert_invariants();
+        assert_eq!(tree.total_line_feeds(&|_| unreachable!()), 99);
+    }
+}
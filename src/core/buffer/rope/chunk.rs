@@ -3,21 +3,38 @@
 use std::ops::Range;
 use std::str::Chars;
 use std::fmt;
-use std::cmp::min;
 use std::iter::FromIterator;
-use std::slice::Iter;
 use std::string::String;
+use unicode_segmentation::UnicodeSegmentation;
+use crate::core::buffer::memory::pool::ChunkPool;
+use crate::core::buffer::rope::summary::{is_cluster_anchor, TextSummary};
 
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub struct Chunk {
     text: String,
     line_endings: Vec<usize>,
+    summary: TextSummary,
 }
 
 impl Chunk {
     pub fn new(text: String) -> Self {
         let line_endings = text.match_indices('\n').map(|(idx, _)| idx).collect();
-        Chunk { text, line_endings }
+        let summary = TextSummary::of(&text);
+        Chunk { text, line_endings, summary }
+    }
+
+    /// Builds a chunk from `text`, taking the storage of its line endings from `pool`.
+    pub fn new_in(text: String, pool: &ChunkPool) -> Self {
+        let mut line_endings = pool.acquire_offsets(text.bytes().filter(|&byte| byte == b'\n').count());
+        line_endings.extend(text.match_indices('\n').map(|(idx, _)| idx));
+        let summary = TextSummary::of(&text);
+        Chunk { text, line_endings, summary }
+    }
+
+    /// Hands the storage of the chunk back to `pool`.
+    pub fn recycle(self, pool: &ChunkPool) {
+        pool.release_string(self.text);
+        pool.release_offsets(self.line_endings);
     }
 
     #[inline]
@@ -40,6 +57,11 @@ impl Chunk {
         &self.line_endings
     }
 
+    #[inline]
+    pub fn summary(&self) -> &TextSummary {
+        &self.summary
+    }
+
     pub fn slice(&self, range: Range<usize>) -> Option<Chunk> {
         if range.start > range.end || range.end > self.len() {
             return None;
@@ -53,8 +75,15 @@ impl Chunk {
         Some(Chunk::new(self.text[range].to_string()))
     }
 
+    pub fn char_at(&self, byte_idx: usize) -> Option<char> {
+        if !self.text.is_char_boundary(byte_idx) {
+            return None;
+        }
+        self.text[byte_idx..].chars().next()
+    }
+
     #[inline]
-    pub fn iter(&self) -> Chars {
+    pub fn iter(&self) -> Chars<'_> {
         self.text.chars()
     }
 
@@ -76,6 +105,22 @@ impl Chunk {
         Some(&self.text[start..end])
     }
 
+    /// Byte offset at which `line` starts, lines being separated by `\n`.
+    pub fn line_to_byte(&self, line: usize) -> Option<usize> {
+        match line {
+            0 => Some(0),
+            _ => self.line_endings.get(line - 1).map(|idx| idx + 1),
+        }
+    }
+
+    /// Index of the line containing `byte_idx`, lines being separated by `\n`.
+    pub fn byte_to_line(&self, byte_idx: usize) -> Option<usize> {
+        if byte_idx > self.len() {
+            return None;
+        }
+        Some(self.line_endings.partition_point(|&idx| idx < byte_idx))
+    }
+
     pub fn concat(&self, other: &Chunk) -> Chunk {
         let mut new_text = String::with_capacity(self.text.len() + other.text.len());
         new_text.push_str(&self.text);
@@ -111,36 +156,169 @@ impl Chunk {
         Some(Chunk::new(new_text))
     }
 
+    /// Replaces `range` with `replacement` without reallocating unless the text outgrows its
+    /// capacity. Line endings before the range are kept, those inside it are replaced by the ones
+    /// of `replacement` and those after it are shifted. Returns false, leaving the chunk
+    /// untouched, when the range is out of bounds or not on char boundaries.
+    ///
+    /// The summary is only measured again between the cluster anchors around the edit, unless
+    /// the edit reaches into the first two clusters of the chunk.
+    pub fn replace_range_in_place(&mut self, range: Range<usize>, replacement: &str) -> bool {
+        if range.start > range.end || range.end > self.len() {
+            return false;
+        }
+        if !self.text.is_char_boundary(range.start) || !self.text.is_char_boundary(range.end) {
+            return false;
+        }
+
+        let window = self.summary_window(range.clone());
+        let removed = window.clone().map(|window| TextSummary::of(&self.text[window]));
+        self.text.replace_range(range.clone(), replacement);
+        let first = self.line_endings.partition_point(|&idx| idx < range.start);
+        let last = self.line_endings.partition_point(|&idx| idx < range.end);
+        let inserted = replacement.match_indices('\n').map(|(idx, _)| range.start + idx);
+        let before = self.line_endings.len();
+        self.line_endings.splice(first..last, inserted);
+        let after_range = last + self.line_endings.len() - before;
+        for idx in &mut self.line_endings[after_range..] {
+            *idx = *idx - range.end + range.start + replacement.len();
+        }
+        match window.zip(removed) {
+            Some((window, removed)) => {
+                let inserted = &self.text[window.start..window.end - range.len() + replacement.len()];
+                self.summary.replace_window(&removed, &TextSummary::of(inserted), &self.text);
+            }
+            None => self.summary = TextSummary::of(&self.text),
+        }
+        true
+    }
+
+    /// Window around `range` outside which an edit of `range` changes no cluster or line break:
+    /// from the last cluster anchor before `range` to the end of the first one after it. None
+    /// when no anchor comes after the start of the second cluster.
+    fn summary_window(&self, range: Range<usize>) -> Option<Range<usize>> {
+        let (second, cluster) = self.text.grapheme_indices(true).nth(1)?;
+        let head = second + cluster.chars().next().map_or(0, char::len_utf8);
+        let (start, _) = self.text[..range.start].char_indices().rev().find(|&(_, ch)| is_cluster_anchor(ch))?;
+        let end = self.text[range.end..].char_indices()
+            .find(|&(_, ch)| is_cluster_anchor(ch))
+            .map_or(self.len(), |(idx, ch)| range.end + idx + ch.len_utf8());
+        (start >= head).then_some(start..end)
+    }
+
+    /// Inserts `text` at `idx` in place; see `replace_range_in_place`.
+    pub fn insert_str_in_place(&mut self, idx: usize, text: &str) -> bool {
+        self.replace_range_in_place(idx..idx, text)
+    }
+
+    /// Removes `range` in place; see `replace_range_in_place`.
+    pub fn remove_range_in_place(&mut self, range: Range<usize>) -> bool {
+        self.replace_range_in_place(range, "")
+    }
+
+    /// Returns true when the chunk is pure ASCII, in which case every offset kind coincides.
+    #[inline]
+    pub fn is_ascii(&self) -> bool {
+        self.summary.chars == self.summary.bytes
+    }
+
     pub fn byte_to_char(&self, byte_idx: usize) -> Option<usize> {
         if byte_idx > self.len() {
             return None;
         }
-        Some(self.text[..byte_idx].chars().count())
+        if self.is_ascii() {
+            return Some(byte_idx);
+        }
+        Some(
+            self.text
+                .char_indices()
+                .take_while(|(idx, ch)| idx + ch.len_utf8() <= byte_idx)
+                .count(),
+        )
     }
 
     pub fn char_to_byte(&self, char_idx: usize) -> Option<usize> {
-        let mut byte_idx = 0;
-        for (i, ch) in self.text.char_indices() {
-            if char_idx == 0 {
-                return Some(byte_idx);
-            }
-            if char_idx == 1 {
-                return Some(i);
-            }
-            byte_idx = i;
-            if char_idx <= 1 {
-                break;
+        if self.is_ascii() {
+            return (char_idx <= self.len()).then_some(char_idx);
+        }
+        self.text
+            .char_indices()
+            .map(|(idx, _)| idx)
+            .chain(std::iter::once(self.len()))
+            .nth(char_idx)
+    }
+
+    #[inline]
+    pub fn char_len(&self) -> usize {
+        self.summary.chars
+    }
+
+    #[inline]
+    pub fn utf16_len(&self) -> usize {
+        self.summary.utf16_units
+    }
+
+    #[inline]
+    pub fn grapheme_len(&self) -> usize {
+        self.summary.graphemes
+    }
+
+    /// Converts a byte offset into UTF-16 code units; offsets inside a char map to that char's start.
+    pub fn byte_to_utf16(&self, byte_idx: usize) -> Option<usize> {
+        if byte_idx > self.len() {
+            return None;
+        }
+        if self.is_ascii() {
+            return Some(byte_idx);
+        }
+        Some(
+            self.text
+                .char_indices()
+                .take_while(|(idx, ch)| idx + ch.len_utf8() <= byte_idx)
+                .map(|(_, ch)| ch.len_utf16())
+                .sum(),
+        )
+    }
+
+    /// Converts UTF-16 code units into a byte offset; a unit inside a surrogate pair maps to the pair's start.
+    pub fn utf16_to_byte(&self, utf16_idx: usize) -> Option<usize> {
+        if utf16_idx > self.utf16_len() {
+            return None;
+        }
+        if self.is_ascii() {
+            return Some(utf16_idx);
+        }
+        let mut units = 0;
+        for (idx, ch) in self.text.char_indices() {
+            units += ch.len_utf16();
+            if units > utf16_idx {
+                return Some(idx);
             }
         }
-        if char_idx == self.text.chars().count() {
-            Some(self.len())
-        } else {
-            None
+        Some(self.len())
+    }
+
+    /// Index of the grapheme cluster containing `byte_idx`, the end of the chunk counting as one past the last.
+    pub fn byte_to_grapheme(&self, byte_idx: usize) -> Option<usize> {
+        if byte_idx > self.len() {
+            return None;
         }
+        Some(
+            self.text
+                .grapheme_indices(true)
+                .take_while(|(idx, _)| *idx <= byte_idx)
+                .count()
+                .saturating_sub(usize::from(byte_idx < self.len())),
+        )
     }
 
-    pub fn char_len(&self) -> usize {
-        self.text.chars().count()
+    /// Byte offset at which grapheme cluster `grapheme_idx` starts.
+    pub fn grapheme_to_byte(&self, grapheme_idx: usize) -> Option<usize> {
+        self.text
+            .grapheme_indices(true)
+            .map(|(idx, _)| idx)
+            .chain(std::iter::once(self.len()))
+            .nth(grapheme_idx)
     }
 
     pub fn find(&self, pattern: &str) -> Option<usize> {
@@ -276,6 +454,7 @@ impl std::borrow::Borrow<str> for Chunk {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use proptest::prelude::*;
 
     #[test]
     fn test_chunk_creation() {
@@ -314,6 +493,7 @@ mod tests {
     }
 
     #[test]
+    #[allow(clippy::reversed_empty_ranges)]
     fn test_slice() {
         let chunk = Chunk::new("Hello world".to_string());
         let slice = chunk.slice(0..5).unwrap();
@@ -331,7 +511,7 @@ mod tests {
         let chunk = Chunk::new("Héllo wörld 🌍".to_string());
         
         // Valid slice at character boundaries
-        let slice = chunk.slice(0..2).unwrap();
+        let slice = chunk.slice(0..3).unwrap();
         assert_eq!(slice.text(), "Hé");
         
         // Invalid slice not at character boundary should return None
@@ -358,7 +538,7 @@ mod tests {
         let chunk = Chunk::new("Héllo 🌍".to_string());
         
         // Valid split at character boundary
-        let (left, right) = chunk.split_at(6).unwrap();
+        let (left, right) = chunk.split_at(7).unwrap();
         assert_eq!(left.text(), "Héllo ");
         assert_eq!(right.text(), "🌍");
         
@@ -375,6 +555,7 @@ mod tests {
     }
 
     #[test]
+    #[allow(clippy::reversed_empty_ranges)]
     fn test_replace_range() {
         let chunk = Chunk::new("Hello world".to_string());
         let result = chunk.replace_range(6..11, "Rust").unwrap();
@@ -387,21 +568,94 @@ mod tests {
         assert!(chunk.replace_range(5..4, "test").is_none());
     }
 
+    #[test]
+    fn test_in_place_edits_patch_line_endings() {
+        let mut chunk = Chunk::from("ab\ncd\nef\n");
+        assert!(chunk.insert_str_in_place(3, "x\ny"));
+        assert_eq!(chunk, Chunk::from("ab\nx\nycd\nef\n"));
+        assert!(chunk.replace_range_in_place(1..6, "é\r\n"));
+        assert_eq!(chunk, Chunk::from("aé\r\ncd\nef\n"));
+        assert!(chunk.remove_range_in_place(0..chunk.len() - 1));
+        assert_eq!(chunk, Chunk::from("\n"));
+
+        let mut chunk = Chunk::from("é\n");
+        assert!(!chunk.insert_str_in_place(1, "x"));
+        assert!(!chunk.remove_range_in_place(2..4));
+        assert_eq!(chunk, Chunk::from("é\n"));
+    }
+
+    proptest! {
+        #[test]
+        fn prop_in_place_edits_patch_summary_around_seams(
+            text in "(\u{1F1EB}|\u{1F468}|\u{200D}|\u{915}|\u{94D}|\u{301}|é|e| |\r|\n){0,24}",
+            edits in proptest::collection::vec((0usize..64, 0usize..8, "(\u{1F1F7}|\u{1F469}|\u{200D}|\u{937}|\u{94D}|\u{301}|a|\r|\n){0,4}"), 1..6),
+        ) {
+            let mut chunk = Chunk::from(text.as_str());
+            let mut expected = text;
+            for (start, len, replacement) in edits {
+                let start = expected.floor_char_boundary(start.min(expected.len()));
+                let end = expected.floor_char_boundary((start + len).min(expected.len()));
+                expected.replace_range(start..end, &replacement);
+                prop_assert!(chunk.replace_range_in_place(start..end, &replacement));
+                prop_assert_eq!(&chunk, &Chunk::from(expected.as_str()));
+            }
+        }
+    }
+
+    #[test]
+    fn test_utf16_conversion() {
+        let chunk = Chunk::from("a🌍é");
+        assert_eq!(chunk.utf16_len(), 4);
+        assert_eq!(chunk.byte_to_utf16(1), Some(1));
+        assert_eq!(chunk.byte_to_utf16(5), Some(3));
+        assert_eq!(chunk.byte_to_utf16(7), Some(4));
+        assert_eq!(chunk.utf16_to_byte(2), Some(1));
+        assert_eq!(chunk.utf16_to_byte(3), Some(5));
+        assert_eq!(chunk.utf16_to_byte(4), Some(7));
+        assert_eq!(chunk.utf16_to_byte(5), None);
+    }
+
+    #[test]
+    fn test_grapheme_conversion() {
+        let chunk = Chunk::from("e\u{301}x👍🏽\r\n");
+        assert_eq!(chunk.grapheme_len(), 4);
+        assert_eq!(chunk.grapheme_to_byte(1), Some(3));
+        assert_eq!(chunk.grapheme_to_byte(2), Some(4));
+        assert_eq!(chunk.grapheme_to_byte(4), Some(chunk.len()));
+        assert_eq!(chunk.byte_to_grapheme(1), Some(0));
+        assert_eq!(chunk.byte_to_grapheme(3), Some(1));
+        assert_eq!(chunk.byte_to_grapheme(6), Some(2));
+        assert_eq!(chunk.byte_to_grapheme(chunk.len()), Some(4));
+    }
+
+    #[test]
+    fn test_line_byte_conversion() {
+        let chunk = Chunk::from("ab\ncd\r\n\nef");
+        assert_eq!(chunk.line_to_byte(0), Some(0));
+        assert_eq!(chunk.line_to_byte(1), Some(3));
+        assert_eq!(chunk.line_to_byte(3), Some(8));
+        assert_eq!(chunk.line_to_byte(4), None);
+        assert_eq!(chunk.byte_to_line(2), Some(0));
+        assert_eq!(chunk.byte_to_line(3), Some(1));
+        assert_eq!(chunk.byte_to_line(10), Some(3));
+        assert_eq!(chunk.byte_to_line(11), None);
+    }
+
     #[test]
     fn test_byte_char_conversion() {
         let chunk = Chunk::new("Héllo 🌍".to_string());
         
-        assert_eq!(chunk.char_len(), 8);
+        assert_eq!(chunk.char_len(), 7);
         assert_eq!(chunk.len(), 11); // bytes
         
         // Test conversions
         assert_eq!(chunk.byte_to_char(0), Some(0));
-        assert_eq!(chunk.byte_to_char(1), Some(0)); // Inside é
-        assert_eq!(chunk.byte_to_char(2), Some(1));
+        assert_eq!(chunk.byte_to_char(2), Some(1)); // Inside é
+        assert_eq!(chunk.byte_to_char(3), Some(2));
         
         assert_eq!(chunk.char_to_byte(0), Some(0));
-        assert_eq!(chunk.char_to_byte(1), Some(2)); // After é
-        assert_eq!(chunk.char_to_byte(8), Some(11)); // End of string
+        assert_eq!(chunk.char_to_byte(2), Some(3)); // After é
+        assert_eq!(chunk.char_to_byte(7), Some(11)); // End of string
     }
 
     #[test]
@@ -483,7 +737,7 @@ mod tests {
         
         // Test with UTF-8
         let utf8_chunk = Chunk::new("Hé🌍llo".to_string());
-        let (result, removed) = utf8_chunk.remove(2).unwrap();
+        let (result, removed) = utf8_chunk.remove(3).unwrap();
         assert_eq!(result.text(), "Héllo");
         assert_eq!(removed, '🌍');
         
@@ -536,7 +790,7 @@ mod tests {
     #[test]
     fn test_rope_operations_simulation() {
         // Simulate rope operations with multiple chunks
-        let mut chunks = vec![
+        let chunks = [
             Chunk::new("Hello ".to_string()),
             Chunk::new("beautiful ".to_string()),
             Chunk::new("world!".to_string()),
@@ -566,7 +820,7 @@ mod tests {
         assert!(document.contains("println!(\"World\")"));
         
         // Add new line
-        let brace_pos = document.rfind('}').unwrap();
+        let brace_pos = document.rfind("}").unwrap();
         document = document.insert(brace_pos, '\n').unwrap();
         assert_eq!(document.line_count(), 4);
         
@@ -610,6 +864,7 @@ mod tests {
     }
 
     #[test]
+    #[allow(clippy::reversed_empty_ranges)]
     fn test_error_conditions() {
         let chunk = Chunk::new("test".to_string());
         
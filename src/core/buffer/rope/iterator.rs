@@ -1,93 +1,449 @@
 // iterator.rs
 // This file is for iterators over ropes.
-use crate::core::buffer::rope::node::{Node, NodeRef};
-use crate::core::buffer::chunk::Chunk;
-use std::cell::RefCell;
-use std::cmp::{min, max};
+//
+// Iterators are built on cursors that keep the path from the root to their current leaf:
+// seeking descends the tree once, in O(log n), and stepping to a neighbouring leaf climbs only
+// up to the closest common ancestor. Cursors walk both ways, and the iterators are double ended,
+// a front & a back cursor walking towards each other.
+use crate::core::buffer::rope::chunk::Chunk;
+use crate::core::buffer::rope::node::Node;
+use crate::core::buffer::rope::rope::Rope;
+use std::borrow::Cow;
 use std::fmt::{Debug, Formatter, Result as FmtResult};
-use std::iter::{Iterator, DoubleEndedIterator, ExactSizeIterator, FusedIterator};
+use std::iter::{DoubleEndedIterator, ExactSizeIterator, FusedIterator, Iterator};
 use std::ops::Range;
-use std::rc::Rc;
+use unicode_segmentation::{GraphemeCursor, GraphemeIncomplete};
 
-/// An iterator over a rope structure.
+/// A cursor on the leaves of a rope.
 #[derive(Clone)]
-pub struct RopeIterator {
-    stack: Vec<(NodeRef, usize)>,
-    reverse_stack: Vec<(NodeRef, usize)>,
-    current_chunk: Option<Chunk>,
-    current_index: usize,
-    total_length: usize,
-    traversed_length: usize,
-    range: Option<Range<usize>>,
-    is_forward: bool,
-    exhausted: bool,
+pub struct RopeChunkCursor<'a> {
+    root: &'a Node,
+    /// Internal nodes from the root down to the leaf, each with the index of the child taken.
+    path: Vec<(&'a Node, usize)>,
+    leaf: &'a Chunk,
+    /// Byte offset at which `leaf` starts.
+    start: usize,
 }
 
-/// Direction for rope traversal
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-pub enum TraversalDirection {
-    Forward,
-    Backward,
+impl Debug for RopeChunkCursor<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        f.debug_struct("RopeChunkCursor")
+            .field("start", &self.start)
+            .field("len", &self.leaf.len())
+            .field("depth", &self.path.len())
+            .finish()
+    }
 }
 
-/// Iterator over rope bytes
-pub struct RopeByteIterator {
-    rope_iter: RopeIterator,
+impl<'a> RopeChunkCursor<'a> {
+    /// Cursor on the leaf containing `byte_idx`, or on the last leaf when `byte_idx` is the end
+    /// of the text or past it. An offset between two leaves belongs to the second one.
+    pub fn new(root: &'a Node, byte_idx: usize) -> Self {
+        let mut path = Vec::with_capacity(root.depth());
+        let (leaf, start) = descend(root, byte_idx, &mut path);
+        Self { root, path, leaf, start }
+    }
+
+    /// Moves to the leaf containing `byte_idx`; returns false, without moving, past the end.
+    pub fn seek(&mut self, byte_idx: usize) -> bool {
+        if byte_idx > self.root.total_length() {
+            return false;
+        }
+        (self.leaf, self.start) = descend(self.root, byte_idx, &mut self.path);
+        true
+    }
+
+    #[inline]
+    pub fn chunk(&self) -> &'a Chunk {
+        self.leaf
+    }
+
+    /// Byte offset at which the current leaf starts.
+    #[inline]
+    pub fn start(&self) -> usize {
+        self.start
+    }
+
+    /// Byte offset at which the current leaf ends.
+    #[inline]
+    pub fn end(&self) -> usize {
+        self.start + self.leaf.len()
+    }
+
+    /// Moves to the next leaf and returns it, or returns `None` on the last leaf.
+    pub fn next_chunk(&mut self) -> Option<&'a Chunk> {
+        let level = self.path.iter().rposition(|(node, index)| index + 1 < node.child_count())?;
+        self.path.truncate(level + 1);
+        self.path[level].1 += 1;
+        let (parent, index) = self.path[level];
+        let mut node = parent.children()[index].as_ref();
+        while let [first, ..] = node.children() {
+            self.path.push((node, 0));
+            node = first;
+        }
+        self.start += self.leaf.len();
+        self.leaf = node.chunk().expect("nodes without children are leaves");
+        Some(self.leaf)
+    }
+
+    /// Moves to the previous leaf and returns it, or returns `None` on the first leaf.
+    pub fn prev_chunk(&mut self) -> Option<&'a Chunk> {
+        let level = self.path.iter().rposition(|&(_, index)| index > 0)?;
+        self.path.truncate(level + 1);
+        self.path[level].1 -= 1;
+        let (parent, index) = self.path[level];
+        let mut node = parent.children()[index].as_ref();
+        while let [.., last] = node.children() {
+            self.path.push((node, node.child_count() - 1));
+            node = last;
+        }
+        self.leaf = node.chunk().expect("nodes without children are leaves");
+        self.start -= self.leaf.len();
+        Some(self.leaf)
+    }
+
+    /// Steps to the neighbouring leaves until the current one contains `byte_idx`, its end included.
+    fn move_to(&mut self, byte_idx: usize) {
+        while byte_idx < self.start && self.prev_chunk().is_some() {}
+        while byte_idx > self.end() && self.next_chunk().is_some() {}
+    }
+
+    /// Text of `range`, which must start in the current leaf; borrowed when it ends there too.
+    fn text_in(&self, range: Range<usize>) -> Cow<'a, str> {
+        let text = self.leaf.text();
+        if range.end <= self.end() {
+            return Cow::Borrowed(&text[range.start - self.start..range.end - self.start]);
+        }
+        let mut owned = String::with_capacity(range.len());
+        owned.push_str(&text[range.start - self.start..]);
+        let mut chunks = self.clone();
+        while chunks.end() < range.end && chunks.next_chunk().is_some() {
+            let end = (range.end - chunks.start).min(chunks.leaf.len());
+            owned.push_str(&chunks.leaf.text()[..end]);
+        }
+        Cow::Owned(owned)
+    }
 }
 
-/// Iterator over rope lines
-pub struct RopeLineIterator {
-    rope_iter: RopeIterator,
-    current_line: String,
+/// Descends from `root` to the leaf containing `byte_idx`, recording the path taken, and returns
+/// the leaf with its start offset.
+fn descend<'a>(root: &'a Node, byte_idx: usize, path: &mut Vec<(&'a Node, usize)>) -> (&'a Chunk, usize) {
+    path.clear();
+    let mut node = root;
+    let mut start = 0;
+    loop {
+        if let Some(chunk) = node.chunk() {
+            return (chunk, start);
+        }
+        let children = node.children();
+        let mut index = 0;
+        while index + 1 < children.len() && byte_idx >= start + children[index].total_length() {
+            start += children[index].total_length();
+            index += 1;
+        }
+        path.push((node, index));
+        node = children[index].as_ref();
+    }
 }
 
-/// Iterator over rope chunks
-pub struct RopeChunkIterator {
-    stack: Vec<(NodeRef, usize)>,
-    range: Option<Range<usize>>,
-    traversed_length: usize,
+/// A cursor between two bytes of a rope.
+#[derive(Debug, Clone)]
+pub struct RopeByteCursor<'a> {
+    chunks: RopeChunkCursor<'a>,
+    position: usize,
 }
 
-impl Debug for RopeIterator {
-    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
-        f.debug_struct("RopeIterator")
-            .field("stack", &self.stack)
-            .field("current_chunk", &self.current_chunk)
-            .field("current_index", &self.current_index)
-            .field("total_length", &self.total_length)
-            .field("traversed_length", &self.traversed_length)
-            .field("range", &self.range)
-            .field("is_forward", &self.is_forward)
-            .field("exhausted", &self.exhausted)
-            .finish()
+impl<'a> RopeByteCursor<'a> {
+    /// Cursor before the byte at `byte_idx`, clamped to the end of the text.
+    pub fn new(root: &'a Node, byte_idx: usize) -> Self {
+        let position = byte_idx.min(root.total_length());
+        Self { chunks: RopeChunkCursor::new(root, position), position }
+    }
+
+    #[inline]
+    pub fn position(&self) -> usize {
+        self.position
+    }
+
+    /// Moves before the byte at `byte_idx`; returns false, without moving, past the end.
+    pub fn seek(&mut self, byte_idx: usize) -> bool {
+        if !self.chunks.seek(byte_idx) {
+            return false;
+        }
+        self.position = byte_idx;
+        true
+    }
+
+    /// Returns the byte after the cursor and moves past it.
+    pub fn next_byte(&mut self) -> Option<u8> {
+        if self.position == self.chunks.end() {
+            self.chunks.next_chunk()?;
+        }
+        let byte = self.chunks.leaf.text().as_bytes()[self.position - self.chunks.start];
+        self.position += 1;
+        Some(byte)
+    }
+
+    /// Returns the byte before the cursor and moves before it.
+    pub fn prev_byte(&mut self) -> Option<u8> {
+        if self.position == self.chunks.start {
+            self.chunks.prev_chunk()?;
+        }
+        self.position -= 1;
+        Some(self.chunks.leaf.text().as_bytes()[self.position - self.chunks.start])
     }
 }
 
-impl RopeIterator {
-    pub fn new(root: NodeRef, range: Option<Range<usize>>) -> Self {
-        let total_length = root.borrow().len();
-        let mut iter = Self {
-            stack: vec![(root.clone(), 0)],
-            reverse_stack: vec![(root, 0)],
-            current_chunk: None,
-            current_index: 0,
-            total_length,
-            traversed_length: 0,
-            range: range.clone(),
+/// A cursor between two chars of a rope.
+#[derive(Debug, Clone)]
+pub struct RopeCharCursor<'a> {
+    chunks: RopeChunkCursor<'a>,
+    position: usize,
+}
+
+impl<'a> RopeCharCursor<'a> {
+    /// Cursor before the char containing `byte_idx`, clamped to the end of the text.
+    pub fn new(root: &'a Node, byte_idx: usize) -> Self {
+        let byte_idx = byte_idx.min(root.total_length());
+        let chunks = RopeChunkCursor::new(root, byte_idx);
+        let text = chunks.leaf.text();
+        let local = (0..=byte_idx - chunks.start).rev().find(|&idx| text.is_char_boundary(idx)).unwrap_or(0);
+        Self { position: chunks.start + local, chunks }
+    }
+
+    /// Byte offset of the cursor.
+    #[inline]
+    pub fn position(&self) -> usize {
+        self.position
+    }
+
+    /// Moves before the char at `byte_idx`; returns false, without moving, when `byte_idx` is
+    /// past the end or not on a char boundary.
+    pub fn seek(&mut self, byte_idx: usize) -> bool {
+        if !self.chunks.seek(byte_idx) {
+            return false;
+        }
+        if !self.chunks.leaf.text().is_char_boundary(byte_idx - self.chunks.start) {
+            self.chunks.seek(self.position);
+            return false;
+        }
+        self.position = byte_idx;
+        true
+    }
+
+    /// Returns the char after the cursor and moves past it.
+    pub fn next_char(&mut self) -> Option<char> {
+        if self.position == self.chunks.end() {
+            self.chunks.next_chunk()?;
+        }
+        let ch = self.chunks.leaf.text()[self.position - self.chunks.start..].chars().next()?;
+        self.position += ch.len_utf8();
+        Some(ch)
+    }
+
+    /// Returns the char before the cursor and moves before it.
+    pub fn prev_char(&mut self) -> Option<char> {
+        if self.position == self.chunks.start {
+            self.chunks.prev_chunk()?;
+        }
+        let ch = self.chunks.leaf.text()[..self.position - self.chunks.start].chars().next_back()?;
+        self.position -= ch.len_utf8();
+        Some(ch)
+    }
+
+    /// Returns the char after the cursor without moving.
+    pub fn peek_char(&self) -> Option<char> {
+        let text = &self.chunks.leaf.text()[self.position - self.chunks.start..];
+        text.chars().next().or_else(|| self.clone().next_char())
+    }
+
+    /// Returns the char before the cursor without moving.
+    pub fn peek_prev_char(&self) -> Option<char> {
+        let text = &self.chunks.leaf.text()[..self.position - self.chunks.start];
+        text.chars().next_back().or_else(|| self.clone().prev_char())
+    }
+}
+
+/// A cursor between two extended grapheme clusters of a rope. Clusters may straddle leaves.
+#[derive(Debug, Clone)]
+pub struct RopeGraphemeCursor<'a> {
+    chunks: RopeChunkCursor<'a>,
+    position: usize,
+    len: usize,
+}
+
+impl<'a> RopeGraphemeCursor<'a> {
+    /// Cursor at `byte_idx`, which is floored to a char boundary and clamped to the end of the
+    /// text. It need not be a cluster boundary: stepping from inside a cluster reaches its ends.
+    pub fn new(root: &'a Node, byte_idx: usize) -> Self {
+        let chars = RopeCharCursor::new(root, byte_idx);
+        Self { chunks: chars.chunks, position: chars.position, len: root.total_length() }
+    }
+
+    /// Byte offset of the cursor.
+    #[inline]
+    pub fn position(&self) -> usize {
+        self.position
+    }
+
+    /// Moves to `byte_idx`; returns false, without moving, when `byte_idx` is past the end or
+    /// not on a char boundary.
+    pub fn seek(&mut self, byte_idx: usize) -> bool {
+        let mut chars = RopeCharCursor { chunks: self.chunks.clone(), position: self.position };
+        if !chars.seek(byte_idx) {
+            return false;
+        }
+        (self.chunks, self.position) = (chars.chunks, chars.position);
+        true
+    }
+
+    /// First cluster boundary after the cursor.
+    pub fn next_boundary(&self) -> Option<usize> {
+        if self.position == self.len {
+            return None;
+        }
+        let mut segmenter = GraphemeCursor::new(self.position, self.len, true);
+        // Only clusters straddling leaves need a cursor of their own.
+        let mut moved: Option<RopeChunkCursor<'a>> = None;
+        loop {
+            let chunks = moved.as_ref().unwrap_or(&self.chunks);
+            match segmenter.next_boundary(chunks.leaf.text(), chunks.start) {
+                Ok(boundary) => return boundary,
+                Err(GraphemeIncomplete::NextChunk) => {
+                    moved.get_or_insert_with(|| self.chunks.clone()).next_chunk()?;
+                }
+                Err(GraphemeIncomplete::PreContext(end)) => provide_context(&mut segmenter, chunks, end),
+                Err(incomplete) => unreachable!("{:?} while looking for the next boundary", incomplete),
+            }
+        }
+    }
+
+    /// Last cluster boundary before the cursor.
+    pub fn prev_boundary(&self) -> Option<usize> {
+        if self.position == 0 {
+            return None;
+        }
+        let mut segmenter = GraphemeCursor::new(self.position, self.len, true);
+        let mut moved: Option<RopeChunkCursor<'a>> = None;
+        loop {
+            let chunks = moved.as_ref().unwrap_or(&self.chunks);
+            match segmenter.prev_boundary(chunks.leaf.text(), chunks.start) {
+                Ok(boundary) => return boundary,
+                Err(GraphemeIncomplete::PrevChunk) => {
+                    moved.get_or_insert_with(|| self.chunks.clone()).prev_chunk()?;
+                }
+                Err(GraphemeIncomplete::PreContext(end)) => provide_context(&mut segmenter, chunks, end),
+                Err(incomplete) => unreachable!("{:?} while looking for the previous boundary", incomplete),
+            }
+        }
+    }
+
+    /// Returns the cluster after the cursor and moves past it. The text is borrowed from the
+    /// rope unless the cluster straddles two leaves.
+    pub fn next_grapheme(&mut self) -> Option<Cow<'a, str>> {
+        let end = self.next_boundary()?;
+        let text = self.chunks.text_in(self.position..end);
+        self.position = end;
+        self.chunks.move_to(end);
+        Some(text)
+    }
+
+    /// Returns the cluster before the cursor and moves before it.
+    pub fn prev_grapheme(&mut self) -> Option<Cow<'a, str>> {
+        let start = self.prev_boundary()?;
+        self.chunks.move_to(start);
+        let text = self.chunks.text_in(start..self.position);
+        self.position = start;
+        Some(text)
+    }
+}
+
+/// Hands `segmenter` the text that precedes `end`, which is the start of a leaf at or before
+/// the one `chunks` is on.
+fn provide_context(segmenter: &mut GraphemeCursor, chunks: &RopeChunkCursor<'_>, end: usize) {
+    let mut context = chunks.clone();
+    while context.start >= end {
+        context.prev_chunk().expect("context is only requested after the start of the text");
+    }
+    segmenter.provide_context(&context.leaf.text()[..end - context.start], context.start);
+}
+
+/// A cursor between two lines of a rope, lines ending as the rope's `LineBreaks` mode says.
+#[derive(Debug, Clone)]
+pub struct RopeLineCursor<'a> {
+    rope: &'a Rope,
+    line: usize,
+}
+
+impl<'a> RopeLineCursor<'a> {
+    /// Cursor before `line`, clamped to the end of the text.
+    pub fn new(rope: &'a Rope, line: usize) -> Self {
+        Self { rope, line: line.min(rope.len_lines()) }
+    }
+
+    /// Index of the line after the cursor.
+    #[inline]
+    pub fn line(&self) -> usize {
+        self.line
+    }
+
+    /// Moves before `line`; returns false, without moving, when there are fewer lines.
+    pub fn seek(&mut self, line: usize) -> bool {
+        if line > self.rope.len_lines() {
+            return false;
+        }
+        self.line = line;
+        true
+    }
+
+    /// Returns the line after the cursor, without its line ending, and moves past it.
+    pub fn next_line(&mut self) -> Option<String> {
+        let text = self.rope.line(self.line).ok()?;
+        self.line += 1;
+        Some(text)
+    }
+
+    /// Returns the line before the cursor, without its line ending, and moves before it.
+    pub fn prev_line(&mut self) -> Option<String> {
+        let line = self.line.checked_sub(1)?;
+        let text = self.rope.line(line).ok()?;
+        self.line = line;
+        Some(text)
+    }
+}
+
+/// Iterator over the chars of a rope, or of a range of it.
+///
+/// `next` walks from the start of the range, or from its end once the iterator is set backward,
+/// and `next_back` walks from the other end.
+#[derive(Debug, Clone)]
+pub struct RopeIterator<'a> {
+    front: RopeCharCursor<'a>,
+    back: RopeCharCursor<'a>,
+    range: Range<usize>,
+    is_forward: bool,
+}
+
+/// Direction for rope traversal
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TraversalDirection {
+    Forward,
+    Backward,
+}
+
+impl<'a> RopeIterator<'a> {
+    /// Iterates over `range`, whose bounds must be char boundaries, or over the whole text.
+    pub fn new(root: &'a Node, range: Option<Range<usize>>) -> Self {
+        let range = range.unwrap_or(0..root.total_length());
+        Self {
+            front: RopeCharCursor::new(root, range.start),
+            back: RopeCharCursor::new(root, range.end),
+            range,
             is_forward: true,
-            exhausted: false,
-        };
-        
-        if let Some(ref range) = range {
-            iter.seek_to_position(range.start);
-        } else {
-            iter.advance_to_next_chunk();
         }
-        
-        iter
     }
 
-    pub fn with_direction(root: NodeRef, range: Option<Range<usize>>, direction: TraversalDirection) -> Self {
+    pub fn with_direction(root: &'a Node, range: Option<Range<usize>>, direction: TraversalDirection) -> Self {
         let mut iter = Self::new(root, range);
         match direction {
             TraversalDirection::Forward => iter.set_forward(),
@@ -112,72 +468,49 @@ impl RopeIterator {
         }
     }
 
+    /// Bytes left between the two ends.
     pub fn remaining_length(&self) -> usize {
-        if let Some(ref range) = self.range {
-            range.end.saturating_sub(self.traversed_length)
-        } else {
-            self.total_length.saturating_sub(self.traversed_length)
-        }
+        self.back.position() - self.front.position()
     }
 
+    /// Byte offset of the end `next` walks from.
     pub fn position(&self) -> usize {
-        self.traversed_length
+        if self.is_forward {
+            self.front.position()
+        } else {
+            self.back.position()
+        }
     }
 
+    /// Moves the end `next` walks from to `pos`, in O(log n). Returns false, without moving, when
+    /// `pos` is not a char boundary or lies outside the range or beyond the other end.
     pub fn seek_to_position(&mut self, pos: usize) -> bool {
-        if pos > self.total_length {
-            return false;
-        }
-
-        self.reset();
-        self.traversed_length = 0;
-
-        while self.traversed_length < pos && !self.exhausted {
-            if let Some(chunk) = &self.current_chunk {
-                let chunk_len = chunk.len();
-                let remaining_to_pos = pos - self.traversed_length;
-                
-                if remaining_to_pos < chunk_len - self.current_index {
-                    self.current_index += remaining_to_pos;
-                    self.traversed_length += remaining_to_pos;
-                    return true;
-                } else {
-                    let advance_amount = chunk_len - self.current_index;
-                    self.traversed_length += advance_amount;
-                    self.advance_to_next_chunk();
-                }
-            } else {
-                break;
-            }
+        if self.is_forward {
+            pos >= self.range.start && pos <= self.back.position() && self.front.seek(pos)
+        } else {
+            pos <= self.range.end && pos >= self.front.position() && self.back.seek(pos)
         }
-
-        self.traversed_length == pos
     }
 
+    /// Returns the char `next` would return.
     pub fn peek(&self) -> Option<char> {
-        self.current_char()
+        if self.remaining_length() == 0 {
+            return None;
+        }
+        if self.is_forward {
+            self.front.peek_char()
+        } else {
+            self.back.peek_prev_char()
+        }
     }
 
+    /// Returns the char `next` would return after skipping `n` chars.
     pub fn peek_ahead(&self, n: usize) -> Option<char> {
-        let mut temp_iter = self.clone();
-        for _ in 0..n {
-            temp_iter.next()?;
-        }
-        temp_iter.current_char()
+        self.clone().nth(n)
     }
 
     pub fn collect_string(&mut self, max_chars: Option<usize>) -> String {
-        let mut result = String::new();
-        let limit = max_chars.unwrap_or(usize::MAX);
-        
-        for (i, ch) in self.enumerate() {
-            if i >= limit {
-                break;
-            }
-            result.push(ch);
-        }
-        
-        result
+        self.take(max_chars.unwrap_or(usize::MAX)).collect()
     }
 
     pub fn skip_while<F>(&mut self, mut predicate: F) -> usize
@@ -185,11 +518,8 @@ impl RopeIterator {
         F: FnMut(char) -> bool,
     {
         let mut skipped = 0;
-        while let Some(ch) = self.current_char() {
-            if !predicate(ch) {
-                break;
-            }
-            self.advance_char();
+        while self.peek().is_some_and(&mut predicate) {
+            self.next();
             skipped += 1;
         }
         skipped
@@ -200,237 +530,453 @@ impl RopeIterator {
         F: FnMut(char) -> bool,
     {
         let mut result = String::new();
-        while let Some(ch) = self.current_char() {
-            if !predicate(ch) {
-                break;
-            }
+        while let Some(ch) = self.peek().filter(|&ch| predicate(ch)) {
             result.push(ch);
-            self.advance_char();
+            self.next();
         }
         result
     }
 
-    pub fn bytes(self) -> RopeByteIterator {
-        RopeByteIterator { rope_iter: self }
+    /// Iterates over the bytes left between the two ends.
+    pub fn bytes(self) -> RopeByteIterator<'a> {
+        RopeByteIterator {
+            front: RopeByteCursor { chunks: self.front.chunks, position: self.front.position },
+            back: RopeByteCursor { chunks: self.back.chunks, position: self.back.position },
+        }
     }
 
-    pub fn lines(self) -> RopeLineIterator {
-        RopeLineIterator {
-            rope_iter: self,
-            current_line: String::new(),
+    fn next_forward(&mut self) -> Option<char> {
+        if self.remaining_length() == 0 {
+            return None;
         }
+        self.front.next_char()
     }
 
-    pub fn chunks(root: NodeRef, range: Option<Range<usize>>) -> RopeChunkIterator {
-        RopeChunkIterator {
-            stack: vec![(root, 0)],
-            range,
-            traversed_length: 0,
+    fn next_backward(&mut self) -> Option<char> {
+        if self.remaining_length() == 0 {
+            return None;
         }
+        self.back.prev_char()
     }
+}
 
-    fn reset(&mut self) {
-        self.stack.clear();
-        self.current_chunk = None;
-        self.current_index = 0;
-        self.traversed_length = 0;
-        self.exhausted = false;
-    }
+impl Iterator for RopeIterator<'_> {
+    type Item = char;
 
-    fn advance_to_next_chunk(&mut self) {
-        while let Some((node_ref, child_index)) = self.stack.pop() {
-            let node = node_ref.borrow();
-            
-            if node.is_leaf() {
-                self.current_chunk = Some(node.get_chunk().clone());
-                self.current_index = 0;
-                return;
-            }
-            
-            if child_index < node.children.len() {
-                self.stack.push((node_ref.clone(), child_index + 1));
-                self.stack.push((node.children[child_index].clone(), 0));
-            }
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.is_forward {
+            self.next_forward()
+        } else {
+            self.next_backward()
         }
-        
-        self.current_chunk = None;
-        self.current_index = 0;
-        self.exhausted = true;
     }
 
-    fn advance_to_prev_chunk(&mut self) {
-        while let Some((node_ref, child_index)) = self.reverse_stack.pop() {
-            let node = node_ref.borrow();
-            
-            if node.is_leaf() {
-                self.current_chunk = Some(node.get_chunk().clone());
-                if let Some(chunk) = &self.current_chunk {
-                    self.current_index = chunk.len().saturating_sub(1);
-                }
-                return;
-            }
-            
-            if child_index > 0 {
-                self.reverse_stack.push((node_ref.clone(), child_index - 1));
-                if let Some(child) = node.children.get(child_index - 1) {
-                    let child_node = child.borrow();
-                    let last_index = if child_node.is_leaf() { 0 } else { child_node.children.len() };
-                    self.reverse_stack.push((child.clone(), last_index));
-                }
-            }
-        }
-        
-        self.current_chunk = None;
-        self.current_index = 0;
-        self.exhausted = true;
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let remaining = self.remaining_length();
+        (remaining.div_ceil(4), Some(remaining))
     }
+}
 
-    fn within_range(&self) -> bool {
-        match &self.range {
-            Some(range) => self.traversed_length >= range.start && self.traversed_length < range.end,
-            None => true,
+impl DoubleEndedIterator for RopeIterator<'_> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        if self.is_forward {
+            self.next_backward()
+        } else {
+            self.next_forward()
         }
     }
+}
+
+impl FusedIterator for RopeIterator<'_> {}
+
+/// Iterator over the bytes of a rope, or of a range of it.
+#[derive(Debug, Clone)]
+pub struct RopeByteIterator<'a> {
+    front: RopeByteCursor<'a>,
+    back: RopeByteCursor<'a>,
+}
+
+impl<'a> RopeByteIterator<'a> {
+    pub fn new(root: &'a Node, range: Range<usize>) -> Self {
+        Self { front: RopeByteCursor::new(root, range.start), back: RopeByteCursor::new(root, range.end) }
+    }
+}
 
-    fn advance_char(&mut self) {
-        if self.exhausted {
-            return;
+impl Iterator for RopeByteIterator<'_> {
+    type Item = u8;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.front.position >= self.back.position {
+            return None;
         }
+        self.front.next_byte()
+    }
 
-        if let Some(chunk) = &self.current_chunk {
-            if self.is_forward {
-                self.current_index += 1;
-                self.traversed_length += 1;
-                
-                if self.current_index >= chunk.len() {
-                    self.advance_to_next_chunk();
-                }
-            } else {
-                if self.current_index == 0 {
-                    self.advance_to_prev_chunk();
-                } else {
-                    self.current_index -= 1;
-                    self.traversed_length = self.traversed_length.saturating_sub(1);
-                }
-            }
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let remaining = self.back.position.saturating_sub(self.front.position);
+        (remaining, Some(remaining))
+    }
+}
+
+impl DoubleEndedIterator for RopeByteIterator<'_> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        if self.front.position >= self.back.position {
+            return None;
         }
+        self.back.prev_byte()
+    }
+}
 
-        if let Some(ref range) = self.range {
-            if (self.is_forward && self.traversed_length >= range.end) ||
-               (!self.is_forward && self.traversed_length < range.start) {
-                self.exhausted = true;
-            }
+impl ExactSizeIterator for RopeByteIterator<'_> {}
+
+impl FusedIterator for RopeByteIterator<'_> {}
+
+/// Iterator over the extended grapheme clusters of a rope.
+#[derive(Debug, Clone)]
+pub struct RopeGraphemeIterator<'a> {
+    front: RopeGraphemeCursor<'a>,
+    back: RopeGraphemeCursor<'a>,
+}
+
+impl<'a> RopeGraphemeIterator<'a> {
+    pub fn new(root: &'a Node) -> Self {
+        Self { front: RopeGraphemeCursor::new(root, 0), back: RopeGraphemeCursor::new(root, root.total_length()) }
+    }
+}
+
+impl<'a> Iterator for RopeGraphemeIterator<'a> {
+    type Item = Cow<'a, str>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.front.position >= self.back.position {
+            return None;
         }
+        self.front.next_grapheme()
     }
+}
 
-    fn current_char(&self) -> Option<char> {
-        if self.exhausted || !self.within_range() {
+impl DoubleEndedIterator for RopeGraphemeIterator<'_> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        if self.front.position >= self.back.position {
             return None;
         }
-        
-        self.current_chunk
-            .as_ref()?
-            .char_at(self.current_index)
+        self.back.prev_grapheme()
     }
 }
 
-impl Iterator for RopeIterator {
-    type Item = char;
+impl FusedIterator for RopeGraphemeIterator<'_> {}
+
+/// Iterator over the lines of a rope, without their line endings. A text ending with a line
+/// break ends with an empty line, so there are always `Rope::len_lines` lines.
+#[derive(Debug, Clone)]
+pub struct RopeLineIterator<'a> {
+    front: RopeLineCursor<'a>,
+    back: RopeLineCursor<'a>,
+}
+
+impl<'a> RopeLineIterator<'a> {
+    pub fn new(rope: &'a Rope) -> Self {
+        Self { front: RopeLineCursor::new(rope, 0), back: RopeLineCursor::new(rope, rope.len_lines()) }
+    }
+}
+
+impl Iterator for RopeLineIterator<'_> {
+    type Item = String;
 
     fn next(&mut self) -> Option<Self::Item> {
-        let ch = self.current_char()?;
-        self.advance_char();
-        Some(ch)
+        if self.front.line >= self.back.line {
+            return None;
+        }
+        self.front.next_line()
     }
 
     fn size_hint(&self) -> (usize, Option<usize>) {
-        let remaining = self.remaining_length();
+        let remaining = self.back.line.saturating_sub(self.front.line);
         (remaining, Some(remaining))
     }
 }
 
-impl DoubleEndedIterator for RopeIterator {
+impl DoubleEndedIterator for RopeLineIterator<'_> {
     fn next_back(&mut self) -> Option<Self::Item> {
-        if self.exhausted {
+        if self.front.line >= self.back.line {
             return None;
         }
-        
-        self.set_backward();
-        let ch = self.current_char()?;
-        self.advance_char();
-        Some(ch)
+        self.back.prev_line()
     }
 }
 
-impl ExactSizeIterator for RopeIterator {
-    fn len(&self) -> usize {
-        self.remaining_length()
-    }
-}
+impl ExactSizeIterator for RopeLineIterator<'_> {}
 
-impl FusedIterator for RopeIterator {}
+impl FusedIterator for RopeLineIterator<'_> {}
 
-impl Iterator for RopeByteIterator {
-    type Item = u8;
+/// Iterator over the leaves of a rope.
+#[derive(Debug, Clone)]
+pub struct RopeChunkIterator<'a> {
+    front: RopeChunkCursor<'a>,
+    back: RopeChunkCursor<'a>,
+    /// Set once the two ends have met.
+    done: bool,
+}
 
-    fn next(&mut self) -> Option<Self::Item> {
-        self.rope_iter.next().map(|ch| {
-            let mut buf = [0; 4];
-            let bytes = ch.encode_utf8(&mut buf).as_bytes();
-            bytes[0]
-        })
+impl<'a> RopeChunkIterator<'a> {
+    pub fn new(root: &'a Node) -> Self {
+        Self {
+            front: RopeChunkCursor::new(root, 0),
+            back: RopeChunkCursor::new(root, root.total_length()),
+            // Only the root leaf of an empty rope is empty.
+            done: root.is_empty(),
+        }
     }
 }
 
-impl Iterator for RopeLineIterator {
-    type Item = String;
+impl<'a> Iterator for RopeChunkIterator<'a> {
+    type Item = &'a Chunk;
 
     fn next(&mut self) -> Option<Self::Item> {
-        if self.rope_iter.exhausted {
+        if self.done {
             return None;
         }
-
-        self.current_line.clear();
-        
-        while let Some(ch) = self.rope_iter.next() {
-            if ch == '\n' {
-                break;
-            }
-            self.current_line.push(ch);
+        let chunk = self.front.chunk();
+        if self.front.start == self.back.start {
+            self.done = true;
+        } else {
+            self.front.next_chunk();
         }
+        Some(chunk)
+    }
+}
 
-        if self.current_line.is_empty() && self.rope_iter.exhausted {
-            None
+impl DoubleEndedIterator for RopeChunkIterator<'_> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let chunk = self.back.chunk();
+        if self.front.start == self.back.start {
+            self.done = true;
         } else {
-            Some(self.current_line.clone())
+            self.back.prev_chunk();
         }
+        Some(chunk)
     }
 }
 
-impl Iterator for RopeChunkIterator {
-    type Item = Chunk;
+impl FusedIterator for RopeChunkIterator<'_> {}
 
-    fn next(&mut self) -> Option<Self::Item> {
-        while let Some((node_ref, child_index)) = self.stack.pop() {
-            let node = node_ref.borrow();
-            
-            if node.is_leaf() {
-                let chunk = node.get_chunk().clone();
-                if let Some(ref range) = self.range {
-                    if self.traversed_length >= range.end {
-                        return None;
-                    }
-                }
-                self.traversed_length += chunk.len();
-                return Some(chunk);
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::buffer::rope::{LeafSizePolicy, LineBreaks};
+    use proptest::prelude::*;
+    use unicode_segmentation::UnicodeSegmentation;
+
+    /// Builds a rope of many small leaves so that every walk crosses leaf boundaries.
+    fn small_leaves(text: &str) -> Rope {
+        Rope::from_text(text).with_leaf_sizes(LeafSizePolicy::new(4, 136).unwrap())
+    }
+
+    fn sample() -> String {
+        "héllo\r\nwörld 🌍 e\u{301} 👍🏽 🇫🇷\n\r\n".repeat(40)
+    }
+
+    #[test]
+    fn test_chunk_cursor_walks_and_seeks() {
+        let text = sample();
+        let rope = small_leaves(&text);
+        let chunks: Vec<&Chunk> = rope.chunks().collect();
+        assert!(chunks.len() > 10);
+        assert_eq!(chunks.iter().map(|chunk| chunk.text()).collect::<String>(), text);
+        let reversed: Vec<&Chunk> = rope.chunks().rev().collect();
+        assert!(reversed.iter().rev().zip(&chunks).all(|(a, b)| std::ptr::eq(*a, *b)));
+
+        let mut cursor = rope.chunk_cursor(0).unwrap();
+        let mut starts = vec![cursor.start()];
+        while cursor.next_chunk().is_some() {
+            starts.push(cursor.start());
+        }
+        assert_eq!(cursor.end(), text.len());
+        let mut backward = vec![cursor.start()];
+        while cursor.prev_chunk().is_some() {
+            backward.push(cursor.start());
+        }
+        backward.reverse();
+        assert_eq!(backward, starts);
+
+        for (index, &start) in starts.iter().enumerate() {
+            assert!(cursor.seek(start + chunks[index].len() / 2));
+            assert_eq!(cursor.start(), start);
+            assert!(std::ptr::eq(cursor.chunk(), chunks[index]));
+        }
+        assert!(cursor.seek(text.len()));
+        assert_eq!(cursor.end(), text.len());
+        assert!(!cursor.seek(text.len() + 1));
+        assert!(rope.chunk_cursor(text.len() + 1).is_err());
+    }
+
+    #[test]
+    fn test_bytes_match_str() {
+        let text = sample();
+        let rope = small_leaves(&text);
+        assert_eq!(rope.bytes().collect::<Vec<u8>>(), text.as_bytes());
+        assert_eq!(rope.bytes().rev().collect::<Vec<u8>>(), text.bytes().rev().collect::<Vec<u8>>());
+        assert_eq!(rope.bytes().len(), text.len());
+
+        let mut bytes = rope.bytes();
+        let mut expected = text.bytes();
+        for step in 0.. {
+            let (got, want) = if step % 3 == 0 { (bytes.next_back(), expected.next_back()) } else { (bytes.next(), expected.next()) };
+            assert_eq!(got, want);
+            if got.is_none() {
+                break;
             }
-            
-            if child_index < node.children.len() {
-                self.stack.push((node_ref.clone(), child_index + 1));
-                self.stack.push((node.children[child_index].clone(), 0));
+        }
+
+        let mut cursor = rope.byte_cursor(7).unwrap();
+        assert_eq!(cursor.prev_byte(), Some(b'\r'));
+        assert_eq!(cursor.next_byte(), Some(b'\r'));
+        assert_eq!(cursor.next_byte(), Some(b'\n'));
+        assert!(cursor.seek(text.len()));
+        assert_eq!(cursor.next_byte(), None);
+        assert_eq!(cursor.prev_byte(), text.bytes().last());
+    }
+
+    #[test]
+    fn test_chars_match_str() {
+        let text = sample();
+        let rope = small_leaves(&text);
+        assert_eq!(rope.iter().collect::<String>(), text);
+        assert_eq!(rope.iter().rev().collect::<String>(), text.chars().rev().collect::<String>());
+        let backward = RopeIterator::with_direction(rope.root(), None, TraversalDirection::Backward);
+        assert_eq!(backward.collect::<String>(), text.chars().rev().collect::<String>());
+
+        let start = text.find('🌍').unwrap();
+        let end = text.rfind('ö').unwrap();
+        let last = text[..end].chars().next_back().unwrap();
+        let mut iter = rope.iter_range(start..end).unwrap();
+        assert_eq!(iter.peek(), Some('🌍'));
+        assert_eq!(iter.next_back(), Some(last));
+        assert_eq!(iter.collect::<String>(), &text[start..end - last.len_utf8()]);
+
+        let mut cursor = rope.char_cursor(start).unwrap();
+        assert_eq!(cursor.prev_char(), Some(' '));
+        assert_eq!(cursor.next_char(), Some(' '));
+        assert_eq!(cursor.peek_char(), Some('🌍'));
+        assert!(!cursor.seek(start + 1));
+        assert_eq!(cursor.position(), start);
+        assert_eq!(rope.char_cursor(start + 1).unwrap_err(), crate::core::buffer::rope::RopeError::NotCharBoundary(start + 1));
+    }
+
+    #[test]
+    fn test_iterator_seek_and_helpers() {
+        let rope = small_leaves(&"abc def\n".repeat(100));
+        let mut iter = rope.iter();
+        assert!(iter.seek_to_position(400));
+        // The inherent helpers borrow the iterator, unlike their `Iterator` namesakes.
+        assert_eq!(RopeIterator::take_while(&mut iter, |ch| ch != ' '), "abc");
+        assert_eq!(RopeIterator::skip_while(&mut iter, char::is_whitespace), 1);
+        assert_eq!(iter.peek_ahead(2), Some('f'));
+        assert_eq!(iter.collect_string(Some(3)), "def");
+        assert_eq!(iter.position(), 407);
+
+        iter.set_backward();
+        assert!(!iter.seek_to_position(16));
+        assert!(iter.seek_to_position(416));
+        assert_eq!(iter.next(), Some('\n'));
+        assert_eq!(iter.position(), 415);
+        assert_eq!(iter.remaining_length(), 8);
+        assert_eq!(iter.next_back(), Some('\n'));
+        assert_eq!(iter.collect::<String>(), "fed cba");
+    }
+
+    #[test]
+    fn test_graphemes_match_str() {
+        let text = sample();
+        let rope = small_leaves(&text);
+        let expected: Vec<&str> = text.graphemes(true).collect();
+        assert_eq!(rope.graphemes().collect::<Vec<_>>(), expected);
+        assert_eq!(rope.graphemes().rev().collect::<Vec<_>>(), expected.iter().rev().copied().collect::<Vec<_>>());
+        assert_eq!(rope.graphemes().count(), rope.len_graphemes());
+
+        let flag = text.find('🇫').unwrap();
+        let mut cursor = rope.grapheme_cursor(flag + 4).unwrap();
+        assert_eq!(cursor.prev_boundary(), Some(flag));
+        assert_eq!(cursor.next_boundary(), Some(flag + 8));
+        assert_eq!(cursor.next_grapheme().as_deref(), Some(&text[flag + 4..flag + 8]));
+        assert!(cursor.seek(flag));
+        assert_eq!(cursor.next_grapheme().as_deref(), Some("🇫🇷"));
+        assert_eq!(cursor.prev_grapheme().as_deref(), Some("🇫🇷"));
+        assert_eq!(cursor.prev_grapheme().as_deref(), Some(" "));
+    }
+
+    #[test]
+    fn test_lines_keep_last_empty_line_and_strip_crlf() {
+        let rope = small_leaves("one\r\ntwo\n\nthree\r\n");
+        assert_eq!(rope.lines().collect::<Vec<_>>(), vec!["one", "two", "", "three", ""]);
+        assert_eq!(rope.lines().rev().collect::<Vec<_>>(), vec!["", "three", "", "two", "one"]);
+        assert_eq!(rope.lines().len(), rope.len_lines());
+        assert_eq!(Rope::new().lines().collect::<Vec<_>>(), vec![""]);
+
+        let mut rope = small_leaves("a\rb\u{2028}c\r\nd");
+        assert_eq!(rope.lines().collect::<Vec<_>>(), vec!["a\rb\u{2028}c", "d"]);
+        rope.set_line_breaks(LineBreaks::Unicode);
+        assert_eq!(rope.lines().collect::<Vec<_>>(), vec!["a", "b", "c", "d"]);
+
+        let mut cursor = rope.line_cursor(2).unwrap();
+        assert_eq!(cursor.prev_line().as_deref(), Some("b"));
+        assert_eq!(cursor.next_line().as_deref(), Some("b"));
+        assert!(cursor.seek(4));
+        assert_eq!(cursor.next_line(), None);
+        assert!(!cursor.seek(5));
+        assert!(rope.line_cursor(5).is_err());
+    }
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(64))]
+
+        #[test]
+        fn prop_cursors_match_str_from_any_offset(
+            text in "([a-zé🌍\r\n]|e\u{301}|🇫🇷|👍🏽){0,400}",
+            at in any::<usize>(),
+            steps in 0usize..40,
+        ) {
+            let rope = small_leaves(&text);
+            let at = text.char_indices().map(|(idx, _)| idx).chain([text.len()]).nth(at % (text.chars().count() + 1)).unwrap();
+
+            let mut bytes = rope.byte_cursor(at).unwrap();
+            let forward: Vec<u8> = (0..steps).map_while(|_| bytes.next_byte()).collect();
+            prop_assert_eq!(&forward[..], &text.as_bytes()[at..(at + steps).min(text.len())]);
+            let mut bytes = rope.byte_cursor(at).unwrap();
+            let backward: Vec<u8> = (0..steps).map_while(|_| bytes.prev_byte()).collect();
+            prop_assert_eq!(backward, text.as_bytes()[at.saturating_sub(steps)..at].iter().rev().copied().collect::<Vec<_>>());
+
+            let mut chars = rope.char_cursor(at).unwrap();
+            let forward: String = (0..steps).map_while(|_| chars.next_char()).collect();
+            prop_assert_eq!(forward, text[at..].chars().take(steps).collect::<String>());
+            let mut chars = rope.char_cursor(at).unwrap();
+            let backward: String = (0..steps).map_while(|_| chars.prev_char()).collect();
+            prop_assert_eq!(backward, text[..at].chars().rev().take(steps).collect::<String>());
+
+            let boundaries: Vec<usize> = text.grapheme_indices(true).map(|(idx, _)| idx).chain([text.len()]).collect();
+            let at = boundaries[at % boundaries.len()];
+            let mut graphemes = rope.grapheme_cursor(at).unwrap();
+            let forward: Vec<String> = (0..steps).map_while(|_| graphemes.next_grapheme().map(Cow::into_owned)).collect();
+            prop_assert_eq!(forward, text[at..].graphemes(true).take(steps).map(str::to_string).collect::<Vec<_>>());
+            let mut graphemes = rope.grapheme_cursor(at).unwrap();
+            let backward: Vec<String> = (0..steps).map_while(|_| graphemes.prev_grapheme().map(Cow::into_owned)).collect();
+            prop_assert_eq!(backward, text[..at].graphemes(true).rev().take(steps).map(str::to_string).collect::<Vec<_>>());
+        }
+
+        #[test]
+        fn prop_lines_match_split(text in "[ab\r\n]{0,300}") {
+            let rope = small_leaves(&text);
+            let mut expected: Vec<&str> = text.split('\n').collect();
+            let last = expected.len() - 1;
+            // Only a `\r` followed by the `\n` ending the line belongs to the line ending.
+            for line in &mut expected[..last] {
+                *line = line.strip_suffix('\r').unwrap_or(line);
             }
+            prop_assert_eq!(rope.lines().collect::<Vec<_>>(), expected.clone());
+            prop_assert_eq!(rope.lines().rev().collect::<Vec<_>>(), expected.into_iter().rev().collect::<Vec<_>>());
         }
-        
-        None
     }
-}
\ No newline at end of file
+}
@@ -1,10 +1,7 @@
 /// metrics.rs
 /// File for tracking various metrics related to the rope data structure.
 use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
-use std::sync::Arc;
-use std::time::{Duration, Instant};
-use tokio::sync::RwLock;
-use tracing::{debug, error, info, instrument, trace, warn};
+use std::time::Duration;
 
 /// Struct to hold various metrics related to the rope data structure.
 #[derive(Debug, Default)]
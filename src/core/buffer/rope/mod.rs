@@ -1,4 +1,16 @@
-mod chunk;
-mod metrics;
-mod node;
-mod rope;
\ No newline at end of file
+//! Rope module
+//! Reexports chunk, iterator, metrics, node, rope, search and summary modules
+
+pub mod chunk;
+pub mod iterator;
+pub mod metrics;
+pub mod node;
+pub mod summary;
+#[allow(clippy::module_inception)]
+pub mod rope;
+pub mod search;
+
+pub use node::LeafSizePolicy;
+pub use rope::{Rope, RopeError};
+pub use search::{SearchError, SearchMatches, SearchOptions, SearchQuery};
+pub use summary::{ClusterContext, LineBreaks, TextSummary};
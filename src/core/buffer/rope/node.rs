@@ -1,266 +1,526 @@
 /// node.rs --- Node structure for rope data structure in text buffer
-
-use crate::core::buffer::content::streaming::{StreamReader, StreamWriter};
-use crate::core::buffer::content::validation::validate_content;
-use crate::core::buffer::content::{encoding::Encoding, line_ending::LineEnding};
-use std::rc::Rc;
-use std::cell::RefCell;
+/// Nodes form a B-tree: every leaf sits at the same depth, internal nodes hold between
+/// `MIN_CHILDREN` & `MAX_CHILDREN` children (the root excepted), and each node caches the
+/// `TextSummary` of its subtree so lookups never walk more than one path.
+/// Children are shared through `Arc`: nodes are never mutated while shared, an edit copies only
+/// the nodes on its root-to-leaf path (`Arc::make_mut`) and reuses every other subtree.
+use crate::core::buffer::memory::pool::ChunkPool;
+use crate::core::buffer::rope::chunk::Chunk;
+use crate::core::buffer::rope::metrics::RopeMetrics;
+use crate::core::buffer::rope::summary::TextSummary;
 use std::ops::Range;
+use std::sync::Arc;
+use unicode_segmentation::GraphemeCursor;
 
-#[derive(Debug, Clone)]
-pub struct Node {
-    pub content: Rc<RefCell<String>>,
-    pub encoding: Encoding,
-    pub line_ending: LineEnding,
-    pub children: Vec<Node>,
-    pub length: usize,
+/// Default maximum number of bytes stored in a single leaf chunk.
+pub const MAX_CHUNK_SIZE: usize = 1024;
+
+/// Default size under which leaves are merged with a sibling (the root leaf excepted).
+pub const MIN_CHUNK_SIZE: usize = MAX_CHUNK_SIZE / 4;
+
+/// Maximum number of children of an internal node.
+pub const MAX_CHILDREN: usize = 8;
+
+/// Minimum number of children of a non-root internal node.
+pub const MIN_CHILDREN: usize = MAX_CHILDREN / 2;
+
+/// Shared handle to a node; cloning it is O(1) and never copies text.
+pub type NodeRef = Arc<Node>;
+
+/// Bounds on the size of leaves: longer leaves are split and, the root leaf excepted, shorter
+/// ones are merged with a sibling.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct LeafSizePolicy {
+    min_bytes: usize,
+    max_bytes: usize,
 }
 
-impl Node {
-    pub fn new(content: String, encoding: Encoding, line_ending: LineEnding) -> Self {
-        let length = content.len();
-        Node {
-            content: Rc::new(RefCell::new(content)),
-            encoding,
-            line_ending,
-            children: Vec::new(),
-            length,
+impl LeafSizePolicy {
+    /// Returns `None` unless `max_bytes` leaves room for two minimal leaves plus the slack
+    /// splits need to reach a grapheme boundary, which keeps split & merged leaves in bounds.
+    pub fn new(min_bytes: usize, max_bytes: usize) -> Option<Self> {
+        if max_bytes < 2 * min_bytes + 4 * SPLIT_SLACK {
+            return None;
         }
+        Some(Self { min_bytes, max_bytes })
     }
 
-    pub fn with_capacity(content: String, encoding: Encoding, line_ending: LineEnding, capacity: usize) -> Self {
-        let length = content.len();
-        Node {
-            content: Rc::new(RefCell::new(content)),
-            encoding,
-            line_ending,
-            children: Vec::with_capacity(capacity),
-            length,
-        }
+    #[inline]
+    pub fn min_bytes(&self) -> usize {
+        self.min_bytes
     }
 
-    pub fn add_child(&mut self, child: Node) {
-        self.length += child.total_length();
-        self.children.push(child);
+    #[inline]
+    pub fn max_bytes(&self) -> usize {
+        self.max_bytes
     }
+}
 
-    pub fn insert_child(&mut self, index: usize, child: Node) {
-        if index <= self.children.len() {
-            self.length += child.total_length();
-            self.children.insert(index, child);
-        }
+impl Default for LeafSizePolicy {
+    fn default() -> Self {
+        Self { min_bytes: MIN_CHUNK_SIZE, max_bytes: MAX_CHUNK_SIZE }
     }
+}
 
-    pub fn remove_child(&mut self, index: usize) -> Option<Node> {
-        if index < self.children.len() {
-            let child = self.children.remove(index);
-            self.length -= child.total_length();
-            Some(child)
-        } else {
-            None
-        }
-    }
+/// What node edits report to & allocate from, and the leaf sizes they maintain.
+#[derive(Debug, Clone, Copy)]
+pub struct NodeContext<'a> {
+    pub metrics: &'a RopeMetrics,
+    pub pool: &'a ChunkPool,
+    pub leaves: LeafSizePolicy,
+}
 
-    pub fn get_content(&self) -> String {
-        self.content.borrow().clone()
-    }
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum NodeData {
+    Leaf(Chunk),
+    Internal(Vec<NodeRef>),
+}
 
-    pub fn get_content_ref(&self) -> std::cell::Ref<String> {
-        self.content.borrow()
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Node {
+    summary: TextSummary,
+    height: usize,
+    data: NodeData,
+}
+
+impl Node {
+    pub fn new() -> Self {
+        Self::leaf(Chunk::default())
     }
 
-    pub fn set_content(&mut self, new_content: String) -> Result<(), String> {
-        match validate_content(&new_content, self.encoding) {
-            Ok(_) => {
-                let old_length = self.length;
-                let new_length = new_content.len();
-                *self.content.borrow_mut() = new_content;
-                self.length = new_length;
-                self.update_parent_length(new_length as i64 - old_length as i64);
-                Ok(())
-            }
-            Err(e) => Err(e),
+    pub fn leaf(chunk: Chunk) -> Self {
+        Node {
+            summary: *chunk.summary(),
+            height: 0,
+            data: NodeData::Leaf(chunk),
         }
     }
 
-    pub fn append_content(&mut self, additional_content: &str) -> Result<(), String> {
-        let combined = format!("{}{}", self.get_content(), additional_content);
-        self.set_content(combined)
+    pub fn leaf_ref(chunk: Chunk) -> NodeRef {
+        Arc::new(Node::leaf(chunk))
     }
 
-    pub fn stream_read(&self, range: Range<usize>) -> String {
-        let reader = StreamReader::new(self.content.clone());
-        reader.read(range)
+    /// Builds an internal node over `children`, which must all share the same height.
+    pub fn from_children(children: Vec<NodeRef>) -> Self {
+        debug_assert!(!children.is_empty(), "internal node without children");
+        let height = children.first().map_or(0, |child| child.height) + 1;
+        debug_assert!(children.iter().all(|child| child.height + 1 == height));
+        Node {
+            summary: children.iter().map(|child| &child.summary).sum(),
+            height,
+            data: NodeData::Internal(children),
+        }
     }
 
-    pub fn stream_write(&mut self, range: Range<usize>, new_content: String) -> Result<(), String> {
-        let mut writer = StreamWriter::new(self.content.clone());
-        match writer.write(range, new_content) {
-            Ok(_) => {
-                self.length = self.content.borrow().len();
-                Ok(())
-            }
-            Err(e) => Err(e),
+    /// Builds a balanced tree holding `text`.
+    pub fn from_text(text: &str, ctx: NodeContext<'_>) -> NodeRef {
+        let metrics = ctx.metrics;
+        let leaves: Vec<NodeRef> = split_text(text, ctx.leaves).into_iter().map(Node::leaf_ref).collect();
+        for _ in &leaves {
+            metrics.record_node(true);
         }
+        Self::from_nodes(leaves, metrics).unwrap_or_else(|| {
+            metrics.record_node(true);
+            Arc::new(Node::new())
+        })
+    }
+
+    /// Stacks levels of internal nodes over `nodes` (all of the same height) until a single root remains.
+    pub fn from_nodes(mut nodes: Vec<NodeRef>, metrics: &RopeMetrics) -> Option<NodeRef> {
+        while nodes.len() > 1 {
+            nodes = group_children(nodes)
+                .into_iter()
+                .map(|group| {
+                    metrics.record_node(false);
+                    Arc::new(Node::from_children(group))
+                })
+                .collect();
+        }
+        nodes.pop()
     }
 
+    #[inline]
+    pub fn summary(&self) -> &TextSummary {
+        &self.summary
+    }
+
+    #[inline]
     pub fn total_length(&self) -> usize {
-        self.length + self.children.iter().map(|child| child.total_length()).sum::<usize>()
+        self.summary.bytes
+    }
+
+    /// Height of the subtree, leaves being at height 0.
+    #[inline]
+    pub fn height(&self) -> usize {
+        self.height
     }
 
+    /// Number of levels in the subtree, a single leaf counting as one.
+    #[inline]
     pub fn depth(&self) -> usize {
-        if self.children.is_empty() {
-            1
-        } else {
-            1 + self.children.iter().map(|child| child.depth()).max().unwrap_or(0)
-        }
+        self.height + 1
     }
 
+    #[inline]
     pub fn is_leaf(&self) -> bool {
-        self.children.is_empty()
+        matches!(self.data, NodeData::Leaf(_))
     }
 
+    #[inline]
     pub fn is_empty(&self) -> bool {
-        self.length == 0 && self.children.is_empty()
+        self.summary.is_empty()
     }
 
     pub fn child_count(&self) -> usize {
-        self.children.len()
+        self.children().len()
+    }
+
+    pub fn children(&self) -> &[NodeRef] {
+        match &self.data {
+            NodeData::Leaf(_) => &[],
+            NodeData::Internal(children) => children,
+        }
+    }
+
+    pub fn chunk(&self) -> Option<&Chunk> {
+        match &self.data {
+            NodeData::Leaf(chunk) => Some(chunk),
+            NodeData::Internal(_) => None,
+        }
     }
 
     pub fn traverse<F>(&self, action: &F) where F: Fn(&Node) {
         action(self);
-        for child in &self.children {
+        for child in self.children() {
             child.traverse(action);
         }
     }
 
-    pub fn traverse_mut<F>(&mut self, action: &F) where F: Fn(&mut Node) {
-        action(self);
-        for child in &mut self.children {
-            child.traverse_mut(action);
+    /// Descends to the leaf where the additive `measure` reaches `target`.
+    ///
+    /// Returns the leaf chunk and the summary of all the text preceding it. Targets past the
+    /// end resolve to the last leaf.
+    pub fn leaf_at<F>(&self, target: usize, measure: F) -> (&Chunk, TextSummary)
+    where
+        F: Fn(&TextSummary) -> usize,
+    {
+        let mut node = self;
+        let mut before = TextSummary::ZERO;
+        loop {
+            match &node.data {
+                NodeData::Leaf(chunk) => return (chunk, before),
+                NodeData::Internal(children) => {
+                    let last = children.len() - 1;
+                    for (index, child) in children.iter().enumerate() {
+                        if index == last || target < measure(&(before + child.summary)) {
+                            node = child.as_ref();
+                            break;
+                        }
+                        before += child.summary;
+                    }
+                }
+            }
         }
     }
 
-    pub fn find_node(&self, index: usize) -> Option<&Node> {
-        if index >= self.total_length() {
-            return None;
-        }
-        if index < self.length {
-            return Some(self);
-        }
-        let mut current_index = self.length;
-        for child in &self.children {
-            let child_length = child.total_length();
-            if index < current_index + child_length {
-                return child.find_node(index - current_index);
+    /// Returns the leaf containing `byte_idx` along with the summary of the text before it.
+    pub fn leaf_at_byte(&self, byte_idx: usize) -> (&Chunk, TextSummary) {
+        self.leaf_at(byte_idx, |summary| summary.bytes)
+    }
+
+    /// Returns the leaf holding the `line_feed`-th line feed (zero based).
+    pub fn leaf_at_line_feed(&self, line_feed: usize) -> (&Chunk, TextSummary) {
+        self.leaf_at(line_feed, |summary| summary.line_feeds)
+    }
+
+    /// Appends the text of `range` to `out`.
+    pub fn collect_range(&self, range: Range<usize>, out: &mut String) {
+        match &self.data {
+            NodeData::Leaf(chunk) => out.push_str(&chunk.text()[range]),
+            NodeData::Internal(children) => {
+                let mut offset = 0;
+                for child in children {
+                    let child_len = child.total_length();
+                    let start = range.start.max(offset);
+                    let end = range.end.min(offset + child_len);
+                    if start < end {
+                        child.collect_range((start - offset)..(end - offset), out);
+                    }
+                    offset += child_len;
+                    if offset >= range.end {
+                        break;
+                    }
+                }
             }
-            current_index += child_length;
         }
-        None
     }
 
-    pub fn find_node_mut(&mut self, index: usize) -> Option<&mut Node> {
-        if index >= self.total_length() {
-            return None;
-        }
-        if index < self.length {
-            return Some(self);
-        }
-        let mut current_index = self.length;
-        for child in &mut self.children {
-            let child_length = child.total_length();
-            if index < current_index + child_length {
-                return child.find_node_mut(index - current_index);
+    /// Inserts `text` at `byte_idx`, which must be a char boundary.
+    ///
+    /// Returns the siblings split off this node when it overflows; they share its height and
+    /// must be inserted right after it by the caller.
+    /// A leaf that still fits is edited in place; one that overflows is rebuilt from storage
+    /// taken from the pool, to which its old storage is given back.
+    pub fn insert(&mut self, byte_idx: usize, text: &str, ctx: NodeContext<'_>) -> Vec<NodeRef> {
+        let NodeContext { metrics, pool, leaves } = ctx;
+        match &mut self.data {
+            NodeData::Leaf(chunk) => {
+                assert!(chunk.text().is_char_boundary(byte_idx), "rope insertion must happen on a char boundary");
+                if chunk.len() + text.len() <= leaves.max_bytes {
+                    chunk.insert_str_in_place(byte_idx, text);
+                    self.summary = *chunk.summary();
+                    return Vec::new();
+                }
+
+                let mut updated = pool.acquire_string(chunk.len() + text.len());
+                updated.push_str(&chunk.text()[..byte_idx]);
+                updated.push_str(text);
+                updated.push_str(&chunk.text()[byte_idx..]);
+                std::mem::take(chunk).recycle(pool);
+                let mut pieces = split_text(&updated, leaves).into_iter();
+                pool.release_string(updated);
+                *self = Node::leaf(pieces.next().unwrap_or_default());
+                let overflow: Vec<NodeRef> = pieces.map(Node::leaf_ref).collect();
+                for _ in &overflow {
+                    metrics.record_split();
+                    metrics.record_node(true);
+                }
+                overflow
+            }
+            NodeData::Internal(children) => {
+                let mut offset = 0;
+                let mut index = children.len() - 1;
+                for (i, child) in children.iter().enumerate() {
+                    if byte_idx <= offset + child.total_length() {
+                        index = i;
+                        break;
+                    }
+                    offset += child.total_length();
+                }
+
+                let overflow = Arc::make_mut(&mut children[index]).insert(byte_idx - offset, text, ctx);
+                children.splice(index + 1..index + 1, overflow);
+
+                if children.len() <= MAX_CHILDREN {
+                    *self = Node::from_children(std::mem::take(children));
+                    return Vec::new();
+                }
+
+                let mut groups = group_children(std::mem::take(children)).into_iter().map(Node::from_children);
+                *self = groups.next().expect("overflowing node has children");
+                let overflow: Vec<NodeRef> = groups.map(Arc::new).collect();
+                for _ in &overflow {
+                    metrics.record_split();
+                    metrics.record_node(false);
+                }
+                overflow
             }
-            current_index += child_length;
         }
-        None
     }
 
-    pub fn split(&mut self, index: usize) -> Option<Node> {
-        if index == 0 || index >= self.length {
-            return None;
-        }
-        let content = self.content.borrow().clone();
-        let (left, right) = content.split_at(index);
-        
-        if let Ok(_) = self.set_content(left.to_string()) {
-            Some(Node::new(right.to_string(), self.encoding, self.line_ending))
-        } else {
-            None
+    /// Removes the bytes in `range`, whose bounds must be char boundaries.
+    ///
+    /// Children left underfull are merged with a sibling; this node itself may end up underfull
+    /// and is then fixed by its parent.
+    pub fn remove(&mut self, range: Range<usize>, ctx: NodeContext<'_>) {
+        let metrics = ctx.metrics;
+        match &mut self.data {
+            NodeData::Leaf(chunk) => {
+                assert!(chunk.remove_range_in_place(range), "rope removal must happen on char boundaries");
+                self.summary = *chunk.summary();
+            }
+            NodeData::Internal(children) => {
+                let mut kept = Vec::with_capacity(children.len());
+                let mut offset = 0;
+                for mut child in children.drain(..) {
+                    let child_len = child.total_length();
+                    let start = range.start.max(offset);
+                    let end = range.end.min(offset + child_len);
+                    if start == offset && end == offset + child_len && child_len > 0 {
+                        record_subtree_removed(&child, metrics);
+                    } else {
+                        if start < end {
+                            Arc::make_mut(&mut child).remove((start - offset)..(end - offset), ctx);
+                        }
+                        kept.push(child);
+                    }
+                    offset += child_len;
+                }
+
+                fix_underfull_children(&mut kept, ctx);
+                *self = if kept.is_empty() {
+                    metrics.record_node_removed(false);
+                    metrics.record_node(true);
+                    Node::new()
+                } else {
+                    Node::from_children(kept)
+                };
+            }
         }
     }
 
-    pub fn merge(&mut self, other: Node) -> Result<(), String> {
-        if self.encoding != other.encoding || self.line_ending != other.line_ending {
-            return Err("Cannot merge nodes with different encoding or line endings".to_string());
-        }
-        
-        let combined = format!("{}{}", self.get_content(), other.get_content());
-        self.set_content(combined)?;
-        
-        for child in other.children {
-            self.add_child(child);
+    /// Replaces an internal node that has a single child by that child, repeatedly.
+    pub fn collapse(&mut self, metrics: &RopeMetrics) {
+        while let NodeData::Internal(children) = &mut self.data {
+            if children.len() != 1 {
+                break;
+            }
+            let child = children.pop().expect("single child");
+            *self = Arc::unwrap_or_clone(child);
+            metrics.record_node_removed(false);
         }
-        
-        Ok(())
     }
 
-    pub fn balance(&mut self) {
-        if self.children.len() <= 2 {
-            return;
+    fn is_underfull(&self, leaves: LeafSizePolicy) -> bool {
+        match &self.data {
+            NodeData::Leaf(chunk) => chunk.len() < leaves.min_bytes,
+            NodeData::Internal(children) => children.len() < MIN_CHILDREN,
         }
-        
-        for child in &mut self.children {
-            child.balance();
-        }
-        
-        if self.children.len() > 4 {
-            let mid = self.children.len() / 2;
-            let right_children = self.children.split_off(mid);
-            
-            let mut right_node = Node::new(String::new(), self.encoding, self.line_ending);
-            right_node.children = right_children;
-            right_node.update_length();
-            
-            self.update_length();
-            self.children.push(right_node);
+    }
+
+    /// Checks the B-tree invariants of the subtree, panicking on the first violation.
+    #[cfg(test)]
+    pub(crate) fn assert_invariants(&self, is_root: bool, leaves: LeafSizePolicy) {
+        match &self.data {
+            NodeData::Leaf(chunk) => {
+                assert_eq!(self.height, 0);
+                assert_eq!(self.summary, TextSummary::of(chunk.text()));
+                assert_eq!(chunk, &Chunk::from(chunk.text()));
+                assert!(chunk.len() <= leaves.max_bytes, "leaf of {} bytes", chunk.len());
+                assert!(is_root || chunk.len() >= leaves.min_bytes, "underfull leaf of {} bytes", chunk.len());
+            }
+            NodeData::Internal(children) => {
+                assert!(children.len() <= MAX_CHILDREN, "node with {} children", children.len());
+                assert!(is_root || children.len() >= MIN_CHILDREN, "node with {} children", children.len());
+                assert!(!is_root || children.len() >= 2, "root with a single child");
+                for child in children {
+                    assert_eq!(child.height + 1, self.height);
+                    child.assert_invariants(false, leaves);
+                }
+                assert_eq!(self.summary, children.iter().map(|child| &child.summary).sum());
+            }
         }
     }
+}
 
-    pub fn compact(&mut self) {
-        self.children.retain(|child| !child.is_empty());
-        
-        if self.children.len() == 1 && self.length == 0 {
-            let child = self.children.remove(0);
-            *self = child;
+impl Default for Node {
+    fn default() -> Self {
+        Node::new()
+    }
+}
+
+/// Bytes a split point may move back to reach a grapheme boundary.
+const SPLIT_SLACK: usize = 32;
+
+/// Splits `text` into evenly sized chunks of at most `leaves.max_bytes()` bytes, cutting at
+/// grapheme boundaries (hence never inside a CRLF pair) unless a cluster is longer than
+/// `SPLIT_SLACK`. Texts longer than one chunk never produce chunks under `leaves.min_bytes()`.
+pub fn split_text(text: &str, leaves: LeafSizePolicy) -> Vec<Chunk> {
+    if text.len() <= leaves.max_bytes {
+        return if text.is_empty() { Vec::new() } else { vec![Chunk::from(text)] };
+    }
+
+    // Leave room for the boundary adjustment so that no piece overshoots the maximum.
+    let count = text.len().div_ceil(leaves.max_bytes - SPLIT_SLACK);
+    let mut chunks = Vec::with_capacity(count);
+    let mut start = 0;
+    for i in 1..=count {
+        let end = split_point(text, i * text.len() / count);
+        if end > start {
+            chunks.push(Chunk::from(&text[start..end]));
+            start = end;
         }
     }
+    chunks
+}
+
+/// Moves `idx` back to the closest grapheme boundary, or to the closest char boundary when the
+/// cluster around it is too long.
+fn split_point(text: &str, idx: usize) -> usize {
+    let mut end = idx;
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+    let mut cursor = GraphemeCursor::new(end, text.len(), true);
+    match cursor.is_boundary(text, 0) {
+        Ok(true) => end,
+        _ => match cursor.prev_boundary(text, 0) {
+            Ok(Some(boundary)) if end - boundary <= SPLIT_SLACK - 4 => boundary,
+            _ => end,
+        },
+    }
+}
+
+/// Distributes `nodes` evenly into groups of at most `MAX_CHILDREN`; when more than one
+/// group is needed, every group holds at least `MIN_CHILDREN`.
+fn group_children<T>(nodes: Vec<T>) -> Vec<Vec<T>> {
+    let count = nodes.len().div_ceil(MAX_CHILDREN);
+    let base = nodes.len() / count;
+    let extra = nodes.len() % count;
 
-    fn update_length(&mut self) {
-        self.length = self.content.borrow().len();
+    let mut groups = Vec::with_capacity(count);
+    let mut nodes = nodes.into_iter();
+    for i in 0..count {
+        let size = base + usize::from(i < extra);
+        groups.push(nodes.by_ref().take(size).collect());
     }
+    groups
+}
 
-    fn update_parent_length(&mut self, delta: i64) {
-        if delta != 0 {
-            self.length = (self.length as i64 + delta) as usize;
+/// Merges underfull children into their siblings until none is left (or a single child remains).
+fn fix_underfull_children(children: &mut Vec<NodeRef>, ctx: NodeContext<'_>) {
+    let mut index = 0;
+    while index < children.len() {
+        if children.len() < 2 || !children[index].is_underfull(ctx.leaves) {
+            index += 1;
+            continue;
         }
+
+        let left = if index + 1 < children.len() { index } else { index - 1 };
+        let right = children.remove(left + 1);
+        let merged = merge_siblings(children.remove(left), right, ctx);
+        children.splice(left..left, merged);
+        index = left;
     }
 }
 
-impl Default for Node {
-    fn default() -> Self {
-        Node::new(String::new(), Encoding::UTF8, LineEnding::LF)
+/// Merges two siblings of the same height into one node, or two balanced ones if they do not fit.
+fn merge_siblings(left: NodeRef, right: NodeRef, ctx: NodeContext<'_>) -> Vec<NodeRef> {
+    let NodeContext { metrics, pool, leaves } = ctx;
+    match (Arc::unwrap_or_clone(left).data, Arc::unwrap_or_clone(right).data) {
+        (NodeData::Leaf(mut left), NodeData::Leaf(right)) => {
+            if left.len() + right.len() <= leaves.max_bytes {
+                metrics.record_merge();
+                metrics.record_node_removed(true);
+                left.insert_str_in_place(left.len(), right.text());
+                right.recycle(pool);
+                vec![Node::leaf_ref(left)]
+            } else {
+                metrics.record_rebalance();
+                let merged = left.concat(&right);
+                split_text(merged.text(), leaves).into_iter().map(Node::leaf_ref).collect()
+            }
+        }
+        (NodeData::Internal(mut left), NodeData::Internal(right)) => {
+            left.extend(right);
+            fix_underfull_children(&mut left, ctx);
+            if left.len() <= MAX_CHILDREN {
+                metrics.record_merge();
+                metrics.record_node_removed(false);
+                vec![Arc::new(Node::from_children(left))]
+            } else {
+                metrics.record_rebalance();
+                group_children(left).into_iter().map(|group| Arc::new(Node::from_children(group))).collect()
+            }
+        }
+        _ => unreachable!("siblings always share the same height"),
     }
 }
 
-impl PartialEq for Node {
-    fn eq(&self, other: &Self) -> bool {
-        self.get_content() == other.get_content() 
-            && self.encoding == other.encoding 
-            && self.line_ending == other.line_ending
-            && self.children == other.children
+pub(crate) fn record_subtree_removed(node: &Node, metrics: &RopeMetrics) {
+    metrics.record_node_removed(node.is_leaf());
+    for child in node.children() {
+        record_subtree_removed(child, metrics);
     }
 }
 
@@ -268,65 +528,154 @@ impl PartialEq for Node {
 mod tests {
     use super::*;
 
+    fn build(text: &str) -> NodeRef {
+        Node::from_text(text, NodeContext { metrics: &RopeMetrics::new(), pool: &ChunkPool::default(), leaves: LeafSizePolicy::default() })
+    }
+
+    fn collect(node: &Node) -> String {
+        let mut out = String::new();
+        node.collect_range(0..node.total_length(), &mut out);
+        out
+    }
+
     #[test]
     fn test_node_creation() {
-        let node = Node::new("Hello, world!".to_string(), Encoding::UTF8, LineEnding::LF);
-        assert_eq!(node.get_content(), "Hello, world!");
-        assert_eq!(node.length, 13);
+        let node = Node::leaf(Chunk::from("Hello, world!"));
+        assert_eq!(node.total_length(), 13);
+        assert_eq!(node.chunk().unwrap().text(), "Hello, world!");
         assert!(node.is_leaf());
+        assert_eq!(node.depth(), 1);
+    }
+
+    #[test]
+    fn test_from_text_is_balanced() {
+        let text = "0123456789\n".repeat(5000);
+        let node = build(&text);
+        node.assert_invariants(true, LeafSizePolicy::default());
+        assert_eq!(node.total_length(), text.len());
+        assert_eq!(node.summary().line_feeds, 5000);
+        assert_eq!(collect(&node), text);
+    }
+
+    #[test]
+    fn test_cached_summary() {
+        let node = build(&"héllo wörld 🌍\n".repeat(300));
+        assert_eq!(*node.summary(), TextSummary::of(&"héllo wörld 🌍\n".repeat(300)));
     }
 
     #[test]
-    fn test_child_operations() {
-        let mut parent = Node::new("Parent".to_string(), Encoding::UTF8, LineEnding::LF);
-        let child = Node::new("Child".to_string(), Encoding::UTF8, LineEnding::LF);
-        
-        parent.add_child(child);
-        assert_eq!(parent.child_count(), 1);
-        assert_eq!(parent.total_length(), 11);
-        
-        let removed = parent.remove_child(0);
-        assert!(removed.is_some());
-        assert_eq!(parent.child_count(), 0);
+    fn test_leaf_at_byte() {
+        let text = "abcdefghij".repeat(1000);
+        let node = build(&text);
+        for byte_idx in [0, 1, 1023, 1024, 5000, text.len() - 1, text.len()] {
+            let (chunk, before) = node.leaf_at_byte(byte_idx);
+            assert!(before.bytes <= byte_idx && byte_idx <= before.bytes + chunk.len());
+            if byte_idx < text.len() {
+                assert_eq!(chunk.text().as_bytes()[byte_idx - before.bytes], text.as_bytes()[byte_idx]);
+            }
+        }
     }
 
     #[test]
-    fn test_split_merge() {
-        let mut node = Node::new("Hello, world!".to_string(), Encoding::UTF8, LineEnding::LF);
-        let split_node = node.split(7);
-        
-        assert!(split_node.is_some());
-        assert_eq!(node.get_content(), "Hello, ");
-        assert_eq!(split_node.unwrap().get_content(), "world!");
+    fn test_leaf_at_line_feed() {
+        let text: String = (0..2000).map(|i| format!("line {}\n", i)).collect();
+        let node = build(&text);
+        let (chunk, before) = node.leaf_at_line_feed(1500);
+        let local = chunk.line_endings()[1500 - before.line_feeds];
+        let line_start = text.match_indices('\n').nth(1499).unwrap().0 + 1;
+        assert_eq!(&text[line_start..before.bytes + local], "line 1500");
     }
 
     #[test]
-    fn test_find_node() {
-        let mut parent = Node::new("Parent".to_string(), Encoding::UTF8, LineEnding::LF);
-        let child = Node::new("Child".to_string(), Encoding::UTF8, LineEnding::LF);
-        
-        parent.add_child(child);
-        
-        let found = parent.find_node(3);
-        assert!(found.is_some());
-        assert_eq!(found.unwrap().get_content(), "Parent");
-        
-        let found_child = parent.find_node(8);
-        assert!(found_child.is_some());
-        assert_eq!(found_child.unwrap().get_content(), "Child");
+    fn test_insert_keeps_invariants() {
+        let metrics = RopeMetrics::new();
+        let pool = ChunkPool::default();
+        let ctx = NodeContext { metrics: &metrics, pool: &pool, leaves: LeafSizePolicy::default() };
+        let mut node = Node::new();
+        let mut model = String::new();
+        for i in 0..3000 {
+            let at = (i * 7919) % (model.len() + 1);
+            let at = (0..=at).rev().find(|&idx| model.is_char_boundary(idx)).unwrap();
+            let overflow = node.insert(at, "ab🌍\n", ctx);
+            if !overflow.is_empty() {
+                let mut level = vec![Arc::new(node)];
+                level.extend(overflow);
+                node = Arc::unwrap_or_clone(Node::from_nodes(level, &metrics).unwrap());
+            }
+            model.insert_str(at, "ab🌍\n");
+        }
+        node.assert_invariants(true, LeafSizePolicy::default());
+        assert_eq!(collect(&node), model);
+    }
+
+    #[test]
+    fn test_remove_keeps_invariants() {
+        let metrics = RopeMetrics::new();
+        let pool = ChunkPool::default();
+        let ctx = NodeContext { metrics: &metrics, pool: &pool, leaves: LeafSizePolicy::default() };
+        let mut model = "0123456789abcdef".repeat(4000);
+        let mut root = Node::from_text(&model, ctx);
+        let node = Arc::make_mut(&mut root);
+        while model.len() > 100 {
+            let start = model.len() / 3;
+            let end = (start + 5000).min(model.len() - 10);
+            node.remove(start..end, ctx);
+            node.collapse(&metrics);
+            model.replace_range(start..end, "");
+            node.assert_invariants(true, LeafSizePolicy::default());
+            assert_eq!(collect(node), model);
+        }
+        node.remove(0..model.len(), ctx);
+        node.collapse(&metrics);
+        assert!(node.is_empty());
+        assert!(node.is_leaf());
+    }
+
+    #[test]
+    fn test_height_is_logarithmic() {
+        let node = build(&"x".repeat(1 << 22));
+        // 4 MiB in ~1 KiB leaves is about 4100 leaves, i.e. at most ceil(log4(4100)) + 1 levels.
+        assert!(node.depth() <= 8, "depth {}", node.depth());
+    }
+
+    #[test]
+    fn test_split_text_sizes() {
+        let text = "é".repeat(3000);
+        let chunks = split_text(&text, LeafSizePolicy::default());
+        assert_eq!(chunks.iter().map(Chunk::text).collect::<String>(), text);
+        assert!(chunks.iter().all(|c| c.len() <= MAX_CHUNK_SIZE && c.len() >= MIN_CHUNK_SIZE));
+    }
+
+    #[test]
+    fn test_custom_leaf_sizes() {
+        assert_eq!(LeafSizePolicy::new(100, 300), None);
+        let leaves = LeafSizePolicy::new(64, 256).unwrap();
+        let metrics = RopeMetrics::new();
+        let pool = ChunkPool::default();
+        let ctx = NodeContext { metrics: &metrics, pool: &pool, leaves };
+        let mut model = "héllo\n".repeat(500);
+        let mut root = Node::from_text(&model, ctx);
+        let node = Arc::make_mut(&mut root);
+        node.assert_invariants(true, leaves);
+        assert!(node.depth() > build(&model).depth());
+        for _ in 0..30 {
+            node.remove(98..189, ctx);
+            node.collapse(&metrics);
+            model.replace_range(98..189, "");
+            node.assert_invariants(true, leaves);
+        }
+        assert_eq!(collect(node), model);
     }
 
     #[test]
-    fn test_balance() {
-        let mut node = Node::with_capacity("Root".to_string(), Encoding::UTF8, LineEnding::LF, 10);
-        for i in 0..6 {
-            let child = Node::new(format!("Child{}", i), Encoding::UTF8, LineEnding::LF);
-            node.add_child(child);
+    fn test_split_text_keeps_clusters() {
+        let text = "e\u{301}\r\n👍🏽".repeat(400);
+        let chunks = split_text(&text, LeafSizePolicy::default());
+        assert!(chunks.len() > 1);
+        for pair in chunks.windows(2) {
+            assert!(!pair[0].summary().continues_into(pair[1].summary()));
         }
-        
-        node.balance();
-        assert!(node.child_count() <= 4);
     }
 }
 
-// -- Made by still-eau (id discord: stilau_) --
\ No newline at end of file
+// -- Made by still-eau (id discord: stilau_) --
@@ -0,0 +1,1130 @@
+/// rope.rs
+/// Defines the `Rope` text buffer, a balanced B-tree of `Chunk` leaves (see `node.rs`).
+use std::error::Error;
+use std::fmt;
+use std::mem;
+use std::ops::Range;
+use std::sync::Arc;
+use std::time::Instant;
+use crate::core::buffer::content::line_ending::{LineEnding, LineEndingAnalysis, Normalization};
+use crate::core::buffer::memory::pool::ChunkPool;
+use crate::core::buffer::rope::iterator::{
+    RopeByteCursor, RopeByteIterator, RopeCharCursor, RopeChunkCursor, RopeChunkIterator, RopeGraphemeCursor,
+    RopeGraphemeIterator, RopeIterator, RopeLineCursor, RopeLineIterator,
+};
+use crate::core::buffer::rope::metrics::RopeMetrics;
+use crate::core::buffer::rope::node::{record_subtree_removed, LeafSizePolicy, Node, NodeContext, NodeRef};
+use crate::core::buffer::rope::summary::{LineBreaks, TextSummary};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RopeError {
+    OutOfBounds { index: usize, len: usize },
+    InvalidRange(Range<usize>),
+    NotCharBoundary(usize),
+}
+
+impl fmt::Display for RopeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RopeError::OutOfBounds { index, len } => write!(f, "Index {} out of bounds for rope of length {}", index, len),
+            RopeError::InvalidRange(range) => write!(f, "Invalid range: {:?}", range),
+            RopeError::NotCharBoundary(index) => write!(f, "Index {} is not a char boundary", index),
+        }
+    }
+}
+
+impl Error for RopeError {}
+
+/// A text buffer stored as a balanced tree of `Chunk` leaves.
+///
+/// Lengths are read from the summaries cached in the tree, so they cost O(1); edits and
+/// offset lookups walk a single root-to-leaf path. Every edit keeps the attached
+/// `RopeMetrics` up to date.
+///
+/// Nodes are persistent: cloning a rope is O(1) and shares the whole tree, and an edit copies
+/// only the path it touches. A clone is therefore a cheap snapshot that can be sent to another
+/// thread while the original keeps being edited.
+///
+/// Line indexing follows the rope's `LineBreaks` mode, `LineBreaks::LineFeed` by default.
+///
+/// Edited leaves take their storage from a `ChunkPool` and give the storage they replace back
+/// to it, so typing mostly reuses the buffers freed by the previous keystroke. Edits that fit
+/// their leaf patch it in place; the `LeafSizePolicy` decides when leaves are split & merged.
+#[derive(Debug, Clone)]
+pub struct Rope {
+    root: NodeRef,
+    metrics: RopeMetrics,
+    line_breaks: LineBreaks,
+    pool: Arc<ChunkPool>,
+    leaves: LeafSizePolicy,
+}
+
+impl Rope {
+    pub fn new() -> Self {
+        let metrics = RopeMetrics::new();
+        metrics.record_node(true);
+        metrics.update_max_height(1);
+        Self {
+            root: Arc::new(Node::new()),
+            metrics,
+            line_breaks: LineBreaks::default(),
+            pool: Arc::default(),
+            leaves: LeafSizePolicy::default(),
+        }
+    }
+
+    pub fn from_text(text: &str) -> Self {
+        let start = Instant::now();
+        let metrics = RopeMetrics::new();
+        let pool = Arc::<ChunkPool>::default();
+        let leaves = LeafSizePolicy::default();
+        let root = Node::from_text(text, NodeContext { metrics: &metrics, pool: &pool, leaves });
+
+        metrics.total_bytes.fetch_add(text.len(), std::sync::atomic::Ordering::Relaxed);
+        metrics.update_max_height(root.depth());
+        metrics.record_time(start.elapsed());
+
+        let rope = Self { root, metrics, line_breaks: LineBreaks::default(), pool, leaves };
+        rope.record_memory();
+        rope
+    }
+
+    #[inline]
+    pub fn len_bytes(&self) -> usize {
+        self.root.total_length()
+    }
+
+    #[inline]
+    pub fn len_chars(&self) -> usize {
+        self.root.summary().chars
+    }
+
+    /// Length in UTF-16 code units, as counted by LSP positions.
+    #[inline]
+    pub fn len_utf16(&self) -> usize {
+        self.root.summary().utf16_units
+    }
+
+    /// Length in extended grapheme clusters, i.e. in user-perceived characters.
+    #[inline]
+    pub fn len_graphemes(&self) -> usize {
+        self.root.summary().graphemes
+    }
+
+    #[inline]
+    pub fn len_lines(&self) -> usize {
+        self.root.summary().breaks(self.line_breaks) + 1
+    }
+
+    #[inline]
+    pub fn line_breaks(&self) -> LineBreaks {
+        self.line_breaks
+    }
+
+    /// Selects which characters end a line for the line indexing methods.
+    pub fn set_line_breaks(&mut self, line_breaks: LineBreaks) {
+        self.line_breaks = line_breaks;
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.len_bytes() == 0
+    }
+
+    /// Summary of the whole text, as cached at the root.
+    #[inline]
+    pub fn summary(&self) -> &TextSummary {
+        self.root.summary()
+    }
+
+    pub fn height(&self) -> usize {
+        self.root.depth()
+    }
+
+    pub fn metrics(&self) -> &RopeMetrics {
+        &self.metrics
+    }
+
+    /// Recycles leaf storage through `pool`, which may be shared by several ropes.
+    pub fn with_pool(mut self, pool: Arc<ChunkPool>) -> Self {
+        self.pool = pool;
+        self
+    }
+
+    pub fn pool(&self) -> &Arc<ChunkPool> {
+        &self.pool
+    }
+
+    /// Keeps leaves within `leaves`, rebuilding the tree when it holds any text.
+    pub fn with_leaf_sizes(mut self, leaves: LeafSizePolicy) -> Self {
+        if leaves == self.leaves {
+            return self;
+        }
+        self.leaves = leaves;
+        if !self.is_empty() {
+            let text = self.to_string();
+            record_subtree_removed(&self.root, &self.metrics);
+            self.root = Node::from_text(&text, self.context());
+            self.metrics.update_max_height(self.root.depth());
+        }
+        self
+    }
+
+    pub fn leaf_sizes(&self) -> LeafSizePolicy {
+        self.leaves
+    }
+
+    pub fn root(&self) -> &Node {
+        &self.root
+    }
+
+    /// Returns true when both ropes share the same root, i.e. one is an unedited clone of the other.
+    pub fn ptr_eq(&self, other: &Rope) -> bool {
+        Arc::ptr_eq(&self.root, &other.root)
+    }
+
+    pub fn is_char_boundary(&self, byte_idx: usize) -> bool {
+        if byte_idx > self.len_bytes() {
+            return false;
+        }
+        let (chunk, before) = self.root.leaf_at_byte(byte_idx);
+        chunk.text().is_char_boundary(byte_idx - before.bytes)
+    }
+
+    /// Returns the byte at `byte_idx`, if any.
+    pub fn byte(&self, byte_idx: usize) -> Option<u8> {
+        if byte_idx >= self.len_bytes() {
+            return None;
+        }
+        let (chunk, before) = self.root.leaf_at_byte(byte_idx);
+        chunk.text().as_bytes().get(byte_idx - before.bytes).copied()
+    }
+
+    /// Converts a byte offset into a char index; offsets inside a char map to that char.
+    pub fn byte_to_char(&self, byte_idx: usize) -> Result<usize, RopeError> {
+        let len = self.len_bytes();
+        if byte_idx > len {
+            return Err(RopeError::OutOfBounds { index: byte_idx, len });
+        }
+        let (chunk, before) = self.root.leaf_at_byte(byte_idx);
+        let local = chunk.byte_to_char(byte_idx - before.bytes).unwrap_or(0);
+        Ok(before.chars + local)
+    }
+
+    pub fn char_to_byte(&self, char_idx: usize) -> Result<usize, RopeError> {
+        let len = self.len_chars();
+        if char_idx > len {
+            return Err(RopeError::OutOfBounds { index: char_idx, len });
+        }
+        let (chunk, before) = self.root.leaf_at(char_idx, |summary| summary.chars);
+        let local = chunk.char_to_byte(char_idx - before.chars).unwrap_or(chunk.len());
+        Ok(before.bytes + local)
+    }
+
+    /// Converts a byte offset into UTF-16 code units; offsets inside a char map to that char.
+    pub fn byte_to_utf16(&self, byte_idx: usize) -> Result<usize, RopeError> {
+        let len = self.len_bytes();
+        if byte_idx > len {
+            return Err(RopeError::OutOfBounds { index: byte_idx, len });
+        }
+        let (chunk, before) = self.root.leaf_at_byte(byte_idx);
+        let local = chunk.byte_to_utf16(byte_idx - before.bytes).unwrap_or(0);
+        Ok(before.utf16_units + local)
+    }
+
+    /// Converts UTF-16 code units into a byte offset; a unit inside a surrogate pair maps to the pair's start.
+    pub fn utf16_to_byte(&self, utf16_idx: usize) -> Result<usize, RopeError> {
+        let len = self.len_utf16();
+        if utf16_idx > len {
+            return Err(RopeError::OutOfBounds { index: utf16_idx, len });
+        }
+        let (chunk, before) = self.root.leaf_at(utf16_idx, |summary| summary.utf16_units);
+        let local = chunk.utf16_to_byte(utf16_idx - before.utf16_units).unwrap_or(chunk.len());
+        Ok(before.bytes + local)
+    }
+
+    pub fn char_to_utf16(&self, char_idx: usize) -> Result<usize, RopeError> {
+        self.byte_to_utf16(self.char_to_byte(char_idx)?)
+    }
+
+    pub fn utf16_to_char(&self, utf16_idx: usize) -> Result<usize, RopeError> {
+        self.byte_to_char(self.utf16_to_byte(utf16_idx)?)
+    }
+
+    /// Index of the grapheme cluster containing `byte_idx`; the end of the text maps to `len_graphemes`.
+    pub fn byte_to_grapheme(&self, byte_idx: usize) -> Result<usize, RopeError> {
+        let len = self.len_bytes();
+        if byte_idx > len {
+            return Err(RopeError::OutOfBounds { index: byte_idx, len });
+        }
+        if byte_idx == len {
+            return Ok(self.len_graphemes());
+        }
+        let (chunk, before) = self.root.leaf_at_byte(byte_idx);
+        let local = chunk.text().floor_char_boundary(byte_idx - before.bytes);
+        let end = local + chunk.text()[local..].chars().next().map_or(0, char::len_utf8);
+        // Counting up to the char at `byte_idx` includes its cluster, whether or not it starts there.
+        Ok((before + TextSummary::of(&chunk.text()[..end])).graphemes - 1)
+    }
+
+    /// Byte offset at which grapheme cluster `grapheme_idx` starts.
+    pub fn grapheme_to_byte(&self, grapheme_idx: usize) -> Result<usize, RopeError> {
+        let len = self.len_graphemes();
+        if grapheme_idx > len {
+            return Err(RopeError::OutOfBounds { index: grapheme_idx, len });
+        }
+        if grapheme_idx == len {
+            return Ok(self.len_bytes());
+        }
+        let (chunk, before) = self.root.leaf_at(grapheme_idx, |summary| summary.graphemes);
+        // Cluster `before.graphemes` is the first to start in the leaf. The text before the
+        // leaf may shift the boundaries within it, so they are found with that text as context.
+        let steps = grapheme_idx - before.graphemes + usize::from(before.continues_into(chunk.summary()));
+        let mut cursor = RopeGraphemeCursor::new(&self.root, before.bytes);
+        for _ in 0..steps {
+            let boundary = cursor.next_boundary().unwrap_or(self.len_bytes());
+            cursor.seek(boundary);
+        }
+        Ok(cursor.position())
+    }
+
+    /// Start of the grapheme cluster following the one at `byte_idx`, clamped to the end of the text.
+    pub fn next_grapheme_boundary(&self, byte_idx: usize) -> Result<usize, RopeError> {
+        let grapheme = self.byte_to_grapheme(byte_idx)?;
+        self.grapheme_to_byte((grapheme + 1).min(self.len_graphemes()))
+    }
+
+    /// Closest grapheme boundary strictly before `byte_idx`, or 0.
+    pub fn prev_grapheme_boundary(&self, byte_idx: usize) -> Result<usize, RopeError> {
+        let grapheme = self.byte_to_grapheme(byte_idx)?;
+        let start = self.grapheme_to_byte(grapheme)?;
+        if start < byte_idx || grapheme == 0 {
+            return Ok(start);
+        }
+        self.grapheme_to_byte(grapheme - 1)
+    }
+
+    /// Byte offset at which `line` starts.
+    pub fn line_to_byte(&self, line: usize) -> Result<usize, RopeError> {
+        let len = self.len_lines();
+        if line >= len {
+            return Err(RopeError::OutOfBounds { index: line, len });
+        }
+        if line == 0 {
+            return Ok(0);
+        }
+
+        let mode = self.line_breaks;
+        let target = line - 1;
+        let (chunk, before) = self.root.leaf_at(target, |summary| summary.breaks(mode));
+        let local = break_ends(chunk.text(), mode, before.ends_with_cr())
+            .nth(target - before.breaks(mode))
+            .expect("cached line breaks match the leaf text");
+        let end = before.bytes + local;
+
+        // A CRLF pair split across two leaves ends after the `\n` of the next leaf.
+        if mode == LineBreaks::Unicode && local == chunk.len() && chunk.text().ends_with('\r') && self.byte(end) == Some(b'\n') {
+            return Ok(end + 1);
+        }
+        Ok(end)
+    }
+
+    /// Index of the line containing `byte_idx`. The end of the text belongs to the last line.
+    pub fn byte_to_line(&self, byte_idx: usize) -> Result<usize, RopeError> {
+        let len = self.len_bytes();
+        if byte_idx > len {
+            return Err(RopeError::OutOfBounds { index: byte_idx, len });
+        }
+
+        let mode = self.line_breaks;
+        let mut byte_idx = byte_idx;
+        if mode == LineBreaks::Unicode && byte_idx > 0 && self.byte(byte_idx - 1) == Some(b'\r') && self.byte(byte_idx) == Some(b'\n') {
+            // Between the two halves of a CRLF pair: still on the line it terminates.
+            byte_idx -= 1;
+        }
+
+        let (chunk, before) = self.root.leaf_at_byte(byte_idx);
+        let local = byte_idx - before.bytes;
+        let local_breaks = match mode {
+            LineBreaks::LineFeed => chunk.byte_to_line(local).unwrap_or(0),
+            LineBreaks::Unicode => break_ends(chunk.text(), mode, before.ends_with_cr())
+                .take_while(|&end| end <= local)
+                .count(),
+        };
+        Ok(before.breaks(mode) + local_breaks)
+    }
+
+    pub fn line_to_char(&self, line: usize) -> Result<usize, RopeError> {
+        self.byte_to_char(self.line_to_byte(line)?)
+    }
+
+    pub fn char_to_line(&self, char_idx: usize) -> Result<usize, RopeError> {
+        self.byte_to_line(self.char_to_byte(char_idx)?)
+    }
+
+    /// Returns the text of `line` without its line ending.
+    pub fn line(&self, line: usize) -> Result<String, RopeError> {
+        let start = self.line_to_byte(line)?;
+        let end = match self.line_to_byte(line + 1) {
+            Ok(next) => next,
+            Err(_) => self.len_bytes(),
+        };
+        let mut text = self.slice(start..end)?;
+        let ending = match self.line_breaks {
+            LineBreaks::LineFeed => text.strip_suffix('\n').map(|rest| rest.strip_suffix('\r').unwrap_or(rest)),
+            LineBreaks::Unicode => text.strip_suffix("\r\n").or_else(|| {
+                let last = text.chars().next_back()?;
+                LineEnding::from_char(last).map(|_| &text[..text.len() - last.len_utf8()])
+            }),
+        };
+        if let Some(content) = ending {
+            text.truncate(content.len());
+        }
+        Ok(text)
+    }
+
+    pub fn insert(&mut self, byte_idx: usize, text: &str) -> Result<(), RopeError> {
+        let len = self.len_bytes();
+        if byte_idx > len {
+            return Err(RopeError::OutOfBounds { index: byte_idx, len });
+        }
+        if !self.is_char_boundary(byte_idx) {
+            return Err(RopeError::NotCharBoundary(byte_idx));
+        }
+        if text.is_empty() {
+            return Ok(());
+        }
+
+        let start = Instant::now();
+        let ctx = NodeContext { metrics: &self.metrics, pool: &self.pool, leaves: self.leaves };
+        let overflow = Arc::make_mut(&mut self.root).insert(byte_idx, text, ctx);
+        if !overflow.is_empty() {
+            let mut level = vec![Arc::clone(&self.root)];
+            level.extend(overflow);
+            self.root = Node::from_nodes(level, &self.metrics).expect("root level is not empty");
+        }
+        self.metrics.update_max_height(self.root.depth());
+
+        self.metrics.record_insertion(text.len());
+        self.metrics.record_time(start.elapsed());
+        self.record_memory();
+        Ok(())
+    }
+
+    pub fn insert_char(&mut self, byte_idx: usize, ch: char) -> Result<(), RopeError> {
+        let mut buf = [0; 4];
+        self.insert(byte_idx, ch.encode_utf8(&mut buf))
+    }
+
+    pub fn remove(&mut self, range: Range<usize>) -> Result<(), RopeError> {
+        self.check_range(&range)?;
+        if range.is_empty() {
+            return Ok(());
+        }
+
+        let start = Instant::now();
+        let removed = range.len();
+        let ctx = NodeContext { metrics: &self.metrics, pool: &self.pool, leaves: self.leaves };
+        let root = Arc::make_mut(&mut self.root);
+        root.remove(range, ctx);
+        root.collapse(&self.metrics);
+
+        self.metrics.record_deletion(removed);
+        self.metrics.record_time(start.elapsed());
+        self.record_memory();
+        Ok(())
+    }
+
+    /// Replaces every range with its text as a single edit. Ranges are offsets into the text
+    /// before the edit and must be sorted & disjoint; on error the rope is left untouched.
+    pub fn apply_edits<S: AsRef<str>>(&mut self, edits: &[(Range<usize>, S)]) -> Result<(), RopeError> {
+        let mut end = 0;
+        for (range, _) in edits {
+            if range.start < end {
+                return Err(RopeError::InvalidRange(range.clone()));
+            }
+            self.check_range(range)?;
+            end = range.end;
+        }
+
+        // Edits applied back to front leave the offsets of the earlier ones valid.
+        for (range, text) in edits.iter().rev() {
+            self.remove(range.clone())?;
+            self.insert(range.start, text.as_ref())?;
+        }
+        Ok(())
+    }
+
+    /// Byte offset & variant of every line ending, a `CRLF` pair split between leaves once.
+    pub fn line_endings(&self) -> Vec<(usize, LineEnding)> {
+        let mut endings = Vec::new();
+        let mut offset = 0;
+        // A CR ending a leaf, which the next one may turn into a CRLF.
+        let mut pending_cr = None;
+        for chunk in self.chunks() {
+            let text: &str = chunk.as_ref();
+            let mut skip_lf = false;
+            if let Some(cr) = pending_cr.take() {
+                skip_lf = text.starts_with('\n');
+                endings.push((cr, if skip_lf { LineEnding::CRLF } else { LineEnding::CR }));
+            }
+            for (idx, ending) in LineEnding::find_all(text) {
+                if idx == 0 && skip_lf {
+                    continue;
+                }
+                if ending == LineEnding::CR && idx + 1 == text.len() {
+                    pending_cr = Some(offset + idx);
+                } else {
+                    endings.push((offset + idx, ending));
+                }
+            }
+            offset += text.len();
+        }
+        endings.extend(pending_cr.map(|cr| (cr, LineEnding::CR)));
+        endings
+    }
+
+    /// Counts the line endings per variant & finds the lines ending otherwise than most.
+    pub fn analyze_line_endings(&self) -> LineEndingAnalysis {
+        LineEndingAnalysis::from_endings(self.line_endings().into_iter().map(|(_, ending)| ending))
+    }
+
+    /// Rewrites every line ending to `target` as a single edit. Applying the returned
+    /// normalization's `undo_edits` puts the original line endings back.
+    pub fn normalize_line_endings(&mut self, target: LineEnding) -> Result<Normalization, RopeError> {
+        let normalization = Normalization::new(target, self.line_endings());
+        self.apply_edits(&normalization.edits())?;
+        Ok(normalization)
+    }
+
+    pub fn slice(&self, range: Range<usize>) -> Result<String, RopeError> {
+        self.check_range(&range)?;
+        let mut result = String::with_capacity(range.len());
+        if !range.is_empty() {
+            self.root.collect_range(range, &mut result);
+        }
+        Ok(result)
+    }
+
+    pub fn iter(&self) -> RopeIterator<'_> {
+        RopeIterator::new(&self.root, None)
+    }
+
+    pub fn iter_range(&self, range: Range<usize>) -> Result<RopeIterator<'_>, RopeError> {
+        self.check_range(&range)?;
+        Ok(RopeIterator::new(&self.root, Some(range)))
+    }
+
+    pub fn bytes(&self) -> RopeByteIterator<'_> {
+        RopeByteIterator::new(&self.root, 0..self.len_bytes())
+    }
+
+    /// Iterates over the extended grapheme clusters, borrowing those that sit within one leaf.
+    pub fn graphemes(&self) -> RopeGraphemeIterator<'_> {
+        RopeGraphemeIterator::new(&self.root)
+    }
+
+    pub fn lines(&self) -> RopeLineIterator<'_> {
+        RopeLineIterator::new(self)
+    }
+
+    pub fn chunks(&self) -> RopeChunkIterator<'_> {
+        RopeChunkIterator::new(&self.root)
+    }
+
+    /// Cursor on the leaf containing `byte_idx`.
+    pub fn chunk_cursor(&self, byte_idx: usize) -> Result<RopeChunkCursor<'_>, RopeError> {
+        let len = self.len_bytes();
+        if byte_idx > len {
+            return Err(RopeError::OutOfBounds { index: byte_idx, len });
+        }
+        Ok(RopeChunkCursor::new(&self.root, byte_idx))
+    }
+
+    pub fn byte_cursor(&self, byte_idx: usize) -> Result<RopeByteCursor<'_>, RopeError> {
+        let len = self.len_bytes();
+        if byte_idx > len {
+            return Err(RopeError::OutOfBounds { index: byte_idx, len });
+        }
+        Ok(RopeByteCursor::new(&self.root, byte_idx))
+    }
+
+    /// Char cursor at `byte_idx`, which must be a char boundary.
+    pub fn char_cursor(&self, byte_idx: usize) -> Result<RopeCharCursor<'_>, RopeError> {
+        self.check_range(&(byte_idx..byte_idx))?;
+        Ok(RopeCharCursor::new(&self.root, byte_idx))
+    }
+
+    /// Grapheme cursor at `byte_idx`, which must be a char boundary.
+    pub fn grapheme_cursor(&self, byte_idx: usize) -> Result<RopeGraphemeCursor<'_>, RopeError> {
+        self.check_range(&(byte_idx..byte_idx))?;
+        Ok(RopeGraphemeCursor::new(&self.root, byte_idx))
+    }
+
+    /// Line cursor before `line`; `len_lines` places it after the last line.
+    pub fn line_cursor(&self, line: usize) -> Result<RopeLineCursor<'_>, RopeError> {
+        let len = self.len_lines();
+        if line > len {
+            return Err(RopeError::OutOfBounds { index: line, len });
+        }
+        Ok(RopeLineCursor::new(self, line))
+    }
+
+    pub(crate) fn check_range(&self, range: &Range<usize>) -> Result<(), RopeError> {
+        let len = self.len_bytes();
+        if range.start > range.end {
+            return Err(RopeError::InvalidRange(range.clone()));
+        }
+        if range.end > len {
+            return Err(RopeError::OutOfBounds { index: range.end, len });
+        }
+        if !self.is_char_boundary(range.start) {
+            return Err(RopeError::NotCharBoundary(range.start));
+        }
+        if !self.is_char_boundary(range.end) {
+            return Err(RopeError::NotCharBoundary(range.end));
+        }
+        Ok(())
+    }
+
+    fn context(&self) -> NodeContext<'_> {
+        NodeContext { metrics: &self.metrics, pool: &self.pool, leaves: self.leaves }
+    }
+
+    fn record_memory(&self) {
+        let nodes = self.metrics.total_nodes.load(std::sync::atomic::Ordering::Relaxed);
+        let pooled = self.pool.stats().retained_bytes;
+        self.metrics.record_memory_usage(self.len_bytes() + nodes * mem::size_of::<Node>() + pooled);
+    }
+}
+
+/// Yields the offset just past each line break of `text`, in order. `after_cr` tells whether the
+/// text directly follows a `\r`, in which case a leading `\n` completes that CRLF pair.
+fn break_ends(text: &str, mode: LineBreaks, after_cr: bool) -> impl Iterator<Item = usize> + '_ {
+    let bytes = text.as_bytes();
+    text.char_indices().filter_map(move |(idx, ch)| match (mode, LineEnding::from_char(ch)?) {
+        (_, LineEnding::LF) => {
+            let completes_crlf = mode == LineBreaks::Unicode
+                && if idx == 0 { after_cr } else { bytes[idx - 1] == b'\r' };
+            (!completes_crlf).then_some(idx + 1)
+        }
+        (LineBreaks::LineFeed, _) => None,
+        (LineBreaks::Unicode, LineEnding::CR) if bytes.get(idx + 1) == Some(&b'\n') => Some(idx + 2),
+        (LineBreaks::Unicode, _) => Some(idx + ch.len_utf8()),
+    })
+}
+
+impl Default for Rope {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Display for Rope {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for chunk in self.chunks() {
+            write!(f, "{}", chunk)?;
+        }
+        Ok(())
+    }
+}
+
+impl From<&str> for Rope {
+    fn from(s: &str) -> Self {
+        Rope::from_text(s)
+    }
+}
+
+impl From<String> for Rope {
+    fn from(s: String) -> Self {
+        Rope::from_text(&s)
+    }
+}
+
+impl PartialEq for Rope {
+    fn eq(&self, other: &Self) -> bool {
+        self.len_bytes() == other.len_bytes() && self.to_string() == other.to_string()
+    }
+}
+
+impl Eq for Rope {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::buffer::rope::chunk::Chunk;
+    use crate::core::buffer::rope::node::MAX_CHUNK_SIZE;
+    use proptest::prelude::*;
+    use std::sync::atomic::Ordering;
+
+    #[test]
+    fn test_empty_rope() {
+        let rope = Rope::new();
+        assert!(rope.is_empty());
+        assert_eq!(rope.len_bytes(), 0);
+        assert_eq!(rope.len_chars(), 0);
+        assert_eq!(rope.len_lines(), 1);
+        assert_eq!(rope.to_string(), "");
+    }
+
+    #[test]
+    fn test_from_text_lengths() {
+        let rope = Rope::from("Héllo\nwörld 🌍\n");
+        assert_eq!(rope.len_bytes(), "Héllo\nwörld 🌍\n".len());
+        assert_eq!(rope.len_chars(), 14);
+        assert_eq!(rope.len_lines(), 3);
+    }
+
+    #[test]
+    fn test_large_text_builds_tree() {
+        let text = "abcdefghij\n".repeat(2000);
+        let rope = Rope::from(text.as_str());
+        assert!(rope.height() > 1);
+        assert_eq!(rope.len_bytes(), text.len());
+        assert_eq!(rope.len_lines(), 2001);
+        assert_eq!(rope.to_string(), text);
+        assert!(rope.chunks().all(|chunk| chunk.len() <= MAX_CHUNK_SIZE));
+    }
+
+    #[test]
+    fn test_insert() {
+        let mut rope = Rope::from("Hello world");
+        rope.insert(5, ",").unwrap();
+        rope.insert(0, ">> ").unwrap();
+        rope.insert(rope.len_bytes(), "!").unwrap();
+        assert_eq!(rope.to_string(), ">> Hello, world!");
+        rope.insert_char(3, '🌍').unwrap();
+        assert_eq!(rope.to_string(), ">> 🌍Hello, world!");
+    }
+
+    #[test]
+    fn test_insert_errors() {
+        let mut rope = Rope::from("é");
+        assert_eq!(rope.insert(1, "x"), Err(RopeError::NotCharBoundary(1)));
+        assert_eq!(rope.insert(10, "x"), Err(RopeError::OutOfBounds { index: 10, len: 2 }));
+    }
+
+    #[test]
+    fn test_insert_splits_leaves() {
+        let mut rope = Rope::new();
+        let line = "0123456789abcdef\n";
+        for i in 0..500 {
+            rope.insert(i * line.len(), line).unwrap();
+        }
+        assert_eq!(rope.len_bytes(), 500 * line.len());
+        assert_eq!(rope.to_string(), line.repeat(500));
+        assert!(rope.chunks().all(|chunk| chunk.len() <= MAX_CHUNK_SIZE));
+        assert!(rope.metrics().total_splits.load(Ordering::Relaxed) > 0);
+    }
+
+    #[test]
+    fn test_remove() {
+        let mut rope = Rope::from("Hello, beautiful world");
+        rope.remove(5..16).unwrap();
+        assert_eq!(rope.to_string(), "Hello world");
+        rope.remove(0..rope.len_bytes()).unwrap();
+        assert!(rope.is_empty());
+        assert!(rope.remove(0..1).is_err());
+    }
+
+    #[test]
+    fn test_remove_across_chunks() {
+        let text = "0123456789".repeat(500);
+        let mut rope = Rope::from(text.as_str());
+        rope.remove(1000..4000).unwrap();
+        let mut expected = text.clone();
+        expected.replace_range(1000..4000, "");
+        assert_eq!(rope.to_string(), expected);
+        assert_eq!(rope.len_bytes(), expected.len());
+    }
+
+    #[test]
+    #[allow(clippy::reversed_empty_ranges)]
+    fn test_slice() {
+        let text = "line one\nline two\n".repeat(300);
+        let rope = Rope::from(text.as_str());
+        assert_eq!(rope.slice(1000..2500).unwrap(), &text[1000..2500]);
+        assert_eq!(rope.slice(0..0).unwrap(), "");
+        assert!(rope.slice(5..2).is_err());
+        assert!(rope.slice(0..text.len() + 1).is_err());
+    }
+
+    #[test]
+    fn test_iterators() {
+        let rope = Rope::from("ab\ncd");
+        assert_eq!(rope.iter().collect::<String>(), "ab\ncd");
+        assert_eq!(rope.lines().collect::<Vec<_>>(), vec!["ab", "cd"]);
+        assert_eq!(rope.chunks().map(Chunk::text).collect::<String>(), "ab\ncd");
+        assert_eq!(rope.iter_range(1..4).unwrap().collect::<String>(), "b\nc");
+    }
+
+    #[test]
+    fn test_iterator_multibyte() {
+        let rope = Rope::from("héllo 🌍");
+        assert_eq!(rope.iter().collect::<String>(), "héllo 🌍");
+    }
+
+    #[test]
+    fn test_metrics_updated_on_edit() {
+        let mut rope = Rope::from("abc");
+        rope.insert(3, "def").unwrap();
+        rope.remove(0..2).unwrap();
+        let metrics = rope.metrics();
+        assert_eq!(metrics.total_insertions.load(Ordering::Relaxed), 1);
+        assert_eq!(metrics.total_deletions.load(Ordering::Relaxed), 1);
+        assert_eq!(metrics.total_bytes.load(Ordering::Relaxed), rope.len_bytes());
+        assert!(metrics.peak_memory_usage.load(Ordering::Relaxed) > 0);
+    }
+
+    #[test]
+    fn test_clone_is_shared_snapshot() {
+        let text = "0123456789\n".repeat(1000);
+        let mut rope = Rope::from(text.as_str());
+        let snapshot = rope.clone();
+        assert!(rope.ptr_eq(&snapshot));
+
+        rope.insert(5000, "edit").unwrap();
+        rope.remove(0..10).unwrap();
+        assert!(!rope.ptr_eq(&snapshot));
+        assert_eq!(snapshot.to_string(), text);
+        assert_eq!(rope.len_bytes(), text.len() - 6);
+    }
+
+    #[test]
+    fn test_edit_copies_only_path() {
+        let rope = Rope::from("abcdefghij".repeat(5000).as_str());
+        let mut edited = rope.clone();
+        edited.insert(0, "x").unwrap();
+        let (old, new) = (rope.root().children(), edited.root().children());
+        assert!(!Arc::ptr_eq(&old[0], &new[0]));
+        assert!(old.iter().zip(new).skip(1).all(|(a, b)| Arc::ptr_eq(a, b)));
+    }
+
+    #[test]
+    fn test_edits_recycle_leaf_storage() {
+        let pool = Arc::new(ChunkPool::default());
+        let mut rope = Rope::from_text(&"x\n".repeat(250)).with_pool(Arc::clone(&pool));
+        for i in 0..200 {
+            rope.insert(250 + i, "a").unwrap();
+        }
+        rope.remove(0..100).unwrap();
+        // Edits that fit their leaf are made in place.
+        assert_eq!(pool.stats().allocations, 0);
+
+        for _ in 0..50 {
+            rope.insert(rope.len_bytes() / 2, &"b".repeat(700)).unwrap();
+        }
+        let stats = pool.stats();
+        assert!(stats.reuses > 40, "{:?}", stats);
+        assert!(stats.allocations < 10, "{:?}", stats);
+        assert!(rope.metrics().peak_memory_usage.load(std::sync::atomic::Ordering::Relaxed) >= rope.len_bytes() + stats.retained_bytes);
+
+        let other = Rope::new().with_pool(Arc::clone(&pool));
+        assert!(Arc::ptr_eq(other.pool(), rope.pool()));
+    }
+
+    #[test]
+    fn test_apply_edits() {
+        let mut rope = Rope::from("one two three");
+        rope.apply_edits(&[(0..3, "1"), (4..4, "and "), (8..13, "3!")]).unwrap();
+        assert_eq!(rope.to_string(), "1 and two 3!");
+
+        let before = rope.clone();
+        assert_eq!(rope.apply_edits(&[(2..5, ""), (4..6, "")]), Err(RopeError::InvalidRange(4..6)));
+        assert_eq!(rope.apply_edits(&[(0..1, ""), (11..40, "")]), Err(RopeError::OutOfBounds { index: 40, len: 12 }));
+        assert!(rope.ptr_eq(&before));
+    }
+
+    #[test]
+    fn test_normalize_line_endings() {
+        let text = "a\r\nbb\ncc\r\rd\r\n".repeat(700);
+        let mut rope = Rope::from_text(&text);
+        assert!(rope.chunks().count() > 1);
+        assert_eq!(rope.line_endings(), LineEnding::find_all(&text).collect::<Vec<_>>());
+        let analysis = rope.analyze_line_endings();
+        assert_eq!(analysis, LineEnding::analyze(&text));
+        assert_eq!(analysis.outliers[..2], [(1, LineEnding::LF), (2, LineEnding::CR)]);
+
+        let normalization = rope.normalize_line_endings(LineEnding::LF).unwrap();
+        assert_eq!(rope.to_string(), LineEnding::normalize(&text, LineEnding::LF).0);
+        assert!(!rope.analyze_line_endings().is_mixed());
+        rope.apply_edits(&normalization.undo_edits()).unwrap();
+        assert_eq!(rope.to_string(), text);
+    }
+
+    #[test]
+    fn test_with_leaf_sizes() {
+        let text = "abc\r\n".repeat(2000);
+        let leaves = LeafSizePolicy::new(512, 4096).unwrap();
+        let mut rope = Rope::from_text(&text).with_leaf_sizes(leaves);
+        rope.root().assert_invariants(true, leaves);
+        assert!(rope.chunks().any(|chunk| chunk.len() > MAX_CHUNK_SIZE));
+        rope.insert(5000, &"x".repeat(5000)).unwrap();
+        rope.remove(0..9000).unwrap();
+        rope.root().assert_invariants(true, leaves);
+        assert_eq!(rope.to_string(), format!("{}{}", &"x".repeat(5000)[4000..], &text[5000..]));
+    }
+
+    #[test]
+    fn test_snapshot_sent_to_thread() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<Rope>();
+
+        let mut rope = Rope::from("hello\nworld\n".repeat(500).as_str());
+        let snapshot = rope.clone();
+        let worker = std::thread::spawn(move || snapshot.lines().filter(|line| line == "world").count());
+        rope.remove(0..rope.len_bytes()).unwrap();
+        assert_eq!(worker.join().unwrap(), 500);
+        assert!(rope.is_empty());
+    }
+
+    #[test]
+    fn test_line_indexing() {
+        let rope = Rope::from("one\ntwo\r\n\nfour");
+        assert_eq!(rope.len_lines(), 4);
+        assert_eq!(rope.line_to_byte(1), Ok(4));
+        assert_eq!(rope.line_to_byte(3), Ok(10));
+        assert!(rope.line_to_byte(4).is_err());
+        assert_eq!(rope.byte_to_line(3), Ok(0));
+        assert_eq!(rope.byte_to_line(4), Ok(1));
+        assert_eq!(rope.byte_to_line(14), Ok(3));
+        assert_eq!(rope.line(1).unwrap(), "two");
+        assert_eq!(rope.line(2).unwrap(), "");
+        assert_eq!(rope.line(3).unwrap(), "four");
+    }
+
+    #[test]
+    fn test_line_char_conversion() {
+        let rope = Rope::from("héllo\n🌍 wörld\nend");
+        assert_eq!(rope.line_to_char(1), Ok(6));
+        assert_eq!(rope.line_to_char(2), Ok(14));
+        assert_eq!(rope.char_to_line(5), Ok(0));
+        assert_eq!(rope.char_to_line(6), Ok(1));
+        assert_eq!(rope.char_to_line(rope.len_chars()), Ok(2));
+        assert_eq!(rope.char_to_byte(7), Ok(11));
+        assert_eq!(rope.byte_to_char(11), Ok(7));
+    }
+
+    #[test]
+    fn test_utf16_conversion() {
+        let rope = Rope::from("a🌍b\né".repeat(400).as_str());
+        assert_eq!(rope.len_utf16(), 6 * 400);
+        assert_eq!(rope.byte_to_utf16(5), Ok(3));
+        assert_eq!(rope.utf16_to_byte(3), Ok(5));
+        assert_eq!(rope.utf16_to_byte(2), Ok(1));
+        assert_eq!(rope.char_to_utf16(1000), Ok(1000 / 5 * 6));
+        assert_eq!(rope.utf16_to_char(rope.len_utf16()), Ok(rope.len_chars()));
+        assert!(rope.utf16_to_byte(rope.len_utf16() + 1).is_err());
+    }
+
+    #[test]
+    fn test_grapheme_navigation() {
+        let rope = Rope::from("e\u{301}x👍🏽\r\n");
+        assert_eq!(rope.len_graphemes(), 4);
+        assert_eq!(rope.next_grapheme_boundary(0), Ok(3));
+        assert_eq!(rope.next_grapheme_boundary(4), Ok(12));
+        assert_eq!(rope.next_grapheme_boundary(12), Ok(14));
+        assert_eq!(rope.next_grapheme_boundary(14), Ok(14));
+        assert_eq!(rope.prev_grapheme_boundary(14), Ok(12));
+        assert_eq!(rope.prev_grapheme_boundary(13), Ok(12));
+        assert_eq!(rope.prev_grapheme_boundary(3), Ok(0));
+        assert_eq!(rope.prev_grapheme_boundary(0), Ok(0));
+    }
+
+    #[test]
+    fn test_grapheme_across_leaves() {
+        let mut rope = Rope::from("e".repeat(2000).as_str());
+        let seam = rope.chunks().next().unwrap().len();
+        rope.insert(seam, "x").unwrap();
+        rope.remove(seam..seam + 1).unwrap();
+        // Leaves now meet between two 'e's; put a combining accent at the start of the second one.
+        let mut rope2 = rope.clone();
+        rope2.insert(seam, "\u{301}").unwrap();
+        assert_eq!(rope2.len_graphemes(), 2000);
+        assert_eq!(rope2.byte_to_grapheme(seam + 1), Ok(seam - 1));
+        assert_eq!(rope2.grapheme_to_byte(seam), Ok(seam + 2));
+    }
+
+    #[test]
+    fn test_unicode_line_breaks() {
+        let mut rope = Rope::from("a\rb\r\nc\u{85}d\u{2028}e\u{2029}f");
+        assert_eq!(rope.len_lines(), 2);
+        rope.set_line_breaks(LineBreaks::Unicode);
+        assert_eq!(rope.len_lines(), 6);
+        let lines: Vec<String> = (0..rope.len_lines()).map(|line| rope.line(line).unwrap()).collect();
+        assert_eq!(lines, ["a", "b", "c", "d", "e", "f"]);
+        assert_eq!(rope.byte_to_line(3), Ok(1));
+        assert_eq!(rope.byte_to_line(4), Ok(1));
+        assert_eq!(rope.byte_to_line(5), Ok(2));
+    }
+
+    #[test]
+    fn test_crlf_split_across_chunks() {
+        let mut rope = Rope::new();
+        rope.insert(0, &format!("{}\n", "x".repeat(2000))).unwrap();
+        let split = rope.chunks().next().unwrap().len();
+        rope.insert(split, "\r").unwrap();
+        rope.set_line_breaks(LineBreaks::Unicode);
+        let cr = rope.to_string().find('\r').unwrap();
+        assert!(rope.chunks().any(|chunk| chunk.text().ends_with('\r')));
+
+        assert_eq!(rope.len_lines(), 3);
+        assert_eq!(rope.line_to_byte(1), Ok(cr + 1));
+        assert_eq!(rope.line_to_byte(2), Ok(rope.len_bytes()));
+        assert_eq!(rope.byte_to_line(cr + 1), Ok(1));
+        assert_eq!(rope.byte_to_line(rope.len_bytes() - 1), Ok(1));
+    }
+
+    #[test]
+    fn test_crlf_pair_split_by_leaves() {
+        // Three even leaves, the first split between `\r` & `x`; removing `x` leaves the pair
+        // straddling two leaves.
+        let text = format!("{}\rx\n{}", "a".repeat(991), "b".repeat(1982));
+        let mut rope = Rope::from(text.as_str());
+        rope.remove(992..993).unwrap();
+        rope.set_line_breaks(LineBreaks::Unicode);
+        assert!(rope.chunks().next().unwrap().text().ends_with('\r'));
+        assert_eq!(rope.len_lines(), 2);
+        assert_eq!(rope.line_to_byte(1), Ok(993));
+        assert_eq!(rope.byte_to_line(992), Ok(0));
+        assert_eq!(rope.byte_to_line(993), Ok(1));
+        assert_eq!(rope.line(0).unwrap(), "a".repeat(991));
+    }
+
+    #[derive(Debug, Clone)]
+    enum Edit {
+        Insert(usize, String),
+        Remove(usize, usize),
+    }
+
+    fn edit_strategy() -> impl Strategy<Value = Edit> {
+        prop_oneof![
+            (any::<usize>(), "[a-zé🌍\n]{0,300}").prop_map(|(at, text)| Edit::Insert(at, text)),
+            (any::<usize>(), 0usize..3000).prop_map(|(at, len)| Edit::Remove(at, len)),
+        ]
+    }
+
+    fn floor_boundary(text: &str, idx: usize) -> usize {
+        (0..=idx.min(text.len())).rev().find(|&i| text.is_char_boundary(i)).unwrap_or(0)
+    }
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(64))]
+
+        #[test]
+        fn prop_edits_match_string_model(
+            initial in "[a-z🌍\n]{0,4000}",
+            edits in prop::collection::vec(edit_strategy(), 1..60),
+        ) {
+            let mut rope = Rope::from(initial.as_str());
+            let mut model = initial;
+            for edit in edits {
+                match edit {
+                    Edit::Insert(at, text) => {
+                        let at = floor_boundary(&model, at % (model.len() + 1));
+                        rope.insert(at, &text).unwrap();
+                        model.insert_str(at, &text);
+                    }
+                    Edit::Remove(at, len) => {
+                        let start = floor_boundary(&model, at % (model.len() + 1));
+                        let end = floor_boundary(&model, start + len);
+                        rope.remove(start..end).unwrap();
+                        model.replace_range(start..end, "");
+                    }
+                }
+                rope.root().assert_invariants(true, rope.leaf_sizes());
+                prop_assert_eq!(rope.len_bytes(), model.len());
+                prop_assert_eq!(rope.len_chars(), model.chars().count());
+                prop_assert_eq!(rope.len_lines(), model.matches('\n').count() + 1);
+            }
+            prop_assert_eq!(rope.to_string(), model);
+        }
+
+        #[test]
+        fn prop_lines_match_string_model(
+            text in "[ab\r\n\u{85}\u{2028}]{0,3000}",
+            unicode in any::<bool>(),
+        ) {
+            let mut rope = Rope::from(text.as_str());
+            let mode = if unicode { LineBreaks::Unicode } else { LineBreaks::LineFeed };
+            rope.set_line_breaks(mode);
+            let starts: Vec<usize> = std::iter::once(0).chain(break_ends(&text, mode, false)).collect();
+            prop_assert_eq!(rope.len_lines(), starts.len());
+            for (line, &start) in starts.iter().enumerate() {
+                prop_assert_eq!(rope.line_to_byte(line), Ok(start));
+            }
+            for byte_idx in (0..=text.len()).filter(|&idx| text.is_char_boundary(idx)) {
+                let expected = starts.partition_point(|&start| start <= byte_idx) - 1;
+                prop_assert_eq!(rope.byte_to_line(byte_idx), Ok(expected));
+            }
+        }
+
+        #[test]
+        fn prop_offsets_match_string_model(
+            text in "(e|\u{301}|🌍|\r|\n|a|👍🏽){0,700}",
+            edits in prop::collection::vec((any::<usize>(), "(\u{301}|e|\n|🏽){0,3}"), 0..20),
+        ) {
+            let mut rope = Rope::from(text.as_str());
+            let mut model = text;
+            for (at, insert) in edits {
+                let at = floor_boundary(&model, at % (model.len() + 1));
+                rope.insert(at, &insert).unwrap();
+                model.insert_str(at, &insert);
+            }
+            use unicode_segmentation::UnicodeSegmentation;
+            prop_assert_eq!(rope.len_utf16(), model.encode_utf16().count());
+            let mut utf16 = 0;
+            for (index, (byte_idx, ch)) in model.char_indices().enumerate() {
+                prop_assert_eq!(rope.char_to_byte(index), Ok(byte_idx));
+                prop_assert_eq!(rope.byte_to_utf16(byte_idx), Ok(utf16));
+                prop_assert_eq!(rope.utf16_to_byte(utf16), Ok(byte_idx));
+                utf16 += ch.len_utf16();
+            }
+            let clusters: Vec<usize> = model.grapheme_indices(true).map(|(idx, _)| idx).collect();
+            prop_assert_eq!(rope.len_graphemes(), clusters.len());
+            for (index, &start) in clusters.iter().enumerate() {
+                prop_assert_eq!(rope.grapheme_to_byte(index), Ok(start));
+                prop_assert_eq!(rope.byte_to_grapheme(start), Ok(index));
+            }
+        }
+
+        #[test]
+        fn prop_graphemes_across_small_leaves(
+            text in "(\u{1F1EB}|\u{1F1F7}|\u{1F468}\u{200D}|\u{1F469}|\u{FE0F}|\u{915}\u{94D}|\u{937}|e\u{301}|a){0,120}",
+            edits in prop::collection::vec((any::<usize>(), "(\u{1F1EB}|\u{200D}|\u{1F469}|\u{94D}|\u{915}){1,3}"), 0..12),
+        ) {
+            let leaves = LeafSizePolicy::new(1, 130).unwrap();
+            let mut rope = Rope::from(text.as_str()).with_leaf_sizes(leaves);
+            let mut model = text;
+            for (at, insert) in edits {
+                let at = floor_boundary(&model, at % (model.len() + 1));
+                rope.insert(at, &insert).unwrap();
+                model.insert_str(at, &insert);
+            }
+            rope.root().assert_invariants(true, leaves);
+            use unicode_segmentation::UnicodeSegmentation;
+            let clusters: Vec<usize> = model.grapheme_indices(true).map(|(idx, _)| idx).collect();
+            prop_assert_eq!(rope.len_graphemes(), clusters.len());
+            for (index, &start) in clusters.iter().enumerate() {
+                prop_assert_eq!(rope.grapheme_to_byte(index), Ok(start));
+            }
+            for (byte_idx, _) in model.char_indices() {
+                prop_assert_eq!(rope.byte_to_grapheme(byte_idx), Ok(clusters.partition_point(|&start| start <= byte_idx) - 1));
+            }
+        }
+
+        #[test]
+        fn prop_slice_matches_string_model(
+            text in "[a-zé🌍\n]{0,5000}",
+            a in any::<usize>(),
+            b in any::<usize>(),
+        ) {
+            let rope = Rope::from(text.as_str());
+            rope.root().assert_invariants(true, rope.leaf_sizes());
+            let a = floor_boundary(&text, a % (text.len() + 1));
+            let b = floor_boundary(&text, b % (text.len() + 1));
+            let (start, end) = (a.min(b), a.max(b));
+            prop_assert_eq!(rope.slice(start..end).unwrap(), &text[start..end]);
+        }
+    }
+}
@@ -0,0 +1,608 @@
+/// search.rs
+/// Defines `SearchQuery`, which finds literal or regex matches anywhere in a rope, including
+/// matches straddling leaves, forward, backward or all at once, and replaces them in one edit.
+use std::error::Error;
+use std::fmt;
+use std::ops::Range;
+use regex::{Regex, RegexBuilder};
+use crate::core::buffer::rope::rope::{Rope, RopeError};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SearchError {
+    EmptyPattern,
+    InvalidPattern(String),
+    Rope(RopeError),
+}
+
+impl fmt::Display for SearchError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SearchError::EmptyPattern => write!(f, "Search pattern is empty"),
+            SearchError::InvalidPattern(message) => write!(f, "Invalid search pattern: {}", message),
+            SearchError::Rope(error) => write!(f, "Rope error: {}", error),
+        }
+    }
+}
+
+impl Error for SearchError {}
+
+impl From<RopeError> for SearchError {
+    fn from(error: RopeError) -> Self {
+        SearchError::Rope(error)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SearchOptions {
+    pub case_sensitive: bool,
+    /// Only keeps matches with no word char, i.e. alphanumeric or `_`, right before or after them.
+    pub whole_word: bool,
+    /// Reads the pattern as a regular expression instead of literal text.
+    pub regex: bool,
+}
+
+impl Default for SearchOptions {
+    fn default() -> Self {
+        Self { case_sensitive: true, whole_word: false, regex: false }
+    }
+}
+
+/// Bytes of text a regex search gathers at a time, leaves being gathered whole.
+const WINDOW: usize = 64 * 1024;
+
+/// Length up to which regex matches are found exactly: a match is only reported once the window
+/// reaches this far past its start, and windows overlap by as much.
+const MATCH_SPAN: usize = WINDOW / 4;
+
+#[derive(Debug, Clone)]
+enum Matcher {
+    /// Case-sensitive literal text, searched leaf by leaf.
+    Literal(String),
+    /// Regular expressions & case-insensitive literals, searched in windows of leaves since the
+    /// `regex` crate needs contiguous input.
+    Regex(Regex),
+}
+
+/// A compiled search pattern.
+///
+/// Literal searches stream the leaves through a window that carries the end of the previous
+/// leaf, so they never copy more than a leaf plus the pattern. Regex searches gather runs of
+/// leaves about `WINDOW` bytes long, along with the chars around them so that look-around such
+/// as `^` or `\b` sees the text around the window. A window only settles matches starting at
+/// least `MATCH_SPAN` bytes before its end, and grows when a match reaches its end, so matches
+/// are exact unless a match longer than `MATCH_SPAN` starts before another one.
+#[derive(Debug, Clone)]
+pub struct SearchQuery {
+    pattern: String,
+    options: SearchOptions,
+    matcher: Matcher,
+}
+
+impl SearchQuery {
+    pub fn new(pattern: &str, options: SearchOptions) -> Result<Self, SearchError> {
+        if pattern.is_empty() {
+            return Err(SearchError::EmptyPattern);
+        }
+        let matcher = if !options.regex && options.case_sensitive {
+            Matcher::Literal(pattern.to_string())
+        } else {
+            let source = if options.regex { pattern.to_string() } else { regex::escape(pattern) };
+            let regex = RegexBuilder::new(&source)
+                .case_insensitive(!options.case_sensitive)
+                .build()
+                .map_err(|error| SearchError::InvalidPattern(error.to_string()))?;
+            Matcher::Regex(regex)
+        };
+        Ok(Self { pattern: pattern.to_string(), options, matcher })
+    }
+
+    pub fn literal(pattern: &str) -> Result<Self, SearchError> {
+        Self::new(pattern, SearchOptions::default())
+    }
+
+    pub fn regex(pattern: &str) -> Result<Self, SearchError> {
+        Self::new(pattern, SearchOptions { regex: true, ..SearchOptions::default() })
+    }
+
+    pub fn pattern(&self) -> &str {
+        &self.pattern
+    }
+
+    pub fn options(&self) -> SearchOptions {
+        self.options
+    }
+
+    /// Iterates lazily over the matches in the whole text, which never overlap.
+    pub fn matches<'a>(&'a self, rope: &'a Rope) -> SearchMatches<'a> {
+        SearchMatches { query: self, rope, range: 0..rope.len_bytes(), window: None }
+    }
+
+    /// Iterates lazily over the matches lying within `range`.
+    pub fn matches_in<'a>(&'a self, rope: &'a Rope, range: Range<usize>) -> Result<SearchMatches<'a>, SearchError> {
+        rope.check_range(&range)?;
+        Ok(SearchMatches { query: self, rope, range, window: None })
+    }
+
+    /// First match starting at or after `from`.
+    pub fn find_next(&self, rope: &Rope, from: usize) -> Result<Option<Range<usize>>, SearchError> {
+        Ok(self.matches_in(rope, from..rope.len_bytes())?.next())
+    }
+
+    /// Last match ending at or before `before`. Regex matches are the last ones a forward
+    /// search finds from the start of the window holding them.
+    pub fn find_prev(&self, rope: &Rope, before: usize) -> Result<Option<Range<usize>>, SearchError> {
+        rope.check_range(&(0..before))?;
+        let accept = |found: &Range<usize>| self.accepts(rope, found);
+        Ok(match &self.matcher {
+            Matcher::Literal(needle) => find_backward(rope, needle, 0..before, accept),
+            Matcher::Regex(regex) => find_regex_backward(rope, regex, 0..before, accept),
+        })
+    }
+
+    pub fn find_all(&self, rope: &Rope) -> Vec<Range<usize>> {
+        self.matches(rope).collect()
+    }
+
+    /// Replaces every match with `replacement`, in which a regex query expands `$1` or `$name`
+    /// to the text of a capture group. All replacements make up one edit. Returns the number of
+    /// matches replaced.
+    pub fn replace_all(&self, rope: &mut Rope, replacement: &str) -> usize {
+        let mut matches = self.matches(rope);
+        let mut edits = Vec::new();
+        while let Some(found) = matches.next() {
+            let text = matches.expand(&found, replacement);
+            edits.push((found, text));
+        }
+        rope.apply_edits(&edits).expect("matches are sorted, disjoint & on char boundaries");
+        edits.len()
+    }
+
+    /// Replaces the first match starting at or after `from` and returns the range of the text
+    /// that replaced it.
+    pub fn replace_next(&self, rope: &mut Rope, from: usize, replacement: &str) -> Result<Option<Range<usize>>, SearchError> {
+        let mut matches = self.matches_in(rope, from..rope.len_bytes())?;
+        let Some(found) = matches.next() else {
+            return Ok(None);
+        };
+        let text = matches.expand(&found, replacement);
+        rope.apply_edits(&[(found.clone(), text.as_str())])?;
+        Ok(Some(found.start..found.start + text.len()))
+    }
+
+    /// Whether `found` passes the options a matcher cannot check by itself.
+    fn accepts(&self, rope: &Rope, found: &Range<usize>) -> bool {
+        if !self.options.whole_word {
+            return true;
+        }
+        let before = rope.char_cursor(found.start).ok().and_then(|cursor| cursor.peek_prev_char());
+        let after = rope.char_cursor(found.end).ok().and_then(|cursor| cursor.peek_char());
+        !before.is_some_and(is_word_char) && !after.is_some_and(is_word_char)
+    }
+}
+
+/// Iterator over the matches of a `SearchQuery`, searching for each one as it is requested.
+#[derive(Debug)]
+pub struct SearchMatches<'a> {
+    query: &'a SearchQuery,
+    rope: &'a Rope,
+    /// Part of the text not searched yet.
+    range: Range<usize>,
+    /// Leaves gathered by the last regex search, reused while the search stays within them.
+    window: Option<Window>,
+}
+
+impl SearchMatches<'_> {
+    /// Text replacing `found`, with capture groups expanded for regex queries.
+    fn expand(&mut self, found: &Range<usize>, replacement: &str) -> String {
+        let Matcher::Regex(regex) = &self.query.matcher else {
+            return replacement.to_string();
+        };
+        if !self.query.options.regex {
+            return replacement.to_string();
+        }
+        let window = match self.window.take() {
+            Some(window) if window.leaves.start <= found.start && found.end <= window.leaves.end => window,
+            _ => Window::gather(self.rope, found.start, found.len()),
+        };
+        let mut text = String::new();
+        match regex.captures_at(&window.text, found.start - window.start) {
+            Some(captures) => captures.expand(replacement, &mut text),
+            None => text.push_str(replacement),
+        }
+        self.window = Some(window);
+        text
+    }
+}
+
+impl Iterator for SearchMatches<'_> {
+    type Item = Range<usize>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.range.start > self.range.end {
+            return None;
+        }
+        let (query, rope) = (self.query, self.rope);
+        let accept = |found: &Range<usize>| query.accepts(rope, found);
+        let found = match &query.matcher {
+            Matcher::Literal(needle) => find_forward(rope, needle, self.range.clone(), accept),
+            Matcher::Regex(regex) => find_regex(rope, regex, self.range.clone(), &mut self.window, accept),
+        };
+        let Some(found) = found else {
+            self.range.start = self.range.end + 1;
+            return None;
+        };
+        // An empty match is followed by a search from the next char, so that it is not found again.
+        self.range.start = if found.is_empty() {
+            found.end + rope.char_cursor(found.end).ok().and_then(|cursor| cursor.peek_char()).map_or(1, char::len_utf8)
+        } else {
+            found.end
+        };
+        Some(found)
+    }
+}
+
+fn is_word_char(ch: char) -> bool {
+    ch.is_alphanumeric() || ch == '_'
+}
+
+fn floor_char_boundary(text: &str, idx: usize) -> usize {
+    (0..=idx.min(text.len())).rev().find(|&idx| text.is_char_boundary(idx)).unwrap_or(0)
+}
+
+fn ceil_char_boundary(text: &str, idx: usize) -> usize {
+    (idx.min(text.len())..=text.len()).find(|&idx| text.is_char_boundary(idx)).unwrap_or(text.len())
+}
+
+/// First occurrence of `needle` within `range` that `accept` keeps. Leaves are pushed through a
+/// window that keeps the last `needle.len() - 1` bytes of the text before them, which is
+/// enough for an occurrence straddling two leaves to lie whole in the window.
+fn find_forward(rope: &Rope, needle: &str, range: Range<usize>, accept: impl Fn(&Range<usize>) -> bool) -> Option<Range<usize>> {
+    let mut chunks = rope.chunk_cursor(range.start).ok()?;
+    let mut window = String::new();
+    let mut window_start = range.start;
+    let mut offset = range.start - chunks.start();
+    loop {
+        let text = chunks.chunk().text();
+        let end = (range.end - chunks.start()).min(text.len());
+        window.push_str(&text[offset..end]);
+
+        let mut from = 0;
+        while let Some(idx) = window[from..].find(needle) {
+            let start = window_start + from + idx;
+            let found = start..start + needle.len();
+            if accept(&found) {
+                return Some(found);
+            }
+            from += idx + window[from + idx..].chars().next().map_or(1, char::len_utf8);
+        }
+
+        if chunks.end() >= range.end || chunks.next_chunk().is_none() {
+            return None;
+        }
+        offset = 0;
+        let keep = floor_char_boundary(&window, window.len().saturating_sub(needle.len() - 1));
+        window.drain(..keep);
+        window_start += keep;
+    }
+}
+
+/// Last occurrence of `needle` within `range` that `accept` keeps, walking the leaves back to
+/// front through a window that keeps the first `needle.len() - 1` bytes of the text after them.
+fn find_backward(rope: &Rope, needle: &str, range: Range<usize>, accept: impl Fn(&Range<usize>) -> bool) -> Option<Range<usize>> {
+    let mut chunks = rope.chunk_cursor(range.end).ok()?;
+    let mut window = String::new();
+    let mut window_end = range.end;
+    let mut end = range.end - chunks.start();
+    loop {
+        let text = chunks.chunk().text();
+        let start = range.start.saturating_sub(chunks.start()).min(end);
+        window.insert_str(0, &text[start..end]);
+        let window_start = window_end - window.len();
+
+        let mut limit = window.len();
+        while let Some(idx) = window[..limit].rfind(needle) {
+            let found = window_start + idx..window_start + idx + needle.len();
+            if accept(&found) {
+                return Some(found);
+            }
+            limit = floor_char_boundary(&window, idx + needle.len() - 1);
+        }
+
+        if chunks.start() <= range.start || chunks.prev_chunk().is_none() {
+            return None;
+        }
+        end = chunks.chunk().len();
+        let keep = ceil_char_boundary(&window, needle.len() - 1);
+        window.truncate(keep);
+        window_end = window_start + keep;
+    }
+}
+
+/// Contiguous text of a run of leaves, along with the chars right before & after it.
+#[derive(Debug)]
+struct Window {
+    /// Offset of `text` in the rope.
+    start: usize,
+    text: String,
+    /// Part of the rope covered by the leaves.
+    leaves: Range<usize>,
+    /// Whether the leaves run to the end of the rope.
+    is_last: bool,
+}
+
+impl Window {
+    /// Gathers the leaves from the one holding `from` until they reach `len` bytes past it or the
+    /// end of the rope.
+    fn gather(rope: &Rope, from: usize, len: usize) -> Window {
+        let mut chunks = rope.chunk_cursor(from).expect("offset within the rope");
+        let first = chunks.start();
+        let before = rope.char_cursor(first).ok().and_then(|cursor| cursor.peek_prev_char());
+        let mut text = String::with_capacity(len + 8);
+        text.extend(before);
+        loop {
+            text.push_str(chunks.chunk().text());
+            if chunks.end() >= from + len || chunks.next_chunk().is_none() {
+                break;
+            }
+        }
+        let end = chunks.end();
+        text.extend(rope.char_cursor(end).ok().and_then(|cursor| cursor.peek_char()));
+        Window {
+            start: first - before.map_or(0, char::len_utf8),
+            text,
+            leaves: first..end,
+            is_last: end == rope.len_bytes(),
+        }
+    }
+
+    /// Whether a search from `at` can go on in this window.
+    fn covers(&self, at: usize) -> bool {
+        self.leaves.start <= at && (self.is_last || at + MATCH_SPAN <= self.leaves.end)
+    }
+
+    fn find_at(&self, regex: &Regex, at: usize) -> Option<Range<usize>> {
+        let found = regex.find_at(&self.text, at - self.start)?.range();
+        Some(self.start + found.start..self.start + found.end)
+    }
+
+    /// Offset of the char boundary at or before `offset`, which must lie in the window.
+    fn floor_char_boundary(&self, offset: usize) -> usize {
+        self.start + self.text.floor_char_boundary(offset - self.start)
+    }
+
+    /// Offset right after the char at `offset`.
+    fn next_char_end(&self, offset: usize) -> usize {
+        offset + self.text[offset - self.start..].chars().next().map_or(1, char::len_utf8)
+    }
+}
+
+/// First match of `regex` lying within `range` that `accept` keeps, searching windows of leaves
+/// & keeping the last one in `cached` for the next search.
+fn find_regex(rope: &Rope, regex: &Regex, range: Range<usize>, cached: &mut Option<Window>, accept: impl Fn(&Range<usize>) -> bool) -> Option<Range<usize>> {
+    let mut at = range.start;
+    let mut len = WINDOW;
+    let mut window = match cached.take() {
+        Some(window) if window.covers(at) => window,
+        _ => Window::gather(rope, at, len),
+    };
+    loop {
+        let found = window.find_at(regex, at);
+        match found {
+            Some(found) if window.is_last || (found.start + MATCH_SPAN <= window.leaves.end && found.end <= window.leaves.end) => {
+                if found.end > range.end {
+                    *cached = Some(window);
+                    return None;
+                }
+                if accept(&found) {
+                    *cached = Some(window);
+                    return Some(found);
+                }
+                at = window.next_char_end(found.start);
+                if at > range.end {
+                    *cached = Some(window);
+                    return None;
+                }
+                if window.covers(at) {
+                    continue;
+                }
+            }
+            None if window.is_last => {
+                *cached = Some(window);
+                return None;
+            }
+            // No match fitting in the window starts more than `MATCH_SPAN` bytes before its
+            // end, so the search goes on from there, or from the same place in a larger window
+            // when the match found there may go on past the window.
+            _ => {
+                let settled = window.floor_char_boundary(window.leaves.end.saturating_sub(MATCH_SPAN).max(at));
+                let next = found.map_or(settled, |found| settled.min(found.start));
+                if next > range.end {
+                    *cached = Some(window);
+                    return None;
+                }
+                if next == at {
+                    len *= 2;
+                } else {
+                    (at, len) = (next, WINDOW);
+                }
+            }
+        }
+        window = Window::gather(rope, at, len);
+    }
+}
+
+/// Last match of `regex` lying within `range` that `accept` keeps, searching forward from the
+/// start of windows of leaves walked back to front, which overlap by `MATCH_SPAN` bytes. A
+/// window grows back when its last match starts too close to its start to be settled.
+fn find_regex_backward(rope: &Rope, regex: &Regex, range: Range<usize>, accept: impl Fn(&Range<usize>) -> bool) -> Option<Range<usize>> {
+    let mut end = range.end;
+    let mut len = WINDOW;
+    loop {
+        let from = end.saturating_sub(len).max(range.start);
+        // Gathering past `end` keeps matches ending there from being cut short.
+        let window = Window::gather(rope, from, end - from + MATCH_SPAN);
+        let from = window.floor_char_boundary(from).max(range.start);
+        let mut last = None;
+        let mut at = from;
+        while let Some(found) = window.find_at(regex, at).filter(|found| found.end <= end) {
+            if accept(&found) {
+                last = Some(found.clone());
+            }
+            at = match found.is_empty() {
+                true => window.next_char_end(found.end),
+                false => found.end,
+            };
+            if at > end {
+                break;
+            }
+        }
+        match last {
+            Some(found) if from == range.start || found.start >= from + MATCH_SPAN => return Some(found),
+            _ if from == range.start => return None,
+            Some(_) => len *= 2,
+            None => (end, len) = ((from + MATCH_SPAN).min(end), WINDOW),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::buffer::rope::LeafSizePolicy;
+    use proptest::prelude::*;
+
+    fn small_leaves(text: &str) -> Rope {
+        Rope::from_text(text).with_leaf_sizes(LeafSizePolicy::new(4, 136).unwrap())
+    }
+
+    fn options(case_sensitive: bool, whole_word: bool, regex: bool) -> SearchOptions {
+        SearchOptions { case_sensitive, whole_word, regex }
+    }
+
+    #[test]
+    fn test_literal_matches_straddling_leaves() {
+        let text = "abcdefghij-needle-".repeat(200);
+        let rope = small_leaves(&text);
+        assert!(rope.chunks().count() > 20);
+        let query = SearchQuery::literal("needle-abc").unwrap();
+        let expected: Vec<Range<usize>> = text.match_indices("needle-abc").map(|(idx, _)| idx..idx + 10).collect();
+        assert_eq!(query.find_all(&rope), expected);
+        assert_eq!(query.find_next(&rope, 11).unwrap(), Some(expected[0].clone()));
+        assert_eq!(query.find_next(&rope, 12).unwrap(), Some(expected[1].clone()));
+        assert_eq!(query.find_prev(&rope, text.len()).unwrap(), expected.last().cloned());
+        assert_eq!(query.find_prev(&rope, expected[3].end - 1).unwrap(), Some(expected[2].clone()));
+        assert_eq!(query.find_prev(&rope, 5).unwrap(), None);
+    }
+
+    #[test]
+    fn test_case_and_whole_word() {
+        let rope = small_leaves("Foo food foo_bar FOO, (foo) élan Élan");
+        let query = SearchQuery::new("foo", options(false, false, false)).unwrap();
+        assert_eq!(query.find_all(&rope).len(), 5);
+        let query = SearchQuery::new("foo", options(false, true, false)).unwrap();
+        assert_eq!(query.find_all(&rope), vec![0..3, 17..20, 23..26]);
+        let query = SearchQuery::new("foo", options(true, true, false)).unwrap();
+        assert_eq!(query.find_all(&rope), vec![23..26]);
+        assert_eq!(query.find_prev(&rope, 22).unwrap(), None);
+        let query = SearchQuery::new("élan", options(false, true, false)).unwrap();
+        assert_eq!(query.find_all(&rope).len(), 2);
+    }
+
+    #[test]
+    fn test_regex_search() {
+        let text = "let x = 10;\nlet yy = 200;\nconst z = 3;\n".repeat(30);
+        let rope = small_leaves(&text);
+        let query = SearchQuery::regex(r"(?m)^let (\w+) = (\d+);$").unwrap();
+        assert_eq!(query.find_all(&rope).len(), 60);
+        let last = query.find_prev(&rope, text.len()).unwrap().unwrap();
+        assert_eq!(&text[last], "let yy = 200;");
+
+        let digits = SearchQuery::regex(r"\d+").unwrap();
+        let mut matches = digits.matches(&rope);
+        assert_eq!(matches.next(), Some(8..10));
+        assert_eq!(matches.next(), Some(21..24));
+        assert!(matches!(SearchQuery::regex("("), Err(SearchError::InvalidPattern(_))));
+        assert_eq!(SearchQuery::literal("").unwrap_err(), SearchError::EmptyPattern);
+    }
+
+    #[test]
+    fn test_regex_search_in_windows() {
+        let text: String = (0..20_000).map(|idx| format!("Item {idx}: x{}\n", "y".repeat(idx % 7))).collect();
+        let rope = small_leaves(&text);
+        assert!(text.len() > 4 * WINDOW);
+        let digits = Regex::new(r"\d+").unwrap();
+        let expected: Vec<Range<usize>> = digits.find_iter(&text).map(|found| found.range()).collect();
+        assert_eq!(SearchQuery::regex(r"\d+").unwrap().find_all(&rope), expected);
+        assert_eq!(SearchQuery::regex(r"\d+").unwrap().find_prev(&rope, text.len()).unwrap(), expected.last().cloned());
+        let middle = expected[expected.len() / 2].clone();
+        assert_eq!(SearchQuery::regex(r"\d+").unwrap().find_prev(&rope, middle.end).unwrap(), Some(middle));
+
+        let query = SearchQuery::new("ITEM 1999", options(false, true, false)).unwrap();
+        let found = text.find("Item 1999:").unwrap();
+        assert_eq!(query.find_all(&rope), vec![found..found + 9]);
+        assert_eq!(query.find_prev(&rope, text.len()).unwrap(), Some(found..found + 9));
+        let lines = SearchQuery::regex(r"(?m)^Item 1\d{4}: xy{6}$").unwrap();
+        assert_eq!(lines.find_all(&rope).len(), text.lines().filter(|line| line.len() == 19 && line.starts_with("Item 1")).count());
+
+        // Matches longer than a window grow it.
+        let text = format!("{}{}-{}", "a".repeat(WINDOW - 100), "b".repeat(2 * WINDOW), "b".repeat(10));
+        let rope = small_leaves(&text);
+        let runs = SearchQuery::regex("b+").unwrap();
+        let start = WINDOW - 100;
+        assert_eq!(runs.find_all(&rope), vec![start..start + 2 * WINDOW, text.len() - 10..text.len()]);
+        assert_eq!(runs.find_prev(&rope, text.len() - 1).unwrap(), Some(start..start + 2 * WINDOW));
+        let mut replaced = rope.clone();
+        assert_eq!(SearchQuery::regex("(b+)-").unwrap().replace_all(&mut replaced, "<$1>"), 1);
+        assert_eq!(replaced.len_bytes(), text.len() + 1);
+    }
+
+    #[test]
+    fn test_empty_regex_matches_advance() {
+        let rope = Rope::from("aé🌍");
+        let query = SearchQuery::regex("x*").unwrap();
+        assert_eq!(query.find_all(&rope), vec![0..0, 1..1, 3..3, 7..7]);
+    }
+
+    #[test]
+    fn test_replace_all_is_one_batch() {
+        let text = "let x = 10;\nlet yy = 200;\n".repeat(50);
+        let mut rope = small_leaves(&text);
+        let query = SearchQuery::regex(r"let (\w+) = (\d+);").unwrap();
+        assert_eq!(query.replace_all(&mut rope, "const $1: u32 = $2;"), 100);
+        assert_eq!(rope.to_string(), "const x: u32 = 10;\nconst yy: u32 = 200;\n".repeat(50));
+
+        let query = SearchQuery::new("U32", options(false, true, false)).unwrap();
+        assert_eq!(query.replace_all(&mut rope, "$1"), 100);
+        assert!(rope.to_string().starts_with("const x: $1 = 10;"));
+        assert_eq!(SearchQuery::literal("missing").unwrap().replace_all(&mut rope, "x"), 0);
+    }
+
+    #[test]
+    fn test_replace_next() {
+        let mut rope = small_leaves(&"ab".repeat(100));
+        let query = SearchQuery::literal("ba").unwrap();
+        assert_eq!(query.replace_next(&mut rope, 0, "<>").unwrap(), Some(1..3));
+        assert_eq!(query.replace_next(&mut rope, 3, "").unwrap(), Some(3..3));
+        assert!(rope.to_string().starts_with("a<>b"));
+        let past_end = rope.len_bytes() + 1;
+        assert!(query.replace_next(&mut rope, past_end, "").is_err());
+    }
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(64))]
+
+        #[test]
+        fn prop_literal_search_matches_str(text in "[abé\n]{0,600}", needle in "[abé\n]{1,4}") {
+            let rope = small_leaves(&text);
+            let query = SearchQuery::literal(&needle).unwrap();
+            let expected: Vec<Range<usize>> = text.match_indices(needle.as_str()).map(|(idx, found)| idx..idx + found.len()).collect();
+            prop_assert_eq!(query.find_all(&rope), expected.clone());
+            let last = text.rmatch_indices(needle.as_str()).next().map(|(idx, found)| idx..idx + found.len());
+            prop_assert_eq!(query.find_prev(&rope, text.len()).unwrap(), last);
+
+            let mut replaced = rope.clone();
+            query.replace_all(&mut replaced, "<>");
+            prop_assert_eq!(replaced.to_string(), text.replace(needle.as_str(), "<>"));
+        }
+    }
+}
@@ -0,0 +1,444 @@
+/// summary.rs
+/// Defines `TextSummary`, the aggregate measures cached by rope leaves & internal nodes.
+use std::ops::{Add, AddAssign};
+use unicode_segmentation::{GraphemeCursor, UnicodeSegmentation};
+use crate::core::buffer::content::line_ending::LineEnding;
+
+/// Which characters end a line when indexing a rope by lines.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum LineBreaks {
+    /// Only `\n` ends a line, so `LF` and `CRLF` text is indexed alike.
+    #[default]
+    LineFeed,
+    /// Every `LineEnding` ends a line: `LF`, `CRLF` (counted once), `CR`, `NEL`, `LS` & `PS`.
+    Unicode,
+}
+
+/// What the end of a text means to the grapheme rules that look back further than one char:
+/// regional indicators pair up into flags (GB12-13), ZWJ joins emoji sequences (GB11) and
+/// linkers join Indic conjuncts (GB9c).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum ClusterContext {
+    #[default]
+    Plain,
+    /// After an odd run of regional indicators, so the next one completes a flag.
+    OddIndicator,
+    /// After `Extended_Pictographic Extend*`.
+    Pictograph,
+    /// After `Extended_Pictographic Extend* ZWJ`, so the next pictograph joins the sequence.
+    PictographJoiner,
+    /// After `InCB=Consonant InCB=Extend*`.
+    Consonant,
+    /// After a consonant and at least one `InCB=Linker`, so the next consonant joins the conjunct.
+    Conjunct,
+}
+
+const CONTEXTS: usize = 6;
+
+impl ClusterContext {
+    pub const ALL: [ClusterContext; CONTEXTS] = [
+        ClusterContext::Plain,
+        ClusterContext::OddIndicator,
+        ClusterContext::Pictograph,
+        ClusterContext::PictographJoiner,
+        ClusterContext::Consonant,
+        ClusterContext::Conjunct,
+    ];
+
+    /// Shortest text ending in this context.
+    fn sample(self) -> &'static str {
+        match self {
+            ClusterContext::Plain => "",
+            ClusterContext::OddIndicator => "\u{1F1E6}",
+            ClusterContext::Pictograph => "\u{1F44D}",
+            ClusterContext::PictographJoiner => "\u{1F44D}\u{200D}",
+            ClusterContext::Consonant => "\u{915}",
+            ClusterContext::Conjunct => "\u{915}\u{94D}",
+        }
+    }
+
+    /// Text which, followed by `last`, ends in this context; `last` must be able to end it.
+    fn lead(self, last: char) -> &'static str {
+        match self {
+            ClusterContext::Plain if is_regional_indicator(last) => "\u{1F1E6}",
+            ClusterContext::Plain | ClusterContext::OddIndicator => "",
+            ClusterContext::Pictograph | ClusterContext::PictographJoiner => "\u{1F44D}",
+            ClusterContext::Consonant => "\u{915}",
+            ClusterContext::Conjunct => "\u{915}\u{94D}",
+        }
+    }
+
+    /// Context at the end of `text`, which the segmentation tables only expose through how
+    /// the last cluster reacts to a pictograph or a consonant appended to it.
+    pub fn after(text: &str) -> ClusterContext {
+        let Some(tail) = text.graphemes(true).next_back() else {
+            return ClusterContext::Plain;
+        };
+        let last = tail.chars().next_back().expect("clusters are not empty");
+        if last.is_ascii() {
+            return ClusterContext::Plain;
+        }
+        if is_regional_indicator(last) {
+            // The last cluster of a run holds a lone indicator when the run is odd.
+            let run = tail.chars().rev().take_while(|&ch| is_regional_indicator(ch)).count();
+            return if run % 2 == 1 { ClusterContext::OddIndicator } else { ClusterContext::Plain };
+        }
+        let joins = |next: &str| format!("{tail}{next}").graphemes(true).nth(1).is_none();
+        if last == '\u{200D}' && joins("\u{1F44D}") {
+            ClusterContext::PictographJoiner
+        } else if last != '\u{200D}' && joins("\u{200D}\u{1F44D}") {
+            ClusterContext::Pictograph
+        } else if joins("\u{915}") && is_grapheme_boundary(last, ClusterContext::Plain, '\u{915}') {
+            ClusterContext::Conjunct
+        } else if joins("\u{94D}\u{915}") {
+            ClusterContext::Consonant
+        } else {
+            ClusterContext::Plain
+        }
+    }
+}
+
+/// Measures of a piece of text, summed up the rope so that offset lookups stay logarithmic.
+///
+/// `line_breaks` counts breaks in `LineBreaks::Unicode` mode and `graphemes` counts extended
+/// grapheme clusters. A `CRLF` pair or a cluster may straddle two leaves, so the summary keeps
+/// the first & last char of the text, and adding two summaries counts such a pair once.
+///
+/// Flags, emoji ZWJ sequences and conjuncts may also change clusters further into the next
+/// text, e.g. a leaf starting with an even run of regional indicators pairs them differently
+/// after an odd run. The summary therefore records, for every `ClusterContext` the text may
+/// follow, the context it leaves & how its cluster count differs from the count after `Plain`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct TextSummary {
+    pub bytes: usize,
+    pub chars: usize,
+    pub utf16_units: usize,
+    pub graphemes: usize,
+    pub line_feeds: usize,
+    pub line_breaks: usize,
+    pub first_char: Option<char>,
+    pub last_char: Option<char>,
+    exits: [ClusterContext; CONTEXTS],
+    deltas: [i8; CONTEXTS],
+}
+
+impl TextSummary {
+    pub const ZERO: TextSummary = TextSummary {
+        bytes: 0,
+        chars: 0,
+        utf16_units: 0,
+        graphemes: 0,
+        line_feeds: 0,
+        line_breaks: 0,
+        first_char: None,
+        last_char: None,
+        exits: [ClusterContext::Plain; CONTEXTS],
+        deltas: [0; CONTEXTS],
+    };
+
+    pub fn of(text: &str) -> Self {
+        let mut summary = TextSummary {
+            bytes: text.len(),
+            first_char: text.chars().next(),
+            last_char: text.chars().next_back(),
+            ..TextSummary::ZERO
+        };
+        let mut after_cr = false;
+        let mut crlf_pairs = 0;
+        for ch in text.chars() {
+            summary.chars += 1;
+            summary.utf16_units += ch.len_utf16();
+            match LineEnding::from_char(ch) {
+                Some(LineEnding::LF) => {
+                    summary.line_feeds += 1;
+                    if after_cr {
+                        crlf_pairs += 1;
+                    } else {
+                        summary.line_breaks += 1;
+                    }
+                }
+                Some(_) => summary.line_breaks += 1,
+                None => {}
+            }
+            after_cr = ch == '\r';
+        }
+        // In ASCII text every char is a cluster of its own, CRLF pairs excepted, which spares
+        // the segmentation that otherwise dominates the cost of summarizing a leaf.
+        if summary.chars == summary.bytes {
+            summary.graphemes = summary.chars - crlf_pairs;
+        } else {
+            summary.measure_clusters(text);
+        }
+        summary
+    }
+
+    /// Counts the clusters of `text` and works out how each context before it changes them.
+    fn measure_clusters(&mut self, text: &str) {
+        let (mut second, mut last) = (None, None);
+        for (index, cluster) in text.grapheme_indices(true).enumerate() {
+            if index == 1 {
+                second = Some(cluster);
+            }
+            self.graphemes += 1;
+            last = Some(cluster);
+        }
+        let own = last.map_or(ClusterContext::Plain, |(_, cluster)| ClusterContext::after(cluster));
+        let indicators = text.chars().take_while(|&ch| is_regional_indicator(ch)).count();
+        for context in ClusterContext::ALL {
+            let index = context as usize;
+            self.exits[index] = if context == ClusterContext::Plain {
+                own
+            } else if indicators == self.chars {
+                match (context, own) {
+                    (ClusterContext::OddIndicator, ClusterContext::OddIndicator) => ClusterContext::Plain,
+                    (ClusterContext::OddIndicator, _) => ClusterContext::OddIndicator,
+                    _ => own,
+                }
+            } else if second.is_none() {
+                ClusterContext::after(&format!("{}{}", context.sample(), text))
+            } else {
+                own
+            };
+            // Only the first cluster boundary can move, the rules looking back no further than
+            // the first char that is not an extender, except for a leading run of indicators.
+            self.deltas[index] = match (context, second) {
+                (ClusterContext::Plain, _) => 0,
+                (ClusterContext::OddIndicator, _) => i8::from(indicators > 0 && indicators % 2 == 0),
+                (_, Some((start, next))) => {
+                    let end = start + next.chars().next().map_or(0, char::len_utf8);
+                    let probe = format!("{}{}", context.sample(), &text[..end]);
+                    -i8::from(!is_boundary_at(&probe, context.sample().len() + start))
+                }
+                (_, None) => 0,
+            };
+        }
+    }
+
+    /// Updates the summary of a text after an edit turned the window measured by `old` into the
+    /// one measured by `new`, `text` being the edited text.
+    ///
+    /// Both windows must start at the same cluster anchor, past the first char of the second
+    /// cluster of the text, and end after the same anchor or at the end of the text. The
+    /// clusters & breaks outside the windows then don't change, and neither do the measures
+    /// depending on how the text starts.
+    pub fn replace_window(&mut self, old: &TextSummary, new: &TextSummary, text: &str) {
+        self.bytes = self.bytes + new.bytes - old.bytes;
+        self.chars = self.chars + new.chars - old.chars;
+        self.utf16_units = self.utf16_units + new.utf16_units - old.utf16_units;
+        self.graphemes = self.graphemes + new.graphemes - old.graphemes;
+        self.line_feeds = self.line_feeds + new.line_feeds - old.line_feeds;
+        self.line_breaks = self.line_breaks + new.line_breaks - old.line_breaks;
+        self.last_char = text.chars().next_back();
+        // With two clusters before the window, any context before the text leaves the context
+        // of its last cluster.
+        self.exits = [ClusterContext::after(text); CONTEXTS];
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.bytes == 0
+    }
+
+    #[inline]
+    pub fn ends_with_cr(&self) -> bool {
+        self.last_char == Some('\r')
+    }
+
+    /// Number of line breaks according to `mode`.
+    #[inline]
+    pub fn breaks(&self, mode: LineBreaks) -> usize {
+        match mode {
+            LineBreaks::LineFeed => self.line_feeds,
+            LineBreaks::Unicode => self.line_breaks,
+        }
+    }
+
+    /// Context the text leaves for the text after it, when it starts a document.
+    #[inline]
+    pub fn context(&self) -> ClusterContext {
+        self.exits[ClusterContext::Plain as usize]
+    }
+
+    /// Returns true when the text summarized by `next`, placed right after this text, starts
+    /// inside this text's last grapheme cluster.
+    pub fn continues_into(&self, next: &TextSummary) -> bool {
+        match (self.last_char, next.first_char) {
+            (Some(last), Some(first)) => !is_grapheme_boundary(last, self.context(), first),
+            _ => false,
+        }
+    }
+}
+
+#[inline]
+fn is_regional_indicator(ch: char) -> bool {
+    ('\u{1F1E6}'..='\u{1F1FF}').contains(&ch)
+}
+
+/// Checks whether `ch` is a cluster anchor: a char the grapheme rules never look back past, and
+/// after which the boundaries don't depend on the text before it. Extenders, regional
+/// indicators, pictographs and Indic consonants are not anchors.
+pub fn is_cluster_anchor(ch: char) -> bool {
+    if ch.is_ascii() {
+        return true;
+    }
+    let mut buf = [0; 4];
+    !is_regional_indicator(ch)
+        && is_grapheme_boundary('a', ClusterContext::Plain, ch)
+        && ClusterContext::after(ch.encode_utf8(&mut buf)) == ClusterContext::Plain
+}
+
+fn is_boundary_at(text: &str, idx: usize) -> bool {
+    GraphemeCursor::new(idx, text.len(), true).is_boundary(text, 0).expect("the whole text is given")
+}
+
+/// Checks whether a grapheme boundary lies between `left`, ending a text in `context`, & `right`.
+pub fn is_grapheme_boundary(left: char, context: ClusterContext, right: char) -> bool {
+    if left.is_ascii() && right.is_ascii() {
+        return !(left == '\r' && right == '\n');
+    }
+    let lead = context.lead(left);
+    let mut buf = [0; 16];
+    buf[..lead.len()].copy_from_slice(lead.as_bytes());
+    let mut len = lead.len() + left.encode_utf8(&mut buf[lead.len()..]).len();
+    let idx = len;
+    len += right.encode_utf8(&mut buf[len..]).len();
+    is_boundary_at(std::str::from_utf8(&buf[..len]).expect("encoded chars are valid UTF-8"), idx)
+}
+
+impl Add for TextSummary {
+    type Output = TextSummary;
+
+    fn add(mut self, other: TextSummary) -> TextSummary {
+        self += other;
+        self
+    }
+}
+
+impl AddAssign for TextSummary {
+    fn add_assign(&mut self, other: TextSummary) {
+        if other.is_empty() {
+            return;
+        }
+        if self.is_empty() {
+            *self = other;
+            return;
+        }
+
+        self.line_breaks += other.line_breaks;
+        if self.ends_with_cr() && other.first_char == Some('\n') {
+            self.line_breaks -= 1;
+        }
+        let (left, right) = (self.last_char.expect("not empty"), other.first_char.expect("not empty"));
+        let context = self.context();
+        let boundary = is_grapheme_boundary(left, context, right);
+        let delta = |context: ClusterContext| other.deltas[context as usize];
+        self.graphemes = (self.graphemes + other.graphemes + usize::from(boundary))
+            .checked_add_signed(isize::from(delta(context)) - 1)
+            .expect("a text holds at least one cluster");
+        for index in 1..CONTEXTS {
+            let entry = self.exits[index];
+            let moved = entry != context && is_grapheme_boundary(left, entry, right) != boundary;
+            let shift = if moved { if boundary { -1 } else { 1 } } else { 0 };
+            self.deltas[index] += shift + delta(entry) - delta(context);
+            self.exits[index] = other.exits[entry as usize];
+        }
+        self.exits[0] = other.exits[context as usize];
+        self.bytes += other.bytes;
+        self.chars += other.chars;
+        self.utf16_units += other.utf16_units;
+        self.line_feeds += other.line_feeds;
+        self.last_char = other.last_char;
+    }
+}
+
+impl<'a> std::iter::Sum<&'a TextSummary> for TextSummary {
+    fn sum<I: Iterator<Item = &'a TextSummary>>(iter: I) -> Self {
+        iter.fold(TextSummary::ZERO, |acc, summary| acc + *summary)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    #[test]
+    fn test_summary_of_text() {
+        let summary = TextSummary::of("hé\n🌍\n");
+        assert_eq!(summary.bytes, 9);
+        assert_eq!(summary.chars, 5);
+        assert_eq!(summary.utf16_units, 6);
+        assert_eq!(summary.graphemes, 5);
+        assert_eq!(summary.line_feeds, 2);
+        assert_eq!(summary.line_breaks, 2);
+    }
+
+    #[test]
+    fn test_summary_unicode_breaks() {
+        let summary = TextSummary::of("a\r\nb\rc\u{85}d\u{2028}e\u{2029}f\n");
+        assert_eq!(summary.breaks(LineBreaks::LineFeed), 2);
+        assert_eq!(summary.breaks(LineBreaks::Unicode), 6);
+    }
+
+    #[test]
+    fn test_summary_crlf_across_seam() {
+        let joined = TextSummary::of("ab\r") + TextSummary::of("\ncd\r");
+        assert_eq!(joined, TextSummary::of("ab\r\ncd\r"));
+        assert_eq!(joined.line_breaks, 2);
+        assert_eq!(TextSummary::of("\r") + TextSummary::ZERO + TextSummary::of("\n"), TextSummary::of("\r\n"));
+    }
+
+    #[test]
+    fn test_summary_graphemes_across_seam() {
+        let joined = TextSummary::of("cafe") + TextSummary::of("\u{301} 👍🏽");
+        assert_eq!(joined, TextSummary::of("cafe\u{301} 👍🏽"));
+        assert_eq!(joined.graphemes, 6);
+        assert!(!is_grapheme_boundary('e', ClusterContext::Plain, '\u{301}'));
+        assert!(is_grapheme_boundary('e', ClusterContext::Plain, 'f'));
+    }
+
+    #[test]
+    fn test_summary_context_across_seams() {
+        let flags = "\u{1F1EB}\u{1F1F7}\u{1F1E9}\u{1F1EA}\u{1F1EE}";
+        let family = "\u{1F468}\u{200D}\u{1F469}\u{200D}\u{1F467}";
+        let conjunct = "\u{915}\u{94D}\u{937}";
+        for text in [flags, family, conjunct] {
+            for (split, _) in text.char_indices().skip(1) {
+                let joined = TextSummary::of(&text[..split]) + TextSummary::of(&text[split..]);
+                assert_eq!(joined, TextSummary::of(text), "{text:?} split at {split}");
+                assert_eq!(joined.graphemes, text.graphemes(true).count());
+            }
+        }
+        assert_eq!(TextSummary::of(flags).context(), ClusterContext::OddIndicator);
+        assert_eq!(TextSummary::of("\u{1F468}\u{200D}").context(), ClusterContext::PictographJoiner);
+        assert_eq!(TextSummary::of("\u{915}\u{94D}").context(), ClusterContext::Conjunct);
+    }
+
+    #[test]
+    fn test_summary_arithmetic() {
+        let a = TextSummary::of("abc\n");
+        let b = TextSummary::of("🌍");
+        let total = a + b;
+        assert_eq!(total, TextSummary::of("abc\n🌍"));
+        assert_eq!([a, b].iter().sum::<TextSummary>(), total);
+    }
+
+    proptest! {
+        #[test]
+        fn prop_clusters_split_at_every_offset(
+            text in "(\u{1F1EB}|\u{1F1F7}|\u{1F468}|\u{1F469}|\u{200D}|\u{FE0F}|\u{1F3FD}|\u{915}|\u{94D}|\u{937}|\u{301}|e|\r|\n){0,24}",
+        ) {
+            let whole = TextSummary::of(&text);
+            prop_assert_eq!(whole.graphemes, text.graphemes(true).count());
+            let splits: Vec<usize> = text.char_indices().map(|(idx, _)| idx).chain([text.len()]).collect();
+            for &a in &splits {
+                prop_assert_eq!(TextSummary::of(&text[..a]) + TextSummary::of(&text[a..]), whole);
+                for &b in splits.iter().filter(|&&b| b > a) {
+                    let (left, middle, right) = (TextSummary::of(&text[..a]), TextSummary::of(&text[a..b]), TextSummary::of(&text[b..]));
+                    prop_assert_eq!(left + (middle + right), whole);
+                    prop_assert_eq!((left + middle) + right, whole);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,119 @@
+/// backend.rs
+/// Defines the `StorageBackend` trait implemented by the places pages can be spilled to.
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+/// Identifies a page of a `PageStore`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct PageId(pub u64);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StorageError {
+    Io(String),
+    UnknownPage(PageId),
+    Corrupted(String),
+    InvalidUtf8(usize),
+    OutOfBounds { index: usize, len: usize },
+}
+
+impl fmt::Display for StorageError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StorageError::Io(e) => write!(f, "I/O error: {}", e),
+            StorageError::UnknownPage(id) => write!(f, "Unknown page {}", id.0),
+            StorageError::Corrupted(e) => write!(f, "Corrupted page: {}", e),
+            StorageError::InvalidUtf8(offset) => write!(f, "Invalid UTF-8 at byte {}", offset),
+            StorageError::OutOfBounds { index, len } => write!(f, "Index {} out of bounds for storage of length {}", index, len),
+        }
+    }
+}
+
+impl Error for StorageError {}
+
+impl From<io::Error> for StorageError {
+    fn from(error: io::Error) -> Self {
+        StorageError::Io(error.to_string())
+    }
+}
+
+/// Somewhere pages evicted from memory are written to, and read back from.
+pub trait StorageBackend: fmt::Debug + Send {
+    fn write(&mut self, id: PageId, bytes: &[u8]) -> Result<(), StorageError>;
+
+    fn read(&mut self, id: PageId) -> Result<Vec<u8>, StorageError>;
+
+    fn remove(&mut self, id: PageId) -> Result<(), StorageError>;
+
+    fn contains(&self, id: PageId) -> bool;
+
+    /// Number of pages held.
+    fn len(&self) -> usize;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Bytes taken by the pages held.
+    fn bytes_used(&self) -> usize;
+}
+
+/// Keeps pages in memory. Useful in tests and when no disk may be touched.
+#[derive(Debug, Default)]
+pub struct MemoryBackend {
+    pages: HashMap<PageId, Vec<u8>>,
+}
+
+impl MemoryBackend {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl StorageBackend for MemoryBackend {
+    fn write(&mut self, id: PageId, bytes: &[u8]) -> Result<(), StorageError> {
+        self.pages.insert(id, bytes.to_vec());
+        Ok(())
+    }
+
+    fn read(&mut self, id: PageId) -> Result<Vec<u8>, StorageError> {
+        self.pages.get(&id).cloned().ok_or(StorageError::UnknownPage(id))
+    }
+
+    fn remove(&mut self, id: PageId) -> Result<(), StorageError> {
+        self.pages.remove(&id).map(|_| ()).ok_or(StorageError::UnknownPage(id))
+    }
+
+    fn contains(&self, id: PageId) -> bool {
+        self.pages.contains_key(&id)
+    }
+
+    fn len(&self) -> usize {
+        self.pages.len()
+    }
+
+    fn bytes_used(&self) -> usize {
+        self.pages.values().map(Vec::len).sum()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_memory_backend() {
+        let mut backend = MemoryBackend::new();
+        backend.write(PageId(1), b"hello").unwrap();
+        backend.write(PageId(2), b"world!").unwrap();
+        assert_eq!(backend.len(), 2);
+        assert_eq!(backend.bytes_used(), 11);
+        assert_eq!(backend.read(PageId(1)).unwrap(), b"hello");
+
+        backend.remove(PageId(1)).unwrap();
+        assert!(!backend.contains(PageId(1)));
+        assert_eq!(backend.read(PageId(1)), Err(StorageError::UnknownPage(PageId(1))));
+        assert_eq!(backend.remove(PageId(1)), Err(StorageError::UnknownPage(PageId(1))));
+    }
+}
@@ -0,0 +1,181 @@
+/// cache.rs
+/// Defines `LruCache`, a least-recently-used cache bounded by the total weight of its entries.
+use std::collections::{BTreeMap, HashMap};
+use std::hash::Hash;
+
+#[derive(Debug)]
+struct Entry<V> {
+    value: V,
+    weight: usize,
+    tick: u64,
+}
+
+/// A cache evicting its least recently used entries once their total weight exceeds the
+/// capacity. Lookups & updates are O(log n).
+///
+/// Evicted entries are handed back to the caller, which decides where they go next.
+#[derive(Debug)]
+pub struct LruCache<K, V> {
+    entries: HashMap<K, Entry<V>>,
+    /// Keys ordered from the least to the most recently used.
+    order: BTreeMap<u64, K>,
+    tick: u64,
+    capacity: usize,
+    weight: usize,
+    hits: usize,
+    misses: usize,
+}
+
+impl<K: Hash + Eq + Clone, V> LruCache<K, V> {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            entries: HashMap::new(),
+            order: BTreeMap::new(),
+            tick: 0,
+            capacity,
+            weight: 0,
+            hits: 0,
+            misses: 0,
+        }
+    }
+
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    #[inline]
+    pub fn weight(&self) -> usize {
+        self.weight
+    }
+
+    #[inline]
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    #[inline]
+    pub fn hits(&self) -> usize {
+        self.hits
+    }
+
+    #[inline]
+    pub fn misses(&self) -> usize {
+        self.misses
+    }
+
+    pub fn contains(&self, key: &K) -> bool {
+        self.entries.contains_key(key)
+    }
+
+    /// Returns the value of `key` and marks it as the most recently used.
+    pub fn get(&mut self, key: &K) -> Option<&V> {
+        let Some(entry) = self.entries.get_mut(key) else {
+            self.misses += 1;
+            return None;
+        };
+        self.hits += 1;
+        self.tick += 1;
+        let key = self.order.remove(&entry.tick).expect("cached keys are ordered");
+        entry.tick = self.tick;
+        self.order.insert(self.tick, key);
+        Some(&entry.value)
+    }
+
+    /// Returns the value of `key` without touching the eviction order or the counters.
+    pub fn peek(&self, key: &K) -> Option<&V> {
+        self.entries.get(key).map(|entry| &entry.value)
+    }
+
+    /// Inserts `value` as the most recently used entry and evicts the least recently used
+    /// ones until the cache fits its capacity. The new entry itself is never evicted.
+    pub fn insert(&mut self, key: K, value: V, weight: usize) -> Vec<(K, V)> {
+        self.remove(&key);
+        self.tick += 1;
+        self.order.insert(self.tick, key.clone());
+        self.entries.insert(key, Entry { value, weight, tick: self.tick });
+        self.weight += weight;
+        self.evict(1)
+    }
+
+    pub fn remove(&mut self, key: &K) -> Option<V> {
+        let entry = self.entries.remove(key)?;
+        self.order.remove(&entry.tick);
+        self.weight -= entry.weight;
+        Some(entry.value)
+    }
+
+    /// Removes & returns the least recently used entry.
+    pub fn pop_lru(&mut self) -> Option<(K, V)> {
+        let (_, key) = self.order.pop_first()?;
+        let entry = self.entries.remove(&key).expect("ordered keys are cached");
+        self.weight -= entry.weight;
+        Some((key, entry.value))
+    }
+
+    /// Changes the capacity, returning the entries evicted to fit it.
+    pub fn set_capacity(&mut self, capacity: usize) -> Vec<(K, V)> {
+        self.capacity = capacity;
+        self.evict(0)
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.order.clear();
+        self.weight = 0;
+    }
+
+    /// Evicts entries while over capacity, keeping at least `keep` of them.
+    fn evict(&mut self, keep: usize) -> Vec<(K, V)> {
+        let mut evicted = Vec::new();
+        while self.weight > self.capacity && self.len() > keep {
+            evicted.extend(self.pop_lru());
+        }
+        evicted
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_evicts_least_recently_used() {
+        let mut cache = LruCache::new(10);
+        assert!(cache.insert("a", 1, 4).is_empty());
+        assert!(cache.insert("b", 2, 4).is_empty());
+        assert_eq!(cache.get(&"a"), Some(&1));
+
+        assert_eq!(cache.insert("c", 3, 4), vec![("b", 2)]);
+        assert_eq!(cache.weight(), 8);
+        assert_eq!(cache.get(&"b"), None);
+        assert_eq!((cache.hits(), cache.misses()), (1, 1));
+        assert_eq!(cache.pop_lru(), Some(("a", 1)));
+    }
+
+    #[test]
+    fn test_oversized_entry_is_kept() {
+        let mut cache = LruCache::new(10);
+        cache.insert(1, "small", 2);
+        assert_eq!(cache.insert(2, "huge", 50), vec![(1, "small")]);
+        assert_eq!(cache.peek(&2), Some(&"huge"));
+        assert_eq!(cache.set_capacity(0), vec![(2, "huge")]);
+        assert!(cache.is_empty());
+    }
+
+    #[test]
+    fn test_reinsert_replaces_weight() {
+        let mut cache = LruCache::new(10);
+        cache.insert('x', 1, 6);
+        cache.insert('x', 2, 3);
+        assert_eq!(cache.len(), 1);
+        assert_eq!(cache.weight(), 3);
+        assert_eq!(cache.remove(&'x'), Some(2));
+        assert_eq!(cache.weight(), 0);
+    }
+}
@@ -0,0 +1,61 @@
+/// compression.rs
+/// Compresses cold pages with DEFLATE before they are kept in memory or spilled to disk.
+use miniz_oxide::deflate::compress_to_vec;
+use miniz_oxide::inflate::decompress_to_vec;
+use crate::core::buffer::storage::backend::StorageError;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Compression {
+    /// Pages are stored as is.
+    None,
+    /// Favors speed, which suits pages that may be read back soon.
+    #[default]
+    Fast,
+    /// Favors ratio, at several times the cost of `Fast`.
+    Best,
+}
+
+impl Compression {
+    fn level(&self) -> u8 {
+        match self {
+            Compression::None => 0,
+            Compression::Fast => 1,
+            Compression::Best => 9,
+        }
+    }
+
+    pub fn compress(&self, bytes: &[u8]) -> Vec<u8> {
+        match self {
+            Compression::None => bytes.to_vec(),
+            _ => compress_to_vec(bytes, self.level()),
+        }
+    }
+
+    pub fn decompress(&self, bytes: &[u8]) -> Result<Vec<u8>, StorageError> {
+        match self {
+            Compression::None => Ok(bytes.to_vec()),
+            _ => decompress_to_vec(bytes).map_err(|e| StorageError::Corrupted(e.to_string())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let text = "2024-01-01,INFO,request served in 12ms\n".repeat(200);
+        for compression in [Compression::None, Compression::Fast, Compression::Best] {
+            let compressed = compression.compress(text.as_bytes());
+            assert_eq!(compression.decompress(&compressed).unwrap(), text.as_bytes());
+        }
+        assert!(Compression::Fast.compress(text.as_bytes()).len() < text.len() / 10);
+        assert_eq!(Compression::None.compress(b"abc"), b"abc");
+    }
+
+    #[test]
+    fn test_corrupted_input() {
+        assert!(matches!(Compression::Fast.decompress(&[0xFF; 16]), Err(StorageError::Corrupted(_))));
+    }
+}
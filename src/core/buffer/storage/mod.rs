@@ -0,0 +1,14 @@
+//! Storage module
+//! Reexports backend, cache, compression, paging, and swap modules
+
+pub mod backend;
+pub mod cache;
+pub mod compression;
+pub mod paging;
+pub mod swap;
+
+pub use backend::{MemoryBackend, PageId, StorageBackend, StorageError};
+pub use cache::LruCache;
+pub use compression::Compression;
+pub use paging::{lock_store, Page, PageStore, PagedText, SharedPageStore, StorageConfig, StorageStats};
+pub use swap::SwapFile;
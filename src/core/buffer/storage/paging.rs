@@ -0,0 +1,562 @@
+/// paging.rs
+/// Defines `PageStore`, which keeps text pages within a memory budget, along with `Page` and
+/// `PagedText`, through which buffers sharing a store keep their text in it.
+/// A page is hot (decoded, in the LRU page cache), cold (compressed in memory) or swapped
+/// (compressed in the swap backend). Pages evicted from the cache are compressed, and the
+/// least recently used cold pages are spilled to swap once the memory budget is exceeded.
+use std::collections::HashMap;
+use std::io::Read;
+use std::ops::Range;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use crate::core::buffer::storage::backend::{PageId, StorageBackend, StorageError};
+use crate::core::buffer::storage::cache::LruCache;
+use crate::core::buffer::storage::compression::Compression;
+use crate::core::buffer::storage::swap::SwapFile;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StorageConfig {
+    /// Target size of the pages buffers split their text into.
+    pub page_size: usize,
+    /// Bytes of hot & cold pages kept in memory before cold pages are spilled to swap.
+    pub memory_budget: usize,
+    /// Share of `memory_budget`, in bytes, kept for hot pages.
+    pub cache_budget: usize,
+    pub compression: Compression,
+    /// Directory of the swap file, created on the first spill.
+    pub swap_dir: PathBuf,
+}
+
+impl Default for StorageConfig {
+    fn default() -> Self {
+        Self {
+            page_size: 64 * 1024,
+            memory_budget: 64 * 1024 * 1024,
+            cache_budget: 16 * 1024 * 1024,
+            compression: Compression::default(),
+            swap_dir: std::env::temp_dir(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct StorageStats {
+    pub hot_pages: usize,
+    pub hot_bytes: usize,
+    pub cold_pages: usize,
+    pub cold_bytes: usize,
+    pub swapped_pages: usize,
+    pub swapped_bytes: usize,
+    pub cache_hits: usize,
+    pub cache_misses: usize,
+}
+
+impl StorageStats {
+    /// Bytes of pages held in memory.
+    #[inline]
+    pub fn resident_bytes(&self) -> usize {
+        self.hot_bytes + self.cold_bytes
+    }
+}
+
+/// A `PageStore` shared by the buffers keeping their text in it.
+pub type SharedPageStore = Arc<Mutex<PageStore>>;
+
+/// Locks a shared store. A panic while the store was locked leaves its pages consistent, so a
+/// poisoned lock is taken over.
+pub fn lock_store(store: &SharedPageStore) -> MutexGuard<'_, PageStore> {
+    store.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Stores text pages under a memory budget.
+#[derive(Debug)]
+pub struct PageStore {
+    config: StorageConfig,
+    next_id: u64,
+    /// Byte length of every live page.
+    pages: HashMap<PageId, usize>,
+    hot: LruCache<PageId, Arc<str>>,
+    cold: LruCache<PageId, Vec<u8>>,
+    swap: Option<Box<dyn StorageBackend>>,
+}
+
+impl PageStore {
+    pub fn new(config: StorageConfig) -> Self {
+        let cache_budget = config.cache_budget.min(config.memory_budget);
+        let cold_budget = config.memory_budget - cache_budget;
+        Self {
+            config,
+            next_id: 0,
+            pages: HashMap::new(),
+            hot: LruCache::new(cache_budget),
+            cold: LruCache::new(cold_budget),
+            swap: None,
+        }
+    }
+
+    /// Spills pages to `backend` instead of a swap file.
+    pub fn with_backend(config: StorageConfig, backend: Box<dyn StorageBackend>) -> Self {
+        let mut store = Self::new(config);
+        store.swap = Some(backend);
+        store
+    }
+
+    /// Wraps the store so that several buffers can keep their text in it.
+    pub fn into_shared(self) -> SharedPageStore {
+        Arc::new(Mutex::new(self))
+    }
+
+    pub fn config(&self) -> &StorageConfig {
+        &self.config
+    }
+
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.pages.len()
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.pages.is_empty()
+    }
+
+    /// Byte length of the page, without loading it.
+    pub fn page_len(&self, id: PageId) -> Option<usize> {
+        self.pages.get(&id).copied()
+    }
+
+    /// Stores `text` as a new hot page.
+    pub fn allocate(&mut self, text: &str) -> Result<PageId, StorageError> {
+        let id = PageId(self.next_id);
+        self.next_id += 1;
+        self.pages.insert(id, text.len());
+        self.make_hot(id, Arc::from(text))?;
+        Ok(id)
+    }
+
+    /// Returns the text of the page, loading it into the cache if it is cold or swapped.
+    pub fn read(&mut self, id: PageId) -> Result<Arc<str>, StorageError> {
+        if let Some(text) = self.hot.get(&id) {
+            return Ok(Arc::clone(text));
+        }
+        let compressed = match self.cold.remove(&id) {
+            Some(compressed) => compressed,
+            None => self.unswap(id)?,
+        };
+        let bytes = self.config.compression.decompress(&compressed)?;
+        let text: Arc<str> = String::from_utf8(bytes)
+            .map_err(|e| StorageError::Corrupted(e.to_string()))?
+            .into();
+        self.make_hot(id, Arc::clone(&text))?;
+        Ok(text)
+    }
+
+    /// Replaces the text of the page.
+    pub fn write(&mut self, id: PageId, text: &str) -> Result<(), StorageError> {
+        self.free(id)?;
+        self.pages.insert(id, text.len());
+        self.make_hot(id, Arc::from(text))
+    }
+
+    pub fn free(&mut self, id: PageId) -> Result<(), StorageError> {
+        if self.pages.remove(&id).is_none() {
+            return Err(StorageError::UnknownPage(id));
+        }
+        if self.hot.remove(&id).is_none()
+            && self.cold.remove(&id).is_none()
+            && let Some(swap) = self.swap.as_mut()
+        {
+            swap.remove(id)?;
+        }
+        Ok(())
+    }
+
+    /// Changes the memory budget, spilling pages right away when it shrinks.
+    pub fn set_memory_budget(&mut self, memory_budget: usize, cache_budget: usize) -> Result<(), StorageError> {
+        self.config.memory_budget = memory_budget;
+        self.config.cache_budget = cache_budget.min(memory_budget);
+        let cold_budget = memory_budget - self.config.cache_budget;
+        let evicted = self.hot.set_capacity(self.config.cache_budget);
+        self.cool(evicted)?;
+        let spilled = self.cold.set_capacity(cold_budget);
+        self.spill(spilled)
+    }
+
+    pub fn stats(&self) -> StorageStats {
+        StorageStats {
+            hot_pages: self.hot.len(),
+            hot_bytes: self.hot.weight(),
+            cold_pages: self.cold.len(),
+            cold_bytes: self.cold.weight(),
+            swapped_pages: self.swap.as_ref().map_or(0, |swap| swap.len()),
+            swapped_bytes: self.swap.as_ref().map_or(0, |swap| swap.bytes_used()),
+            cache_hits: self.hot.hits(),
+            cache_misses: self.hot.misses(),
+        }
+    }
+
+    fn make_hot(&mut self, id: PageId, text: Arc<str>) -> Result<(), StorageError> {
+        let len = text.len();
+        let evicted = self.hot.insert(id, text, len);
+        self.cool(evicted)
+    }
+
+    /// Compresses pages evicted from the cache into the cold tier.
+    fn cool(&mut self, pages: Vec<(PageId, Arc<str>)>) -> Result<(), StorageError> {
+        for (id, text) in pages {
+            let compressed = self.config.compression.compress(text.as_bytes());
+            let len = compressed.len();
+            let spilled = self.cold.insert(id, compressed, len);
+            self.spill(spilled)?;
+        }
+        Ok(())
+    }
+
+    fn spill(&mut self, pages: Vec<(PageId, Vec<u8>)>) -> Result<(), StorageError> {
+        if pages.is_empty() {
+            return Ok(());
+        }
+        if self.swap.is_none() {
+            self.swap = Some(Box::new(SwapFile::create(&self.config.swap_dir)?));
+        }
+        let swap = self.swap.as_mut().expect("swap created above");
+        for (id, compressed) in pages {
+            swap.write(id, &compressed)?;
+        }
+        Ok(())
+    }
+
+    fn unswap(&mut self, id: PageId) -> Result<Vec<u8>, StorageError> {
+        match self.swap.as_mut() {
+            Some(swap) if swap.contains(id) => {
+                let compressed = swap.read(id)?;
+                swap.remove(id)?;
+                Ok(compressed)
+            }
+            _ => Err(StorageError::UnknownPage(id)),
+        }
+    }
+}
+
+/// A page of a shared `PageStore` along with the number of line feeds in its text. The page is
+/// freed when the handle is dropped.
+#[derive(Debug)]
+pub struct Page {
+    store: SharedPageStore,
+    id: PageId,
+    len: usize,
+    line_feeds: usize,
+}
+
+impl Page {
+    pub fn allocate(store: &SharedPageStore, text: &str) -> Result<Self, StorageError> {
+        let id = lock_store(store).allocate(text)?;
+        let line_feeds = text.bytes().filter(|&byte| byte == b'\n').count();
+        Ok(Self { store: Arc::clone(store), id, len: text.len(), line_feeds })
+    }
+
+    #[inline]
+    pub fn id(&self) -> PageId {
+        self.id
+    }
+
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    #[inline]
+    pub fn line_feeds(&self) -> usize {
+        self.line_feeds
+    }
+
+    /// Returns the text of the page, loading it into the cache if needed.
+    pub fn read(&self) -> Result<Arc<str>, StorageError> {
+        lock_store(&self.store).read(self.id)
+    }
+}
+
+impl Drop for Page {
+    fn drop(&mut self) {
+        // The page can only be unknown if the store was cleared behind our back.
+        let _ = lock_store(&self.store).free(self.id);
+    }
+}
+
+/// Read-only text split into the pages of a shared `PageStore`, so that only the pages being
+/// looked at stay decoded in memory. Each page counts its line feeds, so lines are found by
+/// loading just the page holding them.
+#[derive(Debug)]
+pub struct PagedText {
+    store: SharedPageStore,
+    /// Pages in order, each with the offset at which it starts.
+    pages: Vec<(Page, usize)>,
+    len: usize,
+}
+
+impl PagedText {
+    pub fn from_text(text: &str, store: &SharedPageStore) -> Result<Self, StorageError> {
+        Self::from_reader(text.as_bytes(), store)
+    }
+
+    /// Reads UTF-8 text page by page, never holding more than one undecoded page.
+    pub fn from_reader(mut reader: impl Read, store: &SharedPageStore) -> Result<Self, StorageError> {
+        let page_size = lock_store(store).config().page_size.max(4);
+        let mut text = Self { store: Arc::clone(store), pages: Vec::new(), len: 0 };
+        let mut buf = Vec::with_capacity(page_size);
+        loop {
+            let read = reader.by_ref().take((page_size - buf.len()) as u64).read_to_end(&mut buf)?;
+            let valid = match std::str::from_utf8(&buf) {
+                Ok(valid) => valid,
+                // Keep a char cut at the end of the page for the next page.
+                Err(e) if e.error_len().is_none() && read > 0 => std::str::from_utf8(&buf[..e.valid_up_to()]).expect("valid prefix"),
+                Err(e) => return Err(StorageError::InvalidUtf8(text.len + e.valid_up_to())),
+            };
+            if !valid.is_empty() {
+                let page = Page::allocate(store, valid)?;
+                text.pages.push((page, text.len));
+                text.len += valid.len();
+            }
+            let consumed = valid.len();
+            buf.drain(..consumed);
+            if read == 0 {
+                return Ok(text);
+            }
+        }
+    }
+
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn page_count(&self) -> usize {
+        self.pages.len()
+    }
+
+    pub fn store(&self) -> &SharedPageStore {
+        &self.store
+    }
+
+    /// Byte range & line feed count of every page, in order.
+    pub fn pages(&self) -> impl Iterator<Item = (Range<usize>, usize)> + '_ {
+        self.pages.iter().map(|(page, start)| (*start..start + page.len(), page.line_feeds()))
+    }
+
+    /// Whether a page starts at `offset`.
+    pub fn is_page_start(&self, offset: usize) -> bool {
+        self.pages.binary_search_by_key(&offset, |&(_, start)| start).is_ok()
+    }
+
+    /// Total number of line feeds, without loading any page.
+    pub fn line_feeds(&self) -> usize {
+        self.pages.iter().map(|(page, _)| page.line_feeds()).sum()
+    }
+
+    /// Copies `range` out of the pages it spans, loading them as needed.
+    pub fn slice(&self, range: Range<usize>) -> Result<String, StorageError> {
+        self.check_range(&range)?;
+        let mut result = String::with_capacity(range.len());
+        self.for_each_part(range, |page, part| {
+            let part = page.get(part).ok_or(StorageError::OutOfBounds { index: result.len(), len: page.len() })?;
+            result.push_str(part);
+            Ok(())
+        })?;
+        Ok(result)
+    }
+
+    pub fn text(&self) -> Result<String, StorageError> {
+        self.slice(0..self.len)
+    }
+
+    /// Whether `offset` falls between two chars, loading the page holding it unless a page starts
+    /// there.
+    pub fn is_char_boundary(&self, offset: usize) -> Result<bool, StorageError> {
+        if offset >= self.len {
+            return Ok(offset == self.len);
+        }
+        let (page, start) = &self.pages[self.page_index(offset)];
+        if offset == *start {
+            return Ok(true);
+        }
+        Ok(page.read()?.is_char_boundary(offset - start))
+    }
+
+    /// Counts the line feeds within `range`, loading only the pages it covers in part.
+    pub fn line_feeds_in(&self, range: Range<usize>) -> Result<usize, StorageError> {
+        self.check_range(&range)?;
+        let mut line_feeds = 0;
+        let first = self.page_index(range.start);
+        for (page, start) in self.pages[first..].iter().take_while(|(_, start)| *start < range.end) {
+            let part = range.start.max(*start) - start..range.end.min(start + page.len()) - start;
+            line_feeds += match part.len() == page.len() {
+                true => page.line_feeds(),
+                false => page.read()?.as_bytes()[part].iter().filter(|&&byte| byte == b'\n').count(),
+            };
+        }
+        Ok(line_feeds)
+    }
+
+    /// Offset of the `n`th line feed at or after `start`, loading only the page holding it and
+    /// the one `start` is in.
+    pub fn nth_line_feed_from(&self, start: usize, mut n: usize) -> Result<Option<usize>, StorageError> {
+        if start >= self.len {
+            return Ok(None);
+        }
+        let first = self.page_index(start);
+        for (idx, (page, page_start)) in self.pages.iter().enumerate().skip(first) {
+            let local = start.saturating_sub(*page_start);
+            if idx > first && n >= page.line_feeds() {
+                n -= page.line_feeds();
+                continue;
+            }
+            let text = page.read()?;
+            let mut line_feeds = text.as_bytes()[local..].iter().enumerate().filter(|&(_, &byte)| byte == b'\n');
+            match line_feeds.nth(n) {
+                Some((idx, _)) => return Ok(Some(page_start + local + idx)),
+                None => n -= text.as_bytes()[local..].iter().filter(|&&byte| byte == b'\n').count(),
+            }
+        }
+        Ok(None)
+    }
+
+    /// Index of the page holding `offset`.
+    fn page_index(&self, offset: usize) -> usize {
+        self.pages.partition_point(|&(_, start)| start <= offset).saturating_sub(1)
+    }
+
+    fn check_range(&self, range: &Range<usize>) -> Result<(), StorageError> {
+        if range.start > range.end || range.end > self.len {
+            return Err(StorageError::OutOfBounds { index: range.end.max(range.start), len: self.len });
+        }
+        Ok(())
+    }
+
+    /// Calls `f` with each page `range` overlaps and the part of the page it covers.
+    fn for_each_part<F>(&self, range: Range<usize>, mut f: F) -> Result<(), StorageError>
+    where
+        F: FnMut(&str, Range<usize>) -> Result<(), StorageError>,
+    {
+        if range.is_empty() {
+            return Ok(());
+        }
+        let first = self.page_index(range.start);
+        for (page, start) in self.pages[first..].iter().take_while(|(_, start)| *start < range.end) {
+            let text = page.read()?;
+            f(&text, range.start.saturating_sub(*start)..(range.end - start).min(text.len()))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::buffer::storage::backend::MemoryBackend;
+
+    fn small_config() -> StorageConfig {
+        StorageConfig {
+            page_size: 1024,
+            memory_budget: 4096,
+            cache_budget: 2048,
+            ..StorageConfig::default()
+        }
+    }
+
+    fn log_page(idx: usize) -> String {
+        format!("{idx:04},GET /api/items/{idx},200\n").repeat(32)
+    }
+
+    #[test]
+    fn test_pages_move_between_tiers() {
+        let mut store = PageStore::with_backend(small_config(), Box::new(MemoryBackend::new()));
+        let ids: Vec<PageId> = (0..40).map(|idx| store.allocate(&log_page(idx)).unwrap()).collect();
+        let stats = store.stats();
+        assert!(stats.hot_bytes <= 2048);
+        assert!(stats.resident_bytes() <= 4096);
+        assert!(stats.cold_pages > 0);
+        assert!(stats.swapped_pages > 0);
+        assert_eq!(stats.hot_pages + stats.cold_pages + stats.swapped_pages, 40);
+
+        for (idx, &id) in ids.iter().enumerate().rev() {
+            assert_eq!(&*store.read(id).unwrap(), log_page(idx));
+        }
+        assert!(store.stats().resident_bytes() <= 4096);
+        assert_eq!(store.len(), 40);
+    }
+
+    #[test]
+    fn test_write_and_free() {
+        let mut store = PageStore::with_backend(small_config(), Box::new(MemoryBackend::new()));
+        let first = store.allocate("first").unwrap();
+        let ids: Vec<PageId> = (0..20).map(|idx| store.allocate(&log_page(idx)).unwrap()).collect();
+        store.write(first, "rewritten").unwrap();
+        assert_eq!(&*store.read(first).unwrap(), "rewritten");
+        assert_eq!(store.page_len(first), Some(9));
+
+        for id in ids {
+            store.free(id).unwrap();
+        }
+        store.free(first).unwrap();
+        assert_eq!(store.stats(), StorageStats { cache_hits: 1, ..StorageStats::default() });
+        assert_eq!(store.read(first), Err(StorageError::UnknownPage(first)));
+    }
+
+    #[test]
+    fn test_spills_to_swap_file() {
+        let config = StorageConfig { memory_budget: 512, cache_budget: 256, ..small_config() };
+        let mut store = PageStore::new(config);
+        let ids: Vec<PageId> = (0..10).map(|idx| store.allocate(&log_page(idx)).unwrap()).collect();
+        assert!(store.stats().swapped_pages > 0);
+        assert_eq!(&*store.read(ids[0]).unwrap(), log_page(0));
+    }
+
+    #[test]
+    fn test_shrinking_budget_spills() {
+        let mut store = PageStore::with_backend(StorageConfig::default(), Box::new(MemoryBackend::new()));
+        let ids: Vec<PageId> = (0..10).map(|idx| store.allocate(&log_page(idx)).unwrap()).collect();
+        assert_eq!(store.stats().hot_pages, 10);
+        store.set_memory_budget(0, 0).unwrap();
+        assert_eq!(store.stats().swapped_pages, 10);
+        assert_eq!(&*store.read(ids[3]).unwrap(), log_page(3));
+    }
+
+    #[test]
+    fn test_paged_text() {
+        let content: String = (0..200).map(|idx| format!("{idx},naïve,日本\n")).collect();
+        let store = PageStore::with_backend(small_config(), Box::new(MemoryBackend::new())).into_shared();
+        let text = PagedText::from_reader(content.as_bytes(), &store).unwrap();
+        assert_eq!(text.len(), content.len());
+        assert!(text.page_count() > 1);
+        assert_eq!(text.text().unwrap(), content);
+        let start = (1000..).find(|&idx| content.is_char_boundary(idx)).unwrap();
+        let end = (start + 1500..).find(|&idx| content.is_char_boundary(idx)).unwrap();
+        assert_eq!(text.slice(start..end).unwrap(), &content[start..end]);
+        assert_eq!(text.slice(0..0).unwrap(), "");
+        assert!(text.slice(0..content.len() + 1).is_err());
+        assert!(lock_store(text.store()).stats().resident_bytes() <= 4096);
+
+        assert_eq!(text.line_feeds(), 200);
+        assert_eq!(text.line_feeds_in(start..end).unwrap(), content[start..end].matches('\n').count());
+        let (idx, _) = content.match_indices('\n').nth(150).unwrap();
+        assert_eq!(text.nth_line_feed_from(start, 150 - content[..start].matches('\n').count()).unwrap(), Some(idx));
+        assert_eq!(text.nth_line_feed_from(0, 200).unwrap(), None);
+        assert!(text.is_char_boundary(start).unwrap());
+        assert!(!text.is_char_boundary(content.find('ï').unwrap() + 1).unwrap());
+
+        assert_eq!(PagedText::from_reader(&b"ok\xFFno"[..], &store).unwrap_err(), StorageError::InvalidUtf8(2));
+        assert!(PagedText::from_text("", &store).unwrap().is_empty());
+        drop(text);
+        assert_eq!(lock_store(&store).len(), 0);
+    }
+}
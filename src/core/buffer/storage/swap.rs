@@ -0,0 +1,149 @@
+/// swap.rs
+/// Defines `SwapFile`, a temporary file that pages are spilled to once memory runs out.
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use crate::core::buffer::storage::backend::{PageId, StorageBackend, StorageError};
+
+static SWAP_FILES: AtomicUsize = AtomicUsize::new(0);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Slot {
+    offset: u64,
+    capacity: usize,
+    len: usize,
+}
+
+/// A temporary file holding pages in slots. Freed slots are reused by later pages that fit
+/// them, so the file only grows when no slot is large enough. The file is deleted on drop.
+#[derive(Debug)]
+pub struct SwapFile {
+    file: File,
+    path: PathBuf,
+    slots: HashMap<PageId, Slot>,
+    free: Vec<Slot>,
+    end: u64,
+}
+
+impl SwapFile {
+    /// Creates a swap file in `dir`.
+    pub fn create(dir: impl AsRef<Path>) -> Result<Self, StorageError> {
+        let name = format!("kaudocore-{}-{}.swap", std::process::id(), SWAP_FILES.fetch_add(1, Ordering::Relaxed));
+        let path = dir.as_ref().join(name);
+        let file = OpenOptions::new().read(true).write(true).create_new(true).open(&path)?;
+        Ok(Self { file, path, slots: HashMap::new(), free: Vec::new(), end: 0 })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Size of the file, freed slots included.
+    pub fn file_size(&self) -> u64 {
+        self.end
+    }
+
+    /// Takes the smallest free slot fitting `len` bytes, or a new one at the end of the file.
+    fn allocate(&mut self, len: usize) -> Slot {
+        let best = self.free.iter().enumerate()
+            .filter(|(_, slot)| slot.capacity >= len)
+            .min_by_key(|(_, slot)| slot.capacity)
+            .map(|(idx, _)| idx);
+        if let Some(idx) = best {
+            return Slot { len, ..self.free.swap_remove(idx) };
+        }
+        let slot = Slot { offset: self.end, capacity: len, len };
+        self.end += len as u64;
+        slot
+    }
+}
+
+impl StorageBackend for SwapFile {
+    fn write(&mut self, id: PageId, bytes: &[u8]) -> Result<(), StorageError> {
+        if self.contains(id) {
+            self.remove(id)?;
+        }
+        let slot = self.allocate(bytes.len());
+        self.file.seek(SeekFrom::Start(slot.offset))?;
+        self.file.write_all(bytes)?;
+        self.slots.insert(id, slot);
+        Ok(())
+    }
+
+    fn read(&mut self, id: PageId) -> Result<Vec<u8>, StorageError> {
+        let slot = *self.slots.get(&id).ok_or(StorageError::UnknownPage(id))?;
+        let mut bytes = vec![0; slot.len];
+        self.file.seek(SeekFrom::Start(slot.offset))?;
+        self.file.read_exact(&mut bytes)?;
+        Ok(bytes)
+    }
+
+    fn remove(&mut self, id: PageId) -> Result<(), StorageError> {
+        let slot = self.slots.remove(&id).ok_or(StorageError::UnknownPage(id))?;
+        self.free.push(slot);
+        Ok(())
+    }
+
+    fn contains(&self, id: PageId) -> bool {
+        self.slots.contains_key(&id)
+    }
+
+    fn len(&self) -> usize {
+        self.slots.len()
+    }
+
+    fn bytes_used(&self) -> usize {
+        self.slots.values().map(|slot| slot.len).sum()
+    }
+}
+
+impl Drop for SwapFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_write_read_remove() {
+        let mut swap = SwapFile::create(std::env::temp_dir()).unwrap();
+        swap.write(PageId(1), b"first page").unwrap();
+        swap.write(PageId(2), b"second").unwrap();
+        assert_eq!(swap.read(PageId(1)).unwrap(), b"first page");
+        assert_eq!(swap.read(PageId(2)).unwrap(), b"second");
+        assert_eq!(swap.bytes_used(), 16);
+
+        swap.remove(PageId(1)).unwrap();
+        assert_eq!(swap.read(PageId(1)), Err(StorageError::UnknownPage(PageId(1))));
+        assert_eq!(swap.len(), 1);
+    }
+
+    #[test]
+    fn test_reuses_free_slots() {
+        let mut swap = SwapFile::create(std::env::temp_dir()).unwrap();
+        swap.write(PageId(1), &[1; 100]).unwrap();
+        swap.write(PageId(2), &[2; 10]).unwrap();
+        swap.remove(PageId(1)).unwrap();
+        swap.write(PageId(3), &[3; 60]).unwrap();
+        assert_eq!(swap.file_size(), 110);
+        assert_eq!(swap.read(PageId(3)).unwrap(), vec![3; 60]);
+
+        swap.write(PageId(2), &[4; 30]).unwrap();
+        assert_eq!(swap.file_size(), 140);
+        assert_eq!(swap.read(PageId(2)).unwrap(), vec![4; 30]);
+    }
+
+    #[test]
+    fn test_file_removed_on_drop() {
+        let swap = SwapFile::create(std::env::temp_dir()).unwrap();
+        let path = swap.path().to_path_buf();
+        assert!(path.exists());
+        drop(swap);
+        assert!(!path.exists());
+    }
+}
@@ -0,0 +1,428 @@
+/// traits.rs
+/// Defines `TextBuffer`, the interface shared by the rope & piece table backends.
+use std::borrow::Cow;
+use std::error::Error;
+use std::fmt;
+use std::ops::Range;
+use crate::core::buffer::piece_table::{PieceTable, PieceTableError};
+use crate::core::buffer::piece_table::operations::PAGE_LOAD_FAILED;
+use crate::core::buffer::rope::{Rope, RopeError};
+use crate::core::buffer::storage::backend::StorageError;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BufferError {
+    OutOfBounds { index: usize, len: usize },
+    InvalidRange(Range<usize>),
+    NotCharBoundary(usize),
+    Storage(StorageError),
+}
+
+impl fmt::Display for BufferError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BufferError::OutOfBounds { index, len } => write!(f, "Index {} out of bounds for buffer of length {}", index, len),
+            BufferError::InvalidRange(range) => write!(f, "Invalid range: {:?}", range),
+            BufferError::NotCharBoundary(index) => write!(f, "Index {} is not a char boundary", index),
+            BufferError::Storage(error) => write!(f, "Storage error: {}", error),
+        }
+    }
+}
+
+impl Error for BufferError {}
+
+impl From<RopeError> for BufferError {
+    fn from(error: RopeError) -> Self {
+        match error {
+            RopeError::OutOfBounds { index, len } => BufferError::OutOfBounds { index, len },
+            RopeError::InvalidRange(range) => BufferError::InvalidRange(range),
+            RopeError::NotCharBoundary(index) => BufferError::NotCharBoundary(index),
+        }
+    }
+}
+
+impl From<PieceTableError> for BufferError {
+    fn from(error: PieceTableError) -> Self {
+        match error {
+            PieceTableError::OutOfBounds { index, len } => BufferError::OutOfBounds { index, len },
+            PieceTableError::InvalidRange(range) => BufferError::InvalidRange(range),
+            PieceTableError::NotCharBoundary(index) => BufferError::NotCharBoundary(index),
+            PieceTableError::Storage(error) => BufferError::Storage(error),
+        }
+    }
+}
+
+/// A mutable text buffer addressed by byte offsets.
+///
+/// A returned line excludes its line ending. What ends a line is up to the backend: a
+/// `PieceTable` only breaks lines at `\n`, while a `Rope` follows its `LineBreaks` mode, which
+/// breaks at `\n` by default and also at `\r`, NEL, LS & PS in `LineBreaks::Unicode`. The trait
+/// is object safe, so the backend can be picked at runtime, e.g. from the size of the file.
+/// The position conversions have linear default implementations that backends with cached
+/// counts override.
+pub trait TextBuffer: fmt::Debug + Send + Sync {
+    fn from_text(text: &str) -> Self
+    where
+        Self: Sized;
+
+    fn len_bytes(&self) -> usize;
+
+    fn len_lines(&self) -> usize;
+
+    fn is_empty(&self) -> bool {
+        self.len_bytes() == 0
+    }
+
+    fn len_chars(&self) -> usize {
+        self.chunks().map(|chunk| chunk.chars().count()).sum()
+    }
+
+    fn is_char_boundary(&self, byte_idx: usize) -> bool;
+
+    fn insert(&mut self, byte_idx: usize, text: &str) -> Result<(), BufferError>;
+
+    fn remove(&mut self, range: Range<usize>) -> Result<(), BufferError>;
+
+    /// Replaces `range` with `text`. Nothing is changed when `range` is invalid.
+    fn replace(&mut self, range: Range<usize>, text: &str) -> Result<(), BufferError> {
+        let start = range.start;
+        self.remove(range)?;
+        self.insert(start, text)
+    }
+
+    fn slice(&self, range: Range<usize>) -> Result<String, BufferError>;
+
+    /// Iterates over the text in backend-sized pieces, borrowed unless the backend has to
+    /// assemble them. Panics if a backend fails to load a piece from its page store.
+    fn chunks(&self) -> Box<dyn Iterator<Item = Cow<'_, str>> + '_>;
+
+    /// Like `chunks`, but returns the error of a piece the backend fails to load.
+    fn try_chunks(&self) -> Box<dyn Iterator<Item = Result<Cow<'_, str>, BufferError>> + '_> {
+        Box::new(self.chunks().map(Ok))
+    }
+
+    fn text(&self) -> String {
+        let mut result = String::with_capacity(self.len_bytes());
+        self.chunks().for_each(|chunk| result.push_str(&chunk));
+        result
+    }
+
+    /// Returns the text of `line` without its line ending.
+    fn line(&self, line: usize) -> Result<String, BufferError>;
+
+    /// Byte offset at which `line` starts.
+    fn line_to_byte(&self, line: usize) -> Result<usize, BufferError>;
+
+    /// Index of the line containing `byte_idx`. The end of the text belongs to the last line.
+    fn byte_to_line(&self, byte_idx: usize) -> Result<usize, BufferError>;
+
+    /// Converts a byte offset into a char index; offsets inside a char map to that char.
+    fn byte_to_char(&self, byte_idx: usize) -> Result<usize, BufferError> {
+        let len = self.len_bytes();
+        if byte_idx > len {
+            return Err(BufferError::OutOfBounds { index: byte_idx, len });
+        }
+        let mut chars = 0;
+        let mut start = 0;
+        for chunk in self.try_chunks() {
+            let chunk = chunk?;
+            if start + chunk.len() >= byte_idx {
+                let local = byte_idx - start;
+                return Ok(chars + chunk.char_indices().take_while(|(idx, ch)| idx + ch.len_utf8() <= local).count());
+            }
+            chars += chunk.chars().count();
+            start += chunk.len();
+        }
+        Ok(chars)
+    }
+
+    fn char_to_byte(&self, char_idx: usize) -> Result<usize, BufferError> {
+        let mut chars = 0;
+        let mut start = 0;
+        for chunk in self.try_chunks() {
+            let chunk = chunk?;
+            let count = chunk.chars().count();
+            if chars + count >= char_idx {
+                let local = chunk.char_indices().nth(char_idx - chars).map_or(chunk.len(), |(idx, _)| idx);
+                return Ok(start + local);
+            }
+            chars += count;
+            start += chunk.len();
+        }
+        if char_idx == chars {
+            return Ok(start);
+        }
+        Err(BufferError::OutOfBounds { index: char_idx, len: chars })
+    }
+
+    /// Returns a copy of the buffer that later edits of `self` leave untouched. Both backends
+    /// share their text with the copy, so this is O(1) for them.
+    fn snapshot(&self) -> Self
+    where
+        Self: Sized + Clone,
+    {
+        self.clone()
+    }
+}
+
+impl TextBuffer for Rope {
+    fn from_text(text: &str) -> Self {
+        Rope::from_text(text)
+    }
+
+    fn len_bytes(&self) -> usize {
+        self.len_bytes()
+    }
+
+    fn len_lines(&self) -> usize {
+        self.len_lines()
+    }
+
+    fn len_chars(&self) -> usize {
+        self.len_chars()
+    }
+
+    fn is_char_boundary(&self, byte_idx: usize) -> bool {
+        self.is_char_boundary(byte_idx)
+    }
+
+    fn insert(&mut self, byte_idx: usize, text: &str) -> Result<(), BufferError> {
+        Ok(self.insert(byte_idx, text)?)
+    }
+
+    fn remove(&mut self, range: Range<usize>) -> Result<(), BufferError> {
+        Ok(self.remove(range)?)
+    }
+
+    fn slice(&self, range: Range<usize>) -> Result<String, BufferError> {
+        Ok(self.slice(range)?)
+    }
+
+    fn chunks(&self) -> Box<dyn Iterator<Item = Cow<'_, str>> + '_> {
+        Box::new(self.chunks().map(|chunk| Cow::Borrowed(chunk.text())))
+    }
+
+    fn line(&self, line: usize) -> Result<String, BufferError> {
+        Ok(self.line(line)?)
+    }
+
+    fn line_to_byte(&self, line: usize) -> Result<usize, BufferError> {
+        Ok(self.line_to_byte(line)?)
+    }
+
+    fn byte_to_line(&self, byte_idx: usize) -> Result<usize, BufferError> {
+        Ok(self.byte_to_line(byte_idx)?)
+    }
+
+    fn byte_to_char(&self, byte_idx: usize) -> Result<usize, BufferError> {
+        Ok(self.byte_to_char(byte_idx)?)
+    }
+
+    fn char_to_byte(&self, char_idx: usize) -> Result<usize, BufferError> {
+        Ok(self.char_to_byte(char_idx)?)
+    }
+}
+
+impl TextBuffer for PieceTable {
+    fn from_text(text: &str) -> Self {
+        PieceTable::from_text(text)
+    }
+
+    fn len_bytes(&self) -> usize {
+        self.len()
+    }
+
+    fn len_lines(&self) -> usize {
+        self.line_count()
+    }
+
+    fn is_char_boundary(&self, byte_idx: usize) -> bool {
+        self.is_char_boundary(byte_idx)
+    }
+
+    fn insert(&mut self, byte_idx: usize, text: &str) -> Result<(), BufferError> {
+        Ok(self.insert(byte_idx, text)?)
+    }
+
+    fn remove(&mut self, range: Range<usize>) -> Result<(), BufferError> {
+        Ok(self.delete(range)?)
+    }
+
+    fn slice(&self, range: Range<usize>) -> Result<String, BufferError> {
+        Ok(self.slice(range)?)
+    }
+
+    fn chunks(&self) -> Box<dyn Iterator<Item = Cow<'_, str>> + '_> {
+        Box::new(self.try_chunks().map(|chunk| chunk.expect(PAGE_LOAD_FAILED)))
+    }
+
+    fn try_chunks(&self) -> Box<dyn Iterator<Item = Result<Cow<'_, str>, BufferError>> + '_> {
+        Box::new(self.pieces().iter_pieces().map(|piece| Ok(self.piece_text(piece)?)))
+    }
+
+    fn text(&self) -> String {
+        self.text()
+    }
+
+    fn line(&self, line: usize) -> Result<String, BufferError> {
+        Ok(self.line(line)?)
+    }
+
+    fn line_to_byte(&self, line: usize) -> Result<usize, BufferError> {
+        Ok(self.line_to_offset(line)?)
+    }
+
+    fn byte_to_line(&self, byte_idx: usize) -> Result<usize, BufferError> {
+        Ok(self.offset_to_line(byte_idx)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    type Constructor = Box<dyn Fn(&str) -> Box<dyn TextBuffer>>;
+
+    /// Runs `check` against every backend.
+    fn conformance(check: fn(Constructor)) {
+        check(Box::new(|text| Box::new(<Rope as TextBuffer>::from_text(text))));
+        check(Box::new(|text| Box::new(<PieceTable as TextBuffer>::from_text(text))));
+    }
+
+    #[test]
+    fn test_edit() {
+        conformance(|new| {
+            let mut buffer = new("hello world");
+            buffer.insert(5, ",").unwrap();
+            buffer.remove(7..12).unwrap();
+            buffer.replace(0..1, "Jé").unwrap();
+            assert_eq!(buffer.text(), "Jéello, ");
+            assert_eq!(buffer.len_bytes(), 9);
+            assert_eq!(buffer.len_chars(), 8);
+            assert_eq!(buffer.insert(2, "x"), Err(BufferError::NotCharBoundary(2)));
+            let (start, end) = (3, 2);
+            assert_eq!(buffer.remove(start..end), Err(BufferError::InvalidRange(start..end)));
+            assert_eq!(buffer.replace(0..20, "x"), Err(BufferError::OutOfBounds { index: 20, len: 9 }));
+            assert_eq!(buffer.text(), "Jéello, ");
+        });
+    }
+
+    #[test]
+    fn test_slice_and_chunks() {
+        conformance(|new| {
+            let text = "abc🌍\n".repeat(500);
+            let mut buffer = new(&text);
+            buffer.insert(7, "xyz").unwrap();
+            let expected = format!("{}xyz{}", &text[..7], &text[7..]);
+            assert_eq!(buffer.chunks().collect::<String>(), expected);
+            assert_eq!(buffer.slice(3..10).unwrap(), &expected[3..10]);
+            assert_eq!(buffer.slice(4..5), Err(BufferError::NotCharBoundary(4)));
+            assert!(!buffer.is_empty());
+            assert!(new("").is_empty());
+        });
+    }
+
+    #[test]
+    fn test_lines() {
+        conformance(|new| {
+            let buffer = new("one\r\ntwo\n\nfour");
+            assert_eq!(buffer.len_lines(), 4);
+            assert_eq!(buffer.line(0).unwrap(), "one");
+            assert_eq!(buffer.line(2).unwrap(), "");
+            assert_eq!(buffer.line(3).unwrap(), "four");
+            assert_eq!(buffer.line_to_byte(1).unwrap(), 5);
+            assert_eq!(buffer.byte_to_line(9).unwrap(), 2);
+            assert_eq!(buffer.byte_to_line(14).unwrap(), 3);
+            assert_eq!(buffer.line(4), Err(BufferError::OutOfBounds { index: 4, len: 4 }));
+            assert_eq!(buffer.byte_to_line(15), Err(BufferError::OutOfBounds { index: 15, len: 14 }));
+        });
+    }
+
+    #[test]
+    fn test_position_conversions() {
+        conformance(|new| {
+            let buffer = new("aé🌍b");
+            assert_eq!(buffer.byte_to_char(3).unwrap(), 2);
+            assert_eq!(buffer.byte_to_char(7).unwrap(), 3);
+            assert_eq!(buffer.byte_to_char(8).unwrap(), 4);
+            assert_eq!(buffer.byte_to_char(2).unwrap(), 1);
+            assert_eq!(buffer.char_to_byte(3).unwrap(), 7);
+            assert_eq!(buffer.char_to_byte(4).unwrap(), 8);
+            assert!(buffer.char_to_byte(5).is_err());
+        });
+    }
+
+    #[test]
+    fn test_line_semantics_per_backend() {
+        let text = "a\rb\u{2028}c\n";
+        let mut unicode = <Rope as TextBuffer>::from_text(text);
+        unicode.set_line_breaks(crate::core::buffer::rope::LineBreaks::Unicode);
+        let backends: [(&dyn TextBuffer, usize); 3] = [
+            (&<Rope as TextBuffer>::from_text(text), 2),
+            (&<PieceTable as TextBuffer>::from_text(text), 2),
+            (&unicode, 4),
+        ];
+        for (buffer, lines) in backends {
+            assert_eq!(buffer.len_lines(), lines);
+        }
+        assert_eq!(TextBuffer::line(&unicode, 1).unwrap(), "b");
+        assert_eq!(TextBuffer::line(&<PieceTable as TextBuffer>::from_text(text), 0).unwrap(), "a\rb\u{2028}c");
+    }
+
+    fn check_snapshot<B: TextBuffer + Clone>() {
+        let mut buffer = B::from_text("base");
+        let snapshot = buffer.snapshot();
+        buffer.insert(4, " edited").unwrap();
+        assert_eq!(snapshot.text(), "base");
+        assert_eq!(buffer.text(), "base edited");
+    }
+
+    #[test]
+    fn test_snapshot() {
+        check_snapshot::<Rope>();
+        check_snapshot::<PieceTable>();
+    }
+
+    /// `insert` & `remove` edits applied to every backend and to a `String`.
+    fn edits() -> impl Strategy<Value = Vec<(bool, usize, usize, String)>> {
+        prop::collection::vec((any::<bool>(), any::<usize>(), any::<usize>(), "[ab\né🌍]{0,8}"), 0..24)
+    }
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(64))]
+
+        #[test]
+        fn prop_backends_agree(initial in "[ab\né🌍]{0,64}", edits in edits()) {
+            let mut rope = <Rope as TextBuffer>::from_text(&initial);
+            let mut table = <PieceTable as TextBuffer>::from_text(&initial);
+            let mut model = initial.clone();
+            for (is_insert, a, b, text) in edits {
+                let boundaries: Vec<usize> = (0..=model.len()).filter(|&idx| model.is_char_boundary(idx)).collect();
+                let a = boundaries[a % boundaries.len()];
+                let b = boundaries[b % boundaries.len()];
+                if is_insert {
+                    model.insert_str(a, &text);
+                    rope.insert(a, &text).unwrap();
+                    table.insert(a, &text).unwrap();
+                } else {
+                    let range = a.min(b)..a.max(b);
+                    model.replace_range(range.clone(), "");
+                    TextBuffer::remove(&mut rope, range.clone()).unwrap();
+                    TextBuffer::remove(&mut table, range).unwrap();
+                }
+            }
+            let backends: [&dyn TextBuffer; 2] = [&rope, &table];
+            for buffer in backends {
+                prop_assert_eq!(buffer.text(), model.clone());
+                prop_assert_eq!(buffer.len_lines(), model.matches('\n').count() + 1);
+                for idx in (0..=model.len()).filter(|&idx| model.is_char_boundary(idx)) {
+                    prop_assert_eq!(buffer.byte_to_line(idx).unwrap(), model[..idx].matches('\n').count());
+                    prop_assert_eq!(buffer.byte_to_char(idx).unwrap(), model[..idx].chars().count());
+                }
+                for (line, expected) in model.split('\n').enumerate() {
+                    prop_assert_eq!(buffer.line(line).unwrap(), expected);
+                }
+            }
+        }
+    }
+}
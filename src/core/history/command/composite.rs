@@ -0,0 +1,119 @@
+/// composite.rs
+/// Defines `CompositeCommand`, several commands undone as one step, e.g. the edits of all the
+/// cursors of a multi-cursor edit.
+use std::ops::Range;
+use crate::core::buffer::traits::TextBuffer;
+use crate::core::history::command::text_commands::ReplaceText;
+use crate::core::history::command::traits::{Command, CommandError};
+
+/// Commands applied in order & undone in reverse order. If one fails the ones already applied
+/// are undone, so the composite applies entirely or not at all.
+#[derive(Debug)]
+pub struct CompositeCommand {
+    commands: Vec<Box<dyn Command>>,
+    description: String,
+}
+
+impl CompositeCommand {
+    pub fn new(description: impl Into<String>) -> Self {
+        Self { commands: Vec::new(), description: description.into() }
+    }
+
+    /// One edit per cursor. Ranges are offsets into the text before the edit & must be disjoint;
+    /// they are applied back to front so that each offset stays valid.
+    pub fn multi_cursor<S: Into<String>>(edits: impl IntoIterator<Item = (Range<usize>, S)>) -> Self {
+        let mut edits: Vec<(Range<usize>, String)> = edits.into_iter().map(|(range, text)| (range, text.into())).collect();
+        edits.sort_by_key(|(range, _)| std::cmp::Reverse((range.start, range.end)));
+        let mut composite = Self::new("Multi-cursor edit");
+        for (range, text) in edits {
+            composite.push(Box::new(ReplaceText::new(range, text)));
+        }
+        composite
+    }
+
+    pub fn push(&mut self, command: Box<dyn Command>) {
+        self.commands.push(command);
+    }
+
+    pub fn with(mut self, command: Box<dyn Command>) -> Self {
+        self.push(command);
+        self
+    }
+
+    pub fn len(&self) -> usize {
+        self.commands.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.commands.is_empty()
+    }
+
+    pub fn commands(&self) -> &[Box<dyn Command>] {
+        &self.commands
+    }
+}
+
+impl Command for CompositeCommand {
+    fn apply(&mut self, buffer: &mut dyn TextBuffer) -> Result<(), CommandError> {
+        for applied in 0..self.commands.len() {
+            if let Err(error) = self.commands[applied].apply(buffer) {
+                for command in self.commands[..applied].iter().rev() {
+                    command.invert()?.apply(buffer)?;
+                }
+                return Err(error);
+            }
+        }
+        Ok(())
+    }
+
+    fn invert(&self) -> Result<Box<dyn Command>, CommandError> {
+        let mut inverse = CompositeCommand::new(self.description.clone());
+        for command in self.commands.iter().rev() {
+            inverse.push(command.invert()?);
+        }
+        Ok(Box::new(inverse))
+    }
+
+    fn description(&self) -> String {
+        self.description.clone()
+    }
+
+    fn edits(&self) -> Option<Vec<(Range<usize>, usize)>> {
+        let mut edits = Vec::new();
+        for command in &self.commands {
+            edits.extend(command.edits()?);
+        }
+        Some(edits)
+    }
+
+    fn memory_usage(&self) -> usize {
+        std::mem::size_of::<Self>() + self.description.capacity() + self.commands.iter().map(|command| command.memory_usage()).sum::<usize>()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::buffer::piece_table::PieceTable;
+    use crate::core::history::command::text_commands::InsertText;
+
+    #[test]
+    fn test_multi_cursor_edit() {
+        let mut buffer: Box<dyn TextBuffer> = Box::new(<PieceTable as TextBuffer>::from_text("let a = 1;\nlet b = 2;\n"));
+        let mut edit = CompositeCommand::multi_cursor([(4..5, "alpha"), (15..16, "beta"), (0..3, "var")]);
+        edit.apply(buffer.as_mut()).unwrap();
+        assert_eq!(buffer.text(), "var alpha = 1;\nlet beta = 2;\n");
+        edit.invert().unwrap().apply(buffer.as_mut()).unwrap();
+        assert_eq!(buffer.text(), "let a = 1;\nlet b = 2;\n");
+    }
+
+    #[test]
+    fn test_failure_rolls_back() {
+        let mut buffer: Box<dyn TextBuffer> = Box::new(<PieceTable as TextBuffer>::from_text("abc"));
+        let mut composite = CompositeCommand::new("Broken")
+            .with(Box::new(InsertText::new(0, "x")))
+            .with(Box::new(InsertText::new(99, "y")));
+        assert!(composite.apply(buffer.as_mut()).is_err());
+        assert_eq!(buffer.text(), "abc");
+    }
+}
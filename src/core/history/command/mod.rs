@@ -0,0 +1,10 @@
+//! Command module
+//! Reexports composite, text commands, and traits modules
+
+pub mod composite;
+pub mod text_commands;
+pub mod traits;
+
+pub use composite::CompositeCommand;
+pub use text_commands::{DeleteText, InsertText, ReplaceText};
+pub use traits::{Command, CommandError};
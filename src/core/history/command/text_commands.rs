@@ -0,0 +1,245 @@
+/// text_commands.rs
+/// The text edits: `InsertText`, `DeleteText` & `ReplaceText`. Edits made by keystrokes merge
+/// into typing runs that end at line breaks & at the start of each word.
+use std::any::Any;
+use std::ops::Range;
+use crate::core::buffer::content::line_ending::LineEnding;
+use crate::core::buffer::traits::TextBuffer;
+use crate::core::history::command::traits::{Command, CommandError};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InsertText {
+    pub offset: usize,
+    pub text: String,
+    /// Whether the text was typed, which lets the next keystroke merge into it.
+    pub(crate) keystroke: bool,
+}
+
+impl InsertText {
+    pub fn new(offset: usize, text: impl Into<String>) -> Self {
+        Self { offset, text: text.into(), keystroke: false }
+    }
+
+    /// A typed character.
+    pub fn keystroke(offset: usize, ch: char) -> Self {
+        Self { offset, text: ch.to_string(), keystroke: true }
+    }
+
+    pub fn range(&self) -> Range<usize> {
+        self.offset..self.offset + self.text.len()
+    }
+}
+
+impl Command for InsertText {
+    fn apply(&mut self, buffer: &mut dyn TextBuffer) -> Result<(), CommandError> {
+        Ok(buffer.insert(self.offset, &self.text)?)
+    }
+
+    fn invert(&self) -> Result<Box<dyn Command>, CommandError> {
+        Ok(Box::new(DeleteText { range: self.range(), deleted: Some(self.text.clone()), keystroke: false }))
+    }
+
+    fn description(&self) -> String {
+        if self.keystroke { "Typing" } else { "Insert" }.to_string()
+    }
+
+    /// Merges the next typed character, unless it is a line break or starts a new word.
+    fn merge(&mut self, next: &dyn Command) -> bool {
+        let Some(next) = (next as &dyn Any).downcast_ref::<InsertText>() else {
+            return false;
+        };
+        let (Some(last), Some(first)) = (self.text.chars().next_back(), next.text.chars().next()) else {
+            return false;
+        };
+        let breaks = |ch: char| LineEnding::from_char(ch).is_some();
+        if !self.keystroke || !next.keystroke || next.offset != self.range().end || breaks(last) || breaks(first) {
+            return false;
+        }
+        if last.is_whitespace() && !first.is_whitespace() {
+            return false;
+        }
+        self.text.push_str(&next.text);
+        true
+    }
+
+    fn edits(&self) -> Option<Vec<(Range<usize>, usize)>> {
+        Some(vec![(self.offset..self.offset, self.text.len())])
+    }
+
+    fn memory_usage(&self) -> usize {
+        std::mem::size_of::<Self>() + self.text.capacity()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeleteText {
+    pub range: Range<usize>,
+    /// The removed text, known once applied.
+    pub deleted: Option<String>,
+    pub(crate) keystroke: bool,
+}
+
+impl DeleteText {
+    pub fn new(range: Range<usize>) -> Self {
+        Self { range, deleted: None, keystroke: false }
+    }
+
+    /// A character removed by backspace or delete.
+    pub fn keystroke(range: Range<usize>) -> Self {
+        Self { range, deleted: None, keystroke: true }
+    }
+}
+
+impl Command for DeleteText {
+    fn apply(&mut self, buffer: &mut dyn TextBuffer) -> Result<(), CommandError> {
+        let deleted = buffer.slice(self.range.clone())?;
+        buffer.remove(self.range.clone())?;
+        self.deleted = Some(deleted);
+        Ok(())
+    }
+
+    fn invert(&self) -> Result<Box<dyn Command>, CommandError> {
+        let deleted = self.deleted.clone().ok_or(CommandError::NotApplied)?;
+        Ok(Box::new(InsertText::new(self.range.start, deleted)))
+    }
+
+    fn description(&self) -> String {
+        "Delete".to_string()
+    }
+
+    /// Merges the next backspace, which ends where this deletion starts, or the next delete,
+    /// which starts at the same offset.
+    fn merge(&mut self, next: &dyn Command) -> bool {
+        let Some(next) = (next as &dyn Any).downcast_ref::<DeleteText>() else {
+            return false;
+        };
+        let (Some(deleted), Some(next_deleted)) = (&mut self.deleted, &next.deleted) else {
+            return false;
+        };
+        if !self.keystroke || !next.keystroke || next_deleted.chars().any(|ch| LineEnding::from_char(ch).is_some()) {
+            return false;
+        }
+        if next.range.end == self.range.start {
+            deleted.insert_str(0, next_deleted);
+            self.range.start = next.range.start;
+        } else if next.range.start == self.range.start {
+            deleted.push_str(next_deleted);
+            self.range.end += next.range.len();
+        } else {
+            return false;
+        }
+        true
+    }
+
+    fn edits(&self) -> Option<Vec<(Range<usize>, usize)>> {
+        Some(vec![(self.range.clone(), 0)])
+    }
+
+    fn memory_usage(&self) -> usize {
+        std::mem::size_of::<Self>() + self.deleted.as_ref().map_or(0, String::capacity)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReplaceText {
+    pub range: Range<usize>,
+    pub text: String,
+    /// The replaced text, known once applied.
+    pub replaced: Option<String>,
+}
+
+impl ReplaceText {
+    pub fn new(range: Range<usize>, text: impl Into<String>) -> Self {
+        Self { range, text: text.into(), replaced: None }
+    }
+}
+
+impl Command for ReplaceText {
+    fn apply(&mut self, buffer: &mut dyn TextBuffer) -> Result<(), CommandError> {
+        let replaced = buffer.slice(self.range.clone())?;
+        buffer.replace(self.range.clone(), &self.text)?;
+        self.replaced = Some(replaced);
+        Ok(())
+    }
+
+    fn invert(&self) -> Result<Box<dyn Command>, CommandError> {
+        let replaced = self.replaced.clone().ok_or(CommandError::NotApplied)?;
+        let start = self.range.start;
+        Ok(Box::new(ReplaceText { range: start..start + self.text.len(), text: replaced, replaced: Some(self.text.clone()) }))
+    }
+
+    fn description(&self) -> String {
+        "Replace".to_string()
+    }
+
+    fn edits(&self) -> Option<Vec<(Range<usize>, usize)>> {
+        Some(vec![(self.range.clone(), self.text.len())])
+    }
+
+    fn memory_usage(&self) -> usize {
+        std::mem::size_of::<Self>() + self.text.capacity() + self.replaced.as_ref().map_or(0, String::capacity)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::buffer::piece_table::PieceTable;
+    use crate::core::buffer::rope::Rope;
+
+    fn backends(text: &str) -> Vec<Box<dyn TextBuffer>> {
+        vec![Box::new(<Rope as TextBuffer>::from_text(text)), Box::new(<PieceTable as TextBuffer>::from_text(text))]
+    }
+
+    #[test]
+    fn test_apply_and_invert() {
+        for mut buffer in backends("hello world") {
+            let commands: Vec<Box<dyn Command>> = vec![
+                Box::new(InsertText::new(5, ",")),
+                Box::new(DeleteText::new(7..12)),
+                Box::new(ReplaceText::new(0..5, "Grüße")),
+            ];
+            let mut inverses = Vec::new();
+            for mut command in commands {
+                assert_eq!(command.invert().is_ok(), command.description() == "Insert");
+                command.apply(buffer.as_mut()).unwrap();
+                inverses.push(command.invert().unwrap());
+            }
+            assert_eq!(buffer.text(), "Grüße, ");
+            for mut inverse in inverses.into_iter().rev() {
+                inverse.apply(buffer.as_mut()).unwrap();
+            }
+            assert_eq!(buffer.text(), "hello world");
+
+            let mut invalid = DeleteText::new(3..40);
+            assert!(matches!(invalid.apply(buffer.as_mut()), Err(CommandError::Buffer(_))));
+            assert_eq!(buffer.text(), "hello world");
+        }
+    }
+
+    #[test]
+    fn test_typing_merges_per_word() {
+        let mut typing = InsertText::keystroke(0, 'a');
+        assert!(typing.merge(&InsertText::keystroke(1, 'b')));
+        assert!(typing.merge(&InsertText::keystroke(2, ' ')));
+        assert!(!typing.merge(&InsertText::keystroke(3, 'c')));
+        assert!(!typing.merge(&InsertText::keystroke(5, ' ')));
+        assert!(!typing.merge(&InsertText::new(3, " ")));
+        assert!(!typing.merge(&InsertText::keystroke(3, '\n')));
+        assert_eq!(typing.text, "ab ");
+
+        let mut buffer = backends("abcdef").remove(0);
+        let mut first = DeleteText::keystroke(3..4);
+        first.apply(buffer.as_mut()).unwrap();
+        let mut backspace = DeleteText::keystroke(2..3);
+        backspace.apply(buffer.as_mut()).unwrap();
+        let mut delete = DeleteText::keystroke(2..3);
+        delete.apply(buffer.as_mut()).unwrap();
+        assert!(first.merge(&backspace));
+        assert!(first.merge(&delete));
+        assert_eq!(first.range, 2..5);
+        assert_eq!(first.deleted.as_deref(), Some("cde"));
+        first.invert().unwrap().apply(buffer.as_mut()).unwrap();
+        assert_eq!(buffer.text(), "abcdef");
+    }
+}
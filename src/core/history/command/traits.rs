@@ -0,0 +1,71 @@
+/// traits.rs
+/// Defines `Command`, an edit of a `TextBuffer` that knows how to undo itself.
+use std::any::Any;
+use std::error::Error;
+use std::fmt;
+use std::ops::Range;
+use crate::core::buffer::traits::{BufferError, TextBuffer};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CommandError {
+    Buffer(BufferError),
+    /// The command must be applied before it can be inverted.
+    NotApplied,
+}
+
+impl fmt::Display for CommandError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CommandError::Buffer(error) => write!(f, "Buffer error: {}", error),
+            CommandError::NotApplied => write!(f, "Command has not been applied"),
+        }
+    }
+}
+
+impl Error for CommandError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            CommandError::Buffer(error) => Some(error),
+            CommandError::NotApplied => None,
+        }
+    }
+}
+
+impl From<BufferError> for CommandError {
+    fn from(error: BufferError) -> Self {
+        CommandError::Buffer(error)
+    }
+}
+
+/// An edit of a `TextBuffer`, rope or piece table alike.
+///
+/// `apply` records what it needs to be undone, e.g. the text it deleted, so `invert` gives
+/// the command that undoes it only once it has been applied. A command that fails leaves the
+/// buffer as it was.
+pub trait Command: Any + fmt::Debug + Send + Sync {
+    fn apply(&mut self, buffer: &mut dyn TextBuffer) -> Result<(), CommandError>;
+
+    /// The command undoing this one, applied to the buffer as this one left it.
+    fn invert(&self) -> Result<Box<dyn Command>, CommandError>;
+
+    /// Short label for menus, e.g. "Typing".
+    fn description(&self) -> String;
+
+    /// Absorbs `next`, which was applied right after this command, so that both are undone as
+    /// one step. Returns whether it did; the default never merges.
+    fn merge(&mut self, _next: &dyn Command) -> bool {
+        false
+    }
+
+    /// The edits the command made, in order, each as the range of the buffer it replaced & the
+    /// length of the text that replaced it. `None` when the command can't tell, which makes the
+    /// next snapshot of the history a full one.
+    fn edits(&self) -> Option<Vec<(Range<usize>, usize)>> {
+        None
+    }
+
+    /// Bytes the command holds, text included, which bound the memory of the history.
+    fn memory_usage(&self) -> usize {
+        std::mem::size_of_val(self)
+    }
+}
@@ -0,0 +1,335 @@
+/// history.rs
+/// Defines `History`, which applies commands to a buffer & undoes & redoes them.
+use std::time::{Duration, Instant};
+use crate::core::buffer::traits::TextBuffer;
+use crate::core::history::command::traits::{Command, CommandError};
+use crate::core::buffer::storage::backend::StorageError;
+use crate::core::history::memory::metrics::HistoryStats;
+use crate::core::history::snapshot::{SnapshotChain, SnapshotSchedule};
+use crate::core::history::stack::{Branch, StateId, UndoTree};
+
+/// How long after a keystroke the next one still joins its undo step.
+pub const DEFAULT_COALESCE_WINDOW: Duration = Duration::from_secs(1);
+
+/// Undo & redo over the commands applied to one buffer.
+///
+/// The commands form an `UndoTree`: editing after undoing starts a new branch & the undone
+/// one stays reachable through `switch_branch` or `goto_time`. Keystrokes following each
+/// other within the coalesce window merge into one undo step as their commands allow, see
+/// `Command::merge`; `break_coalescing` ends the current step, e.g. when the cursor is moved.
+///
+/// Snapshots of the document are taken as the `SnapshotSchedule` says. Their deltas are built
+/// from the edits of the commands, those undo & redo apply included, so the buffer is only to
+/// be edited through the history. `stats` tells the memory the history takes &
+/// `drop_oldest` frees it, see `MemoryBudget`.
+#[derive(Debug)]
+pub struct History {
+    tree: UndoTree,
+    snapshots: SnapshotChain,
+    coalesce_window: Duration,
+}
+
+impl History {
+    pub fn new() -> Self {
+        Self::from_tree(UndoTree::new())
+    }
+
+    /// Resumes the history kept in `tree`, e.g. one read back from disk.
+    pub fn from_tree(tree: UndoTree) -> Self {
+        Self { tree, snapshots: SnapshotChain::default(), coalesce_window: DEFAULT_COALESCE_WINDOW }
+    }
+
+    /// Keeps at most `limit` undo steps, across all branches.
+    pub fn with_limit(mut self, limit: usize) -> Self {
+        self.tree = self.tree.with_state_limit(limit + 1);
+        self
+    }
+
+    /// Drops the oldest steps once the commands take more than `bytes`.
+    pub fn with_memory_limit(mut self, bytes: usize) -> Self {
+        self.tree = self.tree.with_memory_limit(bytes);
+        self
+    }
+
+    pub fn with_snapshot_schedule(mut self, schedule: SnapshotSchedule) -> Self {
+        self.snapshots = SnapshotChain::new(schedule);
+        self
+    }
+
+    pub fn with_coalesce_window(mut self, window: Duration) -> Self {
+        self.coalesce_window = window;
+        self
+    }
+
+    pub fn execute(&mut self, buffer: &mut dyn TextBuffer, command: Box<dyn Command>) -> Result<(), CommandError> {
+        self.execute_at(buffer, command, Instant::now())
+    }
+
+    /// Applies `command` as if at `time`, which decides whether it joins the last undo step.
+    pub fn execute_at(&mut self, buffer: &mut dyn TextBuffer, mut command: Box<dyn Command>, time: Instant) -> Result<(), CommandError> {
+        command.apply(buffer)?;
+        let edits = command.edits();
+        self.tree.record(command, time, self.coalesce_window);
+        self.snapshots.record_edit(self.tree.current(), buffer, edits.as_deref(), time);
+        Ok(())
+    }
+
+    /// Undoes the last step. Returns `false` when there is nothing to undo.
+    pub fn undo(&mut self, buffer: &mut dyn TextBuffer) -> Result<bool, CommandError> {
+        self.move_through(buffer, |tree, buffer, applied| tree.undo_with(buffer, applied))
+    }
+
+    /// Redoes the step last undone, on the branch last visited. Returns `false` when there is
+    /// nothing to redo.
+    pub fn redo(&mut self, buffer: &mut dyn TextBuffer) -> Result<bool, CommandError> {
+        self.move_through(buffer, |tree, buffer, applied| tree.redo_with(buffer, applied))
+    }
+
+    /// Brings the buffer back, or forward, to its state at `time`.
+    pub fn goto_time(&mut self, buffer: &mut dyn TextBuffer, time: Instant) -> Result<StateId, CommandError> {
+        let target = self.tree.state_at(time);
+        self.goto(buffer, target)?;
+        Ok(target)
+    }
+
+    /// Brings the buffer to its state `ago`, e.g. 30 seconds ago. When `Instant` cannot go
+    /// back that far, every state kept is more recent, so the buffer goes to the oldest one.
+    pub fn go_back(&mut self, buffer: &mut dyn TextBuffer, ago: Duration) -> Result<StateId, CommandError> {
+        match Instant::now().checked_sub(ago) {
+            Some(time) => self.goto_time(buffer, time),
+            None => {
+                let root = self.tree.root();
+                self.goto(buffer, root)?;
+                Ok(root)
+            }
+        }
+    }
+
+    /// Moves to state `target`, see `UndoTree::goto`.
+    fn goto(&mut self, buffer: &mut dyn TextBuffer, target: StateId) -> Result<bool, CommandError> {
+        self.move_through(buffer, |tree, buffer, applied| tree.goto_with(buffer, target, applied))
+    }
+
+    /// Runs `step`, a move through the tree, & records the edits of the commands it applied as
+    /// one edit, so that the next snapshot can still be a delta.
+    fn move_through(
+        &mut self,
+        buffer: &mut dyn TextBuffer,
+        step: impl FnOnce(&mut UndoTree, &mut dyn TextBuffer, &mut dyn FnMut(&dyn Command)) -> Result<bool, CommandError>,
+    ) -> Result<bool, CommandError> {
+        let (mut moves, mut edits) = (0, Some(Vec::new()));
+        let result = step(&mut self.tree, buffer, &mut |command| {
+            moves += 1;
+            edits = edits.take().zip(command.edits()).map(|(mut edits, more)| {
+                edits.extend(more);
+                edits
+            });
+        });
+        if moves > 0 {
+            self.snapshots.record_edit(self.tree.current(), buffer, edits.as_deref(), Instant::now());
+        }
+        result
+    }
+
+    pub fn branches(&self) -> Vec<Branch> {
+        self.tree.branches()
+    }
+
+    pub fn switch_branch(&mut self, buffer: &mut dyn TextBuffer, tip: StateId) -> Result<bool, CommandError> {
+        self.goto(buffer, tip)
+    }
+
+    /// Makes the next command start a new undo step.
+    pub fn break_coalescing(&mut self) {
+        self.tree.seal();
+    }
+
+    pub fn can_undo(&self) -> bool {
+        self.tree.can_undo()
+    }
+
+    pub fn can_redo(&self) -> bool {
+        self.tree.can_redo()
+    }
+
+    pub fn undo_description(&self) -> Option<String> {
+        self.tree.undo_command().map(|command| command.description())
+    }
+
+    pub fn redo_description(&self) -> Option<String> {
+        self.tree.redo_command().map(|command| command.description())
+    }
+
+    pub fn tree(&self) -> &UndoTree {
+        &self.tree
+    }
+
+    pub fn snapshots(&self) -> &SnapshotChain {
+        &self.snapshots
+    }
+
+    /// Text of the document at `state`, if a snapshot of it was taken.
+    pub fn snapshot_text(&self, state: StateId) -> Result<Option<String>, StorageError> {
+        self.snapshots.text_at(state)
+    }
+
+    pub fn stats(&self) -> HistoryStats {
+        HistoryStats {
+            states: self.tree.len(),
+            undo_bytes: self.tree.memory_usage(),
+            snapshots: self.snapshots.len(),
+            compressed_snapshots: self.snapshots.compressed(),
+            snapshot_bytes: self.snapshots.memory_usage(),
+        }
+    }
+
+    /// When the oldest edit or snapshot still kept was made.
+    pub fn oldest_time(&self) -> Option<Instant> {
+        match (self.tree.oldest_time(), self.snapshots.oldest_time()) {
+            (Some(edit), Some(snapshot)) => Some(edit.min(snapshot)),
+            (edit, snapshot) => edit.or(snapshot),
+        }
+    }
+
+    /// Forgets the oldest edit or snapshots, whichever is older. Returns the bytes freed.
+    pub fn drop_oldest(&mut self) -> usize {
+        let edit = self.tree.oldest_time();
+        match self.snapshots.oldest_time() {
+            Some(snapshot) if edit.is_none_or(|edit| snapshot <= edit) => self.snapshots.drop_oldest(),
+            _ => {
+                let before = self.tree.memory_usage();
+                self.tree.drop_oldest();
+                before - self.tree.memory_usage()
+            }
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.tree = UndoTree::new();
+        self.snapshots = SnapshotChain::new(*self.snapshots.schedule());
+    }
+}
+
+impl Default for History {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::buffer::rope::Rope;
+    use crate::core::history::command::{CompositeCommand, DeleteText, InsertText};
+    use crate::core::history::snapshot::Snapshot;
+
+    fn type_text(history: &mut History, buffer: &mut dyn TextBuffer, offset: usize, text: &str, start: Instant) {
+        for (idx, ch) in text.char_indices() {
+            let time = start + Duration::from_millis(100 * idx as u64);
+            history.execute_at(buffer, Box::new(InsertText::keystroke(offset + idx, ch)), time).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_typing_coalesces_per_word() {
+        let mut buffer = <Rope as TextBuffer>::from_text("");
+        let mut history = History::new();
+        let start = Instant::now();
+        type_text(&mut history, &mut buffer, 0, "hello big\nworld", start);
+        assert_eq!(history.tree().len(), 5);
+        assert_eq!(history.undo_description().as_deref(), Some("Typing"));
+
+        assert!(history.undo(&mut buffer).unwrap());
+        assert_eq!(buffer.text(), "hello big\n");
+        assert!(history.undo(&mut buffer).unwrap());
+        assert!(history.undo(&mut buffer).unwrap());
+        assert_eq!(buffer.text(), "hello ");
+        assert!(history.redo(&mut buffer).unwrap());
+        assert_eq!(buffer.text(), "hello big");
+
+        // A pause ends the step, & so does a new edit after an undo, which starts a branch.
+        let later = start + Duration::from_secs(10);
+        history.execute_at(&mut buffer, Box::new(InsertText::keystroke(9, 's')), later).unwrap();
+        assert!(!history.can_redo());
+        assert_eq!(history.branches().len(), 2);
+        history.execute_at(&mut buffer, Box::new(InsertText::keystroke(10, '!')), later + Duration::from_secs(2)).unwrap();
+        assert!(history.undo(&mut buffer).unwrap());
+        assert_eq!(buffer.text(), "hello bigs");
+    }
+
+    #[test]
+    fn test_undo_redo_and_limit() {
+        let mut buffer = <Rope as TextBuffer>::from_text("one two three");
+        let mut history = History::new().with_limit(2);
+        history.execute(&mut buffer, Box::new(DeleteText::new(3..7))).unwrap();
+        history.execute(&mut buffer, Box::new(CompositeCommand::multi_cursor([(0..0, "<"), (9..9, ">")]))).unwrap();
+        history.execute(&mut buffer, Box::new(InsertText::new(0, "1: "))).unwrap();
+        assert_eq!(buffer.text(), "1: <one three>");
+        assert!(history.execute(&mut buffer, Box::new(InsertText::new(99, "x"))).is_err());
+
+        assert!(history.undo(&mut buffer).unwrap());
+        assert!(history.undo(&mut buffer).unwrap());
+        assert!(!history.undo(&mut buffer).unwrap());
+        assert_eq!(buffer.text(), "one three");
+        assert_eq!(history.redo_description().as_deref(), Some("Multi-cursor edit"));
+        while history.redo(&mut buffer).unwrap() {}
+        assert_eq!(buffer.text(), "1: <one three>");
+    }
+
+    #[test]
+    fn test_go_back_before_clock_start() {
+        let mut buffer = <Rope as TextBuffer>::from_text("one");
+        let mut history = History::new();
+        history.execute(&mut buffer, Box::new(InsertText::new(3, " two"))).unwrap();
+        history.break_coalescing();
+        history.execute(&mut buffer, Box::new(InsertText::new(7, " three"))).unwrap();
+
+        let current = history.tree().current();
+        assert_eq!(history.go_back(&mut buffer, Duration::ZERO).unwrap(), current);
+        assert_eq!(history.go_back(&mut buffer, Duration::MAX).unwrap(), history.tree().root());
+        assert_eq!(buffer.text(), "one");
+        assert!(history.redo(&mut buffer).unwrap());
+        assert_eq!(buffer.text(), "one two");
+    }
+
+    #[test]
+    fn test_undo_redo_snapshots_are_deltas() {
+        let schedule = SnapshotSchedule { every_edits: 1, ..SnapshotSchedule::default() };
+        let mut history = History::new().with_snapshot_schedule(schedule);
+        let mut buffer = <Rope as TextBuffer>::from_text("one");
+        history.execute(&mut buffer, Box::new(InsertText::new(3, " two"))).unwrap();
+        history.execute(&mut buffer, Box::new(CompositeCommand::multi_cursor([(0..0, "<"), (7..7, ">")]))).unwrap();
+        let tip = history.tree().current();
+        history.undo(&mut buffer).unwrap();
+        history.undo(&mut buffer).unwrap();
+        history.switch_branch(&mut buffer, tip).unwrap();
+        assert_eq!(buffer.text(), "<one two>");
+
+        let snapshots: Vec<&Snapshot> = history.snapshots().iter().collect();
+        assert_eq!(snapshots.len(), 5);
+        assert!(snapshots[1..].iter().all(|snapshot| matches!(snapshot, Snapshot::Delta(_))));
+        let root = history.tree().root();
+        assert_eq!(history.snapshot_text(root).unwrap().as_deref(), Some("one"));
+        assert_eq!(history.snapshot_text(tip).unwrap().as_deref(), Some("<one two>"));
+    }
+
+    #[test]
+    fn test_snapshots_and_stats() {
+        let schedule = SnapshotSchedule { every_edits: 5, ..SnapshotSchedule::default() };
+        let mut history = History::new().with_snapshot_schedule(schedule);
+        let mut buffer = <Rope as TextBuffer>::from_text("");
+        let start = Instant::now();
+        type_text(&mut history, &mut buffer, 0, "abcdefghij", start);
+        // Snapshots leave the typing step whole.
+        assert_eq!(history.stats().states, 2);
+        assert_eq!(history.stats().snapshots, 2);
+        let state = history.tree().current();
+        assert_eq!(history.snapshot_text(state).unwrap().as_deref(), Some("abcdefghij"));
+        assert!(history.stats().total_bytes() > history.stats().undo_bytes);
+        assert!(history.stats().to_string().starts_with("2 states"));
+
+        let freed = history.drop_oldest();
+        assert!(freed > 0);
+        assert_eq!(history.stats().snapshots, 0);
+    }
+}
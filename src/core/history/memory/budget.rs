@@ -0,0 +1,86 @@
+/// budget.rs
+/// Defines `MemoryBudget`, a bound on the memory the histories of all open documents take.
+use crate::core::history::history::History;
+
+/// Bytes the histories may take together. Past the limit, the oldest edits & snapshots go
+/// first, whichever history they belong to, so a long session on one big file gives up its
+/// early history before the recent edits of another document.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemoryBudget {
+    limit: usize,
+}
+
+impl MemoryBudget {
+    pub fn new(limit: usize) -> Self {
+        Self { limit }
+    }
+
+    pub fn limit(&self) -> usize {
+        self.limit
+    }
+
+    pub fn usage<'a>(histories: impl IntoIterator<Item = &'a History>) -> usize {
+        histories.into_iter().map(|history| history.stats().total_bytes()).sum()
+    }
+
+    /// Prunes the histories until they fit the budget. The current state of each is always
+    /// kept. Returns the bytes freed.
+    pub fn enforce(&self, histories: &mut [&mut History]) -> usize {
+        let mut usage = Self::usage(histories.iter().map(|history| &**history));
+        let start = usage;
+        while usage > self.limit {
+            let oldest = histories
+                .iter()
+                .enumerate()
+                .filter_map(|(idx, history)| history.oldest_time().map(|time| (time, idx)))
+                .min();
+            let Some((_, idx)) = oldest else {
+                break;
+            };
+            let freed = histories[idx].drop_oldest();
+            if freed == 0 {
+                break;
+            }
+            usage -= freed.min(usage);
+        }
+        start - usage
+    }
+}
+
+impl Default for MemoryBudget {
+    fn default() -> Self {
+        Self::new(256 * 1024 * 1024)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, Instant};
+    use crate::core::buffer::rope::Rope;
+    use crate::core::buffer::traits::TextBuffer;
+    use crate::core::history::command::InsertText;
+
+    #[test]
+    fn test_oldest_history_goes_first() {
+        let start = Instant::now();
+        let (mut old_buffer, mut new_buffer) = (<Rope as TextBuffer>::from_text(""), <Rope as TextBuffer>::from_text(""));
+        let (mut old, mut new) = (History::new(), History::new());
+        for idx in 0..50 {
+            let time = start + Duration::from_secs(idx);
+            old.execute_at(&mut old_buffer, Box::new(InsertText::new(0, "x".repeat(100))), time).unwrap();
+            new.execute_at(&mut new_buffer, Box::new(InsertText::new(0, "y".repeat(100))), time + Duration::from_secs(100)).unwrap();
+        }
+        let budget = MemoryBudget::new(MemoryBudget::usage([&old, &new]) * 3 / 5);
+        let freed = budget.enforce(&mut [&mut old, &mut new]);
+        assert!(freed > 0);
+        assert!(MemoryBudget::usage([&old, &new]) <= budget.limit());
+        assert_eq!(new.stats().states, 51);
+        assert!(old.stats().states < 51);
+
+        // Undo stops where the history was cut, the text of the current state untouched.
+        let states = old.stats().states;
+        while old.undo(&mut old_buffer).unwrap() {}
+        assert_eq!(old_buffer.len_bytes(), 100 * (51 - states));
+    }
+}
@@ -0,0 +1,31 @@
+/// metrics.rs
+/// Defines `HistoryStats`, the memory the history of one document takes.
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct HistoryStats {
+    /// States of the undo tree, the root included.
+    pub states: usize,
+    /// Bytes taken by the undo tree & its commands.
+    pub undo_bytes: usize,
+    pub snapshots: usize,
+    pub compressed_snapshots: usize,
+    /// Bytes taken by the snapshots, compressed or not.
+    pub snapshot_bytes: usize,
+}
+
+impl HistoryStats {
+    pub fn total_bytes(&self) -> usize {
+        self.undo_bytes + self.snapshot_bytes
+    }
+}
+
+impl fmt::Display for HistoryStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} states in {} bytes, {} snapshots ({} compressed) in {} bytes",
+            self.states, self.undo_bytes, self.snapshots, self.compressed_snapshots, self.snapshot_bytes
+        )
+    }
+}
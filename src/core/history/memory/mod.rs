@@ -0,0 +1,8 @@
+//! Memory module
+//! Reexports budget and metrics modules
+
+pub mod budget;
+pub mod metrics;
+
+pub use budget::MemoryBudget;
+pub use metrics::HistoryStats;
@@ -0,0 +1,15 @@
+//! History module
+//! Reexports command, history, memory, snapshot, and stack modules
+
+pub mod command;
+#[allow(clippy::module_inception)]
+pub mod history;
+pub mod memory;
+pub mod snapshot;
+pub mod stack;
+
+pub use command::{Command, CommandError, CompositeCommand, DeleteText, InsertText, ReplaceText};
+pub use history::History;
+pub use memory::{HistoryStats, MemoryBudget};
+pub use snapshot::{SnapshotChain, SnapshotSchedule};
+pub use stack::{Branch, StateId, UndoTree};
@@ -0,0 +1,90 @@
+/// compression.rs
+/// Defines `SnapshotData`, the text held by a snapshot, kept as is while it may be read soon &
+/// DEFLATE-compressed once it gets old. Snapshots are compressed while editing, so the fast
+/// level is used.
+use crate::core::buffer::storage::backend::StorageError;
+use crate::core::buffer::storage::compression::Compression;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SnapshotData {
+    Plain(String),
+    /// Text that compressing didn't make smaller, which isn't tried again.
+    Incompressible(String),
+    Compressed { bytes: Vec<u8>, len: usize },
+}
+
+impl SnapshotData {
+    pub fn is_compressed(&self) -> bool {
+        matches!(self, SnapshotData::Compressed { .. })
+    }
+
+    /// Length of the text, compressed or not.
+    pub fn len(&self) -> usize {
+        match self {
+            SnapshotData::Plain(text) | SnapshotData::Incompressible(text) => text.len(),
+            SnapshotData::Compressed { len, .. } => *len,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Whether the text is kept as is & wasn't tried to be compressed yet.
+    pub fn is_plain(&self) -> bool {
+        matches!(self, SnapshotData::Plain(_))
+    }
+
+    /// Compresses the text, or marks it incompressible if that doesn't make it smaller.
+    pub fn compress(&mut self) {
+        if let SnapshotData::Plain(text) = self {
+            let mut bytes = Compression::Fast.compress(text.as_bytes());
+            *self = if bytes.len() < text.len() {
+                bytes.shrink_to_fit();
+                SnapshotData::Compressed { bytes, len: text.len() }
+            } else {
+                SnapshotData::Incompressible(std::mem::take(text))
+            };
+        }
+    }
+
+    pub fn text(&self) -> Result<String, StorageError> {
+        match self {
+            SnapshotData::Plain(text) | SnapshotData::Incompressible(text) => Ok(text.clone()),
+            SnapshotData::Compressed { bytes, .. } => {
+                let bytes = Compression::Fast.decompress(bytes)?;
+                String::from_utf8(bytes).map_err(|error| StorageError::InvalidUtf8(error.utf8_error().valid_up_to()))
+            }
+        }
+    }
+
+    pub fn memory_usage(&self) -> usize {
+        std::mem::size_of::<Self>()
+            + match self {
+                SnapshotData::Plain(text) | SnapshotData::Incompressible(text) => text.capacity(),
+                SnapshotData::Compressed { bytes, .. } => bytes.capacity(),
+            }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_compress() {
+        let text = "let value = compute(42);\n".repeat(100);
+        let mut data = SnapshotData::Plain(text.clone());
+        data.compress();
+        assert!(data.is_compressed());
+        assert!(data.memory_usage() < text.len() / 4);
+        assert_eq!(data.len(), text.len());
+        assert_eq!(data.text().unwrap(), text);
+
+        let mut tiny = SnapshotData::Plain("x".to_string());
+        tiny.compress();
+        assert!(!tiny.is_compressed());
+        assert_eq!(tiny, SnapshotData::Incompressible("x".to_string()));
+        assert_eq!(tiny.text().unwrap(), "x");
+    }
+}
@@ -0,0 +1,79 @@
+/// delta.rs
+/// Defines `DeltaSnapshot`, the text of a document at one state as a change of the text of
+/// the snapshot before it.
+use std::ops::Range;
+use std::time::Instant;
+use crate::core::buffer::storage::backend::StorageError;
+use crate::core::history::snapshot::compression::SnapshotData;
+use crate::core::history::stack::undo_tree::StateId;
+
+/// The text of the previous snapshot with `range` replaced by `data`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeltaSnapshot {
+    pub state: StateId,
+    pub time: Instant,
+    pub range: Range<usize>,
+    pub data: SnapshotData,
+}
+
+impl DeltaSnapshot {
+    /// The delta replacing `range` of the previous snapshot with `text`.
+    pub fn new(state: StateId, time: Instant, range: Range<usize>, text: String) -> Self {
+        Self { state, time, range, data: SnapshotData::Plain(text) }
+    }
+
+    /// The delta turning `previous` into `text`.
+    pub fn between(previous: &str, text: &str, state: StateId, time: Instant) -> Self {
+        let (range, replacement) = changed_ranges(previous, text);
+        Self { state, time, range, data: SnapshotData::Plain(text[replacement].to_string()) }
+    }
+
+    /// Turns the text of the previous snapshot into the text of this one.
+    pub fn apply(&self, text: &mut String) -> Result<(), StorageError> {
+        let len = text.len();
+        if self.range.end > len || !text.is_char_boundary(self.range.start) || !text.is_char_boundary(self.range.end) {
+            return Err(StorageError::OutOfBounds { index: self.range.end, len });
+        }
+        text.replace_range(self.range.clone(), &self.data.text()?);
+        Ok(())
+    }
+
+    pub fn memory_usage(&self) -> usize {
+        std::mem::size_of::<Self>() - std::mem::size_of::<SnapshotData>() + self.data.memory_usage()
+    }
+}
+
+/// The ranges of `old` & of `new` that differ, between their common prefix & suffix, on char
+/// boundaries.
+pub(crate) fn changed_ranges(old: &str, new: &str) -> (Range<usize>, Range<usize>) {
+    let mut prefix = old.bytes().zip(new.bytes()).take_while(|(a, b)| a == b).count();
+    while !old.is_char_boundary(prefix) || !new.is_char_boundary(prefix) {
+        prefix -= 1;
+    }
+    let max_suffix = old.len().min(new.len()) - prefix;
+    let mut suffix = old.bytes().rev().zip(new.bytes().rev()).take(max_suffix).take_while(|(a, b)| a == b).count();
+    while !old.is_char_boundary(old.len() - suffix) || !new.is_char_boundary(new.len() - suffix) {
+        suffix -= 1;
+    }
+    (prefix..old.len() - suffix, prefix..new.len() - suffix)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_between_and_apply() {
+        let now = Instant::now();
+        let cases = [("hello world", "hello big world"), ("aaa", "aa"), ("ü-ü", "ü-ö"), ("", "new"), ("same", "same")];
+        for (old, new) in cases {
+            let delta = DeltaSnapshot::between(old, new, 1, now);
+            let mut text = old.to_string();
+            delta.apply(&mut text).unwrap();
+            assert_eq!(text, new);
+        }
+        let delta = DeltaSnapshot::between("hello world", "hello big world", 1, now);
+        assert_eq!((delta.range.clone(), delta.data.text().unwrap()), (6..6, "big ".to_string()));
+        assert!(delta.apply(&mut "short".to_string()).is_err());
+    }
+}
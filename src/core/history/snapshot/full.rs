@@ -0,0 +1,22 @@
+/// full.rs
+/// Defines `FullSnapshot`, the whole text of a document at one state of its history.
+use std::time::Instant;
+use crate::core::history::snapshot::compression::SnapshotData;
+use crate::core::history::stack::undo_tree::StateId;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FullSnapshot {
+    pub state: StateId,
+    pub time: Instant,
+    pub data: SnapshotData,
+}
+
+impl FullSnapshot {
+    pub fn new(state: StateId, time: Instant, text: String) -> Self {
+        Self { state, time, data: SnapshotData::Plain(text) }
+    }
+
+    pub fn memory_usage(&self) -> usize {
+        std::mem::size_of::<Self>() - std::mem::size_of::<SnapshotData>() + self.data.memory_usage()
+    }
+}
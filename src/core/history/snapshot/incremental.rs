@@ -0,0 +1,307 @@
+/// incremental.rs
+/// Defines `SnapshotChain`, the snapshots of a document: full ones now & then with deltas in
+/// between, the older ones compressed.
+use std::collections::VecDeque;
+use std::ops::Range;
+use std::time::Instant;
+use crate::core::buffer::storage::backend::StorageError;
+use crate::core::buffer::traits::TextBuffer;
+use crate::core::history::snapshot::compression::SnapshotData;
+use crate::core::history::snapshot::delta::DeltaSnapshot;
+use crate::core::history::snapshot::full::FullSnapshot;
+use crate::core::history::snapshot::scheduling::SnapshotSchedule;
+use crate::core::history::stack::undo_tree::StateId;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Snapshot {
+    Full(FullSnapshot),
+    Delta(DeltaSnapshot),
+}
+
+impl Snapshot {
+    pub fn state(&self) -> StateId {
+        match self {
+            Snapshot::Full(full) => full.state,
+            Snapshot::Delta(delta) => delta.state,
+        }
+    }
+
+    pub fn time(&self) -> Instant {
+        match self {
+            Snapshot::Full(full) => full.time,
+            Snapshot::Delta(delta) => delta.time,
+        }
+    }
+
+    pub fn data(&self) -> &SnapshotData {
+        match self {
+            Snapshot::Full(full) => &full.data,
+            Snapshot::Delta(delta) => &delta.data,
+        }
+    }
+
+    fn data_mut(&mut self) -> &mut SnapshotData {
+        match self {
+            Snapshot::Full(full) => &mut full.data,
+            Snapshot::Delta(delta) => &mut delta.data,
+        }
+    }
+
+    pub fn memory_usage(&self) -> usize {
+        match self {
+            Snapshot::Full(full) => full.memory_usage(),
+            Snapshot::Delta(delta) => delta.memory_usage(),
+        }
+    }
+}
+
+/// What changed in the buffer since the latest snapshot.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Changes {
+    Unchanged,
+    /// `old` of the latest snapshot was replaced by what is now `new` of the buffer.
+    Span { old: Range<usize>, new: Range<usize> },
+    /// No delta can be built, since no snapshot was taken yet or the buffer was changed other
+    /// than by the recorded edits.
+    Unknown,
+}
+
+impl Changes {
+    /// Widens the span to the edit replacing `range` of the buffer with `len` bytes.
+    fn add(&mut self, range: Range<usize>, len: usize) {
+        *self = match self {
+            Changes::Unknown => return,
+            Changes::Unchanged => Changes::Span { old: range.clone(), new: range.start..range.start + len },
+            Changes::Span { old, new } => {
+                let (start, end) = (new.start.min(range.start), new.end.max(range.end));
+                Changes::Span { old: start..old.end + (end - new.end), new: start..end + len - range.len() }
+            }
+        };
+    }
+}
+
+/// Snapshots of a document, oldest first, taken as its `SnapshotSchedule` says.
+///
+/// A delta holds the change from the snapshot before it, so reading a snapshot starts from
+/// the full one before it. The span the recorded edits changed since the latest snapshot is
+/// tracked, so a delta only reads that span of the buffer. The oldest snapshots go first, a
+/// full one with its deltas.
+#[derive(Debug, Clone)]
+pub struct SnapshotChain {
+    snapshots: VecDeque<Snapshot>,
+    schedule: SnapshotSchedule,
+    changes: Changes,
+    /// Edits since the latest snapshot.
+    edits: usize,
+    last_time: Instant,
+}
+
+impl SnapshotChain {
+    pub fn new(schedule: SnapshotSchedule) -> Self {
+        Self { snapshots: VecDeque::new(), schedule, changes: Changes::Unknown, edits: 0, last_time: Instant::now() }
+    }
+
+    pub fn schedule(&self) -> &SnapshotSchedule {
+        &self.schedule
+    }
+
+    pub fn len(&self) -> usize {
+        self.snapshots.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.snapshots.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Snapshot> {
+        self.snapshots.iter()
+    }
+
+    /// Counts an edit, made at `time`, & snapshots `buffer`, now at `state`, if one is due.
+    /// `edits` are those of the command, see `Command::edits`. Returns whether it snapshot.
+    pub fn record_edit(&mut self, state: StateId, buffer: &dyn TextBuffer, edits: Option<&[(Range<usize>, usize)]>, time: Instant) -> bool {
+        match edits {
+            Some(edits) => edits.iter().for_each(|(range, len)| self.changes.add(range.clone(), *len)),
+            None => self.changes = Changes::Unknown,
+        }
+        self.edits += 1;
+        if !self.schedule.is_due(self.edits, time.saturating_duration_since(self.last_time)) {
+            return false;
+        }
+        self.take(state, buffer, time);
+        true
+    }
+
+    /// Makes the next snapshot a full one, for when the buffer was changed other than by the
+    /// recorded edits, e.g. by an edit made outside the history.
+    pub fn invalidate(&mut self) {
+        self.changes = Changes::Unknown;
+    }
+
+    /// Snapshots `buffer`, now at `state`. A delta reads the changed span of the buffer only,
+    /// a full snapshot gathers its chunks.
+    pub fn take(&mut self, state: StateId, buffer: &dyn TextBuffer, time: Instant) {
+        let deltas = self.snapshots.iter().rev().position(|snapshot| matches!(snapshot, Snapshot::Full(_)));
+        let delta = match &self.changes {
+            _ if self.schedule.wants_full(deltas) => None,
+            Changes::Unchanged => Some(DeltaSnapshot::new(state, time, 0..0, String::new())),
+            Changes::Span { old, new } => buffer.slice(new.clone()).ok().map(|text| DeltaSnapshot::new(state, time, old.clone(), text)),
+            Changes::Unknown => None,
+        };
+        let snapshot = match delta {
+            Some(delta) => Snapshot::Delta(delta),
+            None => {
+                let mut text = String::with_capacity(buffer.len_bytes());
+                buffer.chunks().for_each(|chunk| text.push_str(&chunk));
+                Snapshot::Full(FullSnapshot::new(state, time, text))
+            }
+        };
+        self.snapshots.push_back(snapshot);
+        self.changes = Changes::Unchanged;
+        self.edits = 0;
+        self.last_time = time;
+
+        // Snapshots leave the plain ones one at a time, so those before the first one already
+        // compressed or found incompressible were tried too.
+        let old = self.snapshots.len().saturating_sub(self.schedule.keep_plain);
+        for snapshot in self.snapshots.range_mut(..old).rev() {
+            if !snapshot.data().is_plain() {
+                break;
+            }
+            snapshot.data_mut().compress();
+        }
+    }
+
+    /// Text of the latest snapshot of `state`, if any.
+    pub fn text_at(&self, state: StateId) -> Result<Option<String>, StorageError> {
+        let Some(idx) = self.snapshots.iter().rposition(|snapshot| snapshot.state() == state) else {
+            return Ok(None);
+        };
+        let start = self.snapshots.range(..=idx).rposition(|snapshot| matches!(snapshot, Snapshot::Full(_)));
+        let Some(start) = start else {
+            return Ok(None);
+        };
+        let mut text = self.snapshots[start].data().text()?;
+        for snapshot in self.snapshots.range(start + 1..=idx) {
+            if let Snapshot::Delta(delta) = snapshot {
+                delta.apply(&mut text)?;
+            }
+        }
+        Ok(Some(text))
+    }
+
+    /// When the oldest snapshot was taken.
+    pub fn oldest_time(&self) -> Option<Instant> {
+        self.snapshots.front().map(Snapshot::time)
+    }
+
+    /// Drops the oldest full snapshot with the deltas that depend on it. Returns the bytes
+    /// freed.
+    pub fn drop_oldest(&mut self) -> usize {
+        let before = self.memory_usage();
+        self.snapshots.pop_front();
+        while let Some(Snapshot::Delta(_)) = self.snapshots.front() {
+            self.snapshots.pop_front();
+        }
+        before - self.memory_usage()
+    }
+
+    pub fn compressed(&self) -> usize {
+        self.snapshots.iter().filter(|snapshot| snapshot.data().is_compressed()).count()
+    }
+
+    pub fn memory_usage(&self) -> usize {
+        self.snapshots.iter().map(Snapshot::memory_usage).sum()
+    }
+}
+
+impl Default for SnapshotChain {
+    fn default() -> Self {
+        Self::new(SnapshotSchedule::default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use crate::core::buffer::rope::Rope;
+
+    fn schedule() -> SnapshotSchedule {
+        SnapshotSchedule { every_edits: 1, interval: Duration::from_secs(60), deltas_per_full: 3, keep_plain: 1 }
+    }
+
+    #[test]
+    fn test_full_and_delta_snapshots() {
+        let mut chain = SnapshotChain::new(schedule());
+        let start = Instant::now();
+        let mut texts = Vec::new();
+        let mut buffer = <Rope as TextBuffer>::from_text(&"fn main() {\n}\n".repeat(50));
+        for state in 0..9 {
+            let line = format!("    step({});\n", state);
+            buffer.insert(12, &line).unwrap();
+            chain.record_edit(state, &buffer, Some(&[(12..12, line.len())]), start + Duration::from_secs(state as u64));
+            texts.push(buffer.text());
+        }
+        let fulls = chain.iter().filter(|snapshot| matches!(snapshot, Snapshot::Full(_))).count();
+        assert_eq!(fulls, 3);
+        // The old full snapshots are compressed, deltas too short to shrink are marked so.
+        assert!(chain.compressed() >= 2);
+        assert!(!chain.iter().last().unwrap().data().is_compressed());
+        assert!(chain.iter().take(chain.len() - 1).all(|snapshot| !snapshot.data().is_plain()));
+        for (state, text) in texts.iter().enumerate() {
+            assert_eq!(chain.text_at(state).unwrap().as_ref(), Some(text));
+        }
+        assert_eq!(chain.text_at(42).unwrap(), None);
+        // Deltas hold the inserted lines only.
+        assert!(chain.iter().all(|snapshot| matches!(snapshot, Snapshot::Full(_)) || snapshot.data().len() < 20));
+
+        assert!(chain.drop_oldest() > 0);
+        assert_eq!(chain.len(), 5);
+        assert_eq!(chain.text_at(0).unwrap(), None);
+        assert_eq!(chain.text_at(5).unwrap().as_ref(), Some(&texts[5]));
+    }
+
+    #[test]
+    fn test_deltas_from_edits() {
+        let schedule = SnapshotSchedule { every_edits: 3, deltas_per_full: 10, ..schedule() };
+        let mut chain = SnapshotChain::new(schedule);
+        let mut buffer = <Rope as TextBuffer>::from_text("one two three four");
+        let now = Instant::now();
+        chain.take(0, &buffer, now);
+
+        // Edits on either side of the span so far widen it.
+        buffer.replace(4..7, "2").unwrap();
+        chain.record_edit(1, &buffer, Some(&[(4..7, 1)]), now);
+        buffer.remove(0..4).unwrap();
+        chain.record_edit(1, &buffer, Some(&[(0..4, 0)]), now);
+        buffer.insert(7, "!").unwrap();
+        assert!(chain.record_edit(1, &buffer, Some(&[(7..7, 1)]), now));
+        let Some(Snapshot::Delta(delta)) = chain.iter().last() else {
+            panic!("expected a delta");
+        };
+        assert_eq!((delta.range.clone(), delta.data.text().unwrap()), (0..13, "2 three!".to_string()));
+        assert_eq!(chain.text_at(1).unwrap().as_deref(), Some("2 three! four"));
+
+        // Edits the chain can't follow make the next snapshot a full one.
+        buffer.insert(0, ">").unwrap();
+        chain.invalidate();
+        chain.take(2, &buffer, now);
+        assert!(matches!(chain.iter().last(), Some(Snapshot::Full(_))));
+        buffer.insert(0, ">").unwrap();
+        chain.record_edit(3, &buffer, None, now);
+        chain.take(3, &buffer, now);
+        assert!(matches!(chain.iter().last(), Some(Snapshot::Full(_))));
+        assert_eq!(chain.text_at(3).unwrap().as_deref(), Some(">>2 three! four"));
+    }
+
+    #[test]
+    fn test_schedule() {
+        let schedule = SnapshotSchedule::default();
+        assert!(!schedule.is_due(0, Duration::from_secs(3600)));
+        assert!(schedule.is_due(1, schedule.interval));
+        assert!(schedule.is_due(schedule.every_edits, Duration::ZERO));
+        assert!(schedule.wants_full(None));
+        assert!(!schedule.wants_full(Some(1)));
+    }
+}
@@ -0,0 +1,14 @@
+//! Snapshot module
+//! Reexports compression, delta, full, incremental, and scheduling modules
+
+pub mod compression;
+pub mod delta;
+pub mod full;
+pub mod incremental;
+pub mod scheduling;
+
+pub use compression::SnapshotData;
+pub use delta::DeltaSnapshot;
+pub use full::FullSnapshot;
+pub use incremental::{Snapshot, SnapshotChain};
+pub use scheduling::SnapshotSchedule;
@@ -0,0 +1,33 @@
+/// scheduling.rs
+/// Defines `SnapshotSchedule`, which decides when a history snapshots its document & whether
+/// as a full snapshot or a delta.
+use std::time::Duration;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SnapshotSchedule {
+    /// Edits after which a snapshot is due.
+    pub every_edits: usize,
+    /// Time after which a snapshot is due if anything was edited.
+    pub interval: Duration,
+    /// Deltas between two full snapshots.
+    pub deltas_per_full: usize,
+    /// Newest snapshots left uncompressed, since they are the likeliest to be read.
+    pub keep_plain: usize,
+}
+
+impl SnapshotSchedule {
+    pub fn is_due(&self, edits: usize, elapsed: Duration) -> bool {
+        edits >= self.every_edits || (edits > 0 && elapsed >= self.interval)
+    }
+
+    /// Whether the next snapshot, after `deltas` deltas, is a full one.
+    pub fn wants_full(&self, deltas: Option<usize>) -> bool {
+        deltas.is_none_or(|deltas| deltas >= self.deltas_per_full)
+    }
+}
+
+impl Default for SnapshotSchedule {
+    fn default() -> Self {
+        Self { every_edits: 100, interval: Duration::from_secs(300), deltas_per_full: 10, keep_plain: 2 }
+    }
+}
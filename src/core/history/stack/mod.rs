@@ -0,0 +1,8 @@
+//! Stack module
+//! Reexports persistence and undo tree modules
+
+pub mod persistence;
+pub mod undo_tree;
+
+pub use persistence::{ChangedPolicy, ContentHash, HistoryStore, PersistenceError, Restored};
+pub use undo_tree::{Branch, StateId, UndoNode, UndoTree};
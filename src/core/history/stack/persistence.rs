@@ -0,0 +1,511 @@
+/// persistence.rs
+/// Saves undo histories to disk so that they survive restarts. A history is only restored
+/// onto the exact text it was saved with, which the content hash in its file checks.
+use std::any::Any;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use crate::core::buffer::storage::compression::Compression;
+use crate::core::buffer::traits::TextBuffer;
+use crate::core::history::command::composite::CompositeCommand;
+use crate::core::history::command::text_commands::{DeleteText, InsertText, ReplaceText};
+use crate::core::history::command::traits::Command;
+use crate::core::history::history::History;
+use crate::core::history::snapshot::delta::changed_ranges;
+use crate::core::history::stack::undo_tree::{StateId, UndoNode, UndoTree};
+
+const MAGIC: &[u8; 4] = b"KUND";
+const VERSION: u8 = 1;
+const NONE: u64 = u64::MAX;
+/// Deepest nesting of composite commands a file may hold.
+const MAX_NESTING: usize = 64;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PersistenceError {
+    Io(String),
+    Corrupted(String),
+    UnsupportedVersion(u8),
+    /// The history holds a command that can't be saved.
+    UnsupportedCommand(String),
+}
+
+impl fmt::Display for PersistenceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PersistenceError::Io(e) => write!(f, "I/O error: {}", e),
+            PersistenceError::Corrupted(e) => write!(f, "Corrupted history file: {}", e),
+            PersistenceError::UnsupportedVersion(version) => write!(f, "Unsupported history file version {}", version),
+            PersistenceError::UnsupportedCommand(description) => write!(f, "Command '{}' cannot be saved", description),
+        }
+    }
+}
+
+impl Error for PersistenceError {}
+
+impl From<io::Error> for PersistenceError {
+    fn from(error: io::Error) -> Self {
+        PersistenceError::Io(error.to_string())
+    }
+}
+
+/// 64-bit FNV-1a hash of a text, with its length, which is stable across runs & platforms.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ContentHash {
+    pub hash: u64,
+    pub len: u64,
+}
+
+impl ContentHash {
+    pub fn of(bytes: &[u8]) -> Self {
+        let mut hash = Self::empty();
+        hash.update(bytes);
+        hash
+    }
+
+    pub fn of_buffer(buffer: &dyn TextBuffer) -> Self {
+        let mut hash = Self::empty();
+        buffer.chunks().for_each(|chunk| hash.update(chunk.as_bytes()));
+        hash
+    }
+
+    fn empty() -> Self {
+        Self { hash: 0xCBF2_9CE4_8422_2325, len: 0 }
+    }
+
+    fn update(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.hash = (self.hash ^ byte as u64).wrapping_mul(0x0100_0000_01B3);
+        }
+        self.len += bytes.len() as u64;
+    }
+}
+
+/// What to do with a saved history when the document changed since it was saved.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ChangedPolicy {
+    /// Start with an empty history.
+    #[default]
+    Discard,
+    /// Keep the history & add the change as one more undo step on top of it, so that undoing
+    /// it brings back the text the history was saved with.
+    Rebase,
+}
+
+#[derive(Debug)]
+pub enum Restored {
+    /// The document is the one the history was saved with.
+    Unchanged(History),
+    /// The document changed; its change is the last undo step.
+    Rebased(History),
+    /// The document changed & the saved history was dropped.
+    Discarded,
+    /// No history was saved for the document.
+    Missing,
+}
+
+/// Where histories are saved: one file per document in a directory of the workspace state,
+/// named after the document's path & checked against a hash of its content.
+///
+/// The file holds the hash of the current state's text & every state of the tree with its
+/// command & wall clock time, DEFLATE-compressed. The text itself is only kept with
+/// `with_rebase`, since rebasing a history onto a changed document needs it.
+#[derive(Debug, Clone)]
+pub struct HistoryStore {
+    dir: PathBuf,
+    saves_text: bool,
+}
+
+impl HistoryStore {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into(), saves_text: false }
+    }
+
+    /// Also saves the text of the document, so that `ChangedPolicy::Rebase` can restore its
+    /// history once it changed. Each file then takes as much room as its document.
+    pub fn with_rebase(mut self, rebase: bool) -> Self {
+        self.saves_text = rebase;
+        self
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// File the history of `document` is saved to.
+    pub fn path_for(&self, document: &Path) -> PathBuf {
+        let key = ContentHash::of(document.to_string_lossy().as_bytes());
+        self.dir.join(format!("{:016x}.undo", key.hash))
+    }
+
+    /// Saves `history`, whose current state is the text of `buffer`. The file is replaced
+    /// atomically, so a crash leaves the previous one.
+    pub fn save(&self, document: &Path, history: &History, buffer: &dyn TextBuffer) -> Result<PathBuf, PersistenceError> {
+        let text = self.saves_text.then(|| buffer.text());
+        let bytes = encode(history.tree(), ContentHash::of_buffer(buffer), text.as_deref())?;
+        fs::create_dir_all(&self.dir)?;
+        let path = self.path_for(document);
+        let temp = path.with_extension("undo.tmp");
+        fs::write(&temp, bytes)?;
+        fs::rename(&temp, &path)?;
+        Ok(path)
+    }
+
+    /// Restores the history of `document`, whose text is now that of `buffer`. A history saved
+    /// without its text is discarded rather than rebased. A file that doesn't parse is an
+    /// error & is left in place.
+    pub fn load(&self, document: &Path, buffer: &dyn TextBuffer, policy: ChangedPolicy) -> Result<Restored, PersistenceError> {
+        let bytes = match fs::read(self.path_for(document)) {
+            Ok(bytes) => bytes,
+            Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(Restored::Missing),
+            Err(error) => return Err(error.into()),
+        };
+        let (mut tree, saved_hash, saved_text) = decode(&bytes)?;
+        if saved_hash == ContentHash::of_buffer(buffer) {
+            return Ok(Restored::Unchanged(History::from_tree(tree)));
+        }
+        match (policy, saved_text) {
+            (ChangedPolicy::Discard, _) | (ChangedPolicy::Rebase, None) => Ok(Restored::Discarded),
+            (ChangedPolicy::Rebase, Some(saved_text)) => {
+                tree.record(Box::new(external_change(&saved_text, &buffer.text())), Instant::now(), Duration::ZERO);
+                tree.seal();
+                Ok(Restored::Rebased(History::from_tree(tree)))
+            }
+        }
+    }
+
+    /// Deletes the saved history of `document`, if any.
+    pub fn remove(&self, document: &Path) -> Result<(), PersistenceError> {
+        match fs::remove_file(self.path_for(document)) {
+            Err(error) if error.kind() != io::ErrorKind::NotFound => Err(error.into()),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// The applied replacement turning `old` into `new`.
+fn external_change(old: &str, new: &str) -> ReplaceText {
+    let (range, replacement) = changed_ranges(old, new);
+    let mut change = ReplaceText::new(range.clone(), &new[replacement]);
+    change.replaced = Some(old[range].to_string());
+    change
+}
+
+fn encode(tree: &UndoTree, hash: ContentHash, text: Option<&str>) -> Result<Vec<u8>, PersistenceError> {
+    let mut body = Writer::default();
+    body.u64(hash.hash);
+    body.u64(hash.len);
+    body.optional_string(text);
+    body.u64(tree.root() as u64);
+    body.u64(tree.current() as u64);
+    let mut states: Vec<(StateId, &UndoNode)> = tree.states().collect();
+    states.sort_unstable_by_key(|&(id, _)| id);
+    body.u64(states.len() as u64);
+    let (now, wall_now) = (Instant::now(), SystemTime::now());
+    for (id, node) in states {
+        body.u64(id as u64);
+        body.u64(node.parent.map_or(NONE, |parent| parent as u64));
+        body.u64(node.active_child.map_or(NONE, |child| child as u64));
+        let wall = wall_now.checked_sub(now.saturating_duration_since(node.time)).unwrap_or(wall_now);
+        body.u64(wall.duration_since(UNIX_EPOCH).unwrap_or_default().as_millis() as u64);
+        match &node.command {
+            Some(command) => encode_command(&mut body, command.as_ref())?,
+            None => body.u8(0),
+        }
+    }
+
+    let mut bytes = MAGIC.to_vec();
+    bytes.push(VERSION);
+    bytes.extend(Compression::Fast.compress(&body.bytes));
+    Ok(bytes)
+}
+
+fn encode_command(out: &mut Writer, command: &dyn Command) -> Result<(), PersistenceError> {
+    let any = command as &dyn Any;
+    if let Some(insert) = any.downcast_ref::<InsertText>() {
+        out.u8(1);
+        out.u64(insert.offset as u64);
+        out.u8(insert.keystroke as u8);
+        out.string(&insert.text);
+    } else if let Some(delete) = any.downcast_ref::<DeleteText>() {
+        out.u8(2);
+        out.u64(delete.range.start as u64);
+        out.u64(delete.range.end as u64);
+        out.u8(delete.keystroke as u8);
+        out.optional_string(delete.deleted.as_deref());
+    } else if let Some(replace) = any.downcast_ref::<ReplaceText>() {
+        out.u8(3);
+        out.u64(replace.range.start as u64);
+        out.u64(replace.range.end as u64);
+        out.string(&replace.text);
+        out.optional_string(replace.replaced.as_deref());
+    } else if let Some(composite) = any.downcast_ref::<CompositeCommand>() {
+        out.u8(4);
+        out.string(&composite.description());
+        out.u64(composite.len() as u64);
+        for command in composite.commands() {
+            encode_command(out, command.as_ref())?;
+        }
+    } else {
+        return Err(PersistenceError::UnsupportedCommand(command.description()));
+    }
+    Ok(())
+}
+
+/// Reads back the tree, the hash of its current text & that text, if it was saved.
+fn decode(bytes: &[u8]) -> Result<(UndoTree, ContentHash, Option<String>), PersistenceError> {
+    if bytes.len() < MAGIC.len() + 1 || !bytes.starts_with(MAGIC) {
+        return Err(PersistenceError::Corrupted("not a history file".to_string()));
+    }
+    let version = bytes[MAGIC.len()];
+    if version != VERSION {
+        return Err(PersistenceError::UnsupportedVersion(version));
+    }
+    let body = Compression::Fast
+        .decompress(&bytes[MAGIC.len() + 1..])
+        .map_err(|error| PersistenceError::Corrupted(error.to_string()))?;
+    let mut reader = Reader { bytes: &body, pos: 0 };
+    let hash = ContentHash { hash: reader.u64()?, len: reader.u64()? };
+    let text = reader.optional_string()?;
+    let root = reader.u64()? as StateId;
+    let current = reader.u64()? as StateId;
+    let count = reader.u64()?;
+
+    let (now, wall_now) = (Instant::now(), SystemTime::now());
+    let mut nodes = HashMap::new();
+    for _ in 0..count {
+        let id = reader.u64()? as StateId;
+        let parent = reader.optional_id()?;
+        let active_child = reader.optional_id()?;
+        let wall = UNIX_EPOCH + Duration::from_millis(reader.u64()?);
+        let time = now.checked_sub(wall_now.duration_since(wall).unwrap_or_default()).unwrap_or(now);
+        let command = decode_command(&mut reader, 0)?;
+        nodes.insert(id, UndoNode { parent, command, children: Vec::new(), active_child, time, sealed: true });
+    }
+    if reader.pos != body.len() {
+        return Err(PersistenceError::Corrupted("trailing bytes".to_string()));
+    }
+    let tree = UndoTree::from_states(nodes, root, current)
+        .ok_or_else(|| PersistenceError::Corrupted("inconsistent tree".to_string()))?;
+    Ok((tree, hash, text))
+}
+
+/// Reads a command nested in `depth` composites.
+fn decode_command(reader: &mut Reader, depth: usize) -> Result<Option<Box<dyn Command>>, PersistenceError> {
+    let command: Box<dyn Command> = match reader.u8()? {
+        0 => return Ok(None),
+        1 => {
+            let offset = reader.u64()? as usize;
+            let keystroke = reader.u8()? != 0;
+            Box::new(InsertText { offset, keystroke, text: reader.string()? })
+        }
+        2 => {
+            let range = reader.u64()? as usize..reader.u64()? as usize;
+            let keystroke = reader.u8()? != 0;
+            Box::new(DeleteText { range, keystroke, deleted: reader.optional_string()? })
+        }
+        3 => {
+            let range = reader.u64()? as usize..reader.u64()? as usize;
+            let text = reader.string()?;
+            Box::new(ReplaceText { range, text, replaced: reader.optional_string()? })
+        }
+        4 if depth >= MAX_NESTING => return Err(PersistenceError::Corrupted("commands nested too deeply".to_string())),
+        4 => {
+            let mut composite = CompositeCommand::new(reader.string()?);
+            for _ in 0..reader.u64()? {
+                let command = decode_command(reader, depth + 1)?.ok_or_else(|| PersistenceError::Corrupted("empty command".to_string()))?;
+                composite.push(command);
+            }
+            Box::new(composite)
+        }
+        tag => return Err(PersistenceError::Corrupted(format!("unknown command {}", tag))),
+    };
+    Ok(Some(command))
+}
+
+#[derive(Default)]
+struct Writer {
+    bytes: Vec<u8>,
+}
+
+impl Writer {
+    fn u8(&mut self, value: u8) {
+        self.bytes.push(value);
+    }
+
+    fn u64(&mut self, value: u64) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn string(&mut self, text: &str) {
+        self.u64(text.len() as u64);
+        self.bytes.extend_from_slice(text.as_bytes());
+    }
+
+    fn optional_string(&mut self, text: Option<&str>) {
+        match text {
+            Some(text) => {
+                self.u8(1);
+                self.string(text);
+            }
+            None => self.u8(0),
+        }
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl Reader<'_> {
+    fn take(&mut self, len: usize) -> Result<&[u8], PersistenceError> {
+        let end = self.pos.checked_add(len).filter(|&end| end <= self.bytes.len());
+        let end = end.ok_or_else(|| PersistenceError::Corrupted("unexpected end of file".to_string()))?;
+        let bytes = &self.bytes[self.pos..end];
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8, PersistenceError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u64(&mut self) -> Result<u64, PersistenceError> {
+        let bytes = self.take(8)?;
+        Ok(u64::from_le_bytes(bytes.try_into().unwrap_or_default()))
+    }
+
+    fn optional_id(&mut self) -> Result<Option<StateId>, PersistenceError> {
+        let id = self.u64()?;
+        Ok((id != NONE).then_some(id as StateId))
+    }
+
+    fn string(&mut self) -> Result<String, PersistenceError> {
+        let len = self.u64()? as usize;
+        let bytes = self.take(len)?;
+        String::from_utf8(bytes.to_vec()).map_err(|error| PersistenceError::Corrupted(error.to_string()))
+    }
+
+    fn optional_string(&mut self) -> Result<Option<String>, PersistenceError> {
+        match self.u8()? {
+            0 => Ok(None),
+            _ => self.string().map(Some),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::buffer::piece_table::PieceTable;
+    use crate::core::buffer::rope::Rope;
+
+    fn store(name: &str) -> HistoryStore {
+        let dir = std::env::temp_dir().join(format!("kaudocore-history-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        HistoryStore::new(dir)
+    }
+
+    /// A history with two branches & a multi-cursor edit.
+    fn edited(buffer: &mut dyn TextBuffer) -> History {
+        let mut history = History::new();
+        history.execute(buffer, Box::new(InsertText::new(0, "fn main() {}\n"))).unwrap();
+        history.execute(buffer, Box::new(ReplaceText::new(3..7, "start"))).unwrap();
+        history.undo(buffer).unwrap();
+        history.execute(buffer, Box::new(CompositeCommand::multi_cursor([(0..2, "pub fn"), (13..13, "// é\n")]))).unwrap();
+        history.execute(buffer, Box::new(DeleteText::new(0..4))).unwrap();
+        history
+    }
+
+    #[test]
+    fn test_unchanged_document_restores_full_history() {
+        let store = store("unchanged");
+        let document = Path::new("/project/src/main.rs");
+        let mut buffer = <Rope as TextBuffer>::from_text("");
+        let history = edited(&mut buffer);
+        store.save(document, &history, &buffer).unwrap();
+
+        let mut reopened = <PieceTable as TextBuffer>::from_text(&buffer.text());
+        let Restored::Unchanged(mut restored) = store.load(document, &reopened, ChangedPolicy::Discard).unwrap() else {
+            panic!("history not restored");
+        };
+        assert_eq!(restored.branches().len(), 2);
+        while restored.undo(&mut reopened).unwrap() {}
+        assert_eq!(reopened.text(), "");
+        let tip = restored.branches()[0].tip;
+        restored.switch_branch(&mut reopened, tip).unwrap();
+        assert_eq!(reopened.text(), "fn start() {}\n");
+
+        assert!(matches!(store.load(Path::new("/elsewhere.rs"), &reopened, ChangedPolicy::Discard), Ok(Restored::Missing)));
+        let _ = fs::remove_dir_all(store.dir());
+    }
+
+    #[test]
+    fn test_text_only_saved_for_rebase() {
+        let store = store("hash-only");
+        let document = Path::new("hash-only.txt");
+        let mut buffer = <Rope as TextBuffer>::from_text("");
+        let history = edited(&mut buffer);
+        let path = store.save(document, &history, &buffer).unwrap();
+        let (_, hash, text) = decode(&fs::read(&path).unwrap()).unwrap();
+        assert_eq!(hash, ContentHash::of_buffer(&buffer));
+        assert_eq!(text, None);
+
+        let changed = <Rope as TextBuffer>::from_text("changed");
+        assert!(matches!(store.load(document, &changed, ChangedPolicy::Rebase), Ok(Restored::Discarded)));
+        assert!(matches!(store.load(document, &buffer, ChangedPolicy::Rebase), Ok(Restored::Unchanged(_))));
+        let _ = fs::remove_dir_all(store.dir());
+    }
+
+    #[test]
+    fn test_nesting_limit() {
+        let nested = |depth: usize| {
+            let mut command = CompositeCommand::new("nested");
+            command.push(Box::new(InsertText::new(0, "x")));
+            for _ in 1..depth {
+                let mut outer = CompositeCommand::new("nested");
+                outer.push(Box::new(command));
+                command = outer;
+            }
+            let mut out = Writer::default();
+            encode_command(&mut out, &command).unwrap();
+            out.bytes
+        };
+        let bytes = nested(MAX_NESTING);
+        assert!(decode_command(&mut Reader { bytes: &bytes, pos: 0 }, 0).is_ok_and(|command| command.is_some()));
+        let bytes = nested(MAX_NESTING + 1);
+        assert!(matches!(decode_command(&mut Reader { bytes: &bytes, pos: 0 }, 0), Err(PersistenceError::Corrupted(_))));
+    }
+
+    #[test]
+    fn test_changed_document() {
+        let store = store("changed").with_rebase(true);
+        let document = Path::new("notes.txt");
+        let mut buffer = <Rope as TextBuffer>::from_text("");
+        let history = edited(&mut buffer);
+        let saved = buffer.text();
+        store.save(document, &history, &buffer).unwrap();
+
+        let mut changed = <Rope as TextBuffer>::from_text(&saved.replace("main", "entry"));
+        assert!(matches!(store.load(document, &changed, ChangedPolicy::Discard), Ok(Restored::Discarded)));
+        let Restored::Rebased(mut rebased) = store.load(document, &changed, ChangedPolicy::Rebase).unwrap() else {
+            panic!("history not rebased");
+        };
+        rebased.undo(&mut changed).unwrap();
+        assert_eq!(changed.text(), saved);
+        while rebased.undo(&mut changed).unwrap() {}
+        assert_eq!(changed.text(), "");
+
+        let path = store.path_for(document);
+        let mut bytes = fs::read(&path).unwrap();
+        bytes.truncate(bytes.len() / 2);
+        fs::write(&path, bytes).unwrap();
+        assert!(matches!(store.load(document, &changed, ChangedPolicy::Discard), Err(PersistenceError::Corrupted(_))));
+        store.remove(document).unwrap();
+        let _ = fs::remove_dir_all(store.dir());
+    }
+}
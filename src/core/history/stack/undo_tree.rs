@@ -0,0 +1,488 @@
+/// undo_tree.rs
+/// Defines `UndoTree`, which keeps every state a buffer went through, so that an edit made
+/// after undoing starts a new branch instead of throwing the undone one away.
+use std::collections::{HashMap, HashSet};
+use std::time::{Duration, Instant};
+use crate::core::buffer::traits::TextBuffer;
+use crate::core::history::command::traits::{Command, CommandError};
+
+/// Identifies a state of the tree. Ids are never reused.
+pub type StateId = usize;
+
+/// The state reached by applying `command` to the parent state.
+#[derive(Debug)]
+pub struct UndoNode {
+    pub parent: Option<StateId>,
+    /// `None` for the root, the oldest state kept.
+    pub command: Option<Box<dyn Command>>,
+    pub children: Vec<StateId>,
+    /// The child redo goes to: the last one entered.
+    pub active_child: Option<StateId>,
+    /// When the state was reached, or last extended by a merged command.
+    pub time: Instant,
+    /// Whether later commands must start a new state rather than merge into this one.
+    pub sealed: bool,
+}
+
+impl UndoNode {
+    fn memory_usage(&self) -> usize {
+        std::mem::size_of::<Self>() + self.command.as_ref().map_or(0, |command| command.memory_usage())
+    }
+}
+
+/// A branch of the tree, named by the state it leads to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Branch {
+    pub tip: StateId,
+    pub time: Instant,
+    /// Edits between the root & the tip.
+    pub depth: usize,
+    /// Whether the current state is on the branch.
+    pub is_current: bool,
+}
+
+/// Every state of a buffer since the oldest one kept, as a tree of commands.
+///
+/// Undo moves to the parent state & redo to the child last entered, so with a single branch
+/// the tree behaves as an undo & a redo stack. `goto` reaches any state by undoing up to the
+/// common ancestor & redoing down from it, which `goto_time` & `switch_branch` build on.
+///
+/// Memory stays under a budget: past it, the oldest leaves off the current branch go first,
+/// then the oldest states of the current branch, the root moving up.
+#[derive(Debug)]
+pub struct UndoTree {
+    nodes: HashMap<StateId, UndoNode>,
+    root: StateId,
+    current: StateId,
+    next_id: StateId,
+    memory_usage: usize,
+    memory_limit: usize,
+    state_limit: usize,
+}
+
+impl UndoTree {
+    pub fn new() -> Self {
+        let root = UndoNode { parent: None, command: None, children: Vec::new(), active_child: None, time: Instant::now(), sealed: true };
+        let memory_usage = root.memory_usage();
+        Self {
+            nodes: HashMap::from([(0, root)]),
+            root: 0,
+            current: 0,
+            next_id: 1,
+            memory_usage,
+            memory_limit: 64 * 1024 * 1024,
+            state_limit: 10_000,
+        }
+    }
+
+    /// Bytes the commands & states may take before the oldest ones are dropped.
+    pub fn with_memory_limit(mut self, bytes: usize) -> Self {
+        self.memory_limit = bytes;
+        self.prune();
+        self
+    }
+
+    /// States kept, the root included.
+    pub fn with_state_limit(mut self, states: usize) -> Self {
+        self.state_limit = states.max(1);
+        self.prune();
+        self
+    }
+
+    pub fn root(&self) -> StateId {
+        self.root
+    }
+
+    pub fn current(&self) -> StateId {
+        self.current
+    }
+
+    pub fn node(&self, id: StateId) -> Option<&UndoNode> {
+        self.nodes.get(&id)
+    }
+
+    /// The states, in no particular order.
+    pub fn states(&self) -> impl Iterator<Item = (StateId, &UndoNode)> {
+        self.nodes.iter().map(|(&id, node)| (id, node))
+    }
+
+    /// Rebuilds a tree from its states, e.g. read back from disk. Children lists are rebuilt
+    /// from the parents, in id order, which is the order they were added in.
+    pub(crate) fn from_states(mut nodes: HashMap<StateId, UndoNode>, root: StateId, current: StateId) -> Option<Self> {
+        if !nodes.contains_key(&root) || !nodes.contains_key(&current) {
+            return None;
+        }
+        let mut ids: Vec<StateId> = nodes.keys().copied().collect();
+        ids.sort_unstable();
+        for node in nodes.values_mut() {
+            node.children.clear();
+        }
+        for &id in &ids {
+            let Some(parent) = nodes[&id].parent else {
+                continue;
+            };
+            nodes.get_mut(&parent)?.children.push(id);
+        }
+        for node in nodes.values_mut() {
+            node.active_child = node.active_child.filter(|child| node.children.contains(child));
+        }
+        let memory_usage = nodes.values().map(UndoNode::memory_usage).sum();
+        let next_id = ids.last().map_or(0, |id| id + 1);
+        let mut tree = Self { nodes, root, current, next_id, memory_usage, ..Self::new() };
+        // Every state must lead back to the root.
+        if tree.nodes.keys().any(|&id| tree.path_to(id).first() != Some(&root)) {
+            return None;
+        }
+        tree.prune();
+        Some(tree)
+    }
+
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.len() == 1
+    }
+
+    pub fn memory_usage(&self) -> usize {
+        self.memory_usage
+    }
+
+    pub fn can_undo(&self) -> bool {
+        self.current != self.root
+    }
+
+    pub fn can_redo(&self) -> bool {
+        self.nodes[&self.current].active_child.is_some()
+    }
+
+    /// The command undo would revert.
+    pub fn undo_command(&self) -> Option<&dyn Command> {
+        self.nodes[&self.current].command.as_deref()
+    }
+
+    /// The command redo would apply.
+    pub fn redo_command(&self) -> Option<&dyn Command> {
+        let child = self.nodes[&self.current].active_child?;
+        self.nodes[&child].command.as_deref()
+    }
+
+    /// Records `command`, already applied at `time`. It merges into the current state when
+    /// that one is an unsealed leaf extended less than `window` before & accepts it, or else
+    /// becomes a new child of the current state. Returns whether it merged.
+    pub fn record(&mut self, command: Box<dyn Command>, time: Instant, window: Duration) -> bool {
+        let node = self.nodes.get_mut(&self.current).expect("current state");
+        if !node.sealed
+            && node.children.is_empty()
+            && time.saturating_duration_since(node.time) <= window
+            && let Some(last) = &mut node.command
+        {
+            let before = last.memory_usage();
+            if last.merge(command.as_ref()) {
+                node.time = time;
+                self.memory_usage = self.memory_usage - before + last.memory_usage();
+                self.prune();
+                return true;
+            }
+        }
+        self.push(command, time);
+        false
+    }
+
+    /// Adds a new child state of the current state & moves to it.
+    fn push(&mut self, command: Box<dyn Command>, time: Instant) {
+        let id = self.next_id;
+        self.next_id += 1;
+        let node = UndoNode { parent: Some(self.current), command: Some(command), children: Vec::new(), active_child: None, time, sealed: false };
+        self.memory_usage += node.memory_usage();
+        self.nodes.insert(id, node);
+        let parent = self.nodes.get_mut(&self.current).expect("current state");
+        parent.children.push(id);
+        parent.active_child = Some(id);
+        self.current = id;
+        self.prune();
+    }
+
+    /// Makes the next command start a new state.
+    pub fn seal(&mut self) {
+        if let Some(node) = self.nodes.get_mut(&self.current) {
+            node.sealed = true;
+        }
+    }
+
+    /// Moves to the parent state. Returns `false` at the root.
+    pub fn undo(&mut self, buffer: &mut dyn TextBuffer) -> Result<bool, CommandError> {
+        self.undo_with(buffer, &mut |_| {})
+    }
+
+    /// Like `undo`, passing the command applied to the buffer, the inverse of the undone one,
+    /// to `applied`.
+    pub fn undo_with(&mut self, buffer: &mut dyn TextBuffer, applied: &mut dyn FnMut(&dyn Command)) -> Result<bool, CommandError> {
+        let node = &self.nodes[&self.current];
+        let (Some(parent), Some(command)) = (node.parent, &node.command) else {
+            return Ok(false);
+        };
+        let mut inverse = command.invert()?;
+        inverse.apply(buffer)?;
+        applied(inverse.as_ref());
+        let child = self.current;
+        self.nodes.get_mut(&parent).expect("parent state").active_child = Some(child);
+        self.current = parent;
+        self.seal();
+        Ok(true)
+    }
+
+    /// Moves to the child state last entered. Returns `false` when there is none.
+    pub fn redo(&mut self, buffer: &mut dyn TextBuffer) -> Result<bool, CommandError> {
+        self.redo_with(buffer, &mut |_| {})
+    }
+
+    /// Like `redo`, passing the command applied to the buffer to `applied`.
+    pub fn redo_with(&mut self, buffer: &mut dyn TextBuffer, applied: &mut dyn FnMut(&dyn Command)) -> Result<bool, CommandError> {
+        let Some(child) = self.nodes[&self.current].active_child else {
+            return Ok(false);
+        };
+        self.enter(buffer, child, applied)?;
+        Ok(true)
+    }
+
+    fn enter(&mut self, buffer: &mut dyn TextBuffer, child: StateId, applied: &mut dyn FnMut(&dyn Command)) -> Result<(), CommandError> {
+        let node = self.nodes.get_mut(&child).expect("child state");
+        if let Some(command) = &mut node.command {
+            command.apply(buffer)?;
+            applied(command.as_ref());
+        }
+        node.sealed = true;
+        self.nodes.get_mut(&self.current).expect("current state").active_child = Some(child);
+        self.current = child;
+        Ok(())
+    }
+
+    /// Moves to state `target` along the tree. On error the buffer is left at the last state
+    /// reached, which becomes current.
+    pub fn goto(&mut self, buffer: &mut dyn TextBuffer, target: StateId) -> Result<bool, CommandError> {
+        self.goto_with(buffer, target, &mut |_| {})
+    }
+
+    /// Like `goto`, passing each command applied to the buffer to `applied`, in order.
+    pub fn goto_with(&mut self, buffer: &mut dyn TextBuffer, target: StateId, applied: &mut dyn FnMut(&dyn Command)) -> Result<bool, CommandError> {
+        if !self.nodes.contains_key(&target) {
+            return Ok(false);
+        }
+        let path = self.path_to(target);
+        let on_path: HashSet<StateId> = path.iter().copied().collect();
+        while !on_path.contains(&self.current) {
+            self.undo_with(buffer, applied)?;
+        }
+        let from = path.iter().position(|&id| id == self.current).unwrap_or(0);
+        for &id in &path[from + 1..] {
+            self.enter(buffer, id, applied)?;
+        }
+        Ok(true)
+    }
+
+    /// The state that was current at `time`: the last one reached by then, or the root.
+    pub fn state_at(&self, time: Instant) -> StateId {
+        self.nodes
+            .iter()
+            .filter(|(_, node)| node.time <= time)
+            .max_by_key(|&(&id, node)| (node.time, id))
+            .map_or(self.root, |(&id, _)| id)
+    }
+
+    /// Goes back, or forward, to the state of `time`, e.g. 30 seconds ago.
+    pub fn goto_time(&mut self, buffer: &mut dyn TextBuffer, time: Instant) -> Result<StateId, CommandError> {
+        let target = self.state_at(time);
+        self.goto(buffer, target)?;
+        Ok(target)
+    }
+
+    /// The branches, one per leaf, oldest first.
+    pub fn branches(&self) -> Vec<Branch> {
+        let mut branches: Vec<Branch> = self
+            .nodes
+            .iter()
+            .filter(|(_, node)| node.children.is_empty())
+            .map(|(&tip, node)| {
+                let path = self.path_to(tip);
+                Branch { tip, time: node.time, depth: path.len() - 1, is_current: path.contains(&self.current) }
+            })
+            .collect();
+        branches.sort_by_key(|branch| (branch.time, branch.tip));
+        branches
+    }
+
+    /// Moves to the tip of a branch listed by `branches`.
+    pub fn switch_branch(&mut self, buffer: &mut dyn TextBuffer, tip: StateId) -> Result<bool, CommandError> {
+        self.goto(buffer, tip)
+    }
+
+    /// Ids from the root to `target`, both included.
+    fn path_to(&self, target: StateId) -> Vec<StateId> {
+        let mut path = vec![target];
+        let mut id = target;
+        while let Some(parent) = self.nodes.get(&id).and_then(|node| node.parent) {
+            // Only a corrupted tree has a cycle.
+            if path.len() > self.nodes.len() {
+                break;
+            }
+            path.push(parent);
+            id = parent;
+        }
+        path.reverse();
+        path
+    }
+
+    /// Drops the oldest states until the tree fits its budget.
+    fn prune(&mut self) {
+        while self.nodes.len() > 1 && (self.memory_usage > self.memory_limit || self.nodes.len() > self.state_limit) {
+            if !self.drop_oldest() {
+                break;
+            }
+        }
+    }
+
+    /// The state `drop_oldest` would forget: the oldest leaf off the current branch, or else
+    /// the root, as long as it isn't the current state.
+    fn oldest(&self) -> Option<StateId> {
+        let current_path: HashSet<StateId> = self.path_to(self.current).into_iter().collect();
+        self.nodes
+            .iter()
+            .filter(|(id, node)| node.children.is_empty() && !current_path.contains(id))
+            .min_by_key(|&(&id, node)| (node.time, id))
+            .map(|(&id, _)| id)
+            .or((self.root != self.current).then_some(self.root))
+    }
+
+    /// When the edit `drop_oldest` would forget was made.
+    pub fn oldest_time(&self) -> Option<Instant> {
+        let id = self.oldest()?;
+        let id = if id == self.root { *self.nodes[&id].children.first()? } else { id };
+        Some(self.nodes[&id].time)
+    }
+
+    /// Forgets the oldest edit, see `oldest`, whatever the budget. Returns whether there was
+    /// one to forget.
+    pub fn drop_oldest(&mut self) -> bool {
+        match self.oldest() {
+            Some(id) if id == self.root => self.drop_root(),
+            Some(id) => self.remove_leaf(id),
+            None => return false,
+        }
+        true
+    }
+
+    fn remove_leaf(&mut self, id: StateId) {
+        let Some(node) = self.nodes.remove(&id) else {
+            return;
+        };
+        self.memory_usage -= node.memory_usage();
+        if let Some(parent) = node.parent.and_then(|parent| self.nodes.get_mut(&parent)) {
+            parent.children.retain(|&child| child != id);
+            if parent.active_child == Some(id) {
+                parent.active_child = parent.children.last().copied();
+            }
+        }
+    }
+
+    /// Forgets the root, whose only child becomes the root: its state can't be undone anymore.
+    fn drop_root(&mut self) {
+        let Some(&child) = self.nodes[&self.root].children.first() else {
+            return;
+        };
+        let old = self.nodes.remove(&self.root).expect("root state");
+        self.memory_usage -= old.memory_usage();
+        let node = self.nodes.get_mut(&child).expect("child state");
+        self.memory_usage -= node.memory_usage();
+        node.parent = None;
+        node.command = None;
+        self.memory_usage += node.memory_usage();
+        self.root = child;
+    }
+}
+
+impl Default for UndoTree {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::buffer::rope::Rope;
+    use crate::core::history::command::InsertText;
+
+    fn insert(tree: &mut UndoTree, buffer: &mut dyn TextBuffer, offset: usize, text: &str, time: Instant) {
+        let mut command = Box::new(InsertText::new(offset, text));
+        command.apply(buffer).unwrap();
+        tree.record(command, time, Duration::ZERO);
+    }
+
+    #[test]
+    fn test_branches_survive_new_edits() {
+        let mut buffer = <Rope as TextBuffer>::from_text("");
+        let mut tree = UndoTree::new();
+        let start = Instant::now();
+        insert(&mut tree, &mut buffer, 0, "a", start);
+        insert(&mut tree, &mut buffer, 1, "b", start + Duration::from_secs(1));
+        let first_tip = tree.current();
+        assert!(tree.undo(&mut buffer).unwrap());
+        insert(&mut tree, &mut buffer, 1, "c", start + Duration::from_secs(2));
+        assert_eq!(buffer.text(), "ac");
+
+        let branches = tree.branches();
+        assert_eq!(branches.len(), 2);
+        assert_eq!((branches[0].tip, branches[0].depth, branches[0].is_current), (first_tip, 2, false));
+        assert!(branches[1].is_current);
+
+        assert!(tree.switch_branch(&mut buffer, first_tip).unwrap());
+        assert_eq!(buffer.text(), "ab");
+        // Redo from the fork follows the branch last entered.
+        tree.undo(&mut buffer).unwrap();
+        tree.redo(&mut buffer).unwrap();
+        assert_eq!(buffer.text(), "ab");
+        assert!(!tree.goto(&mut buffer, 99).unwrap());
+    }
+
+    #[test]
+    fn test_goto_time() {
+        let mut buffer = <Rope as TextBuffer>::from_text("");
+        let mut tree = UndoTree::new();
+        let start = Instant::now();
+        for (idx, word) in ["one ", "two ", "three"].iter().enumerate() {
+            let end = buffer.len_bytes();
+            insert(&mut tree, &mut buffer, end, word, start + Duration::from_secs(60 * idx as u64));
+        }
+        tree.goto_time(&mut buffer, start + Duration::from_secs(90)).unwrap();
+        assert_eq!(buffer.text(), "one two ");
+        tree.goto_time(&mut buffer, start + Duration::from_secs(30)).unwrap();
+        assert_eq!(buffer.text(), "one ");
+        tree.goto_time(&mut buffer, start + Duration::from_secs(600)).unwrap();
+        assert_eq!(buffer.text(), "one two three");
+    }
+
+    #[test]
+    fn test_memory_bound() {
+        let mut buffer = <Rope as TextBuffer>::from_text("");
+        let mut tree = UndoTree::new().with_memory_limit(4096);
+        let start = Instant::now();
+        for idx in 0..200 {
+            let end = buffer.len_bytes();
+            insert(&mut tree, &mut buffer, end, "0123456789", start + Duration::from_millis(idx));
+            if idx % 10 == 0 {
+                tree.undo(&mut buffer).unwrap();
+            }
+            assert!(tree.memory_usage() <= 4096);
+        }
+        let text = buffer.text();
+        let mut undone = 0;
+        while tree.undo(&mut buffer).unwrap() {
+            undone += 1;
+        }
+        assert!(undone > 0 && undone < 180);
+        tree.goto(&mut buffer, tree.branches().last().unwrap().tip).unwrap();
+        assert_eq!(buffer.text(), text);
+    }
+}
@@ -0,0 +1,5 @@
+//! Core module
+//! Reexports the buffer and history modules
+
+pub mod buffer;
+pub mod history;
@@ -0,0 +1,4 @@
+//! KaudoCore --- core text engine of the Kauday code editor.
+
+pub mod core;
+pub mod utils;
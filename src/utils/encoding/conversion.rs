@@ -0,0 +1,329 @@
+/// conversion.rs
+/// Decoders & encoders for the legacy code pages: KOI8-R, Windows-1251, Shift_JIS, GBK & EUC-KR.
+use std::collections::HashMap;
+use std::sync::OnceLock;
+use crate::core::buffer::content::encoding::{Encoding, EncodingError};
+
+/// What the bytes at the start of a slice decode to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DecodeStep {
+    /// A character & the number of bytes it took.
+    Char(char, usize),
+    /// Bytes that don't form a character; decoding resumes right after them.
+    Invalid(usize),
+    /// The slice ends in the middle of a character.
+    Incomplete,
+}
+
+/// A legacy code page. The double-byte ones follow the Windows code pages 932, 936 & 949,
+/// which are what files labelled Shift_JIS, GBK & EUC-KR hold in practice.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CodePage {
+    Koi8R,
+    Windows1251,
+    ShiftJis,
+    Gbk,
+    EucKr,
+}
+
+impl CodePage {
+    pub fn for_encoding(encoding: &Encoding) -> Option<CodePage> {
+        match encoding {
+            Encoding::KOI8R => Some(CodePage::Koi8R),
+            Encoding::WINDOWS1251 => Some(CodePage::Windows1251),
+            Encoding::SHIFTJIS => Some(CodePage::ShiftJis),
+            Encoding::GBK => Some(CodePage::Gbk),
+            Encoding::EUCKR => Some(CodePage::EucKr),
+            _ => None,
+        }
+    }
+
+    pub fn encoding(self) -> Encoding {
+        match self {
+            CodePage::Koi8R => Encoding::KOI8R,
+            CodePage::Windows1251 => Encoding::WINDOWS1251,
+            CodePage::ShiftJis => Encoding::SHIFTJIS,
+            CodePage::Gbk => Encoding::GBK,
+            CodePage::EucKr => Encoding::EUCKR,
+        }
+    }
+
+    /// Decodes the character at the start of `bytes`.
+    pub fn decode_step(self, bytes: &[u8]) -> DecodeStep {
+        let Some(&byte) = bytes.first() else {
+            return DecodeStep::Incomplete;
+        };
+        if byte < 0x80 {
+            return DecodeStep::Char(byte as char, 1);
+        }
+        match self {
+            CodePage::Koi8R => DecodeStep::Char(KOI8_R[byte as usize - 0x80], 1),
+            CodePage::Windows1251 => DecodeStep::Char(WINDOWS_1251[byte as usize - 0x80], 1),
+            CodePage::ShiftJis => match byte {
+                0x80 => DecodeStep::Char('\u{80}', 1),
+                0xA1..=0xDF => DecodeStep::Char(halfwidth_katakana(byte), 1),
+                0x81..=0x9F | 0xE0..=0xFC => SHIFT_JIS.decode_pair(bytes),
+                _ => DecodeStep::Invalid(1),
+            },
+            CodePage::Gbk => match byte {
+                0x80 => DecodeStep::Char('€', 1),
+                0xFF => DecodeStep::Invalid(1),
+                _ => GBK.decode_pair(bytes),
+            },
+            CodePage::EucKr => match byte {
+                0x80 | 0xFF => DecodeStep::Invalid(1),
+                _ => EUC_KR.decode_pair(bytes),
+            },
+        }
+    }
+
+    /// Decodes `bytes`, failing on the first invalid or truncated sequence.
+    pub fn decode(self, bytes: &[u8]) -> Result<String, EncodingError> {
+        let mut text = String::with_capacity(bytes.len());
+        let mut pos = 0;
+        while pos < bytes.len() {
+            match self.decode_step(&bytes[pos..]) {
+                DecodeStep::Char(ch, len) => {
+                    text.push(ch);
+                    pos += len;
+                }
+                DecodeStep::Invalid(_) | DecodeStep::Incomplete => {
+                    return Err(EncodingError::InvalidSequence(format!(
+                        "invalid {} sequence at byte {}",
+                        self.encoding().name(),
+                        pos
+                    )));
+                }
+            }
+        }
+        Ok(text)
+    }
+
+    /// Decodes `bytes`, replacing each invalid or truncated sequence with U+FFFD. Also returns
+    /// how many were replaced.
+    pub fn decode_lossy(self, bytes: &[u8]) -> (String, usize) {
+        let mut text = String::with_capacity(bytes.len());
+        let mut replaced = 0;
+        let mut pos = 0;
+        while pos < bytes.len() {
+            let len = match self.decode_step(&bytes[pos..]) {
+                DecodeStep::Char(ch, len) => {
+                    text.push(ch);
+                    len
+                }
+                DecodeStep::Invalid(len) => {
+                    text.push(char::REPLACEMENT_CHARACTER);
+                    replaced += 1;
+                    len
+                }
+                DecodeStep::Incomplete => {
+                    text.push(char::REPLACEMENT_CHARACTER);
+                    replaced += 1;
+                    bytes.len() - pos
+                }
+            };
+            pos += len;
+        }
+        (text, replaced)
+    }
+
+    /// Appends the bytes of `ch` to `out`, returning false when the code page has no such
+    /// character.
+    pub fn encode_char(self, ch: char, out: &mut Vec<u8>) -> bool {
+        if ch.is_ascii() {
+            out.push(ch as u8);
+            return true;
+        }
+        let single = match self {
+            CodePage::Koi8R => single_byte(&KOI8_R, ch),
+            CodePage::Windows1251 => single_byte(&WINDOWS_1251, ch),
+            CodePage::ShiftJis => match ch {
+                '\u{80}' => Some(0x80),
+                '\u{FF61}'..='\u{FF9F}' => Some((ch as u32 - 0xFF61 + 0xA1) as u8),
+                _ => None,
+            },
+            CodePage::Gbk => (ch == '€').then_some(0x80),
+            CodePage::EucKr => None,
+        };
+        if let Some(byte) = single {
+            out.push(byte);
+            return true;
+        }
+        let pair = match self {
+            CodePage::ShiftJis => SHIFT_JIS.encode(ch),
+            CodePage::Gbk => GBK.encode(ch),
+            CodePage::EucKr => EUC_KR.encode(ch),
+            CodePage::Koi8R | CodePage::Windows1251 => None,
+        };
+        match pair {
+            Some(pair) => {
+                out.extend_from_slice(&pair);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Encodes `text`, failing on the first character the code page can't represent.
+    pub fn encode(self, text: &str) -> Result<Vec<u8>, EncodingError> {
+        let mut bytes = Vec::with_capacity(text.len());
+        for ch in text.chars() {
+            if !self.encode_char(ch, &mut bytes) {
+                return Err(EncodingError::ConversionFailed(format!(
+                    "Character '{}' cannot be represented in {}",
+                    ch,
+                    self.encoding().name()
+                )));
+            }
+        }
+        Ok(bytes)
+    }
+}
+
+fn halfwidth_katakana(byte: u8) -> char {
+    char::from_u32(0xFF61 + (byte - 0xA1) as u32).unwrap_or(char::REPLACEMENT_CHARACTER)
+}
+
+fn single_byte(table: &[char; 128], ch: char) -> Option<u8> {
+    table.iter().position(|&c| c == ch).map(|idx| (idx + 0x80) as u8)
+}
+
+/// A two-byte code page stored as a dense grid of little-endian u16 code points, generated by
+/// `tables/generate.py`. 0 marks an unmapped pair.
+struct DoubleByteTable {
+    data: &'static [u8],
+    first_lead: u8,
+    last_lead: u8,
+    first_trail: u8,
+    last_trail: u8,
+    /// Built on the first encode.
+    reverse: OnceLock<HashMap<char, [u8; 2]>>,
+}
+
+impl DoubleByteTable {
+    const fn new(data: &'static [u8], leads: (u8, u8), trails: (u8, u8)) -> Self {
+        Self {
+            data,
+            first_lead: leads.0,
+            last_lead: leads.1,
+            first_trail: trails.0,
+            last_trail: trails.1,
+            reverse: OnceLock::new(),
+        }
+    }
+
+    fn trail_count(&self) -> usize {
+        (self.last_trail - self.first_trail) as usize + 1
+    }
+
+    fn lookup(&self, lead: u8, trail: u8) -> Option<char> {
+        if !(self.first_lead..=self.last_lead).contains(&lead) || !(self.first_trail..=self.last_trail).contains(&trail) {
+            return None;
+        }
+        let idx = ((lead - self.first_lead) as usize * self.trail_count() + (trail - self.first_trail) as usize) * 2;
+        match u16::from_le_bytes([self.data[idx], self.data[idx + 1]]) {
+            0 => None,
+            code => char::from_u32(code as u32),
+        }
+    }
+
+    fn decode_pair(&self, bytes: &[u8]) -> DecodeStep {
+        let Some(&trail) = bytes.get(1) else {
+            return DecodeStep::Incomplete;
+        };
+        match self.lookup(bytes[0], trail) {
+            Some(ch) => DecodeStep::Char(ch, 2),
+            // An ASCII trail byte starts the next character rather than being swallowed.
+            None if trail < 0x80 => DecodeStep::Invalid(1),
+            None => DecodeStep::Invalid(2),
+        }
+    }
+
+    fn encode(&self, ch: char) -> Option<[u8; 2]> {
+        let reverse = self.reverse.get_or_init(|| {
+            let mut reverse = HashMap::new();
+            for lead in self.first_lead..=self.last_lead {
+                for trail in self.first_trail..=self.last_trail {
+                    if let Some(ch) = self.lookup(lead, trail) {
+                        // Some characters have several codes; the first one is canonical.
+                        reverse.entry(ch).or_insert([lead, trail]);
+                    }
+                }
+            }
+            reverse
+        });
+        reverse.get(&ch).copied()
+    }
+}
+
+static SHIFT_JIS: DoubleByteTable = DoubleByteTable::new(include_bytes!("tables/shift_jis.bin"), (0x81, 0xFC), (0x40, 0xFC));
+static GBK: DoubleByteTable = DoubleByteTable::new(include_bytes!("tables/gbk.bin"), (0x81, 0xFE), (0x40, 0xFE));
+static EUC_KR: DoubleByteTable = DoubleByteTable::new(include_bytes!("tables/euc_kr.bin"), (0x81, 0xFE), (0x41, 0xFE));
+
+/// Bytes 0x80..=0xFF.
+static KOI8_R: [char; 128] = [
+    '─', '│', '┌', '┐', '└', '┘', '├', '┤', '┬', '┴', '┼', '▀', '▄', '█', '▌', '▐',
+    '░', '▒', '▓', '⌠', '■', '∙', '√', '≈', '≤', '≥', '\u{A0}', '⌡', '°', '²', '·', '÷',
+    '═', '║', '╒', 'ё', '╓', '╔', '╕', '╖', '╗', '╘', '╙', '╚', '╛', '╜', '╝', '╞',
+    '╟', '╠', '╡', 'Ё', '╢', '╣', '╤', '╥', '╦', '╧', '╨', '╩', '╪', '╫', '╬', '©',
+    'ю', 'а', 'б', 'ц', 'д', 'е', 'ф', 'г', 'х', 'и', 'й', 'к', 'л', 'м', 'н', 'о',
+    'п', 'я', 'р', 'с', 'т', 'у', 'ж', 'в', 'ь', 'ы', 'з', 'ш', 'э', 'щ', 'ч', 'ъ',
+    'Ю', 'А', 'Б', 'Ц', 'Д', 'Е', 'Ф', 'Г', 'Х', 'И', 'Й', 'К', 'Л', 'М', 'Н', 'О',
+    'П', 'Я', 'Р', 'С', 'Т', 'У', 'Ж', 'В', 'Ь', 'Ы', 'З', 'Ш', 'Э', 'Щ', 'Ч', 'Ъ',
+];
+
+/// Bytes 0x80..=0xFF. 0x98 is undefined & maps to U+0098, like Windows does.
+static WINDOWS_1251: [char; 128] = [
+    'Ђ', 'Ѓ', '‚', 'ѓ', '„', '…', '†', '‡', '€', '‰', 'Љ', '‹', 'Њ', 'Ќ', 'Ћ', 'Џ',
+    'ђ', '‘', '’', '“', '”', '•', '–', '—', '\u{98}', '™', 'љ', '›', 'њ', 'ќ', 'ћ', 'џ',
+    '\u{A0}', 'Ў', 'ў', 'Ј', '¤', 'Ґ', '¦', '§', 'Ё', '©', 'Є', '«', '¬', '\u{AD}', '®', 'Ї',
+    '°', '±', 'І', 'і', 'ґ', 'µ', '¶', '·', 'ё', '№', 'є', '»', 'ј', 'Ѕ', 'ѕ', 'ї',
+    'А', 'Б', 'В', 'Г', 'Д', 'Е', 'Ж', 'З', 'И', 'Й', 'К', 'Л', 'М', 'Н', 'О', 'П',
+    'Р', 'С', 'Т', 'У', 'Ф', 'Х', 'Ц', 'Ч', 'Ш', 'Щ', 'Ъ', 'Ы', 'Ь', 'Э', 'Ю', 'Я',
+    'а', 'б', 'в', 'г', 'д', 'е', 'ж', 'з', 'и', 'й', 'к', 'л', 'м', 'н', 'о', 'п',
+    'р', 'с', 'т', 'у', 'ф', 'х', 'ц', 'ч', 'ш', 'щ', 'ъ', 'ы', 'ь', 'э', 'ю', 'я',
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_known_codes() {
+        assert_eq!(CodePage::ShiftJis.decode(&[0x82, 0xA0, 0x93, 0xFA, 0xB1]).unwrap(), "あ日ｱ");
+        assert_eq!(CodePage::Gbk.decode(&[0xD6, 0xD0, 0xCE, 0xC4, 0x80]).unwrap(), "中文€");
+        assert_eq!(CodePage::EucKr.decode(&[0xC7, 0xD1, 0xB1, 0xDB]).unwrap(), "한글");
+        assert_eq!(CodePage::Koi8R.decode(&[0xD2, 0xD5, 0xD3]).unwrap(), "рус");
+        assert_eq!(CodePage::Windows1251.decode(&[0xF0, 0xF3, 0xF1]).unwrap(), "рус");
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let samples = [
+            (CodePage::ShiftJis, "日本語のテキスト、ｶﾀｶﾅ & ASCII"),
+            (CodePage::Gbk, "简体中文和繁體字 €"),
+            (CodePage::EucKr, "한국어 텍스트와 漢字"),
+            (CodePage::Koi8R, "Съешь же ещё этих мягких французских булок"),
+            (CodePage::Windows1251, "Ґанок, їжак & «лапки» №1"),
+        ];
+        for (page, text) in samples {
+            let bytes = page.encode(text).unwrap();
+            assert_eq!(page.decode(&bytes).unwrap(), text, "{:?}", page);
+        }
+        assert!(CodePage::Koi8R.encode("日本").is_err());
+        assert!(CodePage::EucKr.encode("ｱ").is_err());
+    }
+
+    #[test]
+    fn test_invalid_sequences() {
+        // The ASCII byte after a bad lead is kept.
+        assert_eq!(CodePage::ShiftJis.decode_step(&[0x81, 0x20]), DecodeStep::Invalid(1));
+        assert_eq!(CodePage::ShiftJis.decode_step(&[0x81]), DecodeStep::Incomplete);
+        assert_eq!(CodePage::EucKr.decode_step(&[0xFF, 0xA1]), DecodeStep::Invalid(1));
+        assert!(CodePage::Gbk.decode(&[0xD6]).is_err());
+
+        let (text, replaced) = CodePage::ShiftJis.decode_lossy(&[b'a', 0xA0, b'b', 0x82]);
+        assert_eq!(text, "a\u{FFFD}b\u{FFFD}");
+        assert_eq!(replaced, 2);
+    }
+}
@@ -0,0 +1,415 @@
+/// detection.rs
+/// Defines `EncodingDetector`, which guesses the encoding of raw bytes & ranks every plausible
+/// encoding by confidence.
+use crate::core::buffer::content::encoding::{Encoding, EncodingHandler};
+use crate::utils::encoding::conversion::{CodePage, DecodeStep};
+
+/// Confidence of the best legacy code page guess; the heuristics below can be fooled, unlike a
+/// BOM or valid UTF-8.
+const LEGACY_CEILING: f32 = 0.85;
+
+/// The most frequent hanzi, covering about half of running Chinese text.
+const COMMON_HANZI: &str = "的一是不了在人有我他这个们中来上大为和国地到以说时要就出会可也你对生能而子那得于着下自之年过发后作里用道行所然家种事成方多经么去法学如都同现当没动面起看定天分还进好小部其些主样理心她本前开但因只从想实日军者意无力它与长把机十民第公此已工使情明性知全三又关点正业外将两高间由问很最重并物手应战向头文体政美相见被利什二等产或新己制身果加西斯月话合回特代内信表化老给世位次度门任常先海通教儿原东声提立及比员解水名真论处走义各入几口认条平系气题活尔更别打女变四神总何电数安少报才结反受目太量再感建务做接必场件计管期市直德资命山金指克许统区保至队形社便空决治展马科司五基眼书非则听白却界达光放强即像难且权思王象完设式色路记南品住告类求据程北边死张该交规万取拉格望觉术领共确传师观清今切院让识候带导争运";
+/// The most frequent hangul syllables.
+const COMMON_HANGUL: &str = "이의다는에가하고을를지기서로한사리자도대인어시수있되것들일보아나게해정그전으만부과요제우주장여라소상면구동원무계없성와내까않말경같은니습";
+/// The most frequent Russian letters, about two thirds of the letters of running text.
+const COMMON_CYRILLIC: &str = "оеаинтсрвлкмдпуяыь";
+/// Non-ASCII chars frequent in Western European text.
+const COMMON_WESTERN: &str = "éèàùâêîôûçëïüäößñáíóúãõåøæœÉÀÇÖÜÄ’‘“”–—…€«»°©\u{A0}";
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct EncodingCandidate {
+    pub encoding: Encoding,
+    /// From 0, ruled out, to 1, certain.
+    pub confidence: f32,
+    pub has_bom: bool,
+}
+
+impl EncodingCandidate {
+    fn new(encoding: Encoding, confidence: f32) -> Self {
+        Self { encoding, confidence, has_bom: false }
+    }
+}
+
+/// Guesses encodings from a sample at the start of the bytes.
+///
+/// A BOM settles it. Otherwise UTF-16 shows as NULs in every other byte of mostly-ASCII text,
+/// and valid UTF-8 with non-ASCII chars is hardly ever an accident. Legacy code pages decode
+/// most bytes, so each one is scored on how much its decoding looks like its language: kana
+/// for Shift_JIS, frequent hanzi for GBK, frequent hangul for EUC-KR, runs of lowercase
+/// Cyrillic letters for KOI8-R & Windows-1251, and lone accented letters for Windows-1252.
+#[derive(Debug, Clone, Copy)]
+pub struct EncodingDetector {
+    sample_size: usize,
+}
+
+impl EncodingDetector {
+    pub fn new() -> Self {
+        Self { sample_size: 64 * 1024 }
+    }
+
+    /// Only looks at the first `sample_size` bytes.
+    pub fn with_sample_size(mut self, sample_size: usize) -> Self {
+        self.sample_size = sample_size.max(4);
+        self
+    }
+
+    pub fn sample_size(&self) -> usize {
+        self.sample_size
+    }
+
+    /// Ranks the candidate encodings of `bytes`, most likely first. Never empty since Latin-1
+    /// decodes anything.
+    pub fn detect(&self, bytes: &[u8]) -> Vec<EncodingCandidate> {
+        if let Some(encoding) = bom_encoding(bytes) {
+            return vec![EncodingCandidate { encoding, confidence: 1.0, has_bom: true }];
+        }
+        let truncated = bytes.len() > self.sample_size;
+        let sample = &bytes[..bytes.len().min(self.sample_size)];
+        let nuls = sample.iter().filter(|&&byte| byte == 0).count();
+        if nuls == 0 && sample.is_ascii() {
+            return vec![EncodingCandidate::new(Encoding::UTF8, 1.0), EncodingCandidate::new(Encoding::ASCII, 0.95)];
+        }
+
+        let mut candidates = Vec::new();
+        candidates.extend(utf16_candidate(sample));
+        // Text in a single-byte or UTF-8 file hardly ever contains NULs.
+        let nul_factor = 1.0 - (nuls as f32 * 4.0 / sample.len() as f32).min(1.0);
+        let (multibyte, errors) = utf8_tally(sample, truncated);
+        // A few stray bytes in otherwise valid UTF-8 still make it UTF-8, which then decodes
+        // losslessly.
+        let validity = 1.0 - 4.0 * errors as f32 / (multibyte + errors).max(1) as f32;
+        if validity > 0.0 {
+            let confidence = if multibyte == 0 {
+                1.0
+            } else {
+                0.9 + 0.1 * (1.0 - 0.5f32.powi(multibyte.min(16) as i32))
+            };
+            candidates.push(EncodingCandidate::new(Encoding::UTF8, confidence * validity * nul_factor));
+            if multibyte == 0 {
+                candidates.push(EncodingCandidate::new(Encoding::ASCII, 0.95 * nul_factor));
+            }
+        }
+        for page in [CodePage::ShiftJis, CodePage::Gbk, CodePage::EucKr, CodePage::Koi8R, CodePage::Windows1251] {
+            let score = tally_code_page(page, sample, truncated).score(Profile::of(page));
+            candidates.push(EncodingCandidate::new(page.encoding(), LEGACY_CEILING * score * nul_factor));
+        }
+        let western = tally_western(sample);
+        let score = western.score(Profile::Western);
+        candidates.push(EncodingCandidate::new(Encoding::WINDOWS1252, LEGACY_CEILING * score * nul_factor));
+        // Latin-1 reads 0x80..=0x9F as control chars where Windows-1252 has punctuation.
+        let controls = sample.iter().filter(|byte| (0x80..=0x9F).contains(*byte)).count();
+        let control_factor = 1.0 - controls as f32 / western.non_ascii.max(1) as f32;
+        let latin1 = LEGACY_CEILING * score * nul_factor * 0.9 * control_factor;
+        candidates.push(EncodingCandidate::new(Encoding::LATIN1, latin1.max(0.01)));
+
+        candidates.retain(|candidate| candidate.confidence > 0.0);
+        candidates.sort_by(|a, b| b.confidence.total_cmp(&a.confidence));
+        candidates
+    }
+
+    /// The most likely encoding of `bytes`.
+    pub fn best(&self, bytes: &[u8]) -> EncodingCandidate {
+        self.detect(bytes).swap_remove(0)
+    }
+}
+
+impl Default for EncodingDetector {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Shorthand for `EncodingDetector::default().detect(bytes)`.
+pub fn detect(bytes: &[u8]) -> Vec<EncodingCandidate> {
+    EncodingDetector::default().detect(bytes)
+}
+
+fn bom_encoding(bytes: &[u8]) -> Option<Encoding> {
+    if bytes.starts_with(&[0xEF, 0xBB, 0xBF]) {
+        Some(Encoding::UTF8)
+    } else if bytes.starts_with(&[0xFF, 0xFE]) {
+        Some(Encoding::UTF16LE)
+    } else if bytes.starts_with(&[0xFE, 0xFF]) {
+        Some(Encoding::UTF16BE)
+    } else {
+        None
+    }
+}
+
+/// Mostly-ASCII UTF-16 has a NUL in the high byte of nearly every code unit, i.e. at odd
+/// offsets for little endian & even ones for big endian, and almost none in the low bytes.
+fn utf16_candidate(sample: &[u8]) -> Option<EncodingCandidate> {
+    let units = sample.len() / 2;
+    if units == 0 {
+        return None;
+    }
+    let (mut even, mut odd) = (0, 0);
+    for pair in sample.chunks_exact(2) {
+        even += (pair[0] == 0) as usize;
+        odd += (pair[1] == 0) as usize;
+    }
+    let (encoding, high, low) = if odd >= even { (Encoding::UTF16LE, odd, even) } else { (Encoding::UTF16BE, even, odd) };
+    let ratio = (high as f32 - 4.0 * low as f32) / units as f32;
+    if ratio < 0.2 {
+        return None;
+    }
+    let code_units = sample.chunks_exact(2).map(|pair| match encoding {
+        Encoding::UTF16LE => u16::from_le_bytes([pair[0], pair[1]]),
+        _ => u16::from_be_bytes([pair[0], pair[1]]),
+    });
+    // The sample may end in the middle of a surrogate pair.
+    if char::decode_utf16(code_units).filter(Result::is_err).count() > 1 {
+        return None;
+    }
+    Some(EncodingCandidate::new(encoding, (0.5 + 0.5 * ratio).min(0.99)))
+}
+
+/// Counts the multibyte chars & the invalid sequences of the sample read as UTF-8.
+fn utf8_tally(sample: &[u8], truncated: bool) -> (usize, usize) {
+    if let Ok(text) = std::str::from_utf8(sample) {
+        return (text.chars().filter(|ch| !ch.is_ascii()).count(), 0);
+    }
+    let (mut multibyte, mut errors, mut pos) = (0, 0, 0);
+    while pos < sample.len() {
+        match Encoding::UTF8.decode_step(&sample[pos..]) {
+            DecodeStep::Char(_, len) => {
+                multibyte += (len > 1) as usize;
+                pos += len;
+            }
+            DecodeStep::Invalid(len) => {
+                errors += 1;
+                pos += len;
+            }
+            // The sample may cut the last char short.
+            DecodeStep::Incomplete => {
+                errors += !truncated as usize;
+                break;
+            }
+        }
+    }
+    (multibyte, errors)
+}
+
+/// The language a code page is scored against.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Profile {
+    Japanese,
+    Chinese,
+    Korean,
+    Cyrillic,
+    Western,
+}
+
+impl Profile {
+    fn of(page: CodePage) -> Profile {
+        match page {
+            CodePage::ShiftJis => Profile::Japanese,
+            CodePage::Gbk => Profile::Chinese,
+            CodePage::EucKr => Profile::Korean,
+            CodePage::Koi8R | CodePage::Windows1251 => Profile::Cyrillic,
+        }
+    }
+}
+
+/// Counts over the non-ASCII chars of a decoded sample.
+#[derive(Debug, Default)]
+struct Tally {
+    non_ascii: usize,
+    errors: usize,
+    letters: usize,
+    /// Letters right after another non-ASCII letter.
+    runs: usize,
+    lowercase: usize,
+    /// Chars of the profile's script: kana, GB2312 hanzi, KS X 1001 hangul or Cyrillic.
+    script: usize,
+    /// Chars among the profile's most frequent ones.
+    common: usize,
+    /// Halfwidth katakana, which Chinese & Korean bytes turn into in Shift_JIS.
+    halfwidth: usize,
+    /// Kanji, which can be Japanese but also any double-byte text read as Shift_JIS.
+    ideographs: usize,
+}
+
+impl Tally {
+    fn add(&mut self, profile: Profile, ch: char, bytes: &[u8], after_letter: bool) {
+        self.non_ascii += 1;
+        if ch.is_alphabetic() {
+            self.letters += 1;
+            self.runs += after_letter as usize;
+            self.lowercase += ch.is_lowercase() as usize;
+        }
+        let (lead, trail) = (bytes[0], bytes.get(1).copied().unwrap_or(0));
+        match profile {
+            Profile::Japanese => {
+                self.script += matches!(ch, '\u{3040}'..='\u{30FF}') as usize;
+                self.ideographs += matches!(ch, '\u{4E00}'..='\u{9FFF}') as usize;
+                self.halfwidth += matches!(ch, '\u{FF61}'..='\u{FF9F}') as usize;
+            }
+            Profile::Chinese => {
+                self.script += ((0xB0..=0xF7).contains(&lead) && trail >= 0xA1) as usize;
+                self.common += COMMON_HANZI.contains(ch) as usize;
+            }
+            Profile::Korean => {
+                self.script += ((0xB0..=0xC8).contains(&lead) && trail >= 0xA1) as usize;
+                self.common += COMMON_HANGUL.contains(ch) as usize;
+            }
+            Profile::Cyrillic => {
+                self.script += matches!(ch, '\u{400}'..='\u{4FF}') as usize;
+                self.common += COMMON_CYRILLIC.contains(ch) as usize;
+            }
+            Profile::Western => {
+                self.common += COMMON_WESTERN.contains(ch) as usize;
+            }
+        }
+    }
+
+    /// How much the decoded sample looks like the profile's language, from 0 to 1.
+    fn score(&self, profile: Profile) -> f32 {
+        let total = self.non_ascii + self.errors;
+        if total == 0 {
+            return 0.0;
+        }
+        let validity = (1.0 - 10.0 * self.errors as f32 / total as f32).max(0.0);
+        let n = self.non_ascii.max(1) as f32;
+        let ratio = |count: usize, of: usize| if of == 0 { 0.0 } else { count as f32 / of as f32 };
+        let language = match profile {
+            Profile::Japanese => {
+                let kana = ((self.script as f32 + 0.3 * self.ideographs as f32) / n * 2.0).min(1.0);
+                kana * (1.0 - self.halfwidth as f32 / n)
+            }
+            Profile::Chinese | Profile::Korean => {
+                0.5 * self.script as f32 / n + 0.5 * (self.common as f32 / n / 0.25).min(1.0)
+            }
+            Profile::Cyrillic => {
+                let common = (ratio(self.common, self.script) / 0.6).min(1.0);
+                let runs = (ratio(self.runs, self.letters) / 0.6).min(1.0);
+                self.script as f32 / n * common * ratio(self.lowercase, self.script) * runs
+            }
+            Profile::Western => self.common as f32 / n * (1.0 - ratio(self.runs, self.letters)),
+        };
+        validity * language
+    }
+}
+
+fn tally_code_page(page: CodePage, sample: &[u8], truncated: bool) -> Tally {
+    let profile = Profile::of(page);
+    let mut tally = Tally::default();
+    let mut after_letter = false;
+    let mut pos = 0;
+    while pos < sample.len() {
+        match page.decode_step(&sample[pos..]) {
+            DecodeStep::Char(ch, len) => {
+                if !ch.is_ascii() {
+                    tally.add(profile, ch, &sample[pos..pos + len], after_letter);
+                }
+                after_letter = !ch.is_ascii() && ch.is_alphabetic();
+                pos += len;
+            }
+            DecodeStep::Invalid(len) => {
+                tally.errors += 1;
+                after_letter = false;
+                pos += len;
+            }
+            DecodeStep::Incomplete => {
+                tally.errors += !truncated as usize;
+                break;
+            }
+        }
+    }
+    tally
+}
+
+fn tally_western(sample: &[u8]) -> Tally {
+    let text = EncodingHandler::new(Encoding::WINDOWS1252).decode(sample).unwrap_or_default();
+    let mut tally = Tally::default();
+    let mut after_letter = false;
+    let mut buf = [0; 4];
+    for ch in text.chars() {
+        if !ch.is_ascii() {
+            tally.add(Profile::Western, ch, ch.encode_utf8(&mut buf).as_bytes(), after_letter);
+        }
+        after_letter = !ch.is_ascii() && ch.is_alphabetic();
+    }
+    tally
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn best(page: CodePage, text: &str) -> EncodingCandidate {
+        EncodingDetector::new().best(&page.encode(text).unwrap())
+    }
+
+    #[test]
+    fn test_bom_and_unicode() {
+        let candidates = detect(&[0xFF, 0xFE, b'h', 0]);
+        assert_eq!(candidates, vec![EncodingCandidate { encoding: Encoding::UTF16LE, confidence: 1.0, has_bom: true }]);
+
+        let ascii = detect(b"plain ascii");
+        assert_eq!(ascii[0].encoding, Encoding::UTF8);
+        assert_eq!(ascii[1].encoding, Encoding::ASCII);
+
+        let utf8 = detect("Grüße aus Köln, 世界".as_bytes());
+        assert_eq!(utf8[0].encoding, Encoding::UTF8);
+        assert!(utf8[0].confidence > 0.9);
+    }
+
+    #[test]
+    fn test_utf16_without_bom() {
+        let text = "fn main() { println!(\"héllo\"); }";
+        let le: Vec<u8> = text.encode_utf16().flat_map(u16::to_le_bytes).collect();
+        let be: Vec<u8> = text.encode_utf16().flat_map(u16::to_be_bytes).collect();
+        let best_le = EncodingDetector::new().best(&le);
+        assert_eq!(best_le.encoding, Encoding::UTF16LE);
+        assert!(!best_le.has_bom);
+        assert_eq!(EncodingDetector::new().best(&be).encoding, Encoding::UTF16BE);
+    }
+
+    #[test]
+    fn test_legacy_code_pages() {
+        let samples = [
+            (CodePage::ShiftJis, "日本語のテキストをシフトJISで保存しました。これは文字コードの自動判定のテストです。"),
+            (CodePage::Gbk, "这是一个用于测试中文编码自动检测的例子，我们希望能够正确地识别出国标码。"),
+            (CodePage::EucKr, "이것은 한국어 인코딩 자동 감지를 위한 테스트입니다. 우리는 올바른 결과를 기대합니다."),
+            (CodePage::Koi8R, "Это пример текста на русском языке, который нужно правильно определить."),
+            (CodePage::Windows1251, "Это пример текста на русском языке, который нужно правильно определить."),
+        ];
+        for (page, text) in samples {
+            let candidate = best(page, text);
+            assert_eq!(candidate.encoding, page.encoding(), "{}", text);
+            assert!(candidate.confidence > 0.5 && candidate.confidence <= LEGACY_CEILING);
+        }
+    }
+
+    #[test]
+    fn test_western_and_fallback() {
+        let french = EncodingHandler::new(Encoding::WINDOWS1252)
+            .encode("Le café était très agréable, même si la journée a été longue…")
+            .unwrap();
+        let candidates = detect(&french);
+        assert_eq!(candidates[0].encoding, Encoding::WINDOWS1252);
+        let latin1 = candidates.iter().position(|c| c.encoding == Encoding::LATIN1).unwrap();
+        assert!(latin1 > 0);
+
+        // Binary garbage still gets an answer.
+        assert_eq!(EncodingDetector::new().best(&[0x81, 0x8D, 0x8F, 0x90, 0x9D]).encoding, Encoding::LATIN1);
+    }
+
+    #[test]
+    fn test_utf8_with_stray_byte() {
+        let mut bytes = "ligne é\n".repeat(50).into_bytes();
+        bytes.insert(20, 0xFF);
+        let best = EncodingDetector::new().best(&bytes);
+        assert_eq!(best.encoding, Encoding::UTF8);
+        assert!(best.confidence > LEGACY_CEILING);
+    }
+
+    #[test]
+    fn test_sample_cuts_utf8_char() {
+        let text = "aé".repeat(10);
+        let detector = EncodingDetector::new().with_sample_size(8);
+        assert_eq!(detector.best(text.as_bytes()).encoding, Encoding::UTF8);
+    }
+}
@@ -0,0 +1,10 @@
+//! Encoding module
+//! Reexports conversion, detection, and streaming modules
+
+pub mod conversion;
+pub mod detection;
+pub mod streaming;
+
+pub use conversion::{CodePage, DecodeStep};
+pub use detection::{EncodingCandidate, EncodingDetector};
+pub use streaming::IncrementalDecoder;
@@ -0,0 +1,227 @@
+/// streaming.rs
+/// Defines `IncrementalDecoder`, which decodes bytes as they arrive in reads of any size,
+/// carrying a sequence cut by the end of a read over to the next one.
+use std::borrow::Cow;
+use crate::core::buffer::content::encoding::{Encoding, EncodingError};
+use crate::core::buffer::content::lossless::{raw_byte, raw_byte_marker};
+use crate::utils::encoding::conversion::DecodeStep;
+
+/// Decodes a stream of any `Encoding` one read at a time.
+///
+/// The BOM of the encoding is skipped at the start of the stream. A sequence cut by the end of
+/// a read is kept, at most 3 bytes, & completed by the next read; `finish` tells the decoder no
+/// more bytes follow. In lossless mode undecodable bytes become raw-byte markers, otherwise the
+/// first one fails the read & the rest of that read is dropped.
+#[derive(Debug, Clone)]
+pub struct IncrementalDecoder {
+    encoding: Encoding,
+    lossless: bool,
+    /// Start of the sequence cut by the end of the last read.
+    pending: Vec<u8>,
+    /// Bytes taken in so far.
+    offset: usize,
+    /// Whether a BOM may still come.
+    at_start: bool,
+}
+
+impl IncrementalDecoder {
+    pub fn new(encoding: Encoding) -> Self {
+        Self { encoding, lossless: false, pending: Vec::new(), offset: 0, at_start: true }
+    }
+
+    pub fn with_lossless(mut self, lossless: bool) -> Self {
+        self.lossless = lossless;
+        self
+    }
+
+    pub fn encoding(&self) -> &Encoding {
+        &self.encoding
+    }
+
+    pub fn is_lossless(&self) -> bool {
+        self.lossless
+    }
+
+    /// Bytes of a cut sequence waiting for the next read.
+    pub fn pending(&self) -> &[u8] {
+        &self.pending
+    }
+
+    /// Bytes taken in so far, pending ones included.
+    pub fn bytes_read(&self) -> usize {
+        self.offset
+    }
+
+    /// Starts over on a new stream.
+    pub fn reset(&mut self) {
+        self.pending.clear();
+        self.offset = 0;
+        self.at_start = true;
+    }
+
+    /// Decodes `bytes`, the next read of the stream, appending the text to `out`.
+    pub fn decode(&mut self, bytes: &[u8], out: &mut String) -> Result<(), EncodingError> {
+        let data = if self.pending.is_empty() {
+            Cow::Borrowed(bytes)
+        } else {
+            let mut data = std::mem::take(&mut self.pending);
+            data.extend_from_slice(bytes);
+            Cow::Owned(data)
+        };
+        let start = self.offset + bytes.len() - data.len();
+        self.offset += bytes.len();
+        self.run(&data, start, false, out)
+    }
+
+    /// Ends the stream, decoding what is pending; a sequence still cut short is invalid.
+    pub fn finish(&mut self, out: &mut String) -> Result<(), EncodingError> {
+        let data = std::mem::take(&mut self.pending);
+        let start = self.offset - data.len();
+        // Whatever looked like the start of a BOM is plain text after all.
+        self.at_start = false;
+        let result = self.run(&data, start, true, out);
+        self.reset();
+        result
+    }
+
+    fn run(&mut self, data: &[u8], start: usize, last: bool, out: &mut String) -> Result<(), EncodingError> {
+        let mut pos = 0;
+        if self.at_start {
+            let bom = self.encoding.bom();
+            if data.len() < bom.len() && bom.starts_with(data) {
+                self.pending = data.to_vec();
+                return Ok(());
+            }
+            if data.starts_with(bom) {
+                pos = bom.len();
+            }
+            self.at_start = false;
+        }
+        while pos < data.len() {
+            if self.encoding == Encoding::UTF8 {
+                // Valid UTF-8 is copied as is.
+                let valid = match std::str::from_utf8(&data[pos..]) {
+                    Ok(text) => text,
+                    Err(error) => std::str::from_utf8(&data[pos..pos + error.valid_up_to()]).unwrap_or_default(),
+                };
+                self.push_text(valid, out);
+                pos += valid.len();
+                if pos == data.len() {
+                    break;
+                }
+            }
+            let len = match self.encoding.decode_step(&data[pos..]) {
+                DecodeStep::Char(ch, len) if self.lossless && raw_byte(ch).is_some() => {
+                    out.extend(data[pos..pos + len].iter().map(|&byte| raw_byte_marker(byte)));
+                    len
+                }
+                DecodeStep::Char(ch, len) => {
+                    out.push(ch);
+                    len
+                }
+                DecodeStep::Incomplete if !last => {
+                    self.pending = data[pos..].to_vec();
+                    break;
+                }
+                DecodeStep::Invalid(_) | DecodeStep::Incomplete if !self.lossless => {
+                    return Err(EncodingError::InvalidSequence(format!(
+                        "invalid {} sequence at byte {}",
+                        self.encoding.name(),
+                        start + pos
+                    )));
+                }
+                DecodeStep::Invalid(len) => {
+                    out.extend(data[pos..pos + len].iter().map(|&byte| raw_byte_marker(byte)));
+                    len
+                }
+                DecodeStep::Incomplete => {
+                    out.extend(data[pos..].iter().map(|&byte| raw_byte_marker(byte)));
+                    data.len() - pos
+                }
+            };
+            pos += len;
+        }
+        Ok(())
+    }
+
+    /// Appends valid UTF-8, keeping genuine marker chars as raw bytes in lossless mode like
+    /// `EncodingHandler::decode_lossless` does.
+    fn push_text(&self, text: &str, out: &mut String) {
+        // Markers all start with 0xF4 in UTF-8.
+        if !self.lossless || !text.as_bytes().contains(&0xF4) {
+            out.push_str(text);
+            return;
+        }
+        for ch in text.chars() {
+            match raw_byte(ch) {
+                Some(_) => {
+                    let mut buf = [0; 4];
+                    out.extend(ch.encode_utf8(&mut buf).bytes().map(raw_byte_marker));
+                }
+                None => out.push(ch),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::buffer::content::encoding::EncodingHandler;
+
+    /// Feeds `bytes` in reads of `size` bytes.
+    fn decode_in_reads(decoder: &mut IncrementalDecoder, bytes: &[u8], size: usize) -> Result<String, EncodingError> {
+        let mut out = String::new();
+        for read in bytes.chunks(size) {
+            decoder.decode(read, &mut out)?;
+        }
+        decoder.finish(&mut out)?;
+        Ok(out)
+    }
+
+    #[test]
+    fn test_split_sequences_every_encoding() {
+        let text = "Grüße, мир & 世界 ｱ €";
+        for encoding in Encoding::all() {
+            let Ok(bytes) = EncodingHandler::new(encoding.clone()).with_bom().encode(text) else {
+                continue;
+            };
+            for size in 1..=5 {
+                let mut decoder = IncrementalDecoder::new(encoding.clone());
+                assert_eq!(decode_in_reads(&mut decoder, &bytes, size).unwrap(), text, "{} in reads of {}", encoding, size);
+            }
+        }
+    }
+
+    #[test]
+    fn test_pending_and_errors() {
+        let mut decoder = IncrementalDecoder::new(Encoding::UTF8);
+        let mut out = String::new();
+        decoder.decode(&[b'a', 0xE4, 0xB8], &mut out).unwrap();
+        assert_eq!(out, "a");
+        assert_eq!(decoder.pending(), &[0xE4, 0xB8]);
+        decoder.decode(&[0x96], &mut out).unwrap();
+        assert_eq!(out, "a世");
+
+        decoder.decode(&[0xE4], &mut out).unwrap();
+        assert!(decoder.finish(&mut out).is_err());
+
+        let error = decode_in_reads(&mut IncrementalDecoder::new(Encoding::UTF8), b"ok\xFFno", 2).unwrap_err();
+        assert!(error.to_string().contains("byte 2"));
+    }
+
+    #[test]
+    fn test_lossless_and_partial_bom() {
+        let bytes = b"\xEF\xBB\xBFa\xFFb\xE4\xB8";
+        let mut decoder = IncrementalDecoder::new(Encoding::UTF8).with_lossless(true);
+        let text = decode_in_reads(&mut decoder, bytes, 1).unwrap();
+        let expected = EncodingHandler::new(Encoding::UTF8).decode_lossless(bytes).text;
+        assert_eq!(text, expected);
+
+        // A stream that is only the start of a BOM.
+        let mut decoder = IncrementalDecoder::new(Encoding::LATIN1);
+        assert_eq!(decode_in_reads(&mut decoder, b"\xEF", 1).unwrap(), "ï");
+        let mut decoder = IncrementalDecoder::new(Encoding::UTF16BE).with_lossless(true);
+        assert_eq!(decode_in_reads(&mut decoder, b"\xFE", 1).unwrap(), raw_byte_marker(0xFE).to_string());
+    }
+}
@@ -0,0 +1,29 @@
+#!/usr/bin/env python3
+"""Regenerates the double-byte code page tables loaded by `conversion.rs`.
+
+Each table is a dense grid of little-endian u16 code points indexed by
+`(lead - first_lead) * trail_count + (trail - first_trail)`, 0 meaning unmapped.
+Run from this directory: `python3 generate.py`.
+"""
+
+import struct
+
+TABLES = {
+    # file: (python codec, leads, trails)
+    "shift_jis.bin": ("cp932", range(0x81, 0xFD), range(0x40, 0xFD)),
+    "gbk.bin": ("gbk", range(0x81, 0xFF), range(0x40, 0xFF)),
+    "euc_kr.bin": ("cp949", range(0x81, 0xFF), range(0x41, 0xFF)),
+}
+
+for file, (codec, leads, trails) in TABLES.items():
+    out = bytearray()
+    for lead in leads:
+        for trail in trails:
+            try:
+                text = bytes([lead, trail]).decode(codec)
+            except UnicodeDecodeError:
+                text = ""
+            code = ord(text) if len(text) == 1 and ord(text) <= 0xFFFF else 0
+            out += struct.pack("<H", code)
+    with open(file, "wb") as f:
+        f.write(out)
@@ -0,0 +1,4 @@
+//! Utils module
+//! Reexports the encoding module
+
+pub mod encoding;